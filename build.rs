@@ -0,0 +1,10 @@
+fn main() {
+    // the gRPC API is generated only when it is served
+    #[cfg(feature = "grpc")]
+    {
+        println!("cargo:rerun-if-changed=proto/shortener.proto");
+        let protoc = protoc_bin_vendored::protoc_bin_path().expect("Failed to find the vendored protoc");
+        std::env::set_var("PROTOC", protoc);
+        tonic_build::compile_protos("proto/shortener.proto").expect("Failed to compile the gRPC API");
+    }
+}
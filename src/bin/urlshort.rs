@@ -0,0 +1,8 @@
+//! `urlshort` command line tool, see the `cli` module of the service.
+
+#[path = "../main.rs"]
+pub mod shortener;
+
+fn main() -> std::process::ExitCode {
+    shortener::cli::run()
+}
@@ -43,16 +43,62 @@
 
 #![allow(unused_variables, dead_code)]
 
-use std::{collections::{HashMap, HashSet}, hash::{DefaultHasher, Hash, Hasher}};
+use std::{
+    cell::Cell,
+    collections::{btree_map, BTreeMap, BTreeSet, HashMap, HashSet},
+    hash::{DefaultHasher, Hash, Hasher},
+    io::{self, Write},
+    net::IpAddr,
+    sync::{Mutex, PoisonError},
+    time::Instant,
+};
+use backend::{InMemory, QueryBackend};
 use commands::CommandHandler;
+use dispatch::{
+    AccessRequest, ActorId, AllowAll, AuthorizationPolicy, Command, Operation, Query, Reply, RequestContext, Role,
+    TenantId,
+};
+use errors::ErrorPayload;
+use import::{ImportReport, ImportedLink, ImportedRedirect, LinkImportReport, MergeRules};
+use integrity::{IntegrityCheck, IntegrityReport};
+use scheduler::{ScheduleId, ScheduledCommand};
+use slugs::{SlugGenerationStats, SlugGenerator, SlugRules, SlugViolation};
+use urls::{
+    AllowAllUrls, MagnetValidator, MailtoValidator, QueryParam, RedirectFollower, SchemeAllowlist, SchemeValidator,
+    TelValidator, UrlLimits, UrlNormalization, UrlPolicy, UrlViolation,
+};
+use webhooks::{
+    DeliveryId, DeliveryStatus, EventWebhook, LinkWebhook, NoWebhooks, RetryPolicy, TrackedDelivery, WebhookDelivery,
+    WebhookId, WebhookSender, WebhookTransport, WebhookTrigger,
+};
+use events::{Event, EventKind, EventRecord, VersionedEvent};
+use health::{DestinationHealth, HealthProbe};
+use previews::{DestinationPreview, PreviewFetcher};
+use export::ExportFilter;
+use digests::{DigestPeriod, DigestSink, StatsDigest};
+use rate_limits::{ClientKey, LimitedOperation, RateLimit, RateLimiter, RateLimits};
+use metrics::{MetricsRecorder, NoMetrics};
+use analytics::{
+    Anomaly, AnomalyRules, BotClassifier, BrowserFamily, CountryCode, DeviceClass, GeoResolver, HyperLogLog,
+    IpAnonymization, RedirectBucket, Resolution, Share, UserAgentHeuristics,
+};
+use projections::{
+    CampaignIndex, CountryClicks, DeviceClicks, Projection, ProjectionLag, ProjectionRunner, RedirectRollups, SlugIds,
+    TagIndex,
+};
 use queries::QueryHandler;
+use partitioning::{InstanceId, PartitionRouter, PartitionedShortener, RendezvousRouter, StaticRanges};
+use sync::{Changes, SyncCursor};
+use subscriptions::{ClickFeed, EventBus, EventFilter, Subscription, SubscriptionId, WaitForRedirects};
 use url::Url as baseUrl;
-use chrono::Local;
+use chrono::{DateTime, Datelike, Local, TimeDelta, TimeZone, Utc};
+use sha2::{Digest, Sha256};
 
 const SLUG_LEN: usize = 10;
 
 /// All possible errors of the [`UrlShortenerService`].
-#[derive(Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "event-log", derive(serde::Serialize, serde::Deserialize))]
 pub enum ShortenerError {
     /// This error occurs when an invalid [`Url`] is provided for shortening.
     InvalidUrl,
@@ -64,17 +110,601 @@ pub enum ShortenerError {
     /// This error occurs when the provided [`Slug`] does not map to any existing
     /// short link.
     SlugNotFound,
+
+    /// This error occurs when a [`Projection`] with the same name is already
+    /// registered.
+    ///
+    /// [`Projection`]: projections::Projection
+    ProjectionAlreadyRegistered,
+
+    /// This error occurs when there is no registered [`Projection`] with the
+    /// given name.
+    ///
+    /// [`Projection`]: projections::Projection
+    ProjectionNotFound,
+
+    /// This error occurs when a [`Projection`] can't be built, because
+    /// events it needs were pruned to fit the [`EventWindow`] and it has no
+    /// copy in the snapshot.
+    ///
+    /// [`Projection`]: projections::Projection
+    HistoryPruned,
+
+    /// This error occurs when a redirect is requested for a short link that
+    /// was disabled.
+    LinkDisabled,
+
+    /// This error occurs when a redirect is requested for a password-protected
+    /// short link without a password.
+    PasswordRequired,
+
+    /// This error occurs when a redirect is requested for a password-protected
+    /// short link with a wrong password.
+    InvalidPassword,
+
+    /// This error occurs when the [`AuthorizationPolicy`] denies the
+    /// requested operation.
+    ///
+    /// [`AuthorizationPolicy`]: dispatch::AuthorizationPolicy
+    AccessDenied,
+
+    /// This error occurs when an attempt is made to attach a URL to a slug
+    /// which isn't reserved or whose reservation has expired.
+    SlugNotReserved,
+
+    /// This error occurs when a remote service can't be reached or fails
+    /// internally.
+    ServiceUnavailable,
+
+    /// This error occurs when a redirect is requested for a one-time short
+    /// link which was already followed.
+    LinkConsumed,
+
+    /// This error occurs when an attempt is made to activate a draft link
+    /// which wasn't prepared or has expired.
+    DraftNotFound,
+
+    /// This error occurs when there is no pending scheduled command with the
+    /// given id.
+    ScheduledCommandNotFound,
+
+    /// This error occurs when the awaited condition isn't met in time.
+    TimedOut,
+
+    /// This error occurs when an attempt is made to merge links pointing to
+    /// different destinations.
+    DestinationMismatch,
+
+    /// This error occurs when there is no group with the given id.
+    GroupNotFound,
+
+    /// This error occurs when no free slug was generated within the allowed
+    /// number of attempts.
+    SlugGenerationFailed,
+
+    /// This error occurs when an attempt is made to use a slug from
+    /// [`ServiceConfig::blocked_slugs`].
+    SlugBlocked,
+
+    /// This error occurs when a custom slug breaks one of
+    /// [`ServiceConfig::slug_rules`].
+    InvalidSlug(SlugViolation),
+
+    /// This error occurs when a namespaced slug refers to a namespace which
+    /// doesn't exist.
+    NamespaceNotFound,
+
+    /// This error occurs when an attempt is made to create a namespace which
+    /// already exists.
+    NamespaceAlreadyExists,
+
+    /// This error occurs when the scheme of a [`Url`] provided for
+    /// shortening is not in [`ServiceConfig::allowed_schemes`], e.g.
+    /// `javascript:`.
+    SchemeNotAllowed,
+
+    /// This error occurs when the destination of a [`Url`] is blocked by
+    /// the blocklist of hosts or the [`UrlPolicy`], or when a link to such
+    /// a destination is followed.
+    UrlBlocked,
+
+    /// This error occurs when a [`Url`] provided for shortening breaks one
+    /// of [`ServiceConfig::url_limits`].
+    UrlRejected(UrlViolation),
+
+    /// This error occurs when a [`Url`] provided for shortening points at
+    /// one of [`ServiceConfig::own_hosts`], while
+    /// [`ServiceConfig::allow_own_links`] is not set.
+    SelfReference,
+
+    /// This error occurs when a [`Url`] provided for shortening points at a
+    /// short link which leads back to the new link, or the chain of short
+    /// links it resolves through is longer than [`urls::MAX_OWN_REDIRECTS`].
+    RedirectLoop,
+
+    /// This error occurs when no link was ever assigned to the given
+    /// campaign.
+    CampaignNotFound,
+
+    /// This error occurs when a client exceeds its [`RateLimit`], the
+    /// request may be retried after `retry_after` seconds.
+    RateLimited { retry_after: u64 },
+
+    /// This error occurs when a request which must be authenticated is made
+    /// without an [`ApiKey`].
+    ApiKeyRequired,
+
+    /// This error occurs when a request is made with an [`ApiKey`] which
+    /// doesn't exist or was revoked.
+    InvalidApiKey,
+
+    /// This error occurs when an attempt is made to revoke an [`ApiKey`]
+    /// which doesn't exist or was already revoked.
+    ApiKeyNotFound,
+
+    /// This error occurs when a webhook of domain events is referenced by an
+    /// id which isn't registered.
+    WebhookNotFound,
+
+    /// This error occurs when a valid entry of an atomic batch isn't created,
+    /// because another entry of the batch failed.
+    BatchAborted,
+
+    /// This error occurs when a batch has more entries than the API layer
+    /// accepts at once.
+    BatchTooLarge,
+}
+
+impl ShortenerError {
+    /// Returns the stable machine-readable code of the error. Clients branch
+    /// on these codes, so they must never change.
+    pub fn code(&self) -> &'static str {
+        match self {
+            ShortenerError::InvalidUrl => "invalid_url",
+            ShortenerError::SlugAlreadyInUse => "slug_already_in_use",
+            ShortenerError::SlugNotFound => "slug_not_found",
+            ShortenerError::ProjectionAlreadyRegistered => "projection_already_registered",
+            ShortenerError::ProjectionNotFound => "projection_not_found",
+            ShortenerError::HistoryPruned => "history_pruned",
+            ShortenerError::LinkDisabled => "link_disabled",
+            ShortenerError::PasswordRequired => "password_required",
+            ShortenerError::InvalidPassword => "invalid_password",
+            ShortenerError::AccessDenied => "access_denied",
+            ShortenerError::SlugNotReserved => "slug_not_reserved",
+            ShortenerError::ServiceUnavailable => "service_unavailable",
+            ShortenerError::LinkConsumed => "link_consumed",
+            ShortenerError::DraftNotFound => "draft_not_found",
+            ShortenerError::ScheduledCommandNotFound => "scheduled_command_not_found",
+            ShortenerError::TimedOut => "timed_out",
+            ShortenerError::DestinationMismatch => "destination_mismatch",
+            ShortenerError::GroupNotFound => "group_not_found",
+            ShortenerError::SlugGenerationFailed => "slug_generation_failed",
+            ShortenerError::SlugBlocked => "slug_blocked",
+            ShortenerError::InvalidSlug(SlugViolation::TooShort) => "slug_too_short",
+            ShortenerError::InvalidSlug(SlugViolation::TooLong) => "slug_too_long",
+            ShortenerError::InvalidSlug(SlugViolation::InvalidCharacter) => "slug_invalid_character",
+            ShortenerError::InvalidSlug(SlugViolation::EdgeDash) => "slug_edge_dash",
+            ShortenerError::NamespaceNotFound => "namespace_not_found",
+            ShortenerError::NamespaceAlreadyExists => "namespace_already_exists",
+            ShortenerError::SchemeNotAllowed => "scheme_not_allowed",
+            ShortenerError::UrlBlocked => "url_blocked",
+            ShortenerError::UrlRejected(UrlViolation::TooLong) => "url_too_long",
+            ShortenerError::UrlRejected(UrlViolation::Newline) => "url_contains_newline",
+            ShortenerError::SelfReference => "self_reference",
+            ShortenerError::RedirectLoop => "redirect_loop",
+            ShortenerError::CampaignNotFound => "campaign_not_found",
+            ShortenerError::RateLimited { .. } => "rate_limited",
+            ShortenerError::ApiKeyRequired => "api_key_required",
+            ShortenerError::InvalidApiKey => "invalid_api_key",
+            ShortenerError::ApiKeyNotFound => "api_key_not_found",
+            ShortenerError::WebhookNotFound => "webhook_not_found",
+            ShortenerError::BatchAborted => "batch_aborted",
+            ShortenerError::BatchTooLarge => "batch_too_large",
+        }
+    }
+
+    /// Returns the error with the given code, if any.
+    pub fn from_code(code: &str) -> Option<Self> {
+        let error = match code {
+            "invalid_url" => ShortenerError::InvalidUrl,
+            "slug_already_in_use" => ShortenerError::SlugAlreadyInUse,
+            "slug_not_found" => ShortenerError::SlugNotFound,
+            "projection_already_registered" => ShortenerError::ProjectionAlreadyRegistered,
+            "projection_not_found" => ShortenerError::ProjectionNotFound,
+            "history_pruned" => ShortenerError::HistoryPruned,
+            "link_disabled" => ShortenerError::LinkDisabled,
+            "password_required" => ShortenerError::PasswordRequired,
+            "invalid_password" => ShortenerError::InvalidPassword,
+            "access_denied" => ShortenerError::AccessDenied,
+            "slug_not_reserved" => ShortenerError::SlugNotReserved,
+            "service_unavailable" => ShortenerError::ServiceUnavailable,
+            "link_consumed" => ShortenerError::LinkConsumed,
+            "draft_not_found" => ShortenerError::DraftNotFound,
+            "scheduled_command_not_found" => ShortenerError::ScheduledCommandNotFound,
+            "timed_out" => ShortenerError::TimedOut,
+            "destination_mismatch" => ShortenerError::DestinationMismatch,
+            "group_not_found" => ShortenerError::GroupNotFound,
+            "slug_generation_failed" => ShortenerError::SlugGenerationFailed,
+            "slug_blocked" => ShortenerError::SlugBlocked,
+            "slug_too_short" => ShortenerError::InvalidSlug(SlugViolation::TooShort),
+            "slug_too_long" => ShortenerError::InvalidSlug(SlugViolation::TooLong),
+            "slug_invalid_character" => ShortenerError::InvalidSlug(SlugViolation::InvalidCharacter),
+            "slug_edge_dash" => ShortenerError::InvalidSlug(SlugViolation::EdgeDash),
+            "namespace_not_found" => ShortenerError::NamespaceNotFound,
+            "namespace_already_exists" => ShortenerError::NamespaceAlreadyExists,
+            "scheme_not_allowed" => ShortenerError::SchemeNotAllowed,
+            "url_blocked" => ShortenerError::UrlBlocked,
+            "url_too_long" => ShortenerError::UrlRejected(UrlViolation::TooLong),
+            "url_contains_newline" => ShortenerError::UrlRejected(UrlViolation::Newline),
+            "self_reference" => ShortenerError::SelfReference,
+            "redirect_loop" => ShortenerError::RedirectLoop,
+            "campaign_not_found" => ShortenerError::CampaignNotFound,
+            "rate_limited" => ShortenerError::RateLimited { retry_after: 0 },
+            "api_key_required" => ShortenerError::ApiKeyRequired,
+            "invalid_api_key" => ShortenerError::InvalidApiKey,
+            "api_key_not_found" => ShortenerError::ApiKeyNotFound,
+            "webhook_not_found" => ShortenerError::WebhookNotFound,
+            "batch_aborted" => ShortenerError::BatchAborted,
+            "batch_too_large" => ShortenerError::BatchTooLarge,
+            _ => return None,
+        };
+        Some(error)
+    }
+
+    /// Returns the name of the request field which caused the error, if any.
+    pub fn field(&self) -> Option<&'static str> {
+        match self {
+            ShortenerError::InvalidUrl
+            | ShortenerError::SchemeNotAllowed
+            | ShortenerError::UrlBlocked
+            | ShortenerError::UrlRejected(_)
+            | ShortenerError::SelfReference
+            | ShortenerError::RedirectLoop => Some("url"),
+            ShortenerError::SlugAlreadyInUse
+            | ShortenerError::SlugNotFound
+            | ShortenerError::LinkDisabled
+            | ShortenerError::SlugNotReserved
+            | ShortenerError::LinkConsumed
+            | ShortenerError::DraftNotFound
+            | ShortenerError::DestinationMismatch
+            | ShortenerError::SlugGenerationFailed
+            | ShortenerError::SlugBlocked
+            | ShortenerError::InvalidSlug(_) => Some("slug"),
+            ShortenerError::PasswordRequired | ShortenerError::InvalidPassword => Some("password"),
+            ShortenerError::ProjectionAlreadyRegistered
+            | ShortenerError::ProjectionNotFound
+            | ShortenerError::HistoryPruned => Some("name"),
+            ShortenerError::ScheduledCommandNotFound
+            | ShortenerError::ApiKeyNotFound
+            | ShortenerError::WebhookNotFound => Some("id"),
+            ShortenerError::ApiKeyRequired | ShortenerError::InvalidApiKey => Some("api_key"),
+            ShortenerError::GroupNotFound => Some("group"),
+            ShortenerError::CampaignNotFound => Some("campaign"),
+            ShortenerError::NamespaceNotFound | ShortenerError::NamespaceAlreadyExists => Some("namespace"),
+            ShortenerError::BatchTooLarge => Some("links"),
+            ShortenerError::AccessDenied
+            | ShortenerError::BatchAborted
+            | ShortenerError::ServiceUnavailable
+            | ShortenerError::TimedOut
+            | ShortenerError::RateLimited { .. } => None,
+        }
+    }
+
+    /// Returns the seconds after which the request may be retried, if it
+    /// makes sense to retry it.
+    pub fn retry_after(&self) -> Option<u64> {
+        match self {
+            ShortenerError::RateLimited { retry_after } => Some(*retry_after),
+            _ => None,
+        }
+    }
+
+    /// Returns the HTTP status code of responses failing with the error.
+    pub fn status_code(&self) -> u16 {
+        match self {
+            ShortenerError::InvalidUrl
+            | ShortenerError::SlugBlocked
+            | ShortenerError::InvalidSlug(_)
+            | ShortenerError::SchemeNotAllowed
+            | ShortenerError::UrlRejected(_)
+            | ShortenerError::SelfReference
+            | ShortenerError::RedirectLoop => 400,
+            ShortenerError::PasswordRequired | ShortenerError::ApiKeyRequired | ShortenerError::InvalidApiKey => 401,
+            ShortenerError::LinkDisabled
+            | ShortenerError::InvalidPassword
+            | ShortenerError::AccessDenied
+            | ShortenerError::UrlBlocked => 403,
+            ShortenerError::SlugNotFound
+            | ShortenerError::ProjectionNotFound
+            | ShortenerError::SlugNotReserved
+            | ShortenerError::DraftNotFound
+            | ShortenerError::ScheduledCommandNotFound
+            | ShortenerError::ApiKeyNotFound
+            | ShortenerError::WebhookNotFound
+            | ShortenerError::GroupNotFound
+            | ShortenerError::NamespaceNotFound
+            | ShortenerError::CampaignNotFound => 404,
+            ShortenerError::SlugAlreadyInUse
+            | ShortenerError::ProjectionAlreadyRegistered
+            | ShortenerError::HistoryPruned
+            | ShortenerError::DestinationMismatch
+            | ShortenerError::NamespaceAlreadyExists => 409,
+            ShortenerError::LinkConsumed => 410,
+            ShortenerError::BatchTooLarge => 413,
+            ShortenerError::BatchAborted => 424,
+            ShortenerError::RateLimited { .. } => 429,
+            ShortenerError::SlugGenerationFailed => 500,
+            ShortenerError::ServiceUnavailable => 503,
+            ShortenerError::TimedOut => 504,
+        }
+    }
+}
+
+impl std::fmt::Display for ShortenerError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let message = match self {
+            ShortenerError::InvalidUrl => "invalid URL",
+            ShortenerError::SlugAlreadyInUse => "slug is already in use",
+            ShortenerError::SlugNotFound => "slug not found",
+            ShortenerError::ProjectionAlreadyRegistered => "projection with the same name is already registered",
+            ShortenerError::ProjectionNotFound => "projection not found",
+            ShortenerError::HistoryPruned => "events needed by the projection were pruned",
+            ShortenerError::LinkDisabled => "link is disabled",
+            ShortenerError::PasswordRequired => "password required",
+            ShortenerError::InvalidPassword => "invalid password",
+            ShortenerError::AccessDenied => "access denied",
+            ShortenerError::SlugNotReserved => "slug is not reserved",
+            ShortenerError::ServiceUnavailable => "service unavailable",
+            ShortenerError::LinkConsumed => "one-time link was already used",
+            ShortenerError::DraftNotFound => "draft link not found",
+            ShortenerError::ScheduledCommandNotFound => "scheduled command not found",
+            ShortenerError::TimedOut => "timed out",
+            ShortenerError::DestinationMismatch => "links point to different destinations",
+            ShortenerError::GroupNotFound => "group not found",
+            ShortenerError::SlugGenerationFailed => "failed to generate a free slug",
+            ShortenerError::SlugBlocked => "slug is reserved by the service",
+            ShortenerError::InvalidSlug(SlugViolation::TooShort) => "slug is too short",
+            ShortenerError::InvalidSlug(SlugViolation::TooLong) => "slug is too long",
+            ShortenerError::InvalidSlug(SlugViolation::InvalidCharacter) => {
+                "slug contains a character which is not allowed"
+            },
+            ShortenerError::InvalidSlug(SlugViolation::EdgeDash) => "slug starts or ends with a dash",
+            ShortenerError::NamespaceNotFound => "namespace not found",
+            ShortenerError::NamespaceAlreadyExists => "namespace already exists",
+            ShortenerError::SchemeNotAllowed => "URL scheme is not allowed",
+            ShortenerError::UrlBlocked => "URL destination is blocked",
+            ShortenerError::UrlRejected(UrlViolation::TooLong) => "URL is too long",
+            ShortenerError::UrlRejected(UrlViolation::Newline) => "URL contains a line break",
+            ShortenerError::SelfReference => "URL points at the shortener itself",
+            ShortenerError::RedirectLoop => "URL leads back to the short link",
+            ShortenerError::CampaignNotFound => "campaign not found",
+            ShortenerError::RateLimited { .. } => "too many requests",
+            ShortenerError::ApiKeyRequired => "API key is required",
+            ShortenerError::InvalidApiKey => "API key is invalid",
+            ShortenerError::ApiKeyNotFound => "API key not found",
+            ShortenerError::WebhookNotFound => "webhook not found",
+            ShortenerError::BatchAborted => "batch was aborted because another entry failed",
+            ShortenerError::BatchTooLarge => "batch has too many entries",
+        };
+        f.write_str(message)
+    }
+}
+
+impl std::error::Error for ShortenerError {}
+
+/// Error responses of the API layers.
+pub mod errors {
+    use super::ShortenerError;
+
+    /// Code of errors which are not [`ShortenerError`]s, e.g. failures of the
+    /// transport or storage.
+    pub const INTERNAL_ERROR_CODE: &str = "internal_error";
+
+    /// Stable error payload returned by every API layer, so clients can branch
+    /// on [`ErrorPayload::code`] instead of parsing messages.
+    #[derive(Clone, Debug, PartialEq)]
+    pub struct ErrorPayload {
+        /// Machine-readable code, see [`ShortenerError::code`].
+        pub code: String,
+
+        /// Human-readable description of the error.
+        pub message: String,
+
+        /// Name of the request field which caused the error, if any.
+        pub field: Option<String>,
+
+        /// Seconds after which the request may be retried, if it makes sense
+        /// to retry it.
+        pub retry_after: Option<u64>,
+    }
+
+    impl ErrorPayload {
+        /// Creates the payload of an error which is not a [`ShortenerError`].
+        pub fn internal(message: impl Into<String>) -> Self {
+            Self {
+                code: INTERNAL_ERROR_CODE.to_string(),
+                message: message.into(),
+                field: None,
+                retry_after: None,
+            }
+        }
+
+        /// Returns the [`ShortenerError`] described by the payload, if it
+        /// describes one.
+        pub fn to_error(&self) -> Option<ShortenerError> {
+            match ShortenerError::from_code(&self.code)? {
+                ShortenerError::RateLimited { .. } => {
+                    Some(ShortenerError::RateLimited { retry_after: self.retry_after.unwrap_or_default() })
+                },
+                error => Some(error),
+            }
+        }
+    }
+
+    impl From<&ShortenerError> for ErrorPayload {
+        fn from(error: &ShortenerError) -> Self {
+            Self {
+                code: error.code().to_string(),
+                message: error.to_string(),
+                field: error.field().map(str::to_string),
+                retry_after: error.retry_after(),
+            }
+        }
+    }
 }
 
 /// A unique string (or alias) that represents the shortened version of the
 /// URL.
 #[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "event-log", derive(serde::Serialize, serde::Deserialize))]
 pub struct Slug(pub String);
 
 /// The original URL that the short link points to.
 #[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "event-log", derive(serde::Serialize, serde::Deserialize))]
 pub struct Url(pub String);
 
+/// Stable identity of a short link, assigned at creation. Unlike the [`Slug`],
+/// it never changes during the life of the link.
+///
+/// It is a [ULID](https://github.com/ulid/spec), so ids are sortable by
+/// creation time.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[cfg_attr(feature = "event-log", derive(serde::Serialize, serde::Deserialize))]
+pub struct LinkId(pub String);
+
+impl LinkId {
+    /// Generates a new unique id. Ids generated within the same millisecond
+    /// are still ordered by generation.
+    pub fn generate() -> Self {
+        // Crockford's base32 alphabet used by ULID
+        const ALPHABET: &[u8; 32] = b"0123456789ABCDEFGHJKMNPQRSTVWXYZ";
+        // last generated value, ids are monotonic as per ULID spec
+        static LAST: Mutex<u128> = Mutex::new(0);
+
+        // 48 bits of milliseconds timestamp followed by 80 random bits
+        let timestamp = Utc::now().timestamp_millis() as u128 & ((1 << 48) - 1);
+        let randomness = rand::random::<u128>() & ((1 << 80) - 1);
+        let mut last = LAST.lock().unwrap_or_else(PoisonError::into_inner);
+        let value = ((timestamp << 80) | randomness).max(*last + 1);
+        *last = value;
+
+        // 26 characters, 5 bits each, the first one holds only 3 bits
+        let id = (0..26)
+            .rev()
+            .map(|i| ALPHABET[((value >> (i * 5)) & 0x1f) as usize] as char)
+            .collect();
+        Self(id)
+    }
+}
+
+/// Stable identity of a [`Group`] of links. It is a ULID, like [`LinkId`].
+#[derive(Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[cfg_attr(feature = "event-log", derive(serde::Serialize, serde::Deserialize))]
+pub struct GroupId(pub String);
+
+impl GroupId {
+    /// Generates a new unique id.
+    pub fn generate() -> Self {
+        Self(LinkId::generate().0)
+    }
+}
+
+/// Stable identity of an [`ApiKey`], used to revoke it. It is a ULID, like
+/// [`LinkId`].
+#[derive(Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[cfg_attr(feature = "event-log", derive(serde::Serialize, serde::Deserialize))]
+pub struct ApiKeyId(pub String);
+
+impl ApiKeyId {
+    /// Generates a new unique id.
+    pub fn generate() -> Self {
+        Self(LinkId::generate().0)
+    }
+}
+
+/// API key authenticating requests of an [`ActorId`], see
+/// [`UrlShortenerService::authenticate`]. Only the hash of its secret is
+/// recorded, so the secret can't be shown again after the key is created.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ApiKey {
+    /// Identity of the key.
+    pub id: ApiKeyId,
+
+    /// Secret sent by clients with their requests.
+    pub secret: String,
+}
+
+impl ApiKey {
+    /// Generates a new key with a random secret.
+    pub fn generate() -> Self {
+        Self { id: ApiKeyId::generate(), secret: hex(&rand::random::<[u8; 32]>()) }
+    }
+
+    /// Returns SHA-256 of the secret, hex-encoded. Secrets are random, so
+    /// they aren't salted like passwords.
+    pub fn hash(secret: &str) -> String {
+        hex(&Sha256::digest(secret.as_bytes()))
+    }
+}
+
+/// Compact identity of a [`Slug`] of a particular link, referenced by
+/// redirect events instead of strings. See [`SlugIds`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[cfg_attr(feature = "event-log", derive(serde::Serialize, serde::Deserialize))]
+pub struct SlugId(pub u32);
+
+/// Owner of a [`ShortLink`]. Only the owner may modify the link through the
+/// dispatcher.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "event-log", derive(serde::Serialize, serde::Deserialize))]
+pub struct OwnerId(pub String);
+
+impl From<&ActorId> for OwnerId {
+    fn from(actor: &ActorId) -> Self {
+        Self(actor.0.clone())
+    }
+}
+
+/// Identifier of the visitor following short links, e.g. a cookie value or a
+/// fingerprint of the client.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "event-log", derive(serde::Serialize, serde::Deserialize))]
+pub struct VisitorId(pub String);
+
+/// Identifier of a conversion reported by a downstream system, e.g. an id
+/// of an order or a signup.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "event-log", derive(serde::Serialize, serde::Deserialize))]
+pub struct ConversionId(pub String);
+
+/// Details of the request following a short link, recorded with the
+/// redirect for analytics.
+#[derive(Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "event-log", derive(serde::Serialize, serde::Deserialize))]
+pub struct RedirectContext {
+    /// Visitor following the link, if known.
+    pub visitor: Option<VisitorId>,
+
+    /// Page the visitor came from, the `Referer` header.
+    pub referrer: Option<String>,
+
+    /// Client of the visitor, the `User-Agent` header.
+    pub user_agent: Option<String>,
+
+    /// Address of the visitor. It is recorded as anonymized by
+    /// [`ServiceConfig::ip_anonymization`].
+    pub ip: Option<IpAddr>,
+
+    /// Time of the request, if it was made before the redirect is handled,
+    /// e.g. for redirects served from logs of an edge cache.
+    pub requested_at: Option<DateTime<Utc>>,
+
+    /// Country of the visitor. Resolved from `ip` by the [`GeoResolver`] of
+    /// the service unless it is given.
+    pub country: Option<CountryCode>,
+}
+
 /// Shortened URL representation.
 #[derive(Debug, Clone, PartialEq)]
 pub struct ShortLink {
@@ -86,181 +716,14735 @@ pub struct ShortLink {
     pub url: Url,
 }
 
-/// Statistics of the [`ShortLink`].
-#[derive(Debug, Clone, PartialEq)]
-pub struct Stats {
-    /// [`ShortLink`] to which this [`Stats`] are related.
-    pub link: ShortLink,
+/// Label attached to [`ShortLink`]s to group and find them.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[cfg_attr(feature = "event-log", derive(serde::Serialize, serde::Deserialize))]
+pub struct Tag(pub String);
 
-    /// Count of redirects of the [`ShortLink`].
-    pub redirects: u64,
+/// Name of a marketing campaign [`ShortLink`]s are assigned to, so their
+/// stats add up, see [`UrlShortenerService::get_campaign_stats`].
+#[derive(Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[cfg_attr(feature = "event-log", derive(serde::Serialize, serde::Deserialize))]
+pub struct Campaign(pub String);
+
+/// Optional settings of a [`ShortLink`] given at creation.
+#[derive(Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "event-log", derive(serde::Serialize, serde::Deserialize))]
+pub struct LinkOptions {
+    /// Tags attached to the link.
+    pub tags: Vec<Tag>,
+
+    /// Descriptive metadata of the link.
+    pub metadata: LinkMetadata,
+
+    /// Whether the link is valid for exactly one redirect.
+    pub one_time: bool,
+
+    /// How redirects of the link are served.
+    pub redirect_type: RedirectType,
 }
 
-/// Commands for CQRS.
-pub mod commands {
-    use super::{ShortLink, ShortenerError, Slug, Url};
+/// Descriptive metadata of a [`ShortLink`], it doesn't affect redirects.
+#[derive(Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "event-log", derive(serde::Serialize, serde::Deserialize))]
+pub struct LinkMetadata {
+    /// Human readable title.
+    pub title: Option<String>,
 
-    /// Trait for command handlers.
-    pub trait CommandHandler {
-        /// Creates a new short link. It accepts the original url and an
-        /// optional [`Slug`]. If a [`Slug`] is not provided, the service will generate
-        /// one. Returns the newly created [`ShortLink`].
-        ///
-        /// ## Errors
-        ///
-        /// See [`ShortenerError`].
-        fn handle_create_short_link(
-            &mut self,
-            url: Url,
-            slug: Option<Slug>,
-        ) -> Result<ShortLink, ShortenerError>;
+    /// Human readable description.
+    pub description: Option<String>,
 
-        /// Processes a redirection by [`Slug`], returning the associated
-        /// [`ShortLink`] or a [`ShortenerError`].
-        fn handle_redirect(
-            &mut self,
-            slug: Slug,
-        ) -> Result<ShortLink, ShortenerError>;
+    /// Arbitrary key/value pairs.
+    pub attributes: BTreeMap<String, String>,
+}
+
+/// Salted hash of the password protecting a [`ShortLink`]. The password
+/// itself is never stored.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "event-log", derive(serde::Serialize, serde::Deserialize))]
+pub struct PasswordHash {
+    /// Random salt, hex-encoded.
+    pub salt: String,
+
+    /// SHA-256 of the salt followed by the password, hex-encoded.
+    pub hash: String,
+}
+
+impl PasswordHash {
+    /// Hashes the password with a new random salt.
+    pub fn new(password: &str) -> Self {
+        let salt = hex(&rand::random::<[u8; 16]>());
+        let hash = Self::digest(&salt, password);
+        Self { salt, hash }
+    }
+
+    /// Checks if the password matches the hash.
+    pub fn verify(&self, password: &str) -> bool {
+        Self::digest(&self.salt, password) == self.hash
+    }
+
+    fn digest(salt: &str, password: &str) -> String {
+        let mut hasher = Sha256::new();
+        hasher.update(salt.as_bytes());
+        hasher.update(password.as_bytes());
+        hex(&hasher.finalize())
     }
 }
 
-/// Queries for CQRS
-pub mod queries {
-    use super::{ShortenerError, Slug, Stats};
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
 
-    /// Trait for query handlers.
-    pub trait QueryHandler {
-        /// Returns the [`Stats`] for a specific [`ShortLink`], such as the
-        /// number of redirects (clicks).
-        ///
-        /// [`ShortLink`]: super::ShortLink
-        fn get_stats(&self, slug: Slug) -> Result<Stats, ShortenerError>;
+/// What happens with the old slug of a renamed [`ShortLink`].
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "event-log", derive(serde::Serialize, serde::Deserialize))]
+pub enum OldSlugPolicy {
+    /// The old slug keeps forwarding to the renamed link.
+    Forward,
+
+    /// The old slug keeps forwarding to the renamed link as a deprecated
+    /// alias during the grace period, and then it is released.
+    ForwardFor(TimeDelta),
+
+    /// The old slug stops resolving and can be used by other links.
+    Release,
+}
+
+/// How redirects of a [`ShortLink`] are served over HTTP.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "event-log", derive(serde::Serialize, serde::Deserialize))]
+pub enum RedirectType {
+    /// The destination may change, so clients and caches should keep
+    /// following the short link and every redirect is counted.
+    #[default]
+    Temporary,
+
+    /// The destination never changes, so clients and caches may remember it.
+    /// Redirects served from caches are not counted.
+    Permanent,
+}
+
+impl RedirectType {
+    /// Returns the HTTP status code of the redirect.
+    pub fn status_code(self) -> u16 {
+        match self {
+            RedirectType::Temporary => 302,
+            RedirectType::Permanent => 301,
+        }
     }
 }
 
-/// CQRS and Event Sourcing-based service implementation
-pub struct UrlShortenerService {
-    // store slug creation events as vector
-    url_events: Vec<ShortLink>, 
-    // store redirect events as hashmap of vectors to split events by slugs, we can do it because all slugs are unique, so we can speed up process of calculating stats
-    redirect_events_by_slug: HashMap<String, Vec<Slug>>
+/// Reason why a redirect by [`Slug`] was refused.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[cfg_attr(feature = "event-log", derive(serde::Serialize, serde::Deserialize))]
+pub enum RedirectRefusal {
+    /// The slug doesn't map to any link.
+    NotFound,
+
+    /// The slug was a deprecated alias whose grace period has ended.
+    Expired,
+
+    /// The link is disabled.
+    Disabled,
+
+    /// The link is protected and no password was given.
+    PasswordRequired,
+
+    /// The link is protected and the given password is wrong.
+    InvalidPassword,
+
+    /// The one-time link was already followed.
+    Consumed,
+
+    /// The destination of the link is blocked.
+    Blocked,
+
+    /// The client exceeded its rate limit of redirects, see
+    /// [`UrlShortenerService::check_rate_limit`].
+    RateLimited {
+        /// Seconds after which the client may redirect again.
+        retry_after: u64,
+    },
 }
 
-impl UrlShortenerService {
-    /// Creates a new instance of the service
-    pub fn new() -> Self {
-        Self {
-            url_events: Vec::new(),
-            redirect_events_by_slug: HashMap::new(),
+impl RedirectRefusal {
+    /// Returns the HTTP status code of the response.
+    pub fn status_code(self) -> u16 {
+        match self {
+            RedirectRefusal::NotFound => 404,
+            RedirectRefusal::Expired | RedirectRefusal::Consumed => 410,
+            RedirectRefusal::PasswordRequired => 401,
+            RedirectRefusal::Disabled | RedirectRefusal::InvalidPassword | RedirectRefusal::Blocked => 403,
+            RedirectRefusal::RateLimited { .. } => 429,
         }
     }
 
-    fn log(&self, message: String) {
-        let now = Local::now().format("%Y-%m-%d %H:%M:%S");
-        println!("[{}] {message}", now);
+    /// Returns the error reported by [`CommandHandler::handle_redirect`].
+    /// Expired aliases are reported as not found.
+    ///
+    /// [`CommandHandler::handle_redirect`]: commands::CommandHandler::handle_redirect
+    pub fn error(self) -> ShortenerError {
+        match self {
+            RedirectRefusal::NotFound | RedirectRefusal::Expired => ShortenerError::SlugNotFound,
+            RedirectRefusal::Disabled => ShortenerError::LinkDisabled,
+            RedirectRefusal::PasswordRequired => ShortenerError::PasswordRequired,
+            RedirectRefusal::InvalidPassword => ShortenerError::InvalidPassword,
+            RedirectRefusal::Consumed => ShortenerError::LinkConsumed,
+            RedirectRefusal::Blocked => ShortenerError::UrlBlocked,
+            RedirectRefusal::RateLimited { retry_after } => ShortenerError::RateLimited { retry_after },
+        }
     }
 }
 
-impl commands::CommandHandler for UrlShortenerService {
-    fn handle_create_short_link(
-        &mut self,
-        url: Url,
-        slug: Option<Slug>,
-    ) -> Result<ShortLink, ShortenerError> {
-        if baseUrl::parse(&url.0).is_err() {
-            return Err(ShortenerError::InvalidUrl);
-        }
-        
-        // We need to process all created slugs and make sure that our new slug doesn't match any of existing slugs
-        // It is equal to finding out if we already processed url because we can have only one slug for url
-        if self.url_events.iter().any(|event| event.url == url) {
-            self.log(format!("Failed to create short link: URL {url:?} already exists"));
-            return Err(ShortenerError::SlugAlreadyInUse);
+/// Outcome of a redirect by [`Slug`].
+#[derive(Clone, Debug, PartialEq)]
+pub enum RedirectOutcome {
+    /// The visitor is redirected to the link. Deduplicated redirects are
+    /// served too, they are just not counted.
+    Served {
+        /// The followed link.
+        link: ShortLink,
+
+        /// How the redirect is served.
+        redirect_type: RedirectType,
+
+        /// Whether the visitor should be shown an interstitial page with the
+        /// destination instead of being redirected right away, see
+        /// [`UrlShortenerService::handle_set_interstitial`]. It is up to the
+        /// API layer to show it.
+        interstitial: bool,
+    },
+
+    /// The redirect was refused.
+    Refused(RedirectRefusal),
+}
+
+impl RedirectOutcome {
+    /// Returns the HTTP status code of the response.
+    pub fn status_code(&self) -> u16 {
+        match self {
+            RedirectOutcome::Served { redirect_type, .. } => redirect_type.status_code(),
+            RedirectOutcome::Refused(refusal) => refusal.status_code(),
         }
+    }
 
-        // Collect all slugs in HashSet to provide existance of slug check O(1)
-        let existing_slugs: HashSet<_> = self.url_events.iter().map(|event| &event.slug.0).collect();
-        
-        // Function that generates slug using hash of url
-        fn generate_slug_from_url(url: &str) -> String {
-            let mut hasher = DefaultHasher::new();
-            url.hash(&mut hasher);
-            let hash = hasher.finish();
-        
-            format!("{:x}", hash).chars().take(SLUG_LEN).collect()
+    /// Converts the outcome to the result reported by
+    /// [`CommandHandler::handle_redirect`].
+    ///
+    /// [`CommandHandler::handle_redirect`]: commands::CommandHandler::handle_redirect
+    pub fn into_result(self) -> Result<ShortLink, ShortenerError> {
+        match self {
+            RedirectOutcome::Served { link, .. } => Ok(link),
+            RedirectOutcome::Refused(refusal) => Err(refusal.error()),
         }
+    }
+}
 
-        let short_link = match slug {
-            Some(slug) => {
-                if existing_slugs.contains(&slug.0) {
-                    self.log(format!("Failed to create short link: slug {slug:?} is already in use"));
-                    return Err(ShortenerError::SlugAlreadyInUse);
-                }
-                ShortLink { slug: slug, url }
-            },
-            None => {
-                // We will try to create random slug that doesn't exist yet
-                loop {
-                    let slug_str = generate_slug_from_url(&url.0);
-                    if !existing_slugs.contains(&slug_str) {
-                        break ShortLink { slug: Slug(slug_str), url };
-                    }
-                }                
-            }
-        };
+/// Statistics of the [`ShortLink`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct Stats {
+    /// [`ShortLink`] to which this [`Stats`] are related.
+    pub link: ShortLink,
 
-        // Create event for new slug
-        self.url_events.push(short_link.clone());
-        self.log(format!("Successfully created short link {short_link:?}"));
-        return Ok(short_link);
+    /// Count of redirects of the [`ShortLink`].
+    pub redirects: u64,
+}
+
+/// [`ShortLink`] prepared for review, which is not live yet.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Draft {
+    /// The link as it will be created.
+    pub link: ShortLink,
+
+    /// Time after which the draft can't be activated anymore.
+    pub expires_at: DateTime<Utc>,
+}
+
+/// Folder organizing [`ShortLink`]s. Every link belongs to at most one
+/// group.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Group {
+    /// Stable identity of the group.
+    pub group_id: GroupId,
+
+    /// Human readable name, it doesn't have to be unique.
+    pub name: String,
+
+    /// Owner of the group, if any.
+    pub owner: Option<OwnerId>,
+
+    /// Count of links in the group.
+    pub links: usize,
+}
+
+/// Prefix of slugs like `team-a/launch`, scoping their uniqueness. Only the
+/// owner of the namespace can use slugs in it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Namespace {
+    /// Name of the namespace, the part of its slugs before `/`.
+    pub name: String,
+
+    /// Owner of the namespace, if any.
+    pub owner: Option<OwnerId>,
+
+    /// Count of links whose slugs are in the namespace.
+    pub links: usize,
+}
+
+/// What happens when a [`Url`] which was already shortened is shortened
+/// again. URLs are compared once normalized.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum DuplicateUrlPolicy {
+    /// Creation fails with [`ShortenerError::SlugAlreadyInUse`].
+    #[default]
+    RejectDuplicates,
+
+    /// Another link to the URL is created.
+    AllowDuplicates,
+
+    /// The existing link to the URL owned by the caller is returned instead
+    /// of a new one, the requested slug is ignored. Links owned by others
+    /// are rejected like duplicates, as are duplicates of reserved slugs and
+    /// drafts.
+    ReturnExisting,
+}
+
+/// Overall stats of the [`UrlShortenerService`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct SystemStats {
+    /// Count of links, including archived ones.
+    pub links: usize,
+
+    /// Count of redirects of all links since their last stats resets.
+    pub redirects: u64,
+
+    /// Count of recorded events, including pruned ones.
+    pub events: u64,
+
+    /// Counters of slug generation since the service was started.
+    pub slug_generation: SlugGenerationStats,
+}
+
+/// Redirects of a [`ShortLink`] broken down by devices and browsers of
+/// visitors. Only redirects with a known user agent are counted.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DeviceStats {
+    /// Count of redirects with a known user agent.
+    pub redirects: u64,
+
+    /// Redirects by classes of devices, most frequent first.
+    pub devices: Vec<Share<DeviceClass>>,
+
+    /// Redirects by families of browsers, most frequent first.
+    pub browsers: Vec<Share<BrowserFamily>>,
+}
+
+/// Stats of a [`Campaign`] aggregated over its links.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CampaignStats {
+    /// The campaign.
+    pub campaign: Campaign,
+
+    /// Count of links assigned to the campaign now.
+    pub links: usize,
+
+    /// Count of redirects made while links were assigned to the campaign.
+    pub redirects: u64,
+
+    /// Approximate count of distinct visitors of links of the campaign, see
+    /// [`StatsBreakdown::unique_visitors`].
+    pub unique_visitors: u64,
+
+    /// Links with the most redirects in the campaign, most followed first, at
+    /// most [`CampaignStats::TOP_LINKS`] of them.
+    pub top_links: Vec<(Slug, u64)>,
+}
+
+impl CampaignStats {
+    /// Count of links returned in [`CampaignStats::top_links`].
+    pub const TOP_LINKS: usize = 10;
+}
+
+/// [`Stats`] of a [`ShortLink`] broken down by its slugs.
+#[derive(Debug, Clone, PartialEq)]
+pub struct StatsBreakdown {
+    /// Stats of the link as a whole.
+    pub stats: Stats,
+
+    /// Count of redirects of the link not classified as made by bots, see
+    /// [`analytics::BotClassifier`]. Imported redirects are counted as human.
+    pub human_redirects: u64,
+
+    /// Approximate count of distinct visitors who followed the link, see
+    /// [`analytics::HyperLogLog`]. Visitors are told apart by their
+    /// [`VisitorId`], or by the IP and user agent of their
+    /// [`RedirectContext`]. Redirects without either are not counted.
+    pub unique_visitors: u64,
+
+    /// Count of conversions reported for the link, see
+    /// [`UrlShortenerService::handle_conversion`].
+    pub conversions: u64,
+
+    /// Redirects counted by each slug of the link, in order the slugs were
+    /// given to the link.
+    pub by_slug: Vec<(Slug, u64)>,
+
+    /// Refused redirects of the link by their reasons. Wrong passwords are
+    /// recorded as [`Event::PasswordAttemptFailed`] and are not counted here.
+    ///
+    /// [`Event::PasswordAttemptFailed`]: events::Event::PasswordAttemptFailed
+    pub refused: Vec<(RedirectRefusal, u64)>,
+}
+
+impl StatsBreakdown {
+    /// Returns the part of human redirects which led to a conversion, from
+    /// `0` up, or `0` if there are no human redirects.
+    pub fn conversion_rate(&self) -> f64 {
+        if self.human_redirects == 0 {
+            return 0.0;
+        }
+        self.conversions as f64 / self.human_redirects as f64
+    }
+}
+
+/// Current state of the [`ShortLink`] as seen by the read side.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LinkInfo {
+    /// Stable identity of the link.
+    pub link_id: LinkId,
+
+    /// The link with its current [`Slug`].
+    pub link: ShortLink,
+
+    /// URL given at creation, if it redirected to the destination of the
+    /// link and was resolved.
+    pub original_url: Option<Url>,
+
+    /// Whether the link is disabled.
+    pub disabled: bool,
+
+    /// Whether following the link requires a password.
+    pub password_protected: bool,
+
+    /// Owner of the link, if any.
+    pub owner: Option<OwnerId>,
+
+    /// Tags attached to the link.
+    pub tags: BTreeSet<Tag>,
+
+    /// Descriptive metadata of the link.
+    pub metadata: LinkMetadata,
+
+    /// Additional slugs resolving to the link, in order they were added.
+    pub aliases: Vec<Slug>,
+
+    /// Whether the link is valid for exactly one redirect.
+    pub one_time: bool,
+
+    /// Whether the one-time link was already followed.
+    pub consumed: bool,
+
+    /// How redirects of the link are served.
+    pub redirect_type: RedirectType,
+
+    /// Whether visitors are shown an interstitial page with the destination
+    /// before they are redirected.
+    pub interstitial: bool,
+
+    /// Query parameters added to the destination on redirect.
+    pub query_params: Vec<QueryParam>,
+
+    /// URL of the webhook notified about redirects of the link, if any.
+    pub webhook_url: Option<Url>,
+
+    /// Whether the link was archived for inactivity.
+    pub archived: bool,
+
+    /// Group the link belongs to, if any.
+    pub group: Option<GroupId>,
+
+    /// Campaign the link is assigned to, if any.
+    pub campaign: Option<Campaign>,
+
+    /// Why the destination of the link is blocked, if it is.
+    pub blocked: Option<String>,
+
+    /// Result of the last health check of the destination.
+    pub health: DestinationHealth,
+
+    /// Preview of the destination, once it is fetched.
+    pub preview: Option<DestinationPreview>,
+
+    /// How long raw redirect events of the link are kept, if it overrides
+    /// [`ServiceConfig::click_retention`].
+    pub click_retention: Option<TimeDelta>,
+
+    /// The last suspicious traffic detected, if any.
+    pub anomaly: Option<Anomaly>,
+
+    /// Count of redirects of the link since the last stats reset.
+    pub redirects: u64,
+
+    /// Tenant the link belongs to, its slug is unique only among links of
+    /// the tenant.
+    pub tenant: Option<TenantId>,
+}
+
+/// Commands for CQRS.
+pub mod commands {
+    use super::{ShortLink, ShortenerError, Slug, Url};
+
+    /// Trait for command handlers.
+    pub trait CommandHandler {
+        /// Creates a new short link. It accepts the original url and an
+        /// optional [`Slug`]. If a [`Slug`] is not provided, the service will generate
+        /// one. Returns the newly created [`ShortLink`].
+        ///
+        /// ## Errors
+        ///
+        /// See [`ShortenerError`].
+        fn handle_create_short_link(
+            &mut self,
+            url: Url,
+            slug: Option<Slug>,
+        ) -> Result<ShortLink, ShortenerError>;
+
+        /// Processes a redirection by [`Slug`], returning the associated
+        /// [`ShortLink`] or a [`ShortenerError`].
+        fn handle_redirect(
+            &mut self,
+            slug: Slug,
+        ) -> Result<ShortLink, ShortenerError>;
+    }
+}
+
+/// Queries for CQRS
+pub mod queries {
+    use super::{ShortenerError, Slug, Stats};
+
+    /// Trait for query handlers.
+    pub trait QueryHandler {
+        /// Returns the [`Stats`] for a specific [`ShortLink`], such as the
+        /// number of redirects (clicks).
+        ///
+        /// [`ShortLink`]: super::ShortLink
+        fn get_stats(&self, slug: Slug) -> Result<Stats, ShortenerError>;
+    }
+}
+
+/// Events for Event Sourcing.
+pub mod events {
+    use std::collections::BTreeMap;
+
+    use chrono::{DateTime, TimeDelta, Utc};
+
+    use super::{
+        analytics::{BrowserFamily, CountryCode, DeviceClass, HyperLogLog},
+        projections::SlugIds,
+        scheduler::{ScheduleId, ScheduledCommand},
+        previews::DestinationPreview,
+        urls::QueryParam,
+        webhooks::LinkWebhook,
+        analytics::Anomaly,
+        dispatch::{ActorId, Role, TenantId},
+        webhooks::{EventWebhook, WebhookId},
+        ApiKeyId, Campaign, ConversionId, GroupId, LinkId, LinkMetadata, LinkOptions, OwnerId, PasswordHash,
+        RedirectContext, RedirectRefusal, RedirectType, ShortenerError, Slug, SlugId, Tag, Url, VisitorId,
+    };
+
+    /// All state changes of the [`UrlShortenerService`]. The service state can
+    /// be reconstructed at any moment by replaying these events in order.
+    ///
+    /// [`UrlShortenerService`]: super::UrlShortenerService
+    #[derive(Clone, Debug, PartialEq)]
+    #[cfg_attr(feature = "event-log", derive(serde::Serialize, serde::Deserialize))]
+    pub enum Event {
+        /// A new short link was created.
+        LinkCreated {
+            /// Identity of the created link.
+            link_id: LinkId,
+
+            /// [`Slug`] of the created link.
+            slug: Slug,
+
+            /// The original URL that the short link points to.
+            url: Url,
+
+            /// URL given at creation, if it redirected to `url` and was
+            /// resolved, see [`ServiceConfig::max_resolved_redirects`].
+            ///
+            /// [`ServiceConfig::max_resolved_redirects`]: super::ServiceConfig::max_resolved_redirects
+            original_url: Option<Url>,
+
+            /// Owner of the created link. Links without an owner can be
+            /// modified by anyone.
+            owner: Option<OwnerId>,
+
+            /// Settings the link was created with.
+            options: LinkOptions,
+        },
+
+        /// A short link was followed.
+        Redirected {
+            /// Interned [`Slug`] that was followed, it identifies the link too.
+            slug_id: SlugId,
+
+            /// Visitor who followed the link, if known.
+            visitor: Option<VisitorId>,
+
+            /// Details of the request, if they were captured. Its visitor is
+            /// always `None`, as it is recorded in `visitor`.
+            context: Option<Box<RedirectContext>>,
+
+            /// The redirect was classified as made by a bot, e.g. a crawler
+            /// or a link preview.
+            bot: bool,
+        },
+
+        /// A short link was followed again by the same visitor within the
+        /// deduplication window. The redirect was served, but it is not counted
+        /// in stats.
+        RedirectDeduplicated {
+            /// Interned [`Slug`] that was followed, it identifies the link too.
+            slug_id: SlugId,
+
+            /// Visitor who followed the link, or the fingerprint of their
+            /// request, see [`analytics::fingerprint`].
+            ///
+            /// [`analytics::fingerprint`]: super::analytics::fingerprint
+            visitor: VisitorId,
+        },
+
+        /// Redirects of a link whose raw events were dropped by
+        /// [`UrlShortenerService::handle_prune_clicks`], counted together in
+        /// place of the last of them. Redirects made between other events of
+        /// the log are aggregated apart, so the aggregates keep their order.
+        ///
+        /// [`UrlShortenerService::handle_prune_clicks`]: super::UrlShortenerService::handle_prune_clicks
+        RedirectsAggregated {
+            /// Identity of the link.
+            link_id: LinkId,
+
+            /// [`Slug`] of the link when the redirects were aggregated.
+            slug: Slug,
+
+            /// Counted redirects by interned [`Slug`]s they were made through.
+            redirects: BTreeMap<SlugId, u64>,
+
+            /// Redirects classified as made by bots, they are counted in
+            /// `redirects` too.
+            bot_redirects: u64,
+
+            /// Sketch of distinct visitors of the redirects.
+            visitors: HyperLogLog,
+
+            /// Redirects by countries of visitors, where they were known.
+            countries: BTreeMap<CountryCode, u64>,
+
+            /// Redirects by classes of devices, where the user agent was known.
+            devices: BTreeMap<DeviceClass, u64>,
+
+            /// Redirects by families of browsers, where the user agent was
+            /// known.
+            browsers: BTreeMap<BrowserFamily, u64>,
+
+            /// Redirects by starts of minutes they were requested in.
+            minutes: BTreeMap<DateTime<Utc>, u64>,
+        },
+
+        /// A one-time short link was used up by its only redirect, which is
+        /// recorded right before as [`Event::Redirected`].
+        LinkConsumed {
+            /// Identity of the consumed link.
+            link_id: LinkId,
+
+            /// [`Slug`] of the consumed link.
+            slug: Slug,
+        },
+
+        /// A slug was reserved for a link whose destination URL is not known
+        /// yet. The reservation is completed by [`Event::LinkCreated`] with the
+        /// same slug.
+        SlugReserved {
+            /// Reserved [`Slug`].
+            slug: Slug,
+
+            /// Owner of the reservation, who will own the link created with
+            /// the slug.
+            owner: Option<OwnerId>,
+
+            /// Time after which the slug can be taken by anyone, the
+            /// reservation never expires if `None`.
+            expires_at: Option<DateTime<Utc>>,
+        },
+
+        /// A draft link was prepared for review. Its slug is reserved until
+        /// the draft is activated by [`Event::LinkCreated`] or expires.
+        LinkPrepared {
+            /// Reserved [`Slug`] of the draft.
+            slug: Slug,
+
+            /// Owner of the draft, who will own the link once it's activated.
+            owner: Option<OwnerId>,
+
+            /// Destination of the draft.
+            url: Url,
+
+            /// Settings the link will be created with.
+            options: LinkOptions,
+
+            /// Time after which the draft can't be activated anymore.
+            expires_at: DateTime<Utc>,
+        },
+
+        /// A reservation of a slug timed out, so the slug became available
+        /// again.
+        SlugReservationExpired {
+            /// [`Slug`] whose reservation expired.
+            slug: Slug,
+        },
+
+        /// A short link got a new slug.
+        SlugRenamed {
+            /// Identity of the renamed link.
+            link_id: LinkId,
+
+            /// [`Slug`] the link had before renaming.
+            old_slug: Slug,
+
+            /// [`Slug`] the link has after renaming.
+            new_slug: Slug,
+
+            /// Whether the old slug was released, so it doesn't resolve to
+            /// the link anymore and can be used by other links. Otherwise it
+            /// keeps forwarding to the link.
+            old_slug_released: bool,
+
+            /// Time after which the forwarding old slug is released, it
+            /// forwards forever if `None`.
+            old_slug_expires_at: Option<DateTime<Utc>>,
+        },
+
+        /// A deprecated old slug of a renamed link stopped forwarding to it
+        /// after its grace period.
+        SlugAliasExpired {
+            /// Identity of the link the slug was forwarding to.
+            link_id: LinkId,
+
+            /// Released [`Slug`].
+            slug: Slug,
+        },
+
+        /// A redirect of a short link was refused. Redirects by slugs which
+        /// don't map to any link are not recorded.
+        RedirectRefused {
+            /// Identity of the link.
+            link_id: LinkId,
+
+            /// [`Slug`] the redirect was made through.
+            slug: Slug,
+
+            /// Why the redirect was refused.
+            reason: RedirectRefusal,
+        },
+
+        /// Redirects of a short link were imported from another system.
+        RedirectsImported {
+            /// Identity of the link.
+            link_id: LinkId,
+
+            /// [`Slug`] the redirects were made through.
+            slug: Slug,
+
+            /// Time of the redirects in the source system.
+            at: DateTime<Utc>,
+
+            /// Number of imported redirects.
+            redirects: u64,
+        },
+
+        /// Stats of a short link were reset. Only redirects recorded after
+        /// this event are counted.
+        StatsReset {
+            /// Identity of the link.
+            link_id: LinkId,
+
+            /// [`Slug`] of the link.
+            slug: Slug,
+        },
+
+        /// A short link was protected with a password, replacing the previous
+        /// one if any.
+        PasswordSet {
+            /// Identity of the link.
+            link_id: LinkId,
+
+            /// [`Slug`] of the link.
+            slug: Slug,
+
+            /// Salted hash of the password.
+            password: PasswordHash,
+        },
+
+        /// A password protection was removed from a short link.
+        PasswordRemoved {
+            /// Identity of the link.
+            link_id: LinkId,
+
+            /// [`Slug`] of the link.
+            slug: Slug,
+        },
+
+        /// A redirect to a password-protected short link was requested with a
+        /// wrong password.
+        PasswordAttemptFailed {
+            /// Identity of the link.
+            link_id: LinkId,
+
+            /// [`Slug`] that was requested.
+            slug: Slug,
+
+            /// Visitor who made the attempt, if known.
+            visitor: Option<VisitorId>,
+        },
+
+        /// Tags were attached to a short link.
+        LinkTagged {
+            /// Identity of the link.
+            link_id: LinkId,
+
+            /// [`Slug`] of the link.
+            slug: Slug,
+
+            /// Attached tags.
+            tags: Vec<Tag>,
+        },
+
+        /// Tags were detached from a short link.
+        LinkUntagged {
+            /// Identity of the link.
+            link_id: LinkId,
+
+            /// [`Slug`] of the link.
+            slug: Slug,
+
+            /// Detached tags.
+            tags: Vec<Tag>,
+        },
+
+        /// Metadata of a short link was replaced.
+        MetadataUpdated {
+            /// Identity of the link.
+            link_id: LinkId,
+
+            /// [`Slug`] of the link.
+            slug: Slug,
+
+            /// New metadata of the link.
+            metadata: LinkMetadata,
+        },
+
+        /// An additional slug was attached to a short link. The alias resolves
+        /// to the link, sharing its stats, until it is released.
+        AliasAdded {
+            /// Identity of the link.
+            link_id: LinkId,
+
+            /// Current [`Slug`] of the link.
+            slug: Slug,
+
+            /// The added alias.
+            alias: Slug,
+        },
+
+        /// A duplicate link pointing to the same destination was merged into a
+        /// short link. Slugs of the duplicate became aliases of the link and
+        /// its redirects were added to the stats of the link.
+        LinksMerged {
+            /// Identity of the link.
+            link_id: LinkId,
+
+            /// Current [`Slug`] of the link.
+            slug: Slug,
+
+            /// Identity of the merged duplicate, which no longer exists.
+            merged_link_id: LinkId,
+
+            /// [`Slug`] of the merged duplicate.
+            merged_slug: Slug,
+        },
+
+        /// A webhook was attached to a short link, replacing the previous one.
+        WebhookSet {
+            /// Identity of the link.
+            link_id: LinkId,
+
+            /// [`Slug`] of the link.
+            slug: Slug,
+
+            /// The attached webhook.
+            webhook: LinkWebhook,
+        },
+
+        /// The webhook was detached from a short link.
+        WebhookRemoved {
+            /// Identity of the link.
+            link_id: LinkId,
+
+            /// [`Slug`] of the link.
+            slug: Slug,
+        },
+
+        /// A short link is served with another type of redirect.
+        RedirectTypeSet {
+            /// Identity of the link.
+            link_id: LinkId,
+
+            /// [`Slug`] of the link.
+            slug: Slug,
+
+            /// The new type of redirect.
+            redirect_type: RedirectType,
+        },
+
+        /// Visitors of a short link are shown an interstitial page with its
+        /// destination before they are redirected, or no longer are.
+        InterstitialSet {
+            /// Identity of the link.
+            link_id: LinkId,
+
+            /// [`Slug`] of the link.
+            slug: Slug,
+
+            /// Whether the interstitial is shown.
+            enabled: bool,
+        },
+
+        /// Query parameters added to the destination of a short link on
+        /// redirect were replaced.
+        QueryParamsSet {
+            /// Identity of the link.
+            link_id: LinkId,
+
+            /// [`Slug`] of the link.
+            slug: Slug,
+
+            /// The new parameters, empty if they were removed.
+            params: Vec<QueryParam>,
+        },
+
+        /// A group of links was created.
+        GroupCreated {
+            /// Identity of the group.
+            group_id: GroupId,
+
+            /// Name of the group.
+            name: String,
+
+            /// Owner of the group, if any.
+            owner: Option<OwnerId>,
+        },
+
+        /// A namespace of slugs was created.
+        NamespaceCreated {
+            /// Name of the namespace.
+            name: String,
+
+            /// Owner of the namespace, if any.
+            owner: Option<OwnerId>,
+        },
+
+        /// A short link was moved to another group or out of its group.
+        LinkMovedToGroup {
+            /// Identity of the link.
+            link_id: LinkId,
+
+            /// [`Slug`] of the link.
+            slug: Slug,
+
+            /// Group the link belonged to before.
+            previous_group: Option<GroupId>,
+
+            /// Group the link belongs to now.
+            group: Option<GroupId>,
+        },
+
+        /// A short link got a new owner.
+        OwnershipTransferred {
+            /// Identity of the link.
+            link_id: LinkId,
+
+            /// [`Slug`] of the link.
+            slug: Slug,
+
+            /// Owner of the link before the transfer.
+            previous_owner: Option<OwnerId>,
+
+            /// Owner of the link after the transfer.
+            new_owner: OwnerId,
+        },
+
+        /// A short link was deactivated, so it can't be followed anymore.
+        LinkDisabled {
+            /// Identity of the disabled link.
+            link_id: LinkId,
+
+            /// [`Slug`] of the disabled link.
+            slug: Slug,
+        },
+
+        /// A previously disabled short link was activated again.
+        LinkEnabled {
+            /// Identity of the enabled link.
+            link_id: LinkId,
+
+            /// [`Slug`] of the enabled link.
+            slug: Slug,
+        },
+
+        /// A generated slug took the next number of the sequence of
+        /// [`SlugGenerator::Sequential`].
+        ///
+        /// [`SlugGenerator::Sequential`]: super::slugs::SlugGenerator::Sequential
+        SlugSequenceAdvanced {
+            /// Number the next generated slug is tried with.
+            next: u64,
+        },
+
+        /// A command was scheduled for later execution.
+        CommandScheduled {
+            /// The scheduled command.
+            scheduled: ScheduledCommand,
+        },
+
+        /// A scheduled command was cancelled before its execution.
+        ScheduledCommandCancelled {
+            /// Identifier of the cancelled command.
+            id: ScheduleId,
+        },
+
+        /// A scheduled command was executed. Events of the command itself are
+        /// recorded right before.
+        ScheduledCommandExecuted {
+            /// Identifier of the executed command.
+            id: ScheduleId,
+
+            /// Error the command failed with, if any.
+            error: Option<ShortenerError>,
+        },
+
+        /// A short link was archived for inactivity. Archived links still
+        /// redirect, but are hidden from listings.
+        LinkArchived {
+            /// Identity of the archived link.
+            link_id: LinkId,
+
+            /// [`Slug`] of the archived link.
+            slug: Slug,
+        },
+
+        /// An archived short link was restored.
+        LinkUnarchived {
+            /// Identity of the restored link.
+            link_id: LinkId,
+
+            /// [`Slug`] of the restored link.
+            slug: Slug,
+        },
+
+        /// A host was added to the blocklist. Existing links to it are
+        /// blocked by [`Event::LinkBlocked`] events recorded right after.
+        HostBlocked {
+            /// The blocked host, its subdomains are blocked too.
+            host: String,
+        },
+
+        /// The destination of a short link was found malicious after the link
+        /// was created, so the link can't be followed anymore.
+        LinkBlocked {
+            /// Identity of the blocked link.
+            link_id: LinkId,
+
+            /// [`Slug`] of the blocked link.
+            slug: Slug,
+
+            /// Why the destination is blocked.
+            reason: String,
+        },
+
+        /// The destination of a short link responded to a health check.
+        DestinationHealthy {
+            /// Identity of the checked link.
+            link_id: LinkId,
+
+            /// [`Slug`] of the checked link.
+            slug: Slug,
+        },
+
+        /// The destination of a short link failed a health check, e.g. it
+        /// responded with an error status or didn't respond at all.
+        DestinationBroken {
+            /// Identity of the checked link.
+            link_id: LinkId,
+
+            /// [`Slug`] of the checked link.
+            slug: Slug,
+
+            /// Why the check failed.
+            reason: String,
+        },
+
+        /// Preview of the destination of a short link was fetched.
+        MetadataFetched {
+            /// Identity of the link.
+            link_id: LinkId,
+
+            /// [`Slug`] of the link.
+            slug: Slug,
+
+            /// The fetched preview.
+            preview: DestinationPreview,
+        },
+
+        /// A downstream system reported that a redirect of a short link led to
+        /// a conversion, e.g. a signup or a purchase.
+        ConversionRecorded {
+            /// Identity of the link.
+            link_id: LinkId,
+
+            /// [`Slug`] of the link.
+            slug: Slug,
+
+            /// Identifier of the conversion in the downstream system.
+            conversion_id: ConversionId,
+        },
+
+        /// Retention of raw redirect events of a short link was set or reset
+        /// to the default of the service.
+        ClickRetentionSet {
+            /// Identity of the link.
+            link_id: LinkId,
+
+            /// [`Slug`] of the link.
+            slug: Slug,
+
+            /// How long raw redirect events are kept now.
+            retention: Option<TimeDelta>,
+        },
+
+        /// A short link was assigned to a campaign or removed from its
+        /// campaign.
+        CampaignAssigned {
+            /// Identity of the link.
+            link_id: LinkId,
+
+            /// [`Slug`] of the link.
+            slug: Slug,
+
+            /// Campaign the link is assigned to now.
+            campaign: Option<Campaign>,
+        },
+
+        /// Suspicious traffic of a short link was detected.
+        AnomalyDetected {
+            /// Identity of the link.
+            link_id: LinkId,
+
+            /// [`Slug`] of the link.
+            slug: Slug,
+
+            /// What was detected.
+            anomaly: Anomaly,
+        },
+
+        /// A short link was deleted along with its slugs, which may be used
+        /// by other links from now on.
+        LinkDeleted {
+            /// Identity of the deleted link, which no longer exists.
+            link_id: LinkId,
+
+            /// [`Slug`] of the deleted link.
+            slug: Slug,
+        },
+
+        /// An API key authenticating requests of an actor was created.
+        ApiKeyCreated {
+            /// Identity of the key.
+            key_id: ApiKeyId,
+
+            /// Caller the key authenticates.
+            actor: ActorId,
+
+            /// Role the key grants to the caller.
+            role: Role,
+
+            /// Hash of the secret of the key, see [`ApiKey::hash`].
+            ///
+            /// [`ApiKey::hash`]: super::ApiKey::hash
+            secret_hash: String,
+        },
+
+        /// An API key was revoked, requests made with it are rejected from
+        /// now on.
+        ApiKeyRevoked {
+            /// Identity of the key.
+            key_id: ApiKeyId,
+        },
+
+        /// A webhook was registered for domain events of links of the tenant
+        /// of the event.
+        WebhookRegistered {
+            /// Identity of the webhook.
+            webhook_id: WebhookId,
+
+            /// The registered webhook.
+            webhook: EventWebhook,
+        },
+
+        /// A webhook of domain events was unregistered, it isn't notified
+        /// anymore.
+        WebhookUnregistered {
+            /// Identity of the webhook.
+            webhook_id: WebhookId,
+        },
+    }
+
+    /// Kind of the [`Event`], without its data.
+    #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+    #[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+    pub enum EventKind {
+        /// See [`Event::LinkCreated`].
+        LinkCreated,
+
+        /// See [`Event::Redirected`].
+        Redirected,
+
+        /// See [`Event::RedirectDeduplicated`].
+        RedirectDeduplicated,
+
+        /// See [`Event::RedirectsAggregated`].
+        RedirectsAggregated,
+
+        /// See [`Event::LinkConsumed`].
+        LinkConsumed,
+
+        /// See [`Event::SlugReserved`].
+        SlugReserved,
+
+        /// See [`Event::LinkPrepared`].
+        LinkPrepared,
+
+        /// See [`Event::SlugReservationExpired`].
+        SlugReservationExpired,
+
+        /// See [`Event::SlugRenamed`].
+        SlugRenamed,
+
+        /// See [`Event::SlugAliasExpired`].
+        SlugAliasExpired,
+
+        /// See [`Event::RedirectRefused`].
+        RedirectRefused,
+
+        /// See [`Event::RedirectsImported`].
+        RedirectsImported,
+
+        /// See [`Event::StatsReset`].
+        StatsReset,
+
+        /// See [`Event::PasswordSet`].
+        PasswordSet,
+
+        /// See [`Event::PasswordRemoved`].
+        PasswordRemoved,
+
+        /// See [`Event::PasswordAttemptFailed`].
+        PasswordAttemptFailed,
+
+        /// See [`Event::LinkTagged`].
+        LinkTagged,
+
+        /// See [`Event::LinkUntagged`].
+        LinkUntagged,
+
+        /// See [`Event::MetadataUpdated`].
+        MetadataUpdated,
+
+        /// See [`Event::AliasAdded`].
+        AliasAdded,
+
+        /// See [`Event::LinksMerged`].
+        LinksMerged,
+
+        /// See [`Event::WebhookSet`].
+        WebhookSet,
+
+        /// See [`Event::WebhookRemoved`].
+        WebhookRemoved,
+
+        /// See [`Event::RedirectTypeSet`].
+        RedirectTypeSet,
+
+        /// See [`Event::InterstitialSet`].
+        InterstitialSet,
+
+        /// See [`Event::QueryParamsSet`].
+        QueryParamsSet,
+
+        /// See [`Event::GroupCreated`].
+        GroupCreated,
+
+        /// See [`Event::LinkMovedToGroup`].
+        LinkMovedToGroup,
+
+        /// See [`Event::NamespaceCreated`].
+        NamespaceCreated,
+
+        /// See [`Event::OwnershipTransferred`].
+        OwnershipTransferred,
+
+        /// See [`Event::LinkDisabled`].
+        LinkDisabled,
+
+        /// See [`Event::LinkEnabled`].
+        LinkEnabled,
+
+        /// See [`Event::SlugSequenceAdvanced`].
+        SlugSequenceAdvanced,
+
+        /// See [`Event::CommandScheduled`].
+        CommandScheduled,
+
+        /// See [`Event::ScheduledCommandCancelled`].
+        ScheduledCommandCancelled,
+
+        /// See [`Event::ScheduledCommandExecuted`].
+        ScheduledCommandExecuted,
+
+        /// See [`Event::LinkArchived`].
+        LinkArchived,
+
+        /// See [`Event::LinkUnarchived`].
+        LinkUnarchived,
+
+        /// See [`Event::HostBlocked`].
+        HostBlocked,
+
+        /// See [`Event::LinkBlocked`].
+        LinkBlocked,
+
+        /// See [`Event::DestinationHealthy`].
+        DestinationHealthy,
+
+        /// See [`Event::DestinationBroken`].
+        DestinationBroken,
+
+        /// See [`Event::MetadataFetched`].
+        MetadataFetched,
+
+        /// See [`Event::ConversionRecorded`].
+        ConversionRecorded,
+
+        /// See [`Event::CampaignAssigned`].
+        CampaignAssigned,
+
+        /// See [`Event::ClickRetentionSet`].
+        ClickRetentionSet,
+
+        /// See [`Event::AnomalyDetected`].
+        AnomalyDetected,
+
+        /// See [`Event::LinkDeleted`].
+        LinkDeleted,
+
+        /// See [`Event::ApiKeyCreated`].
+        ApiKeyCreated,
+
+        /// See [`Event::ApiKeyRevoked`].
+        ApiKeyRevoked,
+
+        /// See [`Event::WebhookRegistered`].
+        WebhookRegistered,
+
+        /// See [`Event::WebhookUnregistered`].
+        WebhookUnregistered,
+    }
+
+    impl Event {
+        /// Returns the kind of the event.
+        pub fn kind(&self) -> EventKind {
+            match self {
+                Event::LinkCreated { .. } => EventKind::LinkCreated,
+                Event::Redirected { .. } => EventKind::Redirected,
+                Event::RedirectDeduplicated { .. } => EventKind::RedirectDeduplicated,
+                Event::RedirectsAggregated { .. } => EventKind::RedirectsAggregated,
+                Event::LinkConsumed { .. } => EventKind::LinkConsumed,
+                Event::SlugReserved { .. } => EventKind::SlugReserved,
+                Event::LinkPrepared { .. } => EventKind::LinkPrepared,
+                Event::SlugReservationExpired { .. } => EventKind::SlugReservationExpired,
+                Event::SlugRenamed { .. } => EventKind::SlugRenamed,
+                Event::SlugAliasExpired { .. } => EventKind::SlugAliasExpired,
+                Event::RedirectRefused { .. } => EventKind::RedirectRefused,
+                Event::RedirectsImported { .. } => EventKind::RedirectsImported,
+                Event::StatsReset { .. } => EventKind::StatsReset,
+                Event::PasswordSet { .. } => EventKind::PasswordSet,
+                Event::PasswordRemoved { .. } => EventKind::PasswordRemoved,
+                Event::PasswordAttemptFailed { .. } => EventKind::PasswordAttemptFailed,
+                Event::LinkTagged { .. } => EventKind::LinkTagged,
+                Event::LinkUntagged { .. } => EventKind::LinkUntagged,
+                Event::MetadataUpdated { .. } => EventKind::MetadataUpdated,
+                Event::AliasAdded { .. } => EventKind::AliasAdded,
+                Event::LinksMerged { .. } => EventKind::LinksMerged,
+                Event::WebhookSet { .. } => EventKind::WebhookSet,
+                Event::WebhookRemoved { .. } => EventKind::WebhookRemoved,
+                Event::RedirectTypeSet { .. } => EventKind::RedirectTypeSet,
+                Event::InterstitialSet { .. } => EventKind::InterstitialSet,
+                Event::QueryParamsSet { .. } => EventKind::QueryParamsSet,
+                Event::GroupCreated { .. } => EventKind::GroupCreated,
+                Event::NamespaceCreated { .. } => EventKind::NamespaceCreated,
+                Event::LinkMovedToGroup { .. } => EventKind::LinkMovedToGroup,
+                Event::OwnershipTransferred { .. } => EventKind::OwnershipTransferred,
+                Event::LinkDisabled { .. } => EventKind::LinkDisabled,
+                Event::LinkEnabled { .. } => EventKind::LinkEnabled,
+                Event::SlugSequenceAdvanced { .. } => EventKind::SlugSequenceAdvanced,
+                Event::CommandScheduled { .. } => EventKind::CommandScheduled,
+                Event::ScheduledCommandCancelled { .. } => EventKind::ScheduledCommandCancelled,
+                Event::ScheduledCommandExecuted { .. } => EventKind::ScheduledCommandExecuted,
+                Event::LinkArchived { .. } => EventKind::LinkArchived,
+                Event::LinkUnarchived { .. } => EventKind::LinkUnarchived,
+                Event::HostBlocked { .. } => EventKind::HostBlocked,
+                Event::LinkBlocked { .. } => EventKind::LinkBlocked,
+                Event::DestinationHealthy { .. } => EventKind::DestinationHealthy,
+                Event::DestinationBroken { .. } => EventKind::DestinationBroken,
+                Event::MetadataFetched { .. } => EventKind::MetadataFetched,
+                Event::ConversionRecorded { .. } => EventKind::ConversionRecorded,
+                Event::CampaignAssigned { .. } => EventKind::CampaignAssigned,
+                Event::ClickRetentionSet { .. } => EventKind::ClickRetentionSet,
+                Event::AnomalyDetected { .. } => EventKind::AnomalyDetected,
+                Event::LinkDeleted { .. } => EventKind::LinkDeleted,
+                Event::ApiKeyCreated { .. } => EventKind::ApiKeyCreated,
+                Event::ApiKeyRevoked { .. } => EventKind::ApiKeyRevoked,
+                Event::WebhookRegistered { .. } => EventKind::WebhookRegistered,
+                Event::WebhookUnregistered { .. } => EventKind::WebhookUnregistered,
+            }
+        }
+
+        /// Returns the [`SlugId`] the event refers to, if it refers to an
+        /// interned slug.
+        pub fn slug_id(&self) -> Option<SlugId> {
+            match self {
+                Event::Redirected { slug_id, .. } | Event::RedirectDeduplicated { slug_id, .. } => Some(*slug_id),
+                _ => None,
+            }
+        }
+
+        /// Returns the [`Slug`] of the link the event is related to. Interned
+        /// slugs are resolved by `slug_ids`.
+        pub fn slug<'a>(&'a self, slug_ids: &'a SlugIds) -> Option<&'a Slug> {
+            match self {
+                Event::Redirected { slug_id, .. } | Event::RedirectDeduplicated { slug_id, .. } => {
+                    slug_ids.resolve(*slug_id).map(|(_, slug)| slug)
+                },
+                Event::LinkCreated { slug, .. }
+                | Event::LinkConsumed { slug, .. }
+                | Event::SlugReserved { slug, .. }
+                | Event::LinkPrepared { slug, .. }
+                | Event::SlugReservationExpired { slug }
+                | Event::SlugAliasExpired { slug, .. }
+                | Event::RedirectRefused { slug, .. }
+                | Event::RedirectsImported { slug, .. }
+                | Event::RedirectsAggregated { slug, .. }
+                | Event::StatsReset { slug, .. }
+                | Event::PasswordSet { slug, .. }
+                | Event::PasswordRemoved { slug, .. }
+                | Event::PasswordAttemptFailed { slug, .. }
+                | Event::LinkTagged { slug, .. }
+                | Event::LinkUntagged { slug, .. }
+                | Event::MetadataUpdated { slug, .. }
+                | Event::AliasAdded { slug, .. }
+                | Event::LinksMerged { slug, .. }
+                | Event::WebhookSet { slug, .. }
+                | Event::WebhookRemoved { slug, .. }
+                | Event::RedirectTypeSet { slug, .. }
+                | Event::InterstitialSet { slug, .. }
+                | Event::QueryParamsSet { slug, .. }
+                | Event::LinkMovedToGroup { slug, .. }
+                | Event::OwnershipTransferred { slug, .. }
+                | Event::LinkDisabled { slug, .. }
+                | Event::LinkEnabled { slug, .. }
+                | Event::LinkArchived { slug, .. }
+                | Event::LinkUnarchived { slug, .. }
+                | Event::LinkBlocked { slug, .. }
+                | Event::DestinationHealthy { slug, .. }
+                | Event::DestinationBroken { slug, .. }
+                | Event::MetadataFetched { slug, .. }
+                | Event::ConversionRecorded { slug, .. }
+                | Event::CampaignAssigned { slug, .. }
+                | Event::ClickRetentionSet { slug, .. }
+                | Event::AnomalyDetected { slug, .. }
+                | Event::LinkDeleted { slug, .. } => Some(slug),
+                Event::SlugRenamed { new_slug, .. } => Some(new_slug),
+                Event::CommandScheduled { scheduled } => scheduled.command.target(),
+                Event::ScheduledCommandCancelled { .. }
+                | Event::ScheduledCommandExecuted { .. }
+                | Event::GroupCreated { .. }
+                | Event::NamespaceCreated { .. }
+                | Event::HostBlocked { .. }
+                | Event::SlugSequenceAdvanced { .. }
+                | Event::ApiKeyCreated { .. }
+                | Event::ApiKeyRevoked { .. }
+                | Event::WebhookRegistered { .. }
+                | Event::WebhookUnregistered { .. } => None,
+            }
+        }
+
+        /// Returns the [`LinkId`] of the link the event is related to.
+        /// Interned slugs are resolved by `slug_ids`.
+        pub fn link_id<'a>(&'a self, slug_ids: &'a SlugIds) -> Option<&'a LinkId> {
+            match self {
+                Event::Redirected { slug_id, .. } | Event::RedirectDeduplicated { slug_id, .. } => {
+                    slug_ids.resolve(*slug_id).map(|(link_id, _)| link_id)
+                },
+                Event::LinkCreated { link_id, .. }
+                | Event::LinkConsumed { link_id, .. }
+                | Event::SlugRenamed { link_id, .. }
+                | Event::SlugAliasExpired { link_id, .. }
+                | Event::RedirectRefused { link_id, .. }
+                | Event::RedirectsImported { link_id, .. }
+                | Event::RedirectsAggregated { link_id, .. }
+                | Event::StatsReset { link_id, .. }
+                | Event::PasswordSet { link_id, .. }
+                | Event::PasswordRemoved { link_id, .. }
+                | Event::PasswordAttemptFailed { link_id, .. }
+                | Event::LinkTagged { link_id, .. }
+                | Event::LinkUntagged { link_id, .. }
+                | Event::MetadataUpdated { link_id, .. }
+                | Event::AliasAdded { link_id, .. }
+                | Event::LinksMerged { link_id, .. }
+                | Event::WebhookSet { link_id, .. }
+                | Event::WebhookRemoved { link_id, .. }
+                | Event::RedirectTypeSet { link_id, .. }
+                | Event::InterstitialSet { link_id, .. }
+                | Event::QueryParamsSet { link_id, .. }
+                | Event::LinkMovedToGroup { link_id, .. }
+                | Event::OwnershipTransferred { link_id, .. }
+                | Event::LinkDisabled { link_id, .. }
+                | Event::LinkEnabled { link_id, .. }
+                | Event::LinkArchived { link_id, .. }
+                | Event::LinkUnarchived { link_id, .. }
+                | Event::LinkBlocked { link_id, .. }
+                | Event::DestinationHealthy { link_id, .. }
+                | Event::DestinationBroken { link_id, .. }
+                | Event::MetadataFetched { link_id, .. }
+                | Event::ConversionRecorded { link_id, .. }
+                | Event::CampaignAssigned { link_id, .. }
+                | Event::ClickRetentionSet { link_id, .. }
+                | Event::AnomalyDetected { link_id, .. }
+                | Event::LinkDeleted { link_id, .. } => Some(link_id),
+                Event::SlugReserved { .. }
+                | Event::LinkPrepared { .. }
+                | Event::SlugReservationExpired { .. }
+                | Event::CommandScheduled { .. }
+                | Event::ScheduledCommandCancelled { .. }
+                | Event::ScheduledCommandExecuted { .. }
+                | Event::GroupCreated { .. }
+                | Event::NamespaceCreated { .. }
+                | Event::HostBlocked { .. }
+                | Event::SlugSequenceAdvanced { .. }
+                | Event::ApiKeyCreated { .. }
+                | Event::ApiKeyRevoked { .. }
+                | Event::WebhookRegistered { .. }
+                | Event::WebhookUnregistered { .. } => None,
+            }
+        }
+
+        /// Checks if the event doesn't change the state of the service and is
+        /// only kept for auditing.
+        pub fn is_state_neutral(&self) -> bool {
+            matches!(self, Event::RedirectDeduplicated { .. } | Event::PasswordAttemptFailed { .. })
+        }
+    }
+
+    /// An [`EventRecord`] together with its position in the stream of events
+    /// of its link.
+    #[derive(Clone, Debug, PartialEq)]
+    pub struct VersionedEvent {
+        /// Position of the event among all events of the link, starting from
+        /// `1`.
+        pub version: u64,
+
+        /// The recorded event itself.
+        pub record: EventRecord,
+    }
+
+    /// An [`Event`] together with its position in the event log.
+    #[derive(Clone, Debug, PartialEq)]
+    #[cfg_attr(feature = "event-log", derive(serde::Serialize, serde::Deserialize))]
+    pub struct EventRecord {
+        /// Position of the event in the log, starting from `1`.
+        pub sequence: u64,
+
+        /// Time when the event was recorded.
+        pub recorded_at: DateTime<Utc>,
+
+        /// Caller on whose behalf the event was recorded, `None` if the
+        /// caller was anonymous.
+        pub actor: Option<ActorId>,
+
+        /// Tenant the event belongs to: the tenant of the link for events of
+        /// links, the tenant of the caller otherwise.
+        pub tenant: Option<TenantId>,
+
+        /// The recorded event itself.
+        pub event: Event,
+    }
+}
+
+/// Projections (read models) built from the event log.
+pub mod projections {
+    use std::{any::Any, collections::{BTreeMap, BTreeSet, HashMap}};
+
+    use chrono::{DateTime, Utc};
+
+    use super::{
+        analytics::{self, BrowserFamily, CountryCode, DeviceClass, HyperLogLog, RedirectBucket, RedirectSeries},
+        events::{Event, EventRecord},
+        Campaign, LinkId, ShortenerError, RedirectContext, Slug, SlugId, Tag, TenantId,
+    };
+
+    /// Read model which is built by applying events of the
+    /// [`UrlShortenerService`] one by one.
+    ///
+    /// Projections registered in the service receive every event, both the
+    /// already recorded ones and the ones recorded later.
+    ///
+    /// [`UrlShortenerService`]: super::UrlShortenerService
+    pub trait Projection {
+        /// Unique name of the projection.
+        fn name(&self) -> &str;
+
+        /// Applies a single event to the projection.
+        fn apply(&mut self, record: &EventRecord);
+
+        /// Drops all state of the projection, so it can be rebuilt from
+        /// scratch.
+        fn reset(&mut self);
+
+        /// Sequence number of the last event already applied to the
+        /// projection. Projections restored from a saved state may return it to
+        /// skip replaying events they have already seen.
+        fn checkpoint(&self) -> u64 {
+            0
+        }
+
+        /// Returns a copy of the projection, kept in the snapshot the service
+        /// rolls forward as it prunes old events to fit its [`EventWindow`],
+        /// so the projection can still be rebuilt once they are gone. Events
+        /// are not pruned while a projection without a copy is registered.
+        ///
+        /// [`EventWindow`]: super::EventWindow
+        fn snapshot(&self) -> Option<Self>
+        where
+            Self: Sized,
+        {
+            None
+        }
+    }
+
+    /// Built-in projection indexing links by their tags.
+    #[derive(Clone, Default, PartialEq)]
+    pub struct TagIndex {
+        // ids of links by tags, ordered by creation time of links
+        links: HashMap<Tag, BTreeSet<LinkId>>,
+        checkpoint: u64,
+    }
+
+    impl TagIndex {
+        /// Name of the projection.
+        pub const NAME: &'static str = "tag_index";
+
+        /// Returns ids of links with the tag, ordered by their creation time.
+        pub fn links(&self, tag: &Tag) -> impl Iterator<Item = &LinkId> {
+            self.links.get(tag).into_iter().flatten()
+        }
+    }
+
+    impl Projection for TagIndex {
+        fn name(&self) -> &str {
+            Self::NAME
+        }
+
+        fn apply(&mut self, record: &EventRecord) {
+            let (link_id, tags, tagged) = match &record.event {
+                Event::LinksMerged { merged_link_id: link_id, .. } | Event::LinkDeleted { link_id, .. } => {
+                    for links in self.links.values_mut() {
+                        links.remove(link_id);
+                    }
+                    self.checkpoint = record.sequence;
+                    return;
+                },
+                Event::LinkCreated { link_id, options, .. } => (link_id, &options.tags, true),
+                Event::LinkTagged { link_id, tags, .. } => (link_id, tags, true),
+                Event::LinkUntagged { link_id, tags, .. } => (link_id, tags, false),
+                _ => return,
+            };
+
+            for tag in tags {
+                let links = self.links.entry(tag.clone()).or_default();
+                if tagged {
+                    links.insert(link_id.clone());
+                } else {
+                    links.remove(link_id);
+                }
+            }
+            self.checkpoint = record.sequence;
+        }
+
+        fn reset(&mut self) {
+            *self = Self::default();
+        }
+
+        fn checkpoint(&self) -> u64 {
+            self.checkpoint
+        }
+
+        fn snapshot(&self) -> Option<Self> {
+            Some(self.clone())
+        }
+    }
+
+    /// Projection interning slugs of links as compact [`SlugId`]s. Ids are
+    /// assigned in order in which slugs appear in the log, so replaying the
+    /// log always gives the same ids. The service keeps its own instance to
+    /// resolve redirect events.
+    #[derive(Clone, Default, PartialEq)]
+    pub struct SlugIds {
+        // interned slugs, indexed by their ids
+        slugs: Vec<(LinkId, Slug)>,
+        // ids of interned slugs by link and slug
+        ids: HashMap<(LinkId, String), SlugId>,
+        checkpoint: u64,
+    }
+
+    impl SlugIds {
+        /// Name of the projection.
+        pub const NAME: &'static str = "slug_ids";
+
+        /// Returns the link and the slug with the id.
+        pub fn resolve(&self, slug_id: SlugId) -> Option<(&LinkId, &Slug)> {
+            self.slugs.get(slug_id.0 as usize).map(|(link_id, slug)| (link_id, slug))
+        }
+
+        /// Returns all interned slugs of the link with their ids, in order of
+        /// interning.
+        pub fn slugs_of<'a>(&'a self, link_id: &'a LinkId) -> impl Iterator<Item = (SlugId, &'a Slug)> {
+            self.slugs.iter()
+                .enumerate()
+                .filter(move |(_, (id, _))| id == link_id)
+                .map(|(index, (_, slug))| (SlugId(index as u32), slug))
+        }
+
+        /// Returns the id of the slug of the link, if it was interned.
+        pub fn id(&self, link_id: &LinkId, slug: &Slug) -> Option<SlugId> {
+            self.ids.get(&(link_id.clone(), slug.0.clone())).copied()
+        }
+
+        fn intern(&mut self, link_id: &LinkId, slug: &Slug) {
+            let next_id = SlugId(self.slugs.len() as u32);
+            self.ids.entry((link_id.clone(), slug.0.clone())).or_insert_with(|| {
+                self.slugs.push((link_id.clone(), slug.clone()));
+                next_id
+            });
+        }
+    }
+
+    impl Projection for SlugIds {
+        fn name(&self) -> &str {
+            Self::NAME
+        }
+
+        fn apply(&mut self, record: &EventRecord) {
+            match &record.event {
+                Event::LinkCreated { link_id, slug, .. } => self.intern(link_id, slug),
+                Event::SlugRenamed { link_id, new_slug, .. } => self.intern(link_id, new_slug),
+                Event::AliasAdded { link_id, alias, .. } => self.intern(link_id, alias),
+                Event::LinksMerged { link_id, merged_link_id, .. } => {
+                    let slugs: Vec<_> = self.slugs_of(merged_link_id).map(|(_, slug)| slug.clone()).collect();
+                    for slug in &slugs {
+                        self.intern(link_id, slug);
+                    }
+                },
+                _ => return,
+            }
+            self.checkpoint = record.sequence;
+        }
+
+        fn reset(&mut self) {
+            *self = Self::default();
+        }
+
+        fn checkpoint(&self) -> u64 {
+            self.checkpoint
+        }
+    }
+
+    /// Built-in projection counting redirects of links by countries of
+    /// visitors. Redirects without a known country are not counted.
+    #[derive(Clone, Default, PartialEq)]
+    pub struct CountryClicks {
+        // own interned slugs, as projections don't see each other
+        slug_ids: SlugIds,
+        // redirects of links by countries
+        clicks: HashMap<LinkId, BTreeMap<CountryCode, u64>>,
+        checkpoint: u64,
+    }
+
+    impl CountryClicks {
+        /// Name of the projection.
+        pub const NAME: &'static str = "country_clicks";
+
+        /// Returns redirects of the link by countries, ordered by their codes.
+        pub fn clicks(&self, link_id: &LinkId) -> impl Iterator<Item = (&CountryCode, u64)> {
+            self.clicks.get(link_id).into_iter().flatten().map(|(country, clicks)| (country, *clicks))
+        }
+    }
+
+    impl Projection for CountryClicks {
+        fn name(&self) -> &str {
+            Self::NAME
+        }
+
+        fn apply(&mut self, record: &EventRecord) {
+            self.slug_ids.apply(record);
+            match &record.event {
+                Event::Redirected { slug_id, context, .. } => {
+                    let Some(country) = context.as_ref().and_then(|context| context.country.as_ref()) else {
+                        return;
+                    };
+                    let Some((link_id, _)) = self.slug_ids.resolve(*slug_id) else {
+                        return;
+                    };
+                    let clicks = self.clicks.entry(link_id.clone()).or_default();
+                    *clicks.entry(country.clone()).or_default() += 1;
+                },
+                Event::RedirectsAggregated { link_id, countries, .. } => {
+                    let clicks = self.clicks.entry(link_id.clone()).or_default();
+                    for (country, redirects) in countries {
+                        *clicks.entry(country.clone()).or_default() += redirects;
+                    }
+                },
+                Event::StatsReset { link_id, .. } | Event::LinkDeleted { link_id, .. } => {
+                    self.clicks.remove(link_id);
+                },
+                Event::LinksMerged { link_id, merged_link_id, .. } => {
+                    for (country, merged) in self.clicks.remove(merged_link_id).into_iter().flatten() {
+                        *self.clicks.entry(link_id.clone()).or_default().entry(country).or_default() += merged;
+                    }
+                },
+                _ => return,
+            }
+            self.checkpoint = record.sequence;
+        }
+
+        fn reset(&mut self) {
+            *self = Self::default();
+        }
+
+        fn checkpoint(&self) -> u64 {
+            self.checkpoint
+        }
+
+        fn snapshot(&self) -> Option<Self> {
+            Some(self.clone())
+        }
+    }
+
+    /// Built-in projection counting redirects of links by devices and
+    /// browsers of visitors, see [`analytics::parse_user_agent`]. Redirects
+    /// without a user agent are not counted.
+    #[derive(Clone, Default, PartialEq)]
+    pub struct DeviceClicks {
+        // own interned slugs, as projections don't see each other
+        slug_ids: SlugIds,
+        devices: HashMap<LinkId, BTreeMap<DeviceClass, u64>>,
+        browsers: HashMap<LinkId, BTreeMap<BrowserFamily, u64>>,
+        checkpoint: u64,
+    }
+
+    impl DeviceClicks {
+        /// Name of the projection.
+        pub const NAME: &'static str = "device_clicks";
+
+        /// Returns redirects of the link by classes of devices.
+        pub fn devices(&self, link_id: &LinkId) -> impl Iterator<Item = (&DeviceClass, &u64)> {
+            self.devices.get(link_id).into_iter().flatten()
+        }
+
+        /// Returns redirects of the link by families of browsers.
+        pub fn browsers(&self, link_id: &LinkId) -> impl Iterator<Item = (&BrowserFamily, &u64)> {
+            self.browsers.get(link_id).into_iter().flatten()
+        }
+    }
+
+    impl Projection for DeviceClicks {
+        fn name(&self) -> &str {
+            Self::NAME
+        }
+
+        fn apply(&mut self, record: &EventRecord) {
+            self.slug_ids.apply(record);
+            match &record.event {
+                Event::Redirected { slug_id, context, bot, .. } => {
+                    let Some(user_agent) = context.as_ref().and_then(|context| context.user_agent.as_deref()) else {
+                        return;
+                    };
+                    let Some((link_id, _)) = self.slug_ids.resolve(*slug_id) else {
+                        return;
+                    };
+                    let (device, browser) = analytics::parse_user_agent(user_agent);
+                    let device = if *bot { DeviceClass::Bot } else { device };
+                    *self.devices.entry(link_id.clone()).or_default().entry(device).or_default() += 1;
+                    *self.browsers.entry(link_id.clone()).or_default().entry(browser).or_default() += 1;
+                },
+                Event::RedirectsAggregated { link_id, devices, browsers, .. } => {
+                    let counts = self.devices.entry(link_id.clone()).or_default();
+                    for (device, redirects) in devices {
+                        *counts.entry(*device).or_default() += redirects;
+                    }
+                    let counts = self.browsers.entry(link_id.clone()).or_default();
+                    for (browser, redirects) in browsers {
+                        *counts.entry(*browser).or_default() += redirects;
+                    }
+                },
+                Event::StatsReset { link_id, .. } | Event::LinkDeleted { link_id, .. } => {
+                    self.devices.remove(link_id);
+                    self.browsers.remove(link_id);
+                },
+                Event::LinksMerged { link_id, merged_link_id, .. } => {
+                    for (device, merged) in self.devices.remove(merged_link_id).into_iter().flatten() {
+                        *self.devices.entry(link_id.clone()).or_default().entry(device).or_default() += merged;
+                    }
+                    for (browser, merged) in self.browsers.remove(merged_link_id).into_iter().flatten() {
+                        *self.browsers.entry(link_id.clone()).or_default().entry(browser).or_default() += merged;
+                    }
+                },
+                _ => return,
+            }
+            self.checkpoint = record.sequence;
+        }
+
+        fn reset(&mut self) {
+            *self = Self::default();
+        }
+
+        fn checkpoint(&self) -> u64 {
+            self.checkpoint
+        }
+
+        fn snapshot(&self) -> Option<Self> {
+            Some(self.clone())
+        }
+    }
+
+    /// Redirects of a campaign kept by [`CampaignIndex`].
+    #[derive(Clone, Default, PartialEq)]
+    pub struct CampaignTotals {
+        /// Links assigned to the campaign now.
+        pub links: BTreeSet<LinkId>,
+
+        /// Redirects made while links were assigned to the campaign, by
+        /// links.
+        pub redirects: HashMap<LinkId, u64>,
+
+        /// Distinct visitors of links of the campaign.
+        pub visitors: HyperLogLog,
+    }
+
+    /// Built-in projection aggregating redirects of links by campaigns they
+    /// are assigned to. Campaigns of different tenants are counted apart,
+    /// even if they have the same name. Redirects stay counted in the
+    /// campaign they were made in, even if the link is reassigned or its
+    /// stats are reset.
+    #[derive(Clone, Default, PartialEq)]
+    pub struct CampaignIndex {
+        // own interned slugs, as projections don't see each other
+        slug_ids: SlugIds,
+        // campaigns of links assigned to one, along with tenants of the links
+        assigned: HashMap<LinkId, (Option<TenantId>, Campaign)>,
+        campaigns: HashMap<(Option<TenantId>, Campaign), CampaignTotals>,
+        checkpoint: u64,
+    }
+
+    impl CampaignIndex {
+        /// Name of the projection.
+        pub const NAME: &'static str = "campaign_index";
+
+        /// Returns totals of the campaign of the tenant, if any link was ever
+        /// assigned to it.
+        pub fn campaign(&self, tenant: Option<&TenantId>, campaign: &Campaign) -> Option<&CampaignTotals> {
+            self.campaigns.get(&(tenant.cloned(), campaign.clone()))
+        }
+
+        fn assign(&mut self, link_id: &LinkId, tenant: Option<&TenantId>, campaign: Option<&Campaign>) {
+            if let Some(previous) = self.assigned.remove(link_id) {
+                if let Some(totals) = self.campaigns.get_mut(&previous) {
+                    totals.links.remove(link_id);
+                }
+            }
+            if let Some(campaign) = campaign {
+                let key = (tenant.cloned(), campaign.clone());
+                self.assigned.insert(link_id.clone(), key.clone());
+                self.campaigns.entry(key).or_default().links.insert(link_id.clone());
+            }
+        }
+    }
+
+    impl Projection for CampaignIndex {
+        fn name(&self) -> &str {
+            Self::NAME
+        }
+
+        fn apply(&mut self, record: &EventRecord) {
+            self.slug_ids.apply(record);
+            match &record.event {
+                Event::CampaignAssigned { link_id, campaign, .. } => {
+                    self.assign(link_id, record.tenant.as_ref(), campaign.as_ref());
+                },
+                Event::LinksMerged { merged_link_id: link_id, .. } | Event::LinkDeleted { link_id, .. } => {
+                    self.assign(link_id, None, None);
+                },
+                Event::Redirected { slug_id, visitor, context, .. } => {
+                    let Some((link_id, _)) = self.slug_ids.resolve(*slug_id) else {
+                        return;
+                    };
+                    let Some(totals) = self.assigned.get(link_id).and_then(|campaign| self.campaigns.get_mut(campaign))
+                    else {
+                        return;
+                    };
+                    *totals.redirects.entry(link_id.clone()).or_default() += 1;
+                    match (visitor, context.as_deref()) {
+                        (Some(visitor), _) => totals.visitors.insert(&visitor.0),
+                        (None, Some(RedirectContext { ip: Some(ip), user_agent, .. })) => {
+                            totals.visitors.insert(&(ip, user_agent));
+                        },
+                        _ => {},
+                    }
+                },
+                Event::RedirectsAggregated { link_id, redirects, visitors, .. } => {
+                    let Some(totals) = self.assigned.get(link_id).and_then(|campaign| self.campaigns.get_mut(campaign))
+                    else {
+                        return;
+                    };
+                    *totals.redirects.entry(link_id.clone()).or_default() += redirects.values().sum::<u64>();
+                    totals.visitors.merge(visitors);
+                },
+                _ => return,
+            }
+            self.checkpoint = record.sequence;
+        }
+
+        fn reset(&mut self) {
+            *self = Self::default();
+        }
+
+        fn checkpoint(&self) -> u64 {
+            self.checkpoint
+        }
+
+        fn snapshot(&self) -> Option<Self> {
+            Some(self.clone())
+        }
+    }
+
+    /// Built-in projection keeping counts of redirects of links over time.
+    /// Buckets of a link are rolled up as its events are applied, so each
+    /// link keeps at most a day of minutes and 30 days of hours.
+    #[derive(Clone, Default, PartialEq)]
+    pub struct RedirectRollups {
+        // own interned slugs, as projections don't see each other
+        slug_ids: SlugIds,
+        series: HashMap<LinkId, RedirectSeries>,
+        checkpoint: u64,
+    }
+
+    impl RedirectRollups {
+        /// Name of the projection.
+        pub const NAME: &'static str = "redirect_rollups";
+
+        /// Returns buckets of redirects of the link overlapping the range from
+        /// `from` up to `to`, ordered by their start.
+        pub fn range(&self, link_id: &LinkId, from: DateTime<Utc>, to: DateTime<Utc>) -> Vec<RedirectBucket> {
+            self.series.get(link_id).map(|series| series.range(from, to)).unwrap_or_default()
+        }
+    }
+
+    impl Projection for RedirectRollups {
+        fn name(&self) -> &str {
+            Self::NAME
+        }
+
+        fn apply(&mut self, record: &EventRecord) {
+            self.slug_ids.apply(record);
+            let link_id = match &record.event {
+                Event::Redirected { slug_id, context, .. } => {
+                    let Some((link_id, _)) = self.slug_ids.resolve(*slug_id) else {
+                        return;
+                    };
+                    // redirects served from logs are counted when they were requested
+                    let at = context.as_ref().and_then(|context| context.requested_at).unwrap_or(record.recorded_at);
+                    self.series.entry(link_id.clone()).or_default().add(at, 1);
+                    link_id.clone()
+                },
+                Event::RedirectsAggregated { link_id, minutes, .. } => {
+                    let series = self.series.entry(link_id.clone()).or_default();
+                    for (at, redirects) in minutes {
+                        series.add(*at, *redirects);
+                    }
+                    link_id.clone()
+                },
+                Event::StatsReset { link_id, .. } | Event::LinkDeleted { link_id, .. } => {
+                    self.series.remove(link_id);
+                    self.checkpoint = record.sequence;
+                    return;
+                },
+                Event::LinksMerged { link_id, merged_link_id, .. } => {
+                    if let Some(merged) = self.series.remove(merged_link_id) {
+                        self.series.entry(link_id.clone()).or_default().merge(&merged);
+                    }
+                    link_id.clone()
+                },
+                _ => return,
+            };
+
+            if let Some(series) = self.series.get_mut(&link_id) {
+                series.roll_up(record.recorded_at);
+            }
+            self.checkpoint = record.sequence;
+        }
+
+        fn reset(&mut self) {
+            *self = Self::default();
+        }
+
+        fn checkpoint(&self) -> u64 {
+            self.checkpoint
+        }
+
+        fn snapshot(&self) -> Option<Self> {
+            Some(self.clone())
+        }
+    }
+
+    /// [`Projection`] which can be downcast to its concrete type.
+    trait AnyProjection: Projection {
+        fn as_any(&self) -> &dyn Any;
+    }
+
+    impl<P: Projection + 'static> AnyProjection for P {
+        fn as_any(&self) -> &dyn Any {
+            self
+        }
+    }
+
+    struct Registration {
+        projection: Box<dyn AnyProjection>,
+        // sequence number of the last event applied to the projection
+        checkpoint: u64,
+        // copies the projection with `Projection::snapshot` of its concrete type
+        copy: fn(&dyn AnyProjection) -> Option<Box<dyn AnyProjection>>,
+    }
+
+    impl Registration {
+        fn snapshot(&self) -> Option<Registration> {
+            let projection = (self.copy)(self.projection.as_ref())?;
+            Some(Registration { projection, checkpoint: self.checkpoint, copy: self.copy })
+        }
+    }
+
+    fn copy<P: Projection + 'static>(projection: &dyn AnyProjection) -> Option<Box<dyn AnyProjection>> {
+        let copy = projection.as_any().downcast_ref::<P>()?.snapshot()?;
+        Some(Box::new(copy))
+    }
+
+    /// How far a registered projection is behind the event log.
+    #[derive(Clone, Debug, PartialEq, Eq)]
+    pub struct ProjectionLag {
+        /// Name of the projection.
+        pub name: String,
+
+        /// Count of recorded events not applied to the projection yet.
+        pub lag: u64,
+    }
+
+    /// Keeps registered projections in sync with the event log.
+    #[derive(Default)]
+    pub(crate) struct ProjectionRunner {
+        registrations: Vec<Registration>,
+    }
+
+    impl ProjectionRunner {
+        pub(crate) fn contains(&self, name: &str) -> bool {
+            self.registrations.iter().any(|r| r.projection.name() == name)
+        }
+
+        /// Returns names of the projections in order of their registration.
+        pub(crate) fn names(&self) -> impl Iterator<Item = &str> {
+            self.registrations.iter().map(|r| r.projection.name())
+        }
+
+        /// Registers the projection and catches it up with the given log.
+        pub(crate) fn register<P: Projection + 'static>(&mut self, projection: P, log: &[EventRecord]) {
+            let checkpoint = projection.checkpoint();
+            let mut registration = Registration { projection: Box::new(projection), checkpoint, copy: copy::<P> };
+            Self::catch_up(&mut registration, log);
+            self.registrations.push(registration);
+        }
+
+        /// Registers a copy of the projection at its checkpoint, if it
+        /// supports [`Projection::snapshot`].
+        pub(crate) fn register_copy<P: Projection + 'static>(&mut self, projection: &P) {
+            if let Some(snapshot) = projection.snapshot() {
+                let checkpoint = snapshot.checkpoint();
+                self.registrations.push(Registration { projection: Box::new(snapshot), checkpoint, copy: copy::<P> });
+            }
+        }
+
+        /// Restores the projection from its copy in the snapshot and replays
+        /// the log after the copy into it. Projections without a copy are
+        /// reset and the whole log is replayed into them, unless events were
+        /// pruned.
+        ///
+        /// ## Errors
+        ///
+        /// - [`ShortenerError::ProjectionNotFound`] if there is no projection
+        ///   with such name.
+        /// - [`ShortenerError::HistoryPruned`] if events were pruned and the
+        ///   snapshot has no copy of the projection.
+        pub(crate) fn rebuild(
+            &mut self,
+            name: &str,
+            snapshot: &ProjectionRunner,
+            pruned: bool,
+            log: &[EventRecord],
+        ) -> Result<(), ShortenerError> {
+            let Some(registration) = self.registrations.iter_mut().find(|r| r.projection.name() == name) else {
+                return Err(ShortenerError::ProjectionNotFound);
+            };
+            let copy = snapshot.registrations.iter().find(|r| r.projection.name() == name);
+            match copy.and_then(Registration::snapshot) {
+                Some(copy) => *registration = copy,
+                None if pruned => return Err(ShortenerError::HistoryPruned),
+                None => {
+                    registration.projection.reset();
+                    registration.checkpoint = 0;
+                },
+            }
+            Self::catch_up(registration, log);
+            Ok(())
+        }
+
+        /// Returns copies of the projections supporting
+        /// [`Projection::snapshot`], at their current checkpoints.
+        pub(crate) fn snapshot(&self) -> ProjectionRunner {
+            ProjectionRunner { registrations: self.registrations.iter().filter_map(Registration::snapshot).collect() }
+        }
+
+        /// Dispatches a freshly recorded event to all projections.
+        pub(crate) fn dispatch(&mut self, record: &EventRecord) {
+            for registration in &mut self.registrations {
+                if record.sequence > registration.checkpoint {
+                    registration.projection.apply(record);
+                    registration.checkpoint = record.sequence;
+                }
+            }
+        }
+
+        pub(crate) fn checkpoint(&self, name: &str) -> Option<u64> {
+            self.registrations.iter().find(|r| r.projection.name() == name).map(|r| r.checkpoint)
+        }
+
+        /// Returns lags of all projections behind the last recorded sequence.
+        pub(crate) fn lags(&self, last_sequence: u64) -> Vec<ProjectionLag> {
+            self.registrations
+                .iter()
+                .map(|r| ProjectionLag {
+                    name: r.projection.name().to_owned(),
+                    lag: last_sequence.saturating_sub(r.checkpoint),
+                })
+                .collect()
+        }
+
+        pub(crate) fn get<P: Projection + 'static>(&self) -> Option<&P> {
+            self.registrations.iter().find_map(|r| r.projection.as_any().downcast_ref::<P>())
+        }
+
+        fn catch_up(registration: &mut Registration, log: &[EventRecord]) {
+            let from = log.partition_point(|record| record.sequence <= registration.checkpoint);
+            for record in &log[from..] {
+                registration.projection.apply(record);
+                registration.checkpoint = record.sequence;
+            }
+        }
+    }
+}
+
+/// Subscriptions to the recorded events.
+pub mod subscriptions {
+    use std::{
+        collections::{BTreeSet, HashMap, HashSet},
+        future::Future,
+        pin::Pin,
+        sync::{
+            mpsc::{channel, Receiver, Sender, TryRecvError},
+            Arc, Mutex,
+        },
+        task::{Context, Poll, Waker},
+        thread,
+        time::Instant,
+    };
+
+    use chrono::{DateTime, Utc};
+
+    use super::{
+        events::{Event, EventKind, EventRecord},
+        LinkId, RedirectContext, ShortLink, ShortenerError, Slug, Stats, Tag, TenantId, VisitorId,
+    };
+
+    /// Identifier of the subscription.
+    #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+    pub struct SubscriptionId(pub u64);
+
+    /// Rules deciding which events are delivered to a subscriber. Empty rules
+    /// match everything, non-empty rules of different kinds must all match.
+    #[derive(Clone, Debug, Default, PartialEq)]
+    pub struct EventFilter {
+        /// Kinds of events the subscriber is interested in.
+        pub kinds: HashSet<EventKind>,
+
+        /// Prefixes of slugs the subscriber is interested in. Events which are
+        /// not related to a slug never match non-empty prefixes.
+        pub slug_prefixes: Vec<String>,
+
+        /// Slugs the subscriber is interested in, e.g. to follow redirects by
+        /// a single alias. Events which are not related to a slug never match
+        /// non-empty slugs.
+        pub slugs: Vec<Slug>,
+
+        /// Tags the subscriber is interested in, events match if their link
+        /// has any of them. Events which are not related to a link never match
+        /// non-empty tags.
+        pub tags: HashSet<Tag>,
+
+        /// Links the subscriber is interested in. Events which are not related
+        /// to a link never match non-empty links.
+        pub links: HashSet<LinkId>,
+
+        /// Tenants the subscriber is interested in, e.g. to stream events of a
+        /// single tenant. Events of no tenant never match non-empty tenants.
+        pub tenants: HashSet<TenantId>,
+    }
+
+    impl EventFilter {
+        /// Creates a filter matching all events.
+        pub fn all() -> Self {
+            Self::default()
+        }
+
+        /// Restricts the filter to the given kinds of events.
+        pub fn with_kinds(mut self, kinds: impl IntoIterator<Item = EventKind>) -> Self {
+            self.kinds.extend(kinds);
+            self
+        }
+
+        /// Restricts the filter to events of slugs starting with the prefix.
+        pub fn with_slug_prefix(mut self, prefix: impl Into<String>) -> Self {
+            self.slug_prefixes.push(prefix.into());
+            self
+        }
+
+        /// Restricts the filter to events of the slug.
+        pub fn with_slug(mut self, slug: Slug) -> Self {
+            self.slugs.push(slug);
+            self
+        }
+
+        /// Restricts the filter to events of links with the tag.
+        pub fn with_tag(mut self, tag: Tag) -> Self {
+            self.tags.insert(tag);
+            self
+        }
+
+        /// Restricts the filter to events of the link.
+        pub fn with_link(mut self, link_id: LinkId) -> Self {
+            self.links.insert(link_id);
+            self
+        }
+
+        /// Restricts the filter to events of the tenant.
+        pub fn with_tenant(mut self, tenant: TenantId) -> Self {
+            self.tenants.insert(tenant);
+            self
+        }
+
+        /// Checks if the event matches the filter. `slug`, `link_id` and
+        /// `link_tags` are the resolved slug, the id and the current tags of
+        /// the link the event is related to.
+        pub fn matches(
+            &self,
+            record: &EventRecord,
+            slug: Option<&Slug>,
+            link_id: Option<&LinkId>,
+            link_tags: &BTreeSet<Tag>,
+        ) -> bool {
+            if !self.kinds.is_empty() && !self.kinds.contains(&record.event.kind()) {
+                return false;
+            }
+
+            if !self.links.is_empty() && !link_id.is_some_and(|link_id| self.links.contains(link_id)) {
+                return false;
+            }
+
+            if !self.tenants.is_empty() && !record.tenant.as_ref().is_some_and(|tenant| self.tenants.contains(tenant)) {
+                return false;
+            }
+
+            if !self.tags.is_empty() && self.tags.iter().all(|tag| !link_tags.contains(tag)) {
+                return false;
+            }
+
+            if !self.slugs.is_empty() && !slug.is_some_and(|slug| self.slugs.contains(slug)) {
+                return false;
+            }
+
+            if self.slug_prefixes.is_empty() {
+                return true;
+            }
+
+            slug.is_some_and(|slug| {
+                self.slug_prefixes.iter().any(|prefix| slug.0.starts_with(prefix.as_str()))
+            })
+        }
+    }
+
+    /// Subscription to the events of the [`UrlShortenerService`].
+    ///
+    /// [`UrlShortenerService`]: super::UrlShortenerService
+    pub struct Subscription {
+        /// Identifier of the subscription, used to unsubscribe.
+        pub id: SubscriptionId,
+
+        /// Receiving side of the events matching the subscription filter.
+        pub events: Receiver<EventRecord>,
+
+        // task waiting for the next event, if any
+        waker: Arc<Mutex<Option<Waker>>>,
+    }
+
+    /// Redirect delivered by a [`ClickFeed`].
+    #[derive(Clone, Debug, PartialEq)]
+    pub struct Click {
+        /// Slug that was followed.
+        pub slug: Slug,
+
+        /// Identity of the followed link.
+        pub link_id: LinkId,
+
+        /// Time when the redirect was recorded.
+        pub at: DateTime<Utc>,
+
+        /// Visitor who followed the link, if known.
+        pub visitor: Option<VisitorId>,
+
+        /// Details of the request, if they were captured.
+        pub context: Option<Box<RedirectContext>>,
+
+        /// The redirect was classified as made by a bot.
+        pub bot: bool,
+    }
+
+    /// Live feed of redirects of the [`UrlShortenerService`], e.g. for
+    /// dashboards. It can be read without blocking, awaited with
+    /// [`Self::next_click`], or iterated, blocking until the next redirect.
+    ///
+    /// [`UrlShortenerService`]: super::UrlShortenerService
+    pub struct ClickFeed {
+        /// Identifier of the subscription, used to unsubscribe.
+        pub id: SubscriptionId,
+
+        clicks: Receiver<Click>,
+        // task waiting for the next click, if any
+        waker: Arc<Mutex<Option<Waker>>>,
+    }
+
+    impl ClickFeed {
+        /// Returns the next delivered click, if there is one.
+        pub fn try_next(&self) -> Option<Click> {
+            self.clicks.try_recv().ok()
+        }
+
+        /// Returns a future resolving with the next click, or with `None` once
+        /// the service is dropped. It is not tied to any async runtime.
+        pub fn next_click(&mut self) -> NextClick<'_> {
+            NextClick { feed: self }
+        }
+    }
+
+    impl Iterator for ClickFeed {
+        type Item = Click;
+
+        fn next(&mut self) -> Option<Click> {
+            self.clicks.recv().ok()
+        }
+    }
+
+    /// Future returned by [`ClickFeed::next_click`].
+    pub struct NextClick<'a> {
+        feed: &'a mut ClickFeed,
+    }
+
+    impl Future for NextClick<'_> {
+        type Output = Option<Click>;
+
+        fn poll(self: Pin<&mut Self>, context: &mut Context<'_>) -> Poll<Self::Output> {
+            // register before receiving, so a click published in between wakes us up
+            *self.feed.waker.lock().unwrap() = Some(context.waker().clone());
+
+            match self.feed.clicks.try_recv() {
+                Ok(click) => Poll::Ready(Some(click)),
+                Err(TryRecvError::Empty) => Poll::Pending,
+                Err(TryRecvError::Disconnected) => Poll::Ready(None),
+            }
+        }
+    }
+
+    // where events matching a subscription go
+    enum Delivery {
+        Events(Sender<EventRecord>),
+        Clicks(Sender<Click>),
+    }
+
+    struct Subscriber {
+        filter: EventFilter,
+        delivery: Delivery,
+        waker: Arc<Mutex<Option<Waker>>>,
+    }
+
+    /// Delivers recorded events to subscribers whose filters match them.
+    #[derive(Default)]
+    pub(crate) struct EventBus {
+        subscribers: HashMap<SubscriptionId, Subscriber>,
+        // subscribers indexed by kinds of events they are interested in, so
+        // high-volume events are checked only against interested subscribers
+        by_kind: HashMap<EventKind, Vec<SubscriptionId>>,
+        // subscribers interested in events of any kind
+        any_kind: Vec<SubscriptionId>,
+        next_id: u64,
+    }
+
+    impl EventBus {
+        pub(crate) fn subscribe(&mut self, filter: EventFilter) -> Subscription {
+            let (sender, events) = channel();
+            let (id, waker) = self.register(filter, Delivery::Events(sender));
+            Subscription { id, events, waker }
+        }
+
+        /// Subscribes to redirects matching the filter, whose kinds are
+        /// ignored.
+        pub(crate) fn subscribe_clicks(&mut self, filter: EventFilter) -> ClickFeed {
+            let filter = EventFilter { kinds: HashSet::from([EventKind::Redirected]), ..filter };
+            let (sender, clicks) = channel();
+            let (id, waker) = self.register(filter, Delivery::Clicks(sender));
+            ClickFeed { id, clicks, waker }
+        }
+
+        fn register(&mut self, filter: EventFilter, delivery: Delivery) -> (SubscriptionId, Arc<Mutex<Option<Waker>>>) {
+            self.next_id += 1;
+            let id = SubscriptionId(self.next_id);
+
+            if filter.kinds.is_empty() {
+                self.any_kind.push(id);
+            } else {
+                for kind in &filter.kinds {
+                    self.by_kind.entry(*kind).or_default().push(id);
+                }
+            }
+
+            let waker = Arc::default();
+            self.subscribers.insert(id, Subscriber { filter, delivery, waker: Arc::clone(&waker) });
+            (id, waker)
+        }
+
+        /// Returns `false` if there is no such subscription.
+        pub(crate) fn unsubscribe(&mut self, id: SubscriptionId) -> bool {
+            if self.subscribers.remove(&id).is_none() {
+                return false;
+            }
+
+            self.any_kind.retain(|other| *other != id);
+            for ids in self.by_kind.values_mut() {
+                ids.retain(|other| *other != id);
+            }
+            true
+        }
+
+        pub(crate) fn publish(
+            &mut self,
+            record: &EventRecord,
+            slug: Option<&Slug>,
+            link_id: Option<&LinkId>,
+            link_tags: &BTreeSet<Tag>,
+        ) {
+            let candidates = self.by_kind.get(&record.event.kind())
+                .into_iter()
+                .flatten()
+                .chain(self.any_kind.iter());
+
+            let mut disconnected = Vec::new();
+            for id in candidates {
+                let subscriber = &self.subscribers[id];
+                if !subscriber.filter.matches(record, slug, link_id, link_tags) {
+                    continue;
+                }
+
+                let delivered = match &subscriber.delivery {
+                    Delivery::Events(sender) => sender.send(record.clone()).is_ok(),
+                    Delivery::Clicks(sender) => match (&record.event, slug, link_id) {
+                        (Event::Redirected { visitor, context, bot, .. }, Some(slug), Some(link_id)) => {
+                            sender.send(Click {
+                                slug: slug.clone(),
+                                link_id: link_id.clone(),
+                                at: record.recorded_at,
+                                visitor: visitor.clone(),
+                                context: context.clone(),
+                                bot: *bot,
+                            }).is_ok()
+                        },
+                        _ => continue,
+                    },
+                };
+                if !delivered {
+                    disconnected.push(*id);
+                } else if let Some(waker) = subscriber.waker.lock().unwrap().take() {
+                    waker.wake();
+                }
+            }
+
+            // receiver was dropped, nobody is interested in this subscription anymore
+            for id in disconnected {
+                self.unsubscribe(id);
+            }
+        }
+    }
+    /// Future returned by [`UrlShortenerService::wait_for_redirects`]. It is
+    /// not tied to any async runtime and doesn't borrow the service, so
+    /// redirects can be handled while it is pending.
+    ///
+    /// [`UrlShortenerService::wait_for_redirects`]: super::UrlShortenerService::wait_for_redirects
+    pub struct WaitForRedirects {
+        subscription: Subscription,
+        link: ShortLink,
+        redirects: u64,
+        threshold: u64,
+        deadline: Instant,
+        timer_started: bool,
+    }
+
+    impl WaitForRedirects {
+        pub(crate) fn new(subscription: Subscription, stats: Stats, threshold: u64, deadline: Instant) -> Self {
+            Self {
+                subscription,
+                link: stats.link,
+                redirects: stats.redirects,
+                threshold,
+                deadline,
+                timer_started: false,
+            }
+        }
+
+        fn stats(&self) -> Stats {
+            Stats { link: self.link.clone(), redirects: self.redirects }
+        }
+    }
+
+    impl Future for WaitForRedirects {
+        type Output = Result<Stats, ShortenerError>;
+
+        fn poll(mut self: Pin<&mut Self>, context: &mut Context<'_>) -> Poll<Self::Output> {
+            // register before draining, so an event published in between wakes us up
+            *self.subscription.waker.lock().unwrap() = Some(context.waker().clone());
+
+            loop {
+                if self.redirects >= self.threshold {
+                    return Poll::Ready(Ok(self.stats()));
+                }
+
+                match self.subscription.events.try_recv() {
+                    Ok(record) => match record.event {
+                        Event::Redirected { .. } => self.redirects += 1,
+                        Event::RedirectsImported { redirects, .. } => self.redirects += redirects,
+                        Event::StatsReset { .. } => {
+                            self.redirects = 0;
+                        },
+                        Event::SlugRenamed { new_slug, .. } => self.link.slug = new_slug,
+                        _ => {},
+                    },
+                    Err(TryRecvError::Empty) => break,
+                    // the service was dropped, the threshold will never be reached
+                    Err(TryRecvError::Disconnected) => return Poll::Ready(Err(ShortenerError::ServiceUnavailable)),
+                }
+            }
+
+            let now = Instant::now();
+            if now >= self.deadline {
+                return Poll::Ready(Err(ShortenerError::TimedOut));
+            }
+
+            if !self.timer_started {
+                self.timer_started = true;
+                let waker = Arc::clone(&self.subscription.waker);
+                let timeout = self.deadline - now;
+                thread::spawn(move || {
+                    thread::sleep(timeout);
+                    if let Some(waker) = waker.lock().unwrap().take() {
+                        waker.wake();
+                    }
+                });
+            }
+
+            Poll::Pending
+        }
+    }
+}
+
+/// Slug held by a reservation or a deprecated alias until it expires, with
+/// the tenant, the owner and the original spelling of the slug.
+#[derive(Clone, PartialEq)]
+struct SlugHold<T> {
+    tenant: Option<TenantId>,
+    owner: Option<OwnerId>,
+    slug: Slug,
+    expires_at: T,
+}
+
+/// State of a single link aggregate.
+#[derive(Clone, PartialEq)]
+struct LinkState {
+    // the link with its current slug
+    link: ShortLink,
+    // url given at creation, if it was resolved to another one
+    original_url: Option<Url>,
+    disabled: bool,
+    password: Option<PasswordHash>,
+    owner: Option<OwnerId>,
+    tags: BTreeSet<Tag>,
+    metadata: LinkMetadata,
+    aliases: Vec<Slug>,
+    one_time: bool,
+    consumed: bool,
+    redirect_type: RedirectType,
+    interstitial: bool,
+    query_params: Vec<QueryParam>,
+    webhook: Option<LinkWebhook>,
+    redirects: u64,
+    // redirects classified as made by bots, they are counted in `redirects` too
+    bot_redirects: u64,
+    // conversions since the last stats reset
+    conversions: u64,
+    // all reported conversions, so reporting one again does nothing
+    conversion_ids: HashSet<ConversionId>,
+    // distinct visitors since the last stats reset
+    visitors: HyperLogLog,
+    // redirects since the last stats reset by followed slug
+    redirects_by_slug: HashMap<SlugId, u64>,
+    // refused redirects since the last stats reset by reason
+    refused: BTreeMap<RedirectRefusal, u64>,
+    archived: bool,
+    group: Option<GroupId>,
+    campaign: Option<Campaign>,
+    // how long raw redirect events are kept, overriding the service default
+    click_retention: Option<TimeDelta>,
+    // the last detected anomaly with the time it was detected
+    anomaly: Option<(Anomaly, DateTime<Utc>)>,
+    // why the destination is blocked, if it is
+    blocked: Option<String>,
+    health: DestinationHealth,
+    preview: Option<DestinationPreview>,
+    // time of creation or of the last redirect, whichever is later
+    last_active_at: DateTime<Utc>,
+    tenant: Option<TenantId>,
+}
+
+impl LinkState {
+    fn stats(&self) -> Stats {
+        Stats { link: self.link.clone(), redirects: self.redirects }
+    }
+
+    fn human_redirects(&self) -> u64 {
+        self.redirects - self.bot_redirects
+    }
+}
+
+/// Delta synchronization of the read side for offline clients.
+pub mod sync {
+    use std::collections::HashMap;
+
+    use super::{LinkId, LinkInfo};
+
+    /// Position in the event log up to which the client is synchronized.
+    /// Clients start with the default cursor and then pass the cursor
+    /// returned with the previous [`Changes`].
+    #[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+    pub struct SyncCursor(pub u64);
+
+    /// Changes of links since a [`SyncCursor`].
+    #[derive(Clone, Debug, Default, PartialEq)]
+    pub struct Changes {
+        /// Cursor to request the next changes from.
+        pub cursor: SyncCursor,
+
+        /// Current state of links created or modified since the cursor, it
+        /// replaces the client copy of the link entirely.
+        pub upserts: Vec<LinkInfo>,
+
+        /// Links deleted since the cursor.
+        pub deletions: Vec<LinkId>,
+
+        /// Redirects counted since the cursor for links which are not in
+        /// [`Changes::upserts`], they must be added to the client counters.
+        pub redirect_deltas: HashMap<LinkId, u64>,
+    }
+}
+
+/// Pushdown of queries to storage backends.
+pub mod backend {
+    use super::{LinkId, Tag};
+
+    /// Storage backend answering queries from its own indexes, so they don't
+    /// need state loaded into memory. Every method returns `None` by default,
+    /// and the service then answers the query from memory.
+    pub trait QueryBackend {
+        /// Returns ids of links with the tag, ordered by their creation time.
+        fn links_by_tag(&self, tag: &Tag) -> Option<Vec<LinkId>> {
+            None
+        }
+    }
+
+    /// Backend answering nothing itself, used by default.
+    #[derive(Clone, Copy, Debug, Default)]
+    pub struct InMemory;
+
+    impl QueryBackend for InMemory {}
+}
+
+/// Self-test of the service state.
+pub mod integrity {
+    use std::fmt;
+
+    /// Outcome of a single integrity check.
+    #[derive(Clone, Debug, PartialEq)]
+    pub struct IntegrityCheck {
+        /// What was checked.
+        pub name: &'static str,
+
+        /// Found problems, empty if the check passed.
+        pub problems: Vec<String>,
+
+        /// Whether the check was skipped, because the state it needs is not
+        /// available.
+        pub skipped: bool,
+    }
+
+    /// Pass/fail report of the integrity self-test.
+    #[derive(Clone, Debug, Default, PartialEq)]
+    pub struct IntegrityReport {
+        /// Performed checks in order.
+        pub checks: Vec<IntegrityCheck>,
+    }
+
+    impl IntegrityReport {
+        /// Checks if no check found any problem.
+        pub fn passed(&self) -> bool {
+            self.checks.iter().all(|check| check.problems.is_empty())
+        }
+    }
+
+    impl fmt::Display for IntegrityReport {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            for check in &self.checks {
+                let status = match (check.skipped, check.problems.is_empty()) {
+                    (true, _) => "SKIP",
+                    (false, true) => "PASS",
+                    (false, false) => "FAIL",
+                };
+                writeln!(f, "{status} {}", check.name)?;
+                for problem in &check.problems {
+                    writeln!(f, "    {problem}")?;
+                }
+            }
+            write!(f, "{}", if self.passed() { "OK" } else { "FAILED" })
+        }
+    }
+}
+
+/// Generation of slugs for links created without a custom one.
+pub mod slugs {
+    use percent_encoding::percent_decode_str;
+    use rand::{distributions::Alphanumeric, Rng};
+    use sha2::{Digest, Sha256};
+    use unicode_normalization::{is_nfc, UnicodeNormalization};
+
+    use super::{Slug, SLUG_LEN};
+
+    /// Paths served by the service itself, which short links shouldn't
+    /// shadow. See [`ServiceConfig::blocked_slugs`].
+    ///
+    /// [`ServiceConfig::blocked_slugs`]: super::ServiceConfig::blocked_slugs
+    pub const SERVICE_ROUTES: &[&str] = &["api", "admin", "stats", "health", "metrics", "docs", "static"];
+
+    /// Rule of [`SlugRules`] broken by a slug.
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+    #[cfg_attr(feature = "event-log", derive(serde::Serialize, serde::Deserialize))]
+    pub enum SlugViolation {
+        /// The slug is shorter than [`SlugRules::min_length`].
+        TooShort,
+
+        /// The slug is longer than [`SlugRules::max_length`].
+        TooLong,
+
+        /// The slug contains a character which is neither an ASCII letter or
+        /// digit, nor one of [`SlugRules::extra_chars`], nor a printable non
+        /// ASCII character of a [`SlugRules::unicode`] slug.
+        InvalidCharacter,
+
+        /// The slug starts or ends with a dash, while
+        /// [`SlugRules::edge_dashes`] are not allowed.
+        EdgeDash,
+    }
+
+    /// Rules custom slugs must follow. Generated slugs follow them by
+    /// construction.
+    #[derive(Clone, Debug, PartialEq)]
+    pub struct SlugRules {
+        /// Minimum number of characters.
+        pub min_length: usize,
+
+        /// Maximum number of characters.
+        pub max_length: usize,
+
+        /// Characters allowed besides ASCII letters and digits.
+        pub extra_chars: String,
+
+        /// Whether slugs may start or end with a dash.
+        pub edge_dashes: bool,
+
+        /// Whether slugs may contain printable non ASCII characters, like
+        /// letters of other scripts and emoji.
+        pub unicode: bool,
+    }
+
+    impl Default for SlugRules {
+        fn default() -> Self {
+            Self {
+                min_length: 1,
+                max_length: 64,
+                extra_chars: String::from("-_"),
+                edge_dashes: false,
+                unicode: false,
+            }
+        }
+    }
+
+    impl SlugRules {
+        /// Checks the slug against the rules, returning the first broken one.
+        /// Lengths are counted in characters of the [`normalize`]d slug.
+        pub fn check(&self, slug: &str) -> Result<(), SlugViolation> {
+            let slug = normalize(slug);
+            let length = slug.chars().count();
+            if length < self.min_length {
+                return Err(SlugViolation::TooShort);
+            }
+
+            if length > self.max_length {
+                return Err(SlugViolation::TooLong);
+            }
+
+            let allowed = |char: char| {
+                char.is_ascii_alphanumeric()
+                    || self.extra_chars.contains(char)
+                    || self.unicode && !char.is_ascii() && !char.is_whitespace() && !char.is_control()
+            };
+            if !slug.chars().all(allowed) {
+                return Err(SlugViolation::InvalidCharacter);
+            }
+
+            if !self.edge_dashes && (slug.starts_with('-') || slug.ends_with('-')) {
+                return Err(SlugViolation::EdgeDash);
+            }
+
+            Ok(())
+        }
+    }
+
+    /// Splits the namespaced slug like `team-a/launch` into its namespace and
+    /// the slug within the namespace.
+    pub fn split_namespace(slug: &str) -> Option<(&str, &str)> {
+        slug.split_once('/')
+    }
+
+    /// Normalizes the slug to NFC, so `café` typed with a combining accent is
+    /// the same slug as `café` typed with a precomposed letter.
+    pub fn normalize(slug: &str) -> String {
+        if is_nfc(slug) {
+            slug.to_owned()
+        } else {
+            slug.nfc().collect()
+        }
+    }
+
+    /// Returns the key the slug is compared by: normalized, with homoglyphs
+    /// folded if `fold` is set and lowercased if `lowercase` is set. Slugs
+    /// with the same key are the same slug.
+    pub fn key(slug: &str, fold: bool, lowercase: bool) -> String {
+        let mut key = normalize(slug);
+        if fold {
+            key = fold_homoglyphs(&key);
+        }
+        if lowercase {
+            key = key.to_lowercase();
+        }
+        key
+    }
+
+    /// Letters of other scripts looking the same as ASCII letters.
+    const HOMOGLYPHS: &[(char, char)] = &[
+        // cyrillic
+        ('а', 'a'), ('е', 'e'), ('і', 'i'), ('ј', 'j'), ('о', 'o'), ('р', 'p'), ('с', 'c'), ('у', 'y'), ('х', 'x'),
+        ('ѕ', 's'), ('ԁ', 'd'), ('һ', 'h'), ('ӏ', 'l'), ('ԛ', 'q'), ('ԝ', 'w'), ('А', 'A'), ('В', 'B'), ('Е', 'E'),
+        ('І', 'I'), ('Ј', 'J'), ('К', 'K'), ('М', 'M'), ('Н', 'H'), ('О', 'O'), ('Р', 'P'), ('С', 'C'), ('Т', 'T'),
+        ('У', 'Y'), ('Х', 'X'), ('Ѕ', 'S'),
+        // greek
+        ('ο', 'o'), ('ν', 'v'), ('Α', 'A'), ('Β', 'B'), ('Ε', 'E'), ('Ζ', 'Z'), ('Η', 'H'), ('Ι', 'I'), ('Κ', 'K'),
+        ('Μ', 'M'), ('Ν', 'N'), ('Ο', 'O'), ('Ρ', 'P'), ('Τ', 'T'), ('Υ', 'Y'), ('Χ', 'X'),
+    ];
+
+    /// Replaces letters looking the same as ASCII characters, like cyrillic
+    /// `а` or fullwidth `ａ`, with those ASCII characters. Other characters,
+    /// including accented letters, are kept.
+    pub fn fold_homoglyphs(slug: &str) -> String {
+        slug.chars()
+            .map(|char| match char {
+                // fullwidth forms of printable ASCII characters
+                '\u{FF01}'..='\u{FF5E}' => char::from_u32(char as u32 - 0xFEE0).unwrap_or(char),
+                _ => HOMOGLYPHS.iter().find(|(glyph, _)| *glyph == char).map_or(char, |(_, ascii)| *ascii),
+            })
+            .collect()
+    }
+
+    /// Decodes a percent-encoded path segment of a request to the HTTP API
+    /// into a slug, e.g. `caf%C3%A9` into `café`. Encoded `/` stays a part
+    /// of the slug, so it is rejected by [`SlugRules::check`] instead of
+    /// being routed as another segment.
+    ///
+    /// ## Errors
+    ///
+    /// [`SlugViolation::InvalidCharacter`] if the decoded segment is not
+    /// valid UTF-8.
+    pub fn decode_path_segment(segment: &str) -> Result<Slug, SlugViolation> {
+        percent_decode_str(segment)
+            .decode_utf8()
+            .map(|slug| Slug(normalize(&slug)))
+            .map_err(|_| SlugViolation::InvalidCharacter)
+    }
+
+    /// Offensive words generated slugs must not contain, see
+    /// [`ServiceConfig::filter_profanity`].
+    ///
+    /// [`ServiceConfig::filter_profanity`]: super::ServiceConfig::filter_profanity
+    pub const DENYLIST: &[&str] = &[
+        "anal", "anus", "arse", "ass", "bitch", "boob", "butt", "cock", "crap", "cum", "cunt", "damn", "dick", "dildo",
+        "fag", "fuck", "jizz", "kkk", "nazi", "nigg", "penis", "piss", "poop", "porn", "pussy", "rape", "sex", "shit",
+        "slut", "tit", "twat", "vagina", "wank", "whore",
+    ];
+
+    /// Checks if the slug contains a word of [`DENYLIST`], ignoring case,
+    /// lookalike letters and digits commonly used in place of letters, e.g.
+    /// `5h1t`.
+    pub fn is_offensive(slug: &str) -> bool {
+        let folded: String = fold_homoglyphs(slug)
+            .to_lowercase()
+            .chars()
+            .map(|char| match char {
+                '0' => 'o',
+                '1' => 'i',
+                '3' => 'e',
+                '4' => 'a',
+                '5' => 's',
+                '7' => 't',
+                _ => char,
+            })
+            .collect();
+        DENYLIST.iter().any(|word| folded.contains(word))
+    }
+
+    /// Returns close variants of the slug to suggest when it is taken, best
+    /// first, e.g. `my-link-2`, `my-link-2025`, `my-link-3` for `my-link`.
+    /// Numeric suffix of the slug is replaced, so `my-link-2` is followed by
+    /// `my-link-3`.
+    pub fn variants(slug: &str, year: i32) -> impl Iterator<Item = String> {
+        let base = match slug.rsplit_once('-') {
+            Some((base, suffix)) if !base.is_empty() && suffix.chars().all(|char| char.is_ascii_digit()) => base,
+            _ => slug,
+        }
+        .to_owned();
+        let numbered = {
+            let base = base.clone();
+            (2..100).map(move |number| format!("{base}-{number}"))
+        };
+        [format!("{base}-{year}"), format!("{base}-{}", year + 1)]
+            .into_iter()
+            .chain(numbered)
+            .chain([format!("get-{base}"), format!("my-{base}"), format!("{base}-link")])
+            .filter({
+                let slug = slug.to_owned();
+                move |variant| *variant != slug
+            })
+    }
+
+    /// Counters of slug generation since the service was started. Growing
+    /// [`Self::collision_rate`] of [`SlugGenerator::Random`] means its slugs
+    /// should be longer.
+    #[derive(Clone, Copy, Debug, Default, PartialEq)]
+    pub struct SlugGenerationStats {
+        /// Generated candidate slugs, including rejected ones.
+        pub attempts: u64,
+
+        /// Candidate slugs rejected because they were taken, blocked or
+        /// offensive, each of them caused a retry or a failure.
+        pub collisions: u64,
+
+        /// Generations which gave up without a free slug, see
+        /// [`ShortenerError::SlugGenerationFailed`].
+        ///
+        /// [`ShortenerError::SlugGenerationFailed`]: super::ShortenerError::SlugGenerationFailed
+        pub failures: u64,
+    }
+
+    impl SlugGenerationStats {
+        /// Share of candidate slugs which collided, `0.0` if nothing was
+        /// generated yet.
+        pub fn collision_rate(&self) -> f64 {
+            if self.attempts == 0 {
+                0.0
+            } else {
+                self.collisions as f64 / self.attempts as f64
+            }
+        }
+    }
+
+    /// Digits of base62 numbers, in order of their values.
+    const BASE62: &[u8; 62] = b"0123456789abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ";
+
+    /// Strategy generating slugs of new links.
+    #[derive(Clone, Copy, Debug, Default, PartialEq)]
+    pub enum SlugGenerator {
+        /// Slugs are prefixes of the hash of the URL. The hash of a URL never
+        /// changes, so creation fails if its slug is already in use.
+        #[default]
+        UrlHash,
+
+        /// Slugs are random alphanumeric strings drawn from a cryptographically
+        /// secure generator, so they can't be guessed. Slugs already in use are
+        /// drawn again up to `attempts` times in total.
+        Random { length: usize, attempts: usize },
+
+        /// Slugs are base62 encoded numbers of a counter, so they are as short
+        /// as possible and grow with the number of links. Numbers whose slugs
+        /// are already in use are skipped.
+        Sequential,
+
+        /// Slugs are prefixes of [`canonical`] hashes of the normalized URL,
+        /// so independent instances derive the same slug for the same URL.
+        /// Shortening a URL again returns its canonical link to any caller.
+        /// If the prefix of `length` is taken by another URL, longer prefixes
+        /// are tried.
+        Canonical { length: usize },
+    }
+
+    impl SlugGenerator {
+        /// Random generator of slugs of the default length.
+        pub fn random() -> Self {
+            SlugGenerator::Random { length: SLUG_LEN, attempts: 8 }
+        }
+
+        /// Canonical generator of slugs of the default length.
+        pub fn canonical() -> Self {
+            SlugGenerator::Canonical { length: SLUG_LEN }
+        }
+    }
+
+    /// Length of [`canonical`] hashes.
+    pub const CANONICAL_LEN: usize = 22;
+
+    /// Returns the base62 encoded first 128 bits of SHA-256 of the URL,
+    /// padded to [`CANONICAL_LEN`]. Unlike hashes of the standard library, it
+    /// is the same on every platform and version.
+    pub fn canonical(url: &str) -> String {
+        let digest = Sha256::digest(url.as_bytes());
+        let mut number = u128::from_be_bytes(digest[..16].try_into().expect("SHA-256 is 32 bytes long"));
+        let mut digits = [BASE62[0]; CANONICAL_LEN];
+        for digit in digits.iter_mut().rev() {
+            *digit = BASE62[(number % 62) as usize];
+            number /= 62;
+        }
+        String::from_utf8_lossy(&digits).into_owned()
+    }
+
+    /// Draws a random alphanumeric slug of the given length.
+    pub fn random(length: usize) -> String {
+        // thread generator is a CSPRNG periodically reseeded from the OS
+        rand::thread_rng().sample_iter(&Alphanumeric).take(length).map(char::from).collect()
+    }
+
+    /// Encodes the number in base62, e.g. `9` is `"9"` and `62` is `"10"`.
+    pub fn base62(mut number: u64) -> String {
+        let mut digits = Vec::new();
+        loop {
+            digits.push(BASE62[(number % 62) as usize] as char);
+            number /= 62;
+            if number == 0 {
+                break;
+            }
+        }
+        digits.iter().rev().collect()
+    }
+}
+
+/// Normalization of destination URLs.
+pub mod urls {
+    use std::collections::HashSet;
+
+    use url::Url;
+
+    /// Query parameters added by marketing and analytics tools, which don't
+    /// change the destination. See [`UrlNormalization::strip_tracking_params`].
+    pub const TRACKING_PARAMS: &[&str] = &["fbclid", "gclid", "dclid", "msclkid", "mc_cid", "mc_eid", "_ga", "yclid"];
+
+    /// Prefix of UTM parameters, which are tracking parameters as well.
+    pub const UTM_PREFIX: &str = "utm_";
+
+    /// Optional steps of URL normalization. Scheme and host are always
+    /// lowercased, default ports are stripped and dot-segments of the path
+    /// are resolved.
+    #[derive(Clone, Debug, Default, PartialEq)]
+    pub struct UrlNormalization {
+        /// Whether the trailing slash of a non-empty path is stripped, so
+        /// `https://example.com/docs/` is the same URL as
+        /// `https://example.com/docs`.
+        pub strip_trailing_slash: bool,
+
+        /// Whether UTM and other [`TRACKING_PARAMS`] are removed from the
+        /// query.
+        pub strip_tracking_params: bool,
+    }
+
+    /// Limit of [`UrlLimits`] broken by a URL.
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+    #[cfg_attr(feature = "event-log", derive(serde::Serialize, serde::Deserialize))]
+    pub enum UrlViolation {
+        /// The URL is longer than [`UrlLimits::max_length`].
+        TooLong,
+
+        /// The URL contains a line break, which could be used to inject
+        /// headers into redirect responses.
+        Newline,
+    }
+
+    /// Limits destination URLs must follow.
+    #[derive(Clone, Debug, PartialEq)]
+    pub struct UrlLimits {
+        /// Maximum length in bytes, before control characters are stripped.
+        pub max_length: usize,
+    }
+
+    impl Default for UrlLimits {
+        fn default() -> Self {
+            // common limit of browsers and proxies
+            Self { max_length: 2048 }
+        }
+    }
+
+    /// Checks the URL against the limits, and strips control characters and
+    /// surrounding whitespace from it.
+    ///
+    /// ## Errors
+    ///
+    /// Returns the first broken limit.
+    pub fn sanitize(url: &str, limits: &UrlLimits) -> Result<String, UrlViolation> {
+        if url.len() > limits.max_length {
+            return Err(UrlViolation::TooLong);
+        }
+
+        if url.contains(['\n', '\r']) {
+            return Err(UrlViolation::Newline);
+        }
+
+        Ok(url.chars().filter(|char| !char.is_control()).collect::<String>().trim().to_owned())
+    }
+
+    /// Schemes of URLs which may be shortened, `http` and `https` by
+    /// default. Schemes like `javascript`, `data` and `file` are dangerous
+    /// to redirect to.
+    #[derive(Clone, Debug, PartialEq)]
+    pub struct SchemeAllowlist(pub HashSet<String>);
+
+    impl Default for SchemeAllowlist {
+        fn default() -> Self {
+            Self(["http", "https"].into_iter().map(String::from).collect())
+        }
+    }
+
+    impl SchemeAllowlist {
+        /// Checks if the scheme of the parsed URL is allowed. Schemes are
+        /// compared ignoring case.
+        pub fn allows(&self, url: &Url) -> bool {
+            self.0.iter().any(|scheme| scheme.eq_ignore_ascii_case(url.scheme()))
+        }
+
+        /// Returns the allowlist with the scheme allowed as well, e.g.
+        /// `mailto` or a custom app scheme for deep links.
+        pub fn with(mut self, scheme: &str) -> Self {
+            self.0.insert(scheme.to_ascii_lowercase());
+            self
+        }
+    }
+
+    /// Validation of destinations with a particular scheme, run once the
+    /// scheme is allowed. Validators of `mailto`, `tel` and `magnet` are
+    /// installed by default, closures can validate custom schemes.
+    pub trait SchemeValidator {
+        /// Checks the URL, returning why it is invalid if it is.
+        fn validate(&self, url: &Url) -> Result<(), String>;
+    }
+
+    impl<F: Fn(&Url) -> Result<(), String>> SchemeValidator for F {
+        fn validate(&self, url: &Url) -> Result<(), String> {
+            self(url)
+        }
+    }
+
+    /// Validator of `mailto:` URLs, which must have at least one address
+    /// and only addresses with both a local part and a domain.
+    #[derive(Clone, Copy, Debug, Default)]
+    pub struct MailtoValidator;
+
+    impl SchemeValidator for MailtoValidator {
+        fn validate(&self, url: &Url) -> Result<(), String> {
+            let addresses = percent_encoding::percent_decode_str(url.path()).decode_utf8_lossy().into_owned();
+            if addresses.trim().is_empty() {
+                return Err(String::from("mailto URL has no address"));
+            }
+
+            for address in addresses.split(',').map(str::trim) {
+                let valid = address.split_once('@').is_some_and(|(local, domain)| {
+                    !local.is_empty() && !domain.is_empty() && !domain.contains(['@', ' '])
+                });
+                if !valid {
+                    return Err(format!("{address:?} is not an email address"));
+                }
+            }
+            Ok(())
+        }
+    }
+
+    /// Validator of `tel:` URLs, whose number must have between 3 and 15
+    /// digits, optionally with a leading `+` and visual separators.
+    #[derive(Clone, Copy, Debug, Default)]
+    pub struct TelValidator;
+
+    impl SchemeValidator for TelValidator {
+        fn validate(&self, url: &Url) -> Result<(), String> {
+            // parameters like `;ext=` follow the number
+            let number = url.path().split(';').next().unwrap_or_default();
+            let digits = number.strip_prefix('+').unwrap_or(number);
+            let valid = digits.chars().all(|char| char.is_ascii_digit() || "-.() ".contains(char))
+                && (3..=15).contains(&digits.chars().filter(char::is_ascii_digit).count());
+            if !valid {
+                return Err(format!("{number:?} is not a phone number"));
+            }
+            Ok(())
+        }
+    }
+
+    /// Validator of `magnet:` URLs, which must have an exact topic `xt` URN.
+    #[derive(Clone, Copy, Debug, Default)]
+    pub struct MagnetValidator;
+
+    impl SchemeValidator for MagnetValidator {
+        fn validate(&self, url: &Url) -> Result<(), String> {
+            if !url.query_pairs().any(|(name, value)| name == "xt" && value.starts_with("urn:")) {
+                return Err(String::from("magnet URL has no exact topic"));
+            }
+            Ok(())
+        }
+    }
+
+    /// Policy deciding whether destinations may be shortened and followed,
+    /// so deployments can plug in their own reputation checks on top of the
+    /// blocklist of hosts.
+    pub trait UrlPolicy {
+        /// Returns why the destination is blocked, or `None` if it is
+        /// allowed.
+        fn blocks(&self, url: &Url) -> Option<String>;
+    }
+
+    /// Policy allowing every destination, used by default.
+    #[derive(Clone, Copy, Debug, Default)]
+    pub struct AllowAllUrls;
+
+    impl UrlPolicy for AllowAllUrls {
+        fn blocks(&self, _url: &Url) -> Option<String> {
+            None
+        }
+    }
+
+    /// Checks if the host is the blocked host or its subdomain.
+    pub fn host_matches(host: &str, blocked: &str) -> bool {
+        host == blocked || host.strip_suffix(blocked).is_some_and(|prefix| prefix.ends_with('.'))
+    }
+
+    /// How a [`QueryParam`] is added to the destination.
+    #[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+    #[cfg_attr(feature = "event-log", derive(serde::Serialize, serde::Deserialize))]
+    pub enum ParamMode {
+        /// The parameter is added after the parameters of the destination,
+        /// even if it already has one with the same name.
+        #[default]
+        Append,
+
+        /// The parameter replaces all parameters of the destination with the
+        /// same name.
+        Override,
+    }
+
+    /// Query parameter added to the destination of a link when it is
+    /// followed, e.g. a UTM tag.
+    #[derive(Clone, Debug, PartialEq)]
+    #[cfg_attr(feature = "event-log", derive(serde::Serialize, serde::Deserialize))]
+    pub struct QueryParam {
+        /// Name of the parameter.
+        pub name: String,
+
+        /// Value of the parameter. [`QueryParam::CLICK_ID`] and
+        /// [`QueryParam::SLUG`] placeholders are replaced on every redirect.
+        pub value: String,
+
+        /// How the parameter is added.
+        pub mode: ParamMode,
+    }
+
+    impl QueryParam {
+        /// Placeholder replaced by an id unique to the redirect.
+        pub const CLICK_ID: &'static str = "{click_id}";
+
+        /// Placeholder replaced by the followed slug.
+        pub const SLUG: &'static str = "{slug}";
+
+        /// Parameter appended to the destination.
+        pub fn append(name: impl Into<String>, value: impl Into<String>) -> Self {
+            Self { name: name.into(), value: value.into(), mode: ParamMode::Append }
+        }
+
+        /// Parameter replacing parameters of the destination with the same
+        /// name.
+        pub fn override_with(name: impl Into<String>, value: impl Into<String>) -> Self {
+            Self { name: name.into(), value: value.into(), mode: ParamMode::Override }
+        }
+    }
+
+    /// Returns the destination with the parameters added in order, their
+    /// placeholders replaced by the followed slug and the click id. The URL
+    /// is returned as is if it can't be parsed.
+    pub fn apply_params(url: &str, params: &[QueryParam], slug: &str, click_id: &str) -> String {
+        let Ok(mut url) = Url::parse(url) else {
+            return url.to_owned();
+        };
+
+        let mut pairs: Vec<(String, String)> = url.query_pairs().into_owned().collect();
+        for param in params {
+            if param.mode == ParamMode::Override {
+                pairs.retain(|(name, _)| *name != param.name);
+            }
+            let value = param.value.replace(QueryParam::CLICK_ID, click_id).replace(QueryParam::SLUG, slug);
+            pairs.push((param.name.clone(), value));
+        }
+
+        if pairs.is_empty() {
+            url.set_query(None);
+        } else {
+            url.query_pairs_mut().clear().extend_pairs(pairs);
+        }
+        url.into()
+    }
+
+    /// Source of redirects of destinations, see
+    /// [`ServiceConfig::max_resolved_redirects`].
+    ///
+    /// [`ServiceConfig::max_resolved_redirects`]: super::ServiceConfig::max_resolved_redirects
+    pub trait RedirectFollower {
+        /// Returns the absolute URL the destination redirects to, or `None` if
+        /// it doesn't redirect. It is called while links are created, so it
+        /// should give up quickly on slow destinations.
+        fn next_hop(&self, url: &Url) -> Result<Option<Url>, String>;
+    }
+
+    /// Follower sending a HEAD request to destinations and reading the
+    /// `Location` of redirect responses.
+    #[cfg(feature = "resolve-redirects")]
+    pub struct HttpRedirectFollower {
+        client: reqwest::blocking::Client,
+    }
+
+    #[cfg(feature = "resolve-redirects")]
+    impl HttpRedirectFollower {
+        /// Creates a follower giving up on destinations after the timeout.
+        pub fn new(timeout: std::time::Duration) -> Self {
+            let client = reqwest::blocking::Client::builder()
+                .timeout(timeout)
+                .redirect(reqwest::redirect::Policy::none())
+                .build()
+                .expect("Failed to build HTTP client");
+            Self { client }
+        }
+    }
+
+    #[cfg(feature = "resolve-redirects")]
+    impl Default for HttpRedirectFollower {
+        fn default() -> Self {
+            Self::new(std::time::Duration::from_secs(3))
+        }
+    }
+
+    #[cfg(feature = "resolve-redirects")]
+    impl RedirectFollower for HttpRedirectFollower {
+        fn next_hop(&self, url: &Url) -> Result<Option<Url>, String> {
+            let response = self.client.head(url.as_str()).send().map_err(|error| error.to_string())?;
+            if !response.status().is_redirection() {
+                return Ok(None);
+            }
+
+            let location = response.headers()
+                .get(reqwest::header::LOCATION)
+                .and_then(|location| location.to_str().ok())
+                .ok_or_else(|| String::from("Redirect has no location"))?;
+            url.join(location).map(Some).map_err(|error| error.to_string())
+        }
+    }
+
+    /// Longest chain of short links a destination may resolve through, see
+    /// [`ServiceConfig::allow_own_links`].
+    ///
+    /// [`ServiceConfig::allow_own_links`]: super::ServiceConfig::allow_own_links
+    pub const MAX_OWN_REDIRECTS: usize = 5;
+
+    /// Returns the path of the URL without its leading slash, if the URL is
+    /// on one of the hosts. The path of a short link is its slug.
+    pub fn own_path(url: &str, hosts: &HashSet<String>) -> Option<String> {
+        let url = Url::parse(url).ok()?;
+        let host = url.host_str()?;
+        if !hosts.iter().any(|own| own.eq_ignore_ascii_case(host)) {
+            return None;
+        }
+        Some(url.path().trim_start_matches('/').to_owned())
+    }
+
+    /// Checks if the query parameter is a tracking one.
+    pub fn is_tracking_param(name: &str) -> bool {
+        name.starts_with(UTM_PREFIX) || TRACKING_PARAMS.contains(&name)
+    }
+
+    /// Returns the normalized form of the URL, so equal destinations written
+    /// differently, like `HTTP://Example.com:80/a/../` and
+    /// `http://example.com/`, are the same URL.
+    ///
+    /// ## Errors
+    ///
+    /// Returns an error if the URL can't be parsed.
+    pub fn normalize(url: &str, normalization: &UrlNormalization) -> Result<String, url::ParseError> {
+        // parsing lowercases scheme and host, strips default ports and resolves dot-segments
+        let mut url = Url::parse(url)?;
+
+        if normalization.strip_tracking_params && url.query().is_some() {
+            let pairs: Vec<(String, String)> = url.query_pairs()
+                .filter(|(name, _)| !is_tracking_param(name))
+                .map(|(name, value)| (name.into_owned(), value.into_owned()))
+                .collect();
+            if pairs.is_empty() {
+                url.set_query(None);
+            } else {
+                url.query_pairs_mut().clear().extend_pairs(pairs);
+            }
+        }
+
+        if normalization.strip_trailing_slash && url.path().len() > 1 && url.path().ends_with('/') {
+            let path = url.path().trim_end_matches('/').to_owned();
+            url.set_path(&path);
+        }
+
+        Ok(url.into())
+    }
+}
+
+/// Delayed execution of commands.
+pub mod scheduler {
+    use chrono::{DateTime, Utc};
+
+    use super::dispatch::{Command, RequestContext};
+
+    /// Identifier of a scheduled command.
+    #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+    #[cfg_attr(feature = "event-log", derive(serde::Serialize, serde::Deserialize))]
+    pub struct ScheduleId(pub u64);
+
+    /// Command waiting for its execution time.
+    #[derive(Clone, Debug, PartialEq)]
+    #[cfg_attr(feature = "event-log", derive(serde::Serialize, serde::Deserialize))]
+    pub struct ScheduledCommand {
+        /// Identifier of the scheduled command.
+        pub id: ScheduleId,
+
+        /// Time after which the command is executed.
+        pub due_at: DateTime<Utc>,
+
+        /// Caller on whose behalf the command is executed.
+        pub context: RequestContext,
+
+        /// The command itself.
+        pub command: Command,
+    }
+}
+
+/// Import of links and redirect histories from other systems.
+pub mod import {
+    use std::{
+        collections::{BTreeMap, HashSet},
+        io::{self, Read},
+        mem,
+    };
+
+    use chrono::{DateTime, DurationRound, TimeDelta, Utc};
+
+    use super::{ShortLink, ShortenerError, Slug, Tag, Url, VisitorId};
+
+    /// Redirects of a short link exported from another system.
+    #[derive(Clone, Debug, PartialEq)]
+    #[cfg_attr(feature = "event-log", derive(serde::Serialize, serde::Deserialize))]
+    pub struct ImportedRedirect {
+        /// [`Slug`] of the link, which must already exist in the service.
+        pub slug: Slug,
+
+        /// Time of the redirects, with the precision of the source system.
+        pub at: DateTime<Utc>,
+
+        /// Visitor who followed the link, if the source system tracks them.
+        pub visitor: Option<VisitorId>,
+
+        /// Number of redirects, e.g. when the source system exports
+        /// aggregated counts.
+        pub count: u64,
+    }
+
+    /// Rules reconciling redirects imported from several sources. By default
+    /// every record is imported as is.
+    #[derive(Clone, Debug, Default, PartialEq)]
+    #[cfg_attr(feature = "event-log", derive(serde::Serialize, serde::Deserialize))]
+    pub struct MergeRules {
+        /// Drop records which are exactly equal to an already imported one,
+        /// e.g. when the same redirect was exported by two systems.
+        pub drop_duplicates: bool,
+
+        /// Sum counts of records of the same link within buckets of this
+        /// length, so sources with timestamps of different precision add up
+        /// to a single record per bucket.
+        pub bucket: Option<TimeDelta>,
+    }
+
+    impl MergeRules {
+        /// Applies the rules to the records. Returns the merged records in
+        /// order of their time, along with the number of dropped duplicates.
+        pub fn apply(&self, redirects: Vec<ImportedRedirect>) -> (Vec<ImportedRedirect>, usize) {
+            let total = redirects.len();
+            let mut seen = HashSet::new();
+            let redirects: Vec<_> = redirects
+                .into_iter()
+                .filter(|redirect| {
+                    !self.drop_duplicates
+                        || seen.insert((redirect.slug.0.clone(), redirect.at, redirect.visitor.clone(), redirect.count))
+                })
+                .collect();
+            let dropped = total - redirects.len();
+
+            let Some(bucket) = self.bucket else {
+                let mut redirects = redirects;
+                redirects.sort_by_key(|redirect| redirect.at);
+                return (redirects, dropped);
+            };
+
+            // buckets keyed by their start first, so the result is ordered by time
+            let mut buckets: BTreeMap<(DateTime<Utc>, String), ImportedRedirect> = BTreeMap::new();
+            for redirect in redirects {
+                let at = redirect.at.duration_trunc(bucket).unwrap_or(redirect.at);
+                buckets
+                    .entry((at, redirect.slug.0.clone()))
+                    .and_modify(|merged| merged.count += redirect.count)
+                    .or_insert(ImportedRedirect { at, visitor: None, ..redirect });
+            }
+            (buckets.into_values().collect(), dropped)
+        }
+    }
+
+    /// Outcome of an import.
+    #[derive(Clone, Debug, Default, PartialEq)]
+    pub struct ImportReport {
+        /// Number of imported redirects.
+        pub imported: u64,
+
+        /// Number of records dropped as duplicates.
+        pub duplicates: usize,
+
+        /// Slugs of the records which don't map to any short link, their
+        /// redirects are not imported.
+        pub unknown_slugs: Vec<Slug>,
+    }
+
+    /// Short link exported from another system.
+    #[derive(Clone, Debug, PartialEq)]
+    #[cfg_attr(feature = "event-log", derive(serde::Serialize, serde::Deserialize))]
+    pub struct ImportedLink {
+        /// Line of the source the link was read from, starting from `1`.
+        pub line: usize,
+
+        /// Destination of the link.
+        pub url: Url,
+
+        /// Custom slug of the link, a random one is generated if `None`.
+        pub slug: Option<Slug>,
+
+        /// Tags attached to the link.
+        pub tags: Vec<Tag>,
+    }
+
+    /// Outcome of an import of links.
+    #[derive(Clone, Debug, Default, PartialEq)]
+    pub struct LinkImportReport {
+        /// Created links, along with already existing ones which have the
+        /// same slug and destination.
+        pub created: Vec<ShortLink>,
+
+        /// Lines of links whose custom slug is taken by another link, along
+        /// with the slug.
+        pub conflicts: Vec<(usize, Slug)>,
+
+        /// Lines of links which failed validation, along with the error.
+        pub invalid: Vec<(usize, ShortenerError)>,
+    }
+
+    /// Reads links from CSV with the columns `url,slug,tags`. Empty slugs
+    /// are generated, and tags are separated by commas or semicolons within
+    /// their field. The first row is skipped if it is the header, i.e. its
+    /// first field is `url`, and so are empty rows.
+    ///
+    /// ## Errors
+    ///
+    /// Errors of the reader, including invalid UTF-8.
+    pub fn read_links_csv<R: Read>(mut reader: R) -> io::Result<Vec<ImportedLink>> {
+        let mut text = String::new();
+        reader.read_to_string(&mut text)?;
+
+        let mut links = Vec::new();
+        for (line, fields) in csv_rows(&text) {
+            let field = |index: usize| fields.get(index).map_or("", |field| field.trim());
+            let header = line == 1 && field(0).eq_ignore_ascii_case("url");
+            if header || fields.iter().all(|field| field.trim().is_empty()) {
+                continue;
+            }
+            links.push(ImportedLink {
+                line,
+                url: Url(field(0).to_owned()),
+                slug: Some(field(1)).filter(|slug| !slug.is_empty()).map(|slug| Slug(slug.to_owned())),
+                tags: field(2)
+                    .split([',', ';'])
+                    .map(str::trim)
+                    .filter(|tag| !tag.is_empty())
+                    .map(|tag| Tag(tag.to_owned()))
+                    .collect(),
+            });
+        }
+        Ok(links)
+    }
+
+    /// Splits CSV into rows of fields, along with the line each row starts
+    /// at. Quoted fields may contain separators, line breaks and doubled
+    /// quotes, the counterpart of [`export::write_row`](super::export::write_row).
+    fn csv_rows(text: &str) -> Vec<(usize, Vec<String>)> {
+        let mut rows = Vec::new();
+        let mut fields = Vec::new();
+        let mut field = String::new();
+        let (mut line, mut start) = (1, 1);
+        let mut quoted = false;
+        let mut chars = text.chars().peekable();
+        while let Some(char) = chars.next() {
+            match char {
+                '"' if quoted && chars.peek() == Some(&'"') => {
+                    chars.next();
+                    field.push('"');
+                },
+                '"' => quoted = !quoted,
+                ',' if !quoted => fields.push(mem::take(&mut field)),
+                '\r' if !quoted && chars.peek() == Some(&'\n') => {},
+                '\n' if !quoted => {
+                    fields.push(mem::take(&mut field));
+                    rows.push((start, mem::take(&mut fields)));
+                    line += 1;
+                    start = line;
+                },
+                '\n' => {
+                    field.push(char);
+                    line += 1;
+                },
+                _ => field.push(char),
+            }
+        }
+        if !field.is_empty() || !fields.is_empty() {
+            fields.push(field);
+            rows.push((start, fields));
+        }
+        rows
+    }
+}
+
+/// Per-link webhooks notified about redirects.
+pub mod webhooks {
+    use std::collections::BTreeSet;
+
+    use chrono::{DateTime, TimeDelta, Utc};
+    use sha2::{Digest, Sha256};
+
+    use super::{events::EventRecord, hex, LinkId, Slug, Url, VisitorId};
+
+    /// Header carrying the signature of delivered payloads, as
+    /// `sha256=<hex HMAC-SHA256 of the body keyed by the webhook secret>`.
+    pub const SIGNATURE_HEADER: &str = "X-Shortener-Signature";
+
+    /// Callback notified about redirects of a single link.
+    #[derive(Clone, Debug, PartialEq)]
+    #[cfg_attr(feature = "event-log", derive(serde::Serialize, serde::Deserialize))]
+    pub struct LinkWebhook {
+        /// URL receiving a POST for every delivered redirect.
+        pub url: Url,
+
+        /// Secret signing delivered payloads, so the receiver can verify them.
+        pub secret: String,
+
+        /// Share of redirects delivered, `1.0` delivers every redirect.
+        pub sample_rate: f64,
+    }
+
+    /// Identifier of a webhook registered for domain events.
+    #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+    #[cfg_attr(feature = "event-log", derive(serde::Serialize, serde::Deserialize))]
+    pub struct WebhookId(pub u64);
+
+    /// Domain event an [`EventWebhook`] can be notified about.
+    #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+    #[cfg_attr(feature = "event-log", derive(serde::Serialize, serde::Deserialize))]
+    pub enum WebhookTrigger {
+        /// A link was created.
+        LinkCreated,
+
+        /// A link reached the count of redirects since its last stats reset.
+        RedirectsReached(u64),
+
+        /// A link, or an old slug of it, can't be followed anymore: a one-time
+        /// link was consumed or a deprecated alias expired.
+        LinkExpired,
+    }
+
+    impl WebhookTrigger {
+        /// Returns the name of the trigger in delivered payloads.
+        pub fn name(self) -> &'static str {
+            match self {
+                WebhookTrigger::LinkCreated => "link_created",
+                WebhookTrigger::RedirectsReached(_) => "redirects_reached",
+                WebhookTrigger::LinkExpired => "link_expired",
+            }
+        }
+    }
+
+    /// Callback notified about domain events of all links of its tenant.
+    #[derive(Clone, Debug, PartialEq)]
+    #[cfg_attr(feature = "event-log", derive(serde::Serialize, serde::Deserialize))]
+    pub struct EventWebhook {
+        /// URL receiving a POST for every triggering event.
+        pub url: Url,
+
+        /// Secret signing delivered payloads, so the receiver can verify them.
+        pub secret: String,
+
+        /// Events the webhook is notified about.
+        pub triggers: BTreeSet<WebhookTrigger>,
+    }
+
+    /// Retries of failed deliveries to [`EventWebhook`]s, waiting twice as
+    /// long before every next attempt.
+    #[derive(Clone, Copy, Debug, PartialEq)]
+    pub struct RetryPolicy {
+        /// Attempts made before the delivery is given up, the first one
+        /// included.
+        pub max_attempts: u32,
+
+        /// Wait before the second attempt.
+        pub backoff: TimeDelta,
+    }
+
+    impl Default for RetryPolicy {
+        fn default() -> Self {
+            Self { max_attempts: 5, backoff: TimeDelta::minutes(1) }
+        }
+    }
+
+    impl RetryPolicy {
+        /// Returns the wait after the count of failed attempts.
+        pub fn delay(&self, attempts: u32) -> TimeDelta {
+            self.backoff * 2i32.saturating_pow(attempts.saturating_sub(1).min(30))
+        }
+    }
+
+    /// Identifier of a delivery to an [`EventWebhook`].
+    #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+    pub struct DeliveryId(pub u64);
+
+    /// Where a delivery to an [`EventWebhook`] stands.
+    #[derive(Clone, Copy, Debug, PartialEq)]
+    pub enum DeliveryStatus {
+        /// The delivery waits for its next attempt.
+        Pending { next_attempt_at: DateTime<Utc> },
+
+        /// The receiver accepted the delivery.
+        Delivered { at: DateTime<Utc> },
+
+        /// All attempts failed, the delivery is given up.
+        Failed,
+    }
+
+    /// Delivery to an [`EventWebhook`], tracked until it is accepted or runs
+    /// out of attempts.
+    #[derive(Clone, Debug, PartialEq)]
+    pub struct TrackedDelivery {
+        /// Identity of the delivery.
+        pub id: DeliveryId,
+
+        /// Webhook the delivery is meant for.
+        pub webhook_id: WebhookId,
+
+        /// Event which triggered the delivery.
+        pub trigger: WebhookTrigger,
+
+        /// The signed payload.
+        pub delivery: WebhookDelivery,
+
+        /// Where the delivery stands.
+        pub status: DeliveryStatus,
+
+        /// Attempts made so far.
+        pub attempts: u32,
+
+        /// Why the last attempt failed, if it did.
+        pub last_error: Option<String>,
+    }
+
+    /// Signed payload to be POSTed to a webhook.
+    #[derive(Clone, Debug, PartialEq)]
+    pub struct WebhookDelivery {
+        /// URL of the webhook.
+        pub url: Url,
+
+        /// JSON body describing the redirect.
+        pub body: String,
+
+        /// Value of the [`SIGNATURE_HEADER`].
+        pub signature: String,
+    }
+
+    impl WebhookDelivery {
+        pub(crate) fn redirect(
+            webhook: &LinkWebhook,
+            record: &EventRecord,
+            link_id: &LinkId,
+            slug: &Slug,
+            url: &Url,
+            visitor: Option<&VisitorId>,
+        ) -> Self {
+            let body = format!(
+                r#"{{"event":"redirect","sequence":{},"recorded_at":{},"link_id":{},"slug":{},"url":{},"visitor":{}}}"#,
+                record.sequence,
+                json_string(&record.recorded_at.to_rfc3339()),
+                json_string(&link_id.0),
+                json_string(&slug.0),
+                json_string(&url.0),
+                visitor.map_or_else(|| String::from("null"), |visitor| json_string(&visitor.0)),
+            );
+            let signature = format!("sha256={}", sign(&webhook.secret, &body));
+            Self { url: webhook.url.clone(), body, signature }
+        }
+
+        pub(crate) fn event(
+            webhook: &EventWebhook,
+            trigger: WebhookTrigger,
+            record: &EventRecord,
+            link_id: &LinkId,
+            slug: &Slug,
+            redirects: u64,
+        ) -> Self {
+            let body = format!(
+                r#"{{"event":{},"sequence":{},"recorded_at":{},"link_id":{},"slug":{},"redirects":{}}}"#,
+                json_string(trigger.name()),
+                record.sequence,
+                json_string(&record.recorded_at.to_rfc3339()),
+                json_string(&link_id.0),
+                json_string(&slug.0),
+                redirects,
+            );
+            let signature = format!("sha256={}", sign(&webhook.secret, &body));
+            Self { url: webhook.url.clone(), body, signature }
+        }
+    }
+
+    /// Transport of deliveries to [`EventWebhook`]s.
+    pub trait WebhookTransport {
+        /// POSTs the delivery, returning why it failed if it did. It is
+        /// called while deliveries are handled, so it may block on the
+        /// network.
+        fn post(&self, delivery: &WebhookDelivery) -> Result<(), String>;
+    }
+
+    /// Transport POSTing deliveries with a blocking HTTP client. Responses
+    /// with a status other than success are failures.
+    #[cfg(feature = "webhooks")]
+    pub struct HttpWebhookTransport {
+        client: reqwest::blocking::Client,
+    }
+
+    #[cfg(feature = "webhooks")]
+    impl HttpWebhookTransport {
+        /// Creates a transport giving up on receivers after the timeout.
+        pub fn new(timeout: std::time::Duration) -> Self {
+            let client = reqwest::blocking::Client::builder()
+                .timeout(timeout)
+                .build()
+                .expect("Failed to build HTTP client");
+            Self { client }
+        }
+    }
+
+    #[cfg(feature = "webhooks")]
+    impl Default for HttpWebhookTransport {
+        fn default() -> Self {
+            Self::new(std::time::Duration::from_secs(10))
+        }
+    }
+
+    #[cfg(feature = "webhooks")]
+    impl WebhookTransport for HttpWebhookTransport {
+        fn post(&self, delivery: &WebhookDelivery) -> Result<(), String> {
+            let response = self.client
+                .post(&delivery.url.0)
+                .header(reqwest::header::CONTENT_TYPE, "application/json")
+                .header(SIGNATURE_HEADER, &delivery.signature)
+                .body(delivery.body.clone())
+                .send()
+                .map_err(|error| error.to_string())?;
+            match response.status() {
+                status if status.is_success() => Ok(()),
+                status => Err(format!("receiver responded with {status}")),
+            }
+        }
+    }
+
+    /// Transport of webhook deliveries.
+    pub trait WebhookSender {
+        /// Sends the delivery. It is called while the redirect is handled, so
+        /// it shouldn't block on the network.
+        fn send(&self, delivery: WebhookDelivery);
+    }
+
+    /// Sender dropping all deliveries, used by default.
+    #[derive(Clone, Copy, Debug, Default)]
+    pub struct NoWebhooks;
+
+    impl WebhookSender for NoWebhooks {
+        fn send(&self, _delivery: WebhookDelivery) {}
+    }
+
+    /// Sender POSTing deliveries from a background thread. Failed deliveries
+    /// are not retried.
+    #[cfg(feature = "webhooks")]
+    pub struct HttpWebhookSender {
+        deliveries: std::sync::mpsc::Sender<WebhookDelivery>,
+    }
+
+    #[cfg(feature = "webhooks")]
+    impl HttpWebhookSender {
+        /// Starts the background thread delivering webhooks.
+        pub fn new() -> Self {
+            let (deliveries, receiver) = std::sync::mpsc::channel::<WebhookDelivery>();
+            std::thread::spawn(move || {
+                let client = reqwest::blocking::Client::new();
+                for delivery in receiver {
+                    let _ = client
+                        .post(&delivery.url.0)
+                        .header(reqwest::header::CONTENT_TYPE, "application/json")
+                        .header(SIGNATURE_HEADER, delivery.signature)
+                        .body(delivery.body)
+                        .send();
+                }
+            });
+            Self { deliveries }
+        }
+    }
+
+    #[cfg(feature = "webhooks")]
+    impl Default for HttpWebhookSender {
+        fn default() -> Self {
+            Self::new()
+        }
+    }
+
+    #[cfg(feature = "webhooks")]
+    impl WebhookSender for HttpWebhookSender {
+        fn send(&self, delivery: WebhookDelivery) {
+            // the thread lives as long as the sender
+            let _ = self.deliveries.send(delivery);
+        }
+    }
+
+    /// Returns hex HMAC-SHA256 of the body keyed by the secret.
+    pub fn sign(secret: &str, body: &str) -> String {
+        const BLOCK_LEN: usize = 64;
+
+        let mut key = [0u8; BLOCK_LEN];
+        if secret.len() > BLOCK_LEN {
+            key[..32].copy_from_slice(&Sha256::digest(secret.as_bytes()));
+        } else {
+            key[..secret.len()].copy_from_slice(secret.as_bytes());
+        }
+
+        let inner = Sha256::new().chain_update(key.map(|byte| byte ^ 0x36)).chain_update(body.as_bytes()).finalize();
+        let outer = Sha256::new().chain_update(key.map(|byte| byte ^ 0x5c)).chain_update(inner).finalize();
+        hex(&outer)
+    }
+
+    pub(crate) fn json_string(value: &str) -> String {
+        let mut json = String::with_capacity(value.len() + 2);
+        json.push('"');
+        for c in value.chars() {
+            match c {
+                '"' => json.push_str("\\\""),
+                '\\' => json.push_str("\\\\"),
+                c if c.is_control() => json.push_str(&format!("\\u{:04x}", c as u32)),
+                c => json.push(c),
+            }
+        }
+        json.push('"');
+        json
+    }
+}
+
+/// Health checks of destinations, so dead links can be found.
+pub mod health {
+    use chrono::{DateTime, Utc};
+
+    use super::Url;
+
+    /// Result of the last health check of a destination.
+    #[derive(Clone, Debug, Default, PartialEq)]
+    pub enum DestinationHealth {
+        /// The destination wasn't checked yet.
+        #[default]
+        Unchecked,
+
+        /// The destination responded.
+        Healthy {
+            /// Time of the check.
+            checked_at: DateTime<Utc>,
+        },
+
+        /// The destination failed the check.
+        Broken {
+            /// Time of the check.
+            checked_at: DateTime<Utc>,
+
+            /// Why the check failed.
+            reason: String,
+        },
+    }
+
+    impl DestinationHealth {
+        /// Returns the time of the last check, if any.
+        pub fn checked_at(&self) -> Option<DateTime<Utc>> {
+            match self {
+                Self::Unchecked => None,
+                Self::Healthy { checked_at } | Self::Broken { checked_at, .. } => Some(*checked_at),
+            }
+        }
+
+        /// Checks if the destination failed its last check.
+        pub fn is_broken(&self) -> bool {
+            matches!(self, Self::Broken { .. })
+        }
+    }
+
+    /// Check of whether destinations respond.
+    pub trait HealthProbe {
+        /// Checks the destination, returning why it is broken if it is. It is
+        /// called while health checks are handled, so it may block on the
+        /// network.
+        fn probe(&self, url: &Url) -> Result<(), String>;
+    }
+
+    /// Probe sending a HEAD request to destinations, falling back to GET for
+    /// servers not supporting HEAD. Redirects are followed, and destinations
+    /// ending with a client or server error are broken.
+    #[cfg(feature = "health-checks")]
+    pub struct HttpHealthProbe {
+        client: reqwest::blocking::Client,
+    }
+
+    #[cfg(feature = "health-checks")]
+    impl HttpHealthProbe {
+        /// Creates a probe giving up on destinations after the timeout.
+        pub fn new(timeout: std::time::Duration) -> Self {
+            let client = reqwest::blocking::Client::builder()
+                .timeout(timeout)
+                .build()
+                .expect("Failed to build HTTP client");
+            Self { client }
+        }
+    }
+
+    #[cfg(feature = "health-checks")]
+    impl Default for HttpHealthProbe {
+        fn default() -> Self {
+            Self::new(std::time::Duration::from_secs(10))
+        }
+    }
+
+    #[cfg(feature = "health-checks")]
+    impl HealthProbe for HttpHealthProbe {
+        fn probe(&self, url: &Url) -> Result<(), String> {
+            use reqwest::StatusCode;
+
+            let mut response = self.client.head(&url.0).send().map_err(|error| error.to_string())?;
+            if matches!(response.status(), StatusCode::METHOD_NOT_ALLOWED | StatusCode::NOT_IMPLEMENTED) {
+                response = self.client.get(&url.0).send().map_err(|error| error.to_string())?;
+            }
+
+            let status = response.status();
+            if status.is_client_error() || status.is_server_error() {
+                return Err(format!("Destination responded with {status}"));
+            }
+            Ok(())
+        }
+    }
+}
+
+/// Previews of destinations, like their titles and favicons, for preview
+/// UIs.
+pub mod previews {
+    use url::Url;
+
+    /// Preview of a destination, read from its HTML.
+    #[derive(Clone, Debug, Default, PartialEq)]
+    #[cfg_attr(feature = "event-log", derive(serde::Serialize, serde::Deserialize))]
+    pub struct DestinationPreview {
+        /// Title of the page, from `<title>` or `og:title`.
+        pub title: Option<String>,
+
+        /// Description of the page, from `description` or `og:description`
+        /// meta tags.
+        pub description: Option<String>,
+
+        /// Absolute URL of the favicon, `/favicon.ico` if the page doesn't
+        /// link any.
+        pub favicon: Option<String>,
+    }
+
+    /// Source of previews of destinations.
+    pub trait PreviewFetcher {
+        /// Fetches the preview of the destination, returning why it failed if
+        /// it did. It is called while previews are fetched, so it may block
+        /// on the network.
+        fn fetch(&self, url: &Url) -> Result<DestinationPreview, String>;
+    }
+
+    /// Fetcher downloading the beginning of destinations and parsing their
+    /// HTML with [`parse_preview`].
+    #[cfg(feature = "previews")]
+    pub struct HttpPreviewFetcher {
+        client: reqwest::blocking::Client,
+    }
+
+    #[cfg(feature = "previews")]
+    impl HttpPreviewFetcher {
+        /// Longest prefix of the page read, the head of the page is expected
+        /// within it.
+        pub const MAX_BODY_LEN: u64 = 256 * 1024;
+
+        /// Creates a fetcher giving up on destinations after the timeout.
+        pub fn new(timeout: std::time::Duration) -> Self {
+            let client = reqwest::blocking::Client::builder()
+                .timeout(timeout)
+                .build()
+                .expect("Failed to build HTTP client");
+            Self { client }
+        }
+    }
+
+    #[cfg(feature = "previews")]
+    impl Default for HttpPreviewFetcher {
+        fn default() -> Self {
+            Self::new(std::time::Duration::from_secs(10))
+        }
+    }
+
+    #[cfg(feature = "previews")]
+    impl PreviewFetcher for HttpPreviewFetcher {
+        fn fetch(&self, url: &Url) -> Result<DestinationPreview, String> {
+            use std::io::Read;
+
+            let response = self.client.get(url.as_str()).send().map_err(|error| error.to_string())?;
+            let status = response.status();
+            if !status.is_success() {
+                return Err(format!("Destination responded with {status}"));
+            }
+
+            // relative favicons are resolved against the page after redirects
+            let base = response.url().clone();
+            let mut body = Vec::new();
+            response.take(Self::MAX_BODY_LEN).read_to_end(&mut body).map_err(|error| error.to_string())?;
+            Ok(parse_preview(&String::from_utf8_lossy(&body), &base))
+        }
+    }
+
+    /// Reads the preview from the head of the HTML page at the URL.
+    pub fn parse_preview(html: &str, url: &Url) -> DestinationPreview {
+        // ASCII lowercasing keeps byte offsets, so both strings are indexed the same way
+        let lower = html.to_ascii_lowercase();
+        let mut preview = DestinationPreview::default();
+        let mut og_title = None;
+        let mut favicon = None;
+
+        let mut position = 0;
+        while let Some(start) = lower[position..].find('<').map(|start| position + start) {
+            let Some(end) = lower[start..].find('>').map(|end| start + end) else {
+                break;
+            };
+            let tag = &html[start + 1..end];
+            let name = tag.split(|char: char| char.is_ascii_whitespace() || char == '/').next().unwrap_or_default();
+            match name.to_ascii_lowercase().as_str() {
+                "title" if preview.title.is_none() => {
+                    if let Some(close) = lower[end..].find("</title") {
+                        preview.title = Some(decode_entities(html[end + 1..end + close].trim()));
+                    }
+                },
+                "meta" => {
+                    let attributes = attributes(tag);
+                    let key = attribute(&attributes, "name").or_else(|| attribute(&attributes, "property"));
+                    let content = attribute(&attributes, "content").map(decode_entities);
+                    match key.map(str::to_ascii_lowercase).as_deref() {
+                        Some("description" | "og:description") if preview.description.is_none() => {
+                            preview.description = content;
+                        },
+                        Some("og:title") if og_title.is_none() => og_title = content,
+                        _ => {},
+                    }
+                },
+                "link" => {
+                    let attributes = attributes(tag);
+                    let is_icon = attribute(&attributes, "rel")
+                        .is_some_and(|rel| rel.split_ascii_whitespace().any(|rel| rel.eq_ignore_ascii_case("icon")));
+                    if is_icon && favicon.is_none() {
+                        favicon = attribute(&attributes, "href").and_then(|href| url.join(&decode_entities(href)).ok());
+                    }
+                },
+                "/head" | "body" => break,
+                _ => {},
+            }
+            position = end + 1;
+        }
+
+        preview.title = preview.title.filter(|title| !title.is_empty()).or(og_title);
+        preview.favicon = favicon.or_else(|| url.join("/favicon.ico").ok()).map(String::from);
+        preview
+    }
+
+    /// Returns attributes of the tag with their lowercase names.
+    fn attributes(tag: &str) -> Vec<(String, &str)> {
+        let mut attributes = Vec::new();
+        // skip the name of the tag
+        let mut rest = tag.trim_start_matches(|char: char| !char.is_ascii_whitespace());
+        loop {
+            rest = rest.trim_start_matches(|char: char| char.is_ascii_whitespace() || char == '/');
+            let name_len = rest
+                .find(|char: char| char.is_ascii_whitespace() || "=/".contains(char))
+                .unwrap_or(rest.len());
+            if name_len == 0 {
+                return attributes;
+            }
+            let name = rest[..name_len].to_ascii_lowercase();
+            rest = rest[name_len..].trim_start();
+
+            let Some(value) = rest.strip_prefix('=').map(str::trim_start) else {
+                attributes.push((name, ""));
+                continue;
+            };
+            let (value, remaining) = match value.chars().next() {
+                Some(quote @ ('"' | '\'')) => {
+                    let value = &value[1..];
+                    let end = value.find(quote).unwrap_or(value.len());
+                    (&value[..end], value.get(end + 1..).unwrap_or_default())
+                },
+                _ => value.split_at(value.find(|char: char| char.is_ascii_whitespace()).unwrap_or(value.len())),
+            };
+            attributes.push((name, value));
+            rest = remaining;
+        }
+    }
+
+    fn attribute<'a>(attributes: &[(String, &'a str)], name: &str) -> Option<&'a str> {
+        attributes.iter().find(|(attribute, _)| attribute == name).map(|(_, value)| *value)
+    }
+
+    /// Decodes the most common HTML entities.
+    fn decode_entities(text: &str) -> String {
+        text.replace("&lt;", "<")
+            .replace("&gt;", ">")
+            .replace("&quot;", "\"")
+            .replace("&#39;", "'")
+            .replace("&apos;", "'")
+            .replace("&nbsp;", " ")
+            .replace("&amp;", "&")
+    }
+}
+
+/// Building blocks of redirect analytics.
+pub mod analytics {
+    use std::{
+        collections::BTreeMap,
+        hash::{Hash, Hasher},
+        net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    };
+
+    use chrono::{DateTime, TimeDelta, Utc};
+
+    use sha2::{Digest, Sha256};
+
+    use super::{RedirectContext, VisitorId};
+
+    /// ISO 3166-1 alpha-2 code of a country, e.g. `"DE"`.
+    #[derive(Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+    #[cfg_attr(feature = "event-log", derive(serde::Serialize, serde::Deserialize))]
+    pub struct CountryCode(pub String);
+
+    impl CountryCode {
+        /// Returns the code in upper case if it is made of two ASCII letters.
+        pub fn parse(code: &str) -> Option<Self> {
+            (code.len() == 2 && code.bytes().all(|byte| byte.is_ascii_alphabetic()))
+                .then(|| Self(code.to_ascii_uppercase()))
+        }
+    }
+
+    /// Resolver of countries of visitors by their addresses, e.g. backed by a
+    /// GeoIP database. It is called for every redirect with a known address,
+    /// so it must not block.
+    pub trait GeoResolver {
+        /// Returns the country of the address, if it is known.
+        fn country(&self, ip: IpAddr) -> Option<CountryCode>;
+    }
+
+    impl<F: Fn(IpAddr) -> Option<CountryCode>> GeoResolver for F {
+        fn country(&self, ip: IpAddr) -> Option<CountryCode> {
+            self(ip)
+        }
+    }
+
+    /// Returns a stable identity of the visitor derived from the address and
+    /// the user agent of their request, for visitors without a [`VisitorId`].
+    /// Requests without an address have no fingerprint.
+    pub fn fingerprint(context: &RedirectContext) -> Option<VisitorId> {
+        let ip = context.ip?;
+        let digest = Sha256::new()
+            .chain_update(ip.to_string())
+            .chain_update([0])
+            .chain_update(context.user_agent.as_deref().unwrap_or_default())
+            .finalize();
+        let hex: String = digest[..8].iter().map(|byte| format!("{byte:02x}")).collect();
+        Some(VisitorId(format!("fingerprint-{hex}")))
+    }
+
+    /// How addresses of visitors are anonymized before they are recorded with
+    /// redirects, so no raw address ends up in the event log.
+    #[derive(Clone, Debug, Default, PartialEq)]
+    pub enum IpAnonymization {
+        /// Addresses are recorded as is.
+        #[default]
+        Off,
+
+        /// Host bits of addresses are zeroed, keeping the `/24` network of
+        /// IPv4 and the `/48` network of IPv6 addresses. Countries can still
+        /// be told, while visitors of the same network look the same.
+        Truncate,
+
+        /// Addresses are replaced with pseudonymous IPv6 addresses from the
+        /// unique local range `fd00::/8`, derived from salted hashes of them.
+        /// The same visitor keeps the same pseudonym, so unique visitors are
+        /// still counted. The salt must be kept secret, and rotating it makes
+        /// all visitors look new.
+        Hash {
+            /// Secret mixed into hashes of addresses.
+            salt: String,
+        },
+    }
+
+    impl IpAnonymization {
+        /// Returns the address as it may be recorded.
+        pub fn apply(&self, ip: IpAddr) -> IpAddr {
+            match self {
+                IpAnonymization::Off => ip,
+                IpAnonymization::Truncate => match ip {
+                    IpAddr::V4(ip) => {
+                        let [a, b, c, _] = ip.octets();
+                        IpAddr::V4(Ipv4Addr::new(a, b, c, 0))
+                    },
+                    IpAddr::V6(ip) => {
+                        let segments = ip.segments();
+                        IpAddr::V6(Ipv6Addr::new(segments[0], segments[1], segments[2], 0, 0, 0, 0, 0))
+                    },
+                },
+                IpAnonymization::Hash { salt } => {
+                    let digest = Sha256::new()
+                        .chain_update(salt)
+                        .chain_update([0])
+                        .chain_update(ip.to_string())
+                        .finalize();
+                    let mut octets = [0; 16];
+                    octets.copy_from_slice(&digest[..16]);
+                    octets[0] = 0xfd;
+                    IpAddr::V6(Ipv6Addr::from(octets))
+                },
+            }
+        }
+    }
+
+    /// Classifier telling redirects made by bots, e.g. crawlers, link
+    /// previews and scripts, from redirects made by humans.
+    pub trait BotClassifier {
+        /// Checks if the redirect with the context was made by a bot.
+        fn is_bot(&self, context: &RedirectContext) -> bool;
+    }
+
+    impl<F: Fn(&RedirectContext) -> bool> BotClassifier for F {
+        fn is_bot(&self, context: &RedirectContext) -> bool {
+            self(context)
+        }
+    }
+
+    /// Default [`BotClassifier`], which looks for well-known markers of bots
+    /// in the user agent. Redirects with an empty user agent are made by bots,
+    /// while redirects without a known user agent are assumed to be human.
+    #[derive(Clone, Copy, Debug, Default)]
+    pub struct UserAgentHeuristics;
+
+    impl UserAgentHeuristics {
+        /// Lowercase fragments of user agents of bots.
+        pub const MARKERS: &'static [&'static str] = &[
+            "bot", "crawl", "spider", "slurp", "preview", "facebookexternalhit", "headless", "lighthouse",
+            "curl/", "wget/", "python-requests", "python-urllib", "go-http-client", "okhttp", "java/",
+        ];
+    }
+
+    impl BotClassifier for UserAgentHeuristics {
+        fn is_bot(&self, context: &RedirectContext) -> bool {
+            let Some(user_agent) = &context.user_agent else {
+                return false;
+            };
+            let user_agent = user_agent.trim().to_ascii_lowercase();
+            user_agent.is_empty() || Self::MARKERS.iter().any(|marker| user_agent.contains(marker))
+        }
+    }
+
+    /// Sketch estimating the count of distinct items in bounded memory, a
+    /// byte per register, with a standard error of about 3%.
+    #[derive(Clone, Debug, Default, PartialEq)]
+    #[cfg_attr(feature = "event-log", derive(serde::Serialize, serde::Deserialize))]
+    pub struct HyperLogLog {
+        // empty until the first item is inserted, so idle links take no memory
+        registers: Vec<u8>,
+    }
+
+    impl HyperLogLog {
+        /// Bits of the hash of an item selecting its register.
+        pub const PRECISION: u32 = 10;
+
+        /// Count of registers of a non-empty sketch.
+        pub const REGISTERS: usize = 1 << Self::PRECISION;
+
+        /// Adds the item to the sketch. Inserting the same item again doesn't
+        /// change the estimate. Items are hashed with SHA-256, so sketches
+        /// persisted with the event log stay valid across Rust releases.
+        pub fn insert<T: Hash + ?Sized>(&mut self, item: &T) {
+            let mut hasher = StableHasher(Sha256::new());
+            item.hash(&mut hasher);
+            let hash = hasher.finish();
+
+            if self.registers.is_empty() {
+                self.registers = vec![0; Self::REGISTERS];
+            }
+            let index = (hash >> (u64::BITS - Self::PRECISION)) as usize;
+            // the guard bit caps the rank once all remaining bits are zero
+            let rank = ((hash << Self::PRECISION) | (1 << (Self::PRECISION - 1))).leading_zeros() + 1;
+            self.registers[index] = self.registers[index].max(rank as u8);
+        }
+
+        /// Returns the estimated count of distinct inserted items.
+        pub fn estimate(&self) -> u64 {
+            if self.registers.is_empty() {
+                return 0;
+            }
+
+            let registers = Self::REGISTERS as f64;
+            let alpha = 0.7213 / (1.0 + 1.079 / registers);
+            let sum: f64 = self.registers.iter().map(|rank| (-f64::from(*rank)).exp2()).sum();
+            let estimate = alpha * registers * registers / sum;
+
+            // small counts are estimated more precisely from empty registers
+            let empty = self.registers.iter().filter(|rank| **rank == 0).count();
+            if estimate <= 2.5 * registers && empty > 0 {
+                return (registers * (registers / empty as f64).ln()).round() as u64;
+            }
+            estimate.round() as u64
+        }
+
+        /// Adds all items of the other sketch to this one.
+        pub fn merge(&mut self, other: &Self) {
+            if other.registers.is_empty() {
+                return;
+            }
+            if self.registers.is_empty() {
+                self.registers = other.registers.clone();
+                return;
+            }
+            for (rank, other) in self.registers.iter_mut().zip(&other.registers) {
+                *rank = (*rank).max(*other);
+            }
+        }
+
+        /// Removes all items from the sketch.
+        pub fn clear(&mut self) {
+            self.registers = Vec::new();
+        }
+    }
+
+    /// Hasher feeding SHA-256, with integers written little-endian and sizes
+    /// widened to 64 bits, so hashes are the same on every platform.
+    struct StableHasher(Sha256);
+
+    impl Hasher for StableHasher {
+        fn finish(&self) -> u64 {
+            let digest = self.0.clone().finalize();
+            u64::from_be_bytes(digest[..8].try_into().expect("SHA-256 digests have 32 bytes"))
+        }
+
+        fn write(&mut self, bytes: &[u8]) {
+            self.0.update(bytes);
+        }
+
+        fn write_u16(&mut self, value: u16) {
+            self.write(&value.to_le_bytes());
+        }
+
+        fn write_u32(&mut self, value: u32) {
+            self.write(&value.to_le_bytes());
+        }
+
+        fn write_u64(&mut self, value: u64) {
+            self.write(&value.to_le_bytes());
+        }
+
+        fn write_u128(&mut self, value: u128) {
+            self.write(&value.to_le_bytes());
+        }
+
+        fn write_usize(&mut self, value: usize) {
+            self.write_u64(value as u64);
+        }
+    }
+
+    /// Class of the device of a visitor, see [`parse_user_agent`].
+    #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+    #[cfg_attr(feature = "event-log", derive(serde::Serialize, serde::Deserialize))]
+    pub enum DeviceClass {
+        /// A desktop or a laptop.
+        Desktop,
+
+        /// A phone.
+        Mobile,
+
+        /// A tablet.
+        Tablet,
+
+        /// A bot, see [`BotClassifier`].
+        Bot,
+
+        /// A device which can't be told from the user agent.
+        Unknown,
+    }
+
+    /// Family of the browser of a visitor, see [`parse_user_agent`].
+    #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+    #[cfg_attr(feature = "event-log", derive(serde::Serialize, serde::Deserialize))]
+    pub enum BrowserFamily {
+        /// Google Chrome and other Chromium-based browsers not listed here.
+        Chrome,
+
+        /// Mozilla Firefox.
+        Firefox,
+
+        /// Apple Safari.
+        Safari,
+
+        /// Microsoft Edge.
+        Edge,
+
+        /// Opera.
+        Opera,
+
+        /// Any other client.
+        Other,
+    }
+
+    /// Returns the class of the device and the family of the browser
+    /// telling them by well-known fragments of the user agent.
+    pub fn parse_user_agent(user_agent: &str) -> (DeviceClass, BrowserFamily) {
+        let user_agent = user_agent.to_ascii_lowercase();
+        let has = |fragments: &[&str]| fragments.iter().any(|fragment| user_agent.contains(fragment));
+
+        let device = if has(&["ipad", "tablet"]) || (has(&["android"]) && !has(&["mobile"])) {
+            DeviceClass::Tablet
+        } else if has(&["mobi", "iphone", "ipod", "android"]) {
+            DeviceClass::Mobile
+        } else if has(&["windows", "macintosh", "x11", "linux", "cros"]) {
+            DeviceClass::Desktop
+        } else {
+            DeviceClass::Unknown
+        };
+        // Chromium-based browsers mention Chrome and Safari too, so they are checked first
+        let browser = if has(&["edg/", "edga/", "edgios/"]) {
+            BrowserFamily::Edge
+        } else if has(&["opr/", "opera"]) {
+            BrowserFamily::Opera
+        } else if has(&["firefox/", "fxios/"]) {
+            BrowserFamily::Firefox
+        } else if has(&["chrome/", "crios/", "chromium/"]) {
+            BrowserFamily::Chrome
+        } else if has(&["safari/"]) {
+            BrowserFamily::Safari
+        } else {
+            BrowserFamily::Other
+        };
+        (device, browser)
+    }
+
+    /// Part of redirects made with an item, e.g. a [`DeviceClass`].
+    #[derive(Clone, Debug, PartialEq)]
+    pub struct Share<T> {
+        /// The item.
+        pub item: T,
+
+        /// Count of redirects made with the item.
+        pub redirects: u64,
+
+        /// Part of all counted redirects made with the item, from `0` to `100`.
+        pub percent: f64,
+    }
+
+    impl<T: Clone + Ord> Share<T> {
+        /// Returns shares of the counted items, most frequent first.
+        pub fn of<'a>(counts: impl IntoIterator<Item = (&'a T, &'a u64)>) -> Vec<Self> where T: 'a {
+            let mut shares: Vec<_> = counts.into_iter()
+                .map(|(item, redirects)| Self { item: item.clone(), redirects: *redirects, percent: 0.0 })
+                .collect();
+            let total: u64 = shares.iter().map(|share| share.redirects).sum();
+            for share in &mut shares {
+                share.percent = share.redirects as f64 * 100.0 / total as f64;
+            }
+            shares.sort_by(|a, b| b.redirects.cmp(&a.redirects).then_with(|| a.item.cmp(&b.item)));
+            shares
+        }
+    }
+
+    /// Length of the period counted by a [`RedirectBucket`].
+    #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+    pub enum Resolution {
+        /// A minute, kept for a day.
+        Minute,
+
+        /// An hour, kept for 30 days.
+        Hour,
+
+        /// A day, kept forever.
+        Day,
+    }
+
+    impl Resolution {
+        /// Returns the length of the period.
+        pub fn duration(self) -> TimeDelta {
+            match self {
+                Resolution::Minute => TimeDelta::minutes(1),
+                Resolution::Hour => TimeDelta::hours(1),
+                Resolution::Day => TimeDelta::days(1),
+            }
+        }
+
+        /// Returns how long buckets with the resolution are kept before they
+        /// are rolled up into coarser ones, `None` if they are kept forever.
+        pub fn retention(self) -> Option<TimeDelta> {
+            match self {
+                Resolution::Minute => Some(TimeDelta::hours(24)),
+                Resolution::Hour => Some(TimeDelta::days(30)),
+                Resolution::Day => None,
+            }
+        }
+
+        /// Returns the start of the period the time falls into, in UTC.
+        pub fn truncate(self, time: DateTime<Utc>) -> DateTime<Utc> {
+            let seconds = self.duration().num_seconds();
+            DateTime::from_timestamp(time.timestamp().div_euclid(seconds) * seconds, 0).unwrap_or(time)
+        }
+    }
+
+    /// Count of redirects in a period of time.
+    #[derive(Clone, Copy, Debug, PartialEq)]
+    pub struct RedirectBucket {
+        /// Start of the period.
+        pub start: DateTime<Utc>,
+
+        /// Length of the period.
+        pub resolution: Resolution,
+
+        /// Count of redirects in the period.
+        pub redirects: u64,
+    }
+
+    /// Counts of redirects over time, rolled up into coarser buckets as they
+    /// age, see [`Resolution::retention`]. Periods of different resolutions
+    /// never overlap.
+    #[derive(Clone, Debug, Default, PartialEq)]
+    pub struct RedirectSeries {
+        minutes: BTreeMap<DateTime<Utc>, u64>,
+        hours: BTreeMap<DateTime<Utc>, u64>,
+        days: BTreeMap<DateTime<Utc>, u64>,
+    }
+
+    impl RedirectSeries {
+        /// Counts redirects made at the time.
+        pub fn add(&mut self, at: DateTime<Utc>, redirects: u64) {
+            *self.minutes.entry(Resolution::Minute.truncate(at)).or_default() += redirects;
+        }
+
+        /// Adds all redirects of the other series to this one.
+        pub fn merge(&mut self, other: &Self) {
+            let tiers = [
+                (&mut self.minutes, &other.minutes),
+                (&mut self.hours, &other.hours),
+                (&mut self.days, &other.days),
+            ];
+            for (buckets, other) in tiers {
+                for (start, redirects) in other {
+                    *buckets.entry(*start).or_default() += redirects;
+                }
+            }
+        }
+
+        /// Rolls buckets older than their retention at the time up into
+        /// coarser ones. Cutoffs are aligned to the coarser resolution, so
+        /// rolled up periods are always whole.
+        pub fn roll_up(&mut self, now: DateTime<Utc>) {
+            Self::roll(&mut self.minutes, &mut self.hours, Resolution::Minute, Resolution::Hour, now);
+            Self::roll(&mut self.hours, &mut self.days, Resolution::Hour, Resolution::Day, now);
+        }
+
+        fn roll(
+            fine: &mut BTreeMap<DateTime<Utc>, u64>,
+            coarse: &mut BTreeMap<DateTime<Utc>, u64>,
+            fine_resolution: Resolution,
+            coarse_resolution: Resolution,
+            now: DateTime<Utc>,
+        ) {
+            let Some(retention) = fine_resolution.retention() else {
+                return;
+            };
+            let kept = fine.split_off(&coarse_resolution.truncate(now - retention));
+            for (start, redirects) in std::mem::replace(fine, kept) {
+                *coarse.entry(coarse_resolution.truncate(start)).or_default() += redirects;
+            }
+        }
+
+        /// Returns buckets overlapping the range from `from` up to `to`,
+        /// ordered by their start.
+        pub fn range(&self, from: DateTime<Utc>, to: DateTime<Utc>) -> Vec<RedirectBucket> {
+            let tiers = [
+                (Resolution::Day, &self.days),
+                (Resolution::Hour, &self.hours),
+                (Resolution::Minute, &self.minutes),
+            ];
+            tiers.into_iter()
+                .flat_map(|(resolution, buckets)| {
+                    buckets.range(resolution.truncate(from)..)
+                        .take_while(move |(start, _)| **start < to)
+                        .map(move |(start, redirects)| RedirectBucket {
+                            start: *start,
+                            resolution,
+                            redirects: *redirects,
+                        })
+                })
+                .collect()
+        }
+    }
+
+    /// Thresholds of suspicious traffic flagged by
+    /// [`UrlShortenerService::handle_detect_anomalies`].
+    ///
+    /// [`UrlShortenerService::handle_detect_anomalies`]: super::UrlShortenerService::handle_detect_anomalies
+    #[derive(Clone, Copy, Debug, PartialEq)]
+    pub struct AnomalyRules {
+        /// Length of the window of recent traffic which is checked.
+        pub window: TimeDelta,
+
+        /// Number of windows before the recent one whose average redirects
+        /// are the baseline of a spike.
+        pub baseline_windows: u32,
+
+        /// Recent redirects spike if they exceed the baseline this many times.
+        pub spike_factor: f64,
+
+        /// Links with less recent traffic are never flagged, so a handful of
+        /// clicks of a quiet link isn't an anomaly.
+        pub min_redirects: u64,
+
+        /// Traffic is dominated by one visitor if this share of recent
+        /// redirects, deduplicated ones included, comes from them.
+        pub max_visitor_share: f64,
+    }
+
+    impl Default for AnomalyRules {
+        fn default() -> Self {
+            Self {
+                window: TimeDelta::hours(1),
+                baseline_windows: 24,
+                spike_factor: 10.0,
+                min_redirects: 50,
+                max_visitor_share: 0.8,
+            }
+        }
+    }
+
+    /// Suspicious traffic of a link, e.g. click fraud or a bot gone wild.
+    #[derive(Clone, Debug, PartialEq)]
+    #[cfg_attr(feature = "event-log", derive(serde::Serialize, serde::Deserialize))]
+    pub enum Anomaly {
+        /// Redirects in the recent window spiked over the baseline.
+        Spike {
+            /// Redirects in the recent window.
+            redirects: u64,
+
+            /// Average redirects per window before it.
+            baseline: f64,
+        },
+
+        /// Most of the recent traffic came from a single visitor.
+        DominantVisitor {
+            /// The visitor, or the fingerprint of their requests, see
+            /// [`fingerprint`].
+            visitor: VisitorId,
+
+            /// Share of the recent traffic coming from the visitor.
+            share: f64,
+        },
+    }
+}
+
+/// Export of stats in CSV format for spreadsheets.
+pub mod export {
+    use std::io::{self, Write};
+
+    use chrono::{DateTime, Utc};
+
+    use super::Tag;
+
+    /// Columns of exported rows. Rows of links leave columns of clicks empty
+    /// and the other way around.
+    pub const HEADER: &[&str] = &[
+        "record", "slug", "url", "redirects", "human_redirects", "unique_visitors",
+        "at", "visitor", "referrer", "user_agent", "country", "bot",
+    ];
+
+    /// Links and clicks to export. By default stats of all live links are
+    /// exported without clicks.
+    #[derive(Clone, Debug, Default, PartialEq)]
+    pub struct ExportFilter {
+        /// Export only links with the tag.
+        pub tag: Option<Tag>,
+
+        /// Export archived links too.
+        pub include_archived: bool,
+
+        /// Export raw clicks of exported links after their stats, one row per
+        /// redirect still in the event log.
+        pub include_clicks: bool,
+
+        /// Export only clicks made since the time.
+        pub clicks_since: Option<DateTime<Utc>>,
+    }
+
+    /// Writes a row of the fields, quoting the ones with separators, quotes or
+    /// line breaks.
+    pub fn write_row<W: Write, S: AsRef<str>>(writer: &mut W, fields: &[S]) -> io::Result<()> {
+        for (index, field) in fields.iter().enumerate() {
+            if index > 0 {
+                writer.write_all(b",")?;
+            }
+            let field = field.as_ref();
+            if field.contains([',', '"', '\n', '\r']) {
+                write!(writer, "\"{}\"", field.replace('"', "\"\""))?;
+            } else {
+                writer.write_all(field.as_bytes())?;
+            }
+        }
+        writer.write_all(b"\r\n")
+    }
+}
+
+/// Event log of the [`UrlShortenerService`] persisted to JSON Lines files,
+/// one [`EventRecord`](events::EventRecord) per line, so the state of the
+/// service outlives its process, see [`UrlShortenerService::restore`].
+///
+/// Every line carries the SHA-256 checksum of its record chained with the
+/// checksum of the line before it, so edited, reordered or dropped lines are
+/// detected when the log is read.
+#[cfg(feature = "event-log")]
+pub mod event_log {
+    use std::{
+        fmt,
+        fs::{self, File, OpenOptions},
+        io::{self, BufRead, BufReader, BufWriter, Seek, SeekFrom, Write},
+        path::{Path, PathBuf},
+    };
+
+    use serde::Deserialize;
+    use serde_json::value::RawValue;
+    use sha2::{Digest, Sha256};
+
+    use super::events::EventRecord;
+
+    #[derive(Deserialize)]
+    struct Line<'a> {
+        checksum: String,
+        #[serde(borrow)]
+        record: &'a RawValue,
+    }
+
+    /// Failure to read or write an event log.
+    #[derive(Debug)]
+    pub enum EventLogError {
+        /// The log can't be read or written.
+        Io(io::Error),
+
+        /// The line at the (1-based) number isn't a valid record.
+        Malformed { line: usize, message: String },
+
+        /// The checksum of the line at the number doesn't match its record
+        /// and the lines before it.
+        ChecksumMismatch { line: usize },
+
+        /// The record at the line number doesn't follow the record before it.
+        OutOfOrder { line: usize, sequence: u64 },
+    }
+
+    impl fmt::Display for EventLogError {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            match self {
+                Self::Io(error) => write!(f, "{error}"),
+                Self::Malformed { line, message } => write!(f, "line {line} is not a valid record: {message}"),
+                Self::ChecksumMismatch { line } => write!(f, "checksum of line {line} doesn't match"),
+                Self::OutOfOrder { line, sequence } => write!(f, "event {sequence} at line {line} is out of order"),
+            }
+        }
+    }
+
+    impl std::error::Error for EventLogError {}
+
+    impl From<io::Error> for EventLogError {
+        fn from(error: io::Error) -> Self {
+            Self::Io(error)
+        }
+    }
+
+    /// Computes the checksum of the serialized record following a line with
+    /// the checksum, the first line follows an empty checksum.
+    fn checksum(previous: &str, record: &str) -> String {
+        let digest = Sha256::new().chain_update(previous.as_bytes()).chain_update(record.as_bytes()).finalize();
+        digest.iter().map(|byte| format!("{byte:02x}")).collect()
+    }
+
+    /// Checker of lines of a log, read in order.
+    #[derive(Debug, Default)]
+    struct LineChecker {
+        // checksum of the previous line
+        previous: String,
+        // sequence of the previous valid record
+        last_sequence: Option<u64>,
+    }
+
+    impl LineChecker {
+        /// Checks the line, returning its record. The checksum of the line is
+        /// what the next line must follow, even if it doesn't match, so an
+        /// edited line doesn't fail the lines after it.
+        fn check(&mut self, line: usize, text: &str) -> Result<EventRecord, EventLogError> {
+            let malformed = |error: serde_json::Error| EventLogError::Malformed { line, message: error.to_string() };
+            let parsed: Line = serde_json::from_str(text).map_err(malformed)?;
+            let expected = checksum(&self.previous, parsed.record.get());
+            self.previous = parsed.checksum;
+            if expected != self.previous {
+                return Err(EventLogError::ChecksumMismatch { line });
+            }
+
+            let record: EventRecord = serde_json::from_str(parsed.record.get()).map_err(malformed)?;
+            if self.last_sequence.is_some_and(|last| last >= record.sequence) {
+                return Err(EventLogError::OutOfOrder { line, sequence: record.sequence });
+            }
+            self.last_sequence = Some(record.sequence);
+            Ok(record)
+        }
+    }
+
+    /// Reads records of the log in order, verifying their checksums and
+    /// sequences. Returns the records along with the checksum of the last
+    /// line, which lines appended to the log must follow.
+    ///
+    /// ## Errors
+    ///
+    /// The first [`EventLogError`] found in the log.
+    pub fn read_records<R: BufRead>(reader: R) -> Result<(Vec<EventRecord>, String), EventLogError> {
+        let mut records = Vec::new();
+        let mut checker = LineChecker::default();
+        for (index, text) in reader.lines().enumerate() {
+            let text = text?;
+            if !text.trim().is_empty() {
+                records.push(checker.check(index + 1, &text)?);
+            }
+        }
+        Ok((records, checker.previous))
+    }
+
+    /// Outcome of [`verify`].
+    #[derive(Debug, Default)]
+    pub struct Verification {
+        /// Number of valid records.
+        pub records: usize,
+
+        /// Problems found in the log, in order of their lines.
+        pub problems: Vec<EventLogError>,
+    }
+
+    impl Verification {
+        /// Checks if no problem was found.
+        pub fn passed(&self) -> bool {
+            self.problems.is_empty()
+        }
+    }
+
+    /// Checks checksums and sequences of all lines of the log, unlike
+    /// [`read_records`] which stops at the first problem. Lines after an
+    /// edited one are checked against its checksum, so only the edited line
+    /// is reported.
+    ///
+    /// ## Errors
+    ///
+    /// Errors of the reader.
+    pub fn verify<R: BufRead>(reader: R) -> io::Result<Verification> {
+        let mut verification = Verification::default();
+        let mut checker = LineChecker::default();
+        for (index, text) in reader.lines().enumerate() {
+            let text = text?;
+            if text.trim().is_empty() {
+                continue;
+            }
+            match checker.check(index + 1, &text) {
+                Ok(_) => verification.records += 1,
+                Err(problem) => verification.problems.push(problem),
+            }
+        }
+        Ok(verification)
+    }
+
+    /// Writes the records as lines following a line with the checksum, an
+    /// empty one for a new log. Returns the checksum of the last written
+    /// line.
+    ///
+    /// ## Errors
+    ///
+    /// Errors of the writer.
+    pub fn write_records<'a, W: Write>(
+        mut writer: W,
+        previous: &str,
+        records: impl IntoIterator<Item = &'a EventRecord>,
+    ) -> Result<String, EventLogError> {
+        let mut previous = previous.to_owned();
+        for record in records {
+            let record = serde_json::to_string(record).map_err(io::Error::from)?;
+            previous = checksum(&previous, &record);
+            writeln!(writer, r#"{{"checksum":"{previous}","record":{record}}}"#)?;
+        }
+        writer.flush()?;
+        Ok(previous)
+    }
+
+    /// Event log kept in a file, appended to as the service records events.
+    /// Only one process may append to the file at a time, others may follow
+    /// it with [`Self::read_appended`].
+    #[derive(Debug)]
+    pub struct EventLogFile {
+        path: PathBuf,
+        // checker of lines read or written so far
+        checker: LineChecker,
+        // count of lines read or written so far
+        lines: usize,
+        // length of the part of the file read or written so far
+        offset: u64,
+    }
+
+    impl EventLogFile {
+        /// Opens the log at the path, returning it along with its records. A
+        /// missing file is an empty log, the file is created once records are
+        /// appended to it.
+        ///
+        /// ## Errors
+        ///
+        /// The first [`EventLogError`] found in the log.
+        pub fn open(path: impl Into<PathBuf>) -> Result<(Self, Vec<EventRecord>), EventLogError> {
+            let mut log = Self { path: path.into(), checker: LineChecker::default(), lines: 0, offset: 0 };
+            let records = log.read_appended()?;
+            Ok((log, records))
+        }
+
+        /// Returns the path of the file.
+        pub fn path(&self) -> &Path {
+            &self.path
+        }
+
+        /// Reads records appended to the file since it was last read or
+        /// written, e.g. by another process. A line which isn't complete yet
+        /// is left for the next read.
+        ///
+        /// ## Errors
+        ///
+        /// The first [`EventLogError`] found in the appended lines.
+        pub fn read_appended(&mut self) -> Result<Vec<EventRecord>, EventLogError> {
+            let mut file = match File::open(&self.path) {
+                Ok(file) => file,
+                Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+                Err(error) => return Err(error.into()),
+            };
+            file.seek(SeekFrom::Start(self.offset))?;
+
+            let mut reader = BufReader::new(file);
+            let mut records = Vec::new();
+            let mut text = String::new();
+            loop {
+                text.clear();
+                let read = reader.read_line(&mut text)?;
+                if !text.ends_with('\n') {
+                    break;
+                }
+                self.offset += read as u64;
+                self.lines += 1;
+                if !text.trim().is_empty() {
+                    records.push(self.checker.check(self.lines, text.trim_end())?);
+                }
+            }
+            Ok(records)
+        }
+
+        /// Appends records recorded after the last record of the file, the
+        /// others are skipped, so all events of a service can be passed.
+        /// Returns the count of appended records.
+        ///
+        /// ## Errors
+        ///
+        /// Errors of writing the file.
+        pub fn append(&mut self, records: &[EventRecord]) -> Result<usize, EventLogError> {
+            let last_sequence = self.checker.last_sequence.unwrap_or(0);
+            let start = records.partition_point(|record| record.sequence <= last_sequence);
+            let records = &records[start..];
+            let Some(last) = records.last() else {
+                return Ok(0);
+            };
+
+            let file = OpenOptions::new().create(true).append(true).open(&self.path)?;
+            self.checker.previous = write_records(BufWriter::new(&file), &self.checker.previous, records)?;
+            self.checker.last_sequence = Some(last.sequence);
+            self.lines += records.len();
+            self.offset = file.metadata()?.len();
+            Ok(records.len())
+        }
+
+        /// Replaces the content of the file with the records, which must be
+        /// all records of the log, e.g. once raw redirects were dropped by
+        /// [`UrlShortenerService::handle_prune_clicks`] so they don't stay on
+        /// disk. The records are written to a temporary file next to the log,
+        /// which then takes its place, so the log is never left half written.
+        /// Processes following the log must open it again.
+        ///
+        /// [`UrlShortenerService::handle_prune_clicks`]: super::UrlShortenerService::handle_prune_clicks
+        ///
+        /// ## Errors
+        ///
+        /// Errors of writing the files.
+        pub fn rewrite(&mut self, records: &[EventRecord]) -> Result<(), EventLogError> {
+            let mut temporary = self.path.clone().into_os_string();
+            temporary.push(".tmp");
+            let file = File::create(&temporary)?;
+            let previous = write_records(BufWriter::new(&file), "", records)?;
+            file.sync_all()?;
+            fs::rename(&temporary, &self.path)?;
+
+            self.checker = LineChecker { previous, last_sequence: records.last().map(|record| record.sequence) };
+            self.lines = records.len();
+            self.offset = file.metadata()?.len();
+            Ok(())
+        }
+    }
+}
+
+/// Periodic digests of redirects and new links handed to pluggable sinks.
+pub mod digests {
+    use std::{
+        fmt,
+        fs::OpenOptions,
+        io::Write,
+        path::PathBuf,
+    };
+
+    use chrono::{DateTime, TimeDelta, Utc};
+
+    use super::{
+        webhooks::{json_string, sign, WebhookDelivery, WebhookSender},
+        ShortLink, Slug, Url,
+    };
+
+    /// Period covered by a digest.
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+    #[cfg_attr(feature = "event-log", derive(serde::Serialize, serde::Deserialize))]
+    pub enum DigestPeriod {
+        /// The last 24 hours.
+        Daily,
+
+        /// The last 7 days.
+        Weekly,
+    }
+
+    impl DigestPeriod {
+        /// Returns the length of the period.
+        pub fn duration(self) -> TimeDelta {
+            match self {
+                DigestPeriod::Daily => TimeDelta::days(1),
+                DigestPeriod::Weekly => TimeDelta::weeks(1),
+            }
+        }
+    }
+
+    /// Summary of the service over a period of time.
+    #[derive(Clone, Debug, PartialEq)]
+    pub struct StatsDigest {
+        /// Period covered by the digest.
+        pub period: DigestPeriod,
+
+        /// Start of the period.
+        pub from: DateTime<Utc>,
+
+        /// End of the period, exclusive.
+        pub to: DateTime<Utc>,
+
+        /// Redirects of all links in the period.
+        pub total_redirects: u64,
+
+        /// Links with the most redirects in the period, most followed first,
+        /// at most [`Self::TOP_LINKS`].
+        pub top_links: Vec<(Slug, u64)>,
+
+        /// Links created in the period which still exist, in order of
+        /// creation.
+        pub new_links: Vec<ShortLink>,
+    }
+
+    impl StatsDigest {
+        /// Maximum number of links in [`Self::top_links`].
+        pub const TOP_LINKS: usize = 10;
+
+        /// Returns the digest as a JSON object.
+        pub fn to_json(&self) -> String {
+            let period = match self.period {
+                DigestPeriod::Daily => "daily",
+                DigestPeriod::Weekly => "weekly",
+            };
+            let top_links: Vec<_> = self.top_links.iter()
+                .map(|(slug, redirects)| format!(r#"{{"slug":{},"redirects":{redirects}}}"#, json_string(&slug.0)))
+                .collect();
+            let new_links: Vec<_> = self.new_links.iter()
+                .map(|link| format!(r#"{{"slug":{},"url":{}}}"#, json_string(&link.slug.0), json_string(&link.url.0)))
+                .collect();
+            format!(
+                concat!(
+                    r#"{{"event":"digest","period":"{}","from":{},"to":{},"total_redirects":{},"#,
+                    r#""top_links":[{}],"new_links":[{}]}}"#,
+                ),
+                period,
+                json_string(&self.from.to_rfc3339()),
+                json_string(&self.to.to_rfc3339()),
+                self.total_redirects,
+                top_links.join(","),
+                new_links.join(","),
+            )
+        }
+    }
+
+    /// Plain text report, e.g. for the body of an email.
+    impl fmt::Display for StatsDigest {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            writeln!(f, "{:?} digest from {} to {}", self.period, self.from, self.to)?;
+            writeln!(f, "Total redirects: {}", self.total_redirects)?;
+            writeln!(f, "Top links:")?;
+            for (slug, redirects) in &self.top_links {
+                writeln!(f, "  {} - {redirects}", slug.0)?;
+            }
+            writeln!(f, "New links:")?;
+            for link in &self.new_links {
+                writeln!(f, "  {} -> {}", link.slug.0, link.url.0)?;
+            }
+            Ok(())
+        }
+    }
+
+    /// Destination of digests, e.g. an email hook, a webhook or a file.
+    pub trait DigestSink {
+        /// Delivers the digest, returning why it failed if it did. It is
+        /// called while the digest command is handled, so it shouldn't block
+        /// for long.
+        fn deliver(&self, digest: &StatsDigest) -> Result<(), String>;
+    }
+
+    impl<F: Fn(&StatsDigest) -> Result<(), String>> DigestSink for F {
+        fn deliver(&self, digest: &StatsDigest) -> Result<(), String> {
+            self(digest)
+        }
+    }
+
+    /// Sink appending the plain text report of every digest to a file.
+    #[derive(Clone, Debug, PartialEq)]
+    pub struct FileDigestSink {
+        /// File the reports are appended to, created if it doesn't exist.
+        pub path: PathBuf,
+    }
+
+    impl DigestSink for FileDigestSink {
+        fn deliver(&self, digest: &StatsDigest) -> Result<(), String> {
+            let mut file = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&self.path)
+                .map_err(|error| error.to_string())?;
+            writeln!(file, "{digest}").map_err(|error| error.to_string())
+        }
+    }
+
+    /// Sink POSTing the JSON of every digest signed like per-link webhooks,
+    /// see [`SIGNATURE_HEADER`].
+    ///
+    /// [`SIGNATURE_HEADER`]: super::webhooks::SIGNATURE_HEADER
+    pub struct WebhookDigestSink<S> {
+        /// URL receiving the digests.
+        pub url: Url,
+
+        /// Secret signing delivered digests.
+        pub secret: String,
+
+        /// Transport of the deliveries.
+        pub sender: S,
+    }
+
+    impl<S: WebhookSender> DigestSink for WebhookDigestSink<S> {
+        fn deliver(&self, digest: &StatsDigest) -> Result<(), String> {
+            let body = digest.to_json();
+            let signature = format!("sha256={}", sign(&self.secret, &body));
+            self.sender.send(WebhookDelivery { url: self.url.clone(), body, signature });
+            Ok(())
+        }
+    }
+}
+
+/// Token-bucket rate limiting of link creation and redirects, so a single
+/// client can't exhaust the service. Clients are told apart by their IP and
+/// API key, a request is limited by the buckets of both.
+pub mod rate_limits {
+    use std::{collections::HashMap, net::IpAddr};
+
+    use chrono::{DateTime, TimeDelta, Utc};
+
+    use super::dispatch::TenantId;
+
+    /// Number of buckets above which full buckets are dropped, as they are
+    /// the same as new ones.
+    const PRUNE_THRESHOLD: usize = 10_000;
+
+    /// Operation limited by [`RateLimits`].
+    #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+    pub enum LimitedOperation {
+        /// Creation of links.
+        CreateLink,
+
+        /// Redirects by slugs.
+        Redirect,
+    }
+
+    /// Key of the token bucket of a client.
+    #[derive(Clone, Debug, PartialEq, Eq, Hash)]
+    pub enum ClientKey {
+        /// Address the request came from.
+        Ip(IpAddr),
+
+        /// API key the request was made with.
+        ApiKey(String),
+    }
+
+    /// Allowance of a token bucket: bursts of up to `capacity` requests,
+    /// refilled evenly at `capacity` requests per `period`.
+    #[derive(Clone, Copy, Debug, PartialEq)]
+    pub struct RateLimit {
+        /// Most requests allowed at once.
+        pub capacity: u32,
+
+        /// Time taken to refill an empty bucket.
+        pub period: TimeDelta,
+    }
+
+    impl RateLimit {
+        /// Allows `count` requests per minute.
+        pub fn per_minute(count: u32) -> Self {
+            Self { capacity: count, period: TimeDelta::minutes(1) }
+        }
+
+        /// Returns tokens added to a bucket per millisecond.
+        fn rate(self) -> f64 {
+            f64::from(self.capacity) / self.period.num_milliseconds().max(1) as f64
+        }
+    }
+
+    /// Rate limits by operation, operations without one are unlimited.
+    #[derive(Clone, Debug, Default, PartialEq)]
+    pub struct RateLimits {
+        /// Limit of link creation.
+        pub create_link: Option<RateLimit>,
+
+        /// Limit of redirects.
+        pub redirect: Option<RateLimit>,
+    }
+
+    impl RateLimits {
+        /// Returns the limit of the operation, if it is limited.
+        pub fn limit(&self, operation: LimitedOperation) -> Option<RateLimit> {
+            match operation {
+                LimitedOperation::CreateLink => self.create_link,
+                LimitedOperation::Redirect => self.redirect,
+            }
+        }
+    }
+
+    #[derive(Clone, Copy, Debug)]
+    struct TokenBucket {
+        tokens: f64,
+        updated_at: DateTime<Utc>,
+        full_at: DateTime<Utc>,
+    }
+
+    impl TokenBucket {
+        fn refill(&mut self, limit: RateLimit, now: DateTime<Utc>) {
+            let elapsed = (now - self.updated_at).num_milliseconds().max(0) as f64;
+            self.tokens = (self.tokens + elapsed * limit.rate()).min(f64::from(limit.capacity));
+            self.updated_at = now;
+        }
+    }
+
+    /// Token buckets of clients, kept in memory only.
+    #[derive(Debug, Default)]
+    pub struct RateLimiter {
+        buckets: HashMap<(Option<TenantId>, LimitedOperation, ClientKey), TokenBucket>,
+    }
+
+    impl RateLimiter {
+        /// Takes a token from the bucket of every client key at the time.
+        /// Nothing is taken unless all buckets have a token.
+        ///
+        /// ## Errors
+        ///
+        /// Returns how long to wait for a token of every bucket if any is
+        /// empty.
+        pub fn take(
+            &mut self,
+            tenant: Option<&TenantId>,
+            operation: LimitedOperation,
+            clients: &[ClientKey],
+            limit: RateLimit,
+            now: DateTime<Utc>,
+        ) -> Result<(), TimeDelta> {
+            if self.buckets.len() > PRUNE_THRESHOLD {
+                self.buckets.retain(|_, bucket| bucket.full_at > now);
+            }
+
+            let full = TokenBucket { tokens: f64::from(limit.capacity), updated_at: now, full_at: now };
+            let mut wait = 0.0_f64;
+            for client in clients {
+                let bucket = self.buckets
+                    .entry((tenant.cloned(), operation, client.clone()))
+                    .or_insert(full);
+                bucket.refill(limit, now);
+                wait = wait.max((1.0 - bucket.tokens) / limit.rate());
+            }
+            if wait > 0.0 {
+                return Err(TimeDelta::milliseconds(wait.ceil() as i64));
+            }
+
+            for client in clients {
+                if let Some(bucket) = self.buckets.get_mut(&(tenant.cloned(), operation, client.clone())) {
+                    bucket.tokens -= 1.0;
+                    let missing = f64::from(limit.capacity) - bucket.tokens;
+                    bucket.full_at = now + TimeDelta::milliseconds((missing / limit.rate()).ceil() as i64);
+                }
+            }
+            Ok(())
+        }
+    }
+}
+
+/// Metrics of the service, recorded through [`MetricsRecorder`] so the core
+/// doesn't depend on a metrics backend.
+pub mod metrics {
+    use std::{
+        collections::BTreeMap,
+        fmt::Write,
+        sync::{Arc, Mutex, PoisonError},
+    };
+
+    /// Labels of a metric, as names and values.
+    pub type Labels<'a> = &'a [(&'static str, &'a str)];
+
+    /// Backend receiving metrics of the service.
+    pub trait MetricsRecorder {
+        /// Adds the value to the counter.
+        fn increment_counter(&self, name: &'static str, labels: Labels<'_>, value: u64);
+
+        /// Sets the gauge to the value.
+        fn set_gauge(&self, name: &'static str, labels: Labels<'_>, value: f64);
+
+        /// Records an observation of the histogram.
+        fn observe_histogram(&self, name: &'static str, labels: Labels<'_>, value: f64);
+    }
+
+    /// Recorder dropping all metrics, used by default.
+    #[derive(Clone, Copy, Debug, Default)]
+    pub struct NoMetrics;
+
+    impl MetricsRecorder for NoMetrics {
+        fn increment_counter(&self, _name: &'static str, _labels: Labels<'_>, _value: u64) {}
+
+        fn set_gauge(&self, _name: &'static str, _labels: Labels<'_>, _value: f64) {}
+
+        fn observe_histogram(&self, _name: &'static str, _labels: Labels<'_>, _value: f64) {}
+    }
+
+    /// Upper bounds of buckets of histograms of [`PrometheusRecorder`], in
+    /// seconds.
+    pub const BUCKETS: &[f64] = &[0.0001, 0.0005, 0.001, 0.005, 0.01, 0.05, 0.1, 0.5, 1.0, 5.0];
+
+    // metrics by their names and labels
+    type Series = BTreeMap<(&'static str, Vec<(&'static str, String)>), Metric>;
+
+    #[derive(Clone, Debug, PartialEq)]
+    enum Metric {
+        Counter(u64),
+        Gauge(f64),
+        // counts of observations by bucket, not cumulative, the last one is `+Inf`
+        Histogram { buckets: Vec<u64>, sum: f64, count: u64 },
+    }
+
+    /// Recorder keeping metrics in memory and rendering them in the
+    /// Prometheus text format. Clones share the metrics, so one clone can be
+    /// given to the service and another one to the server scraping it.
+    #[derive(Clone, Debug, Default)]
+    pub struct PrometheusRecorder {
+        metrics: Arc<Mutex<Series>>,
+    }
+
+    impl PartialEq for PrometheusRecorder {
+        fn eq(&self, other: &Self) -> bool {
+            Arc::ptr_eq(&self.metrics, &other.metrics)
+        }
+    }
+
+    impl PrometheusRecorder {
+        fn update(&self, name: &'static str, labels: Labels<'_>, default: Metric, update: impl FnOnce(&mut Metric)) {
+            let labels = labels.iter().map(|(label, value)| (*label, (*value).to_owned())).collect();
+            let mut metrics = self.metrics.lock().unwrap_or_else(PoisonError::into_inner);
+            update(metrics.entry((name, labels)).or_insert(default));
+        }
+
+        /// Renders all metrics recorded so far in the Prometheus text format.
+        pub fn render(&self) -> String {
+            let metrics = self.metrics.lock().unwrap_or_else(PoisonError::into_inner);
+            let mut text = String::new();
+            let mut previous = None;
+            for ((name, labels), metric) in metrics.iter() {
+                if previous != Some(*name) {
+                    let kind = match metric {
+                        Metric::Counter(_) => "counter",
+                        Metric::Gauge(_) => "gauge",
+                        Metric::Histogram { .. } => "histogram",
+                    };
+                    let _ = writeln!(text, "# TYPE {name} {kind}");
+                    previous = Some(*name);
+                }
+                match metric {
+                    Metric::Counter(value) => {
+                        let _ = writeln!(text, "{name}{} {value}", render_labels(labels, None));
+                    },
+                    Metric::Gauge(value) => {
+                        let _ = writeln!(text, "{name}{} {value}", render_labels(labels, None));
+                    },
+                    Metric::Histogram { buckets, sum, count } => {
+                        let mut cumulative = 0;
+                        for (index, observations) in buckets.iter().enumerate() {
+                            cumulative += observations;
+                            let le = BUCKETS.get(index).map_or_else(|| String::from("+Inf"), f64::to_string);
+                            let labels = render_labels(labels, Some(&le));
+                            let _ = writeln!(text, "{name}_bucket{labels} {cumulative}");
+                        }
+                        let _ = writeln!(text, "{name}_sum{} {sum}", render_labels(labels, None));
+                        let _ = writeln!(text, "{name}_count{} {count}", render_labels(labels, None));
+                    },
+                }
+            }
+            text
+        }
+    }
+
+    impl MetricsRecorder for PrometheusRecorder {
+        fn increment_counter(&self, name: &'static str, labels: Labels<'_>, value: u64) {
+            self.update(name, labels, Metric::Counter(0), |metric| {
+                if let Metric::Counter(counter) = metric {
+                    *counter += value;
+                }
+            });
+        }
+
+        fn set_gauge(&self, name: &'static str, labels: Labels<'_>, value: f64) {
+            self.update(name, labels, Metric::Gauge(0.0), |metric| *metric = Metric::Gauge(value));
+        }
+
+        fn observe_histogram(&self, name: &'static str, labels: Labels<'_>, value: f64) {
+            let default = Metric::Histogram { buckets: vec![0; BUCKETS.len() + 1], sum: 0.0, count: 0 };
+            self.update(name, labels, default, |metric| {
+                if let Metric::Histogram { buckets, sum, count } = metric {
+                    let bucket = BUCKETS.iter().position(|bound| value <= *bound).unwrap_or(BUCKETS.len());
+                    buckets[bucket] += 1;
+                    *sum += value;
+                    *count += 1;
+                }
+            });
+        }
+    }
+
+    fn render_labels(labels: &[(&'static str, String)], le: Option<&str>) -> String {
+        let labels: Vec<_> = labels
+            .iter()
+            .map(|(label, value)| (*label, value.as_str()))
+            .chain(le.map(|le| ("le", le)))
+            .map(|(label, value)| format!("{label}=\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\"")))
+            .collect();
+        if labels.is_empty() {
+            String::new()
+        } else {
+            format!("{{{}}}", labels.join(","))
+        }
+    }
+}
+
+/// Dispatching of commands and queries on behalf of callers.
+pub mod dispatch {
+    use chrono::{DateTime, TimeDelta, Utc};
+
+    use super::{
+        analytics::{CountryCode, RedirectBucket},
+        digests::{DigestPeriod, StatsDigest},
+        events::{EventRecord, VersionedEvent},
+        sync::{Changes, SyncCursor},
+        import::{ImportReport, ImportedLink, ImportedRedirect, LinkImportReport, MergeRules},
+        scheduler::{ScheduleId, ScheduledCommand},
+        urls::QueryParam,
+        webhooks::{EventWebhook, LinkWebhook, TrackedDelivery, WebhookId},
+        ApiKey, ApiKeyId, Campaign, CampaignStats, ConversionId, DeviceStats, Draft, Group, GroupId, LinkId, LinkInfo,
+        LinkMetadata, LinkOptions, OldSlugPolicy, OwnerId, RedirectOutcome, Namespace, RedirectContext, RedirectType,
+        ShortLink, ShortenerError, Slug, Stats, StatsBreakdown, SystemStats, Tag, Url, VisitorId,
+    };
+
+    /// Identity of the caller, e.g. a user or an API client.
+    #[derive(Clone, Debug, PartialEq, Eq, Hash)]
+    #[cfg_attr(feature = "event-log", derive(serde::Serialize, serde::Deserialize))]
+    pub struct ActorId(pub String);
+
+    /// Identity of the tenant the caller acts in.
+    #[derive(Clone, Debug, PartialEq, Eq, Hash)]
+    #[cfg_attr(feature = "event-log", derive(serde::Serialize, serde::Deserialize))]
+    pub struct TenantId(pub String);
+
+    /// Header, or gRPC metadata, of the API key authenticating clients of
+    /// the API layers, see [`UrlShortenerService::authenticate`]. Their
+    /// requests are rate limited by the key besides their address.
+    ///
+    /// [`UrlShortenerService::authenticate`]: super::UrlShortenerService::authenticate
+    pub const API_KEY_HEADER: &str = "x-api-key";
+
+    /// Role of a caller, granted by their API key. Every role may do
+    /// everything the roles before it may.
+    #[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+    #[cfg_attr(feature = "event-log", derive(serde::Serialize, serde::Deserialize))]
+    pub enum Role {
+        /// May follow links and query them with their stats.
+        Viewer,
+
+        /// May also create links and modify the ones they own.
+        Editor,
+
+        /// May also modify and delete any link, maintain the service and
+        /// inspect the event store.
+        Admin,
+    }
+
+    /// Who makes the request. Anonymous requests have neither actor nor
+    /// tenant.
+    #[derive(Clone, Debug, Default, PartialEq)]
+    #[cfg_attr(feature = "event-log", derive(serde::Serialize, serde::Deserialize))]
+    pub struct RequestContext {
+        /// The caller.
+        pub actor: Option<ActorId>,
+
+        /// The tenant the caller acts in.
+        pub tenant: Option<TenantId>,
+
+        /// Role of the caller. Requests without a role are checked by the
+        /// [`AuthorizationPolicy`] and link ownership only.
+        pub role: Option<Role>,
+    }
+
+    /// Commands of the [`UrlShortenerService`], each one maps to its
+    /// `handle_*` method.
+    ///
+    /// [`UrlShortenerService`]: super::UrlShortenerService
+    #[derive(Clone, Debug, PartialEq)]
+    #[cfg_attr(feature = "event-log", derive(serde::Serialize, serde::Deserialize))]
+    pub enum Command {
+        /// See [`CommandHandler::handle_create_short_link`].
+        ///
+        /// [`CommandHandler::handle_create_short_link`]: super::commands::CommandHandler::handle_create_short_link
+        CreateShortLink { url: Url, slug: Option<Slug>, options: LinkOptions },
+
+        /// See [`UrlShortenerService::handle_create_short_links`].
+        ///
+        /// [`UrlShortenerService::handle_create_short_links`]: super::UrlShortenerService::handle_create_short_links
+        CreateShortLinks { links: Vec<(Url, Option<Slug>)> },
+
+        /// See [`UrlShortenerService::handle_create_short_links_atomically`].
+        ///
+        /// [`UrlShortenerService::handle_create_short_links_atomically`]: super::UrlShortenerService::handle_create_short_links_atomically
+        CreateShortLinksAtomically { links: Vec<(Url, Option<Slug>)> },
+
+        /// See [`UrlShortenerService::handle_import_links`].
+        ///
+        /// [`UrlShortenerService::handle_import_links`]: super::UrlShortenerService::handle_import_links
+        ImportLinks { links: Vec<ImportedLink> },
+
+        /// See [`CommandHandler::handle_redirect`] and
+        /// [`UrlShortenerService::handle_redirect_from`].
+        ///
+        /// [`CommandHandler::handle_redirect`]: super::commands::CommandHandler::handle_redirect
+        /// [`UrlShortenerService::handle_redirect_from`]: super::UrlShortenerService::handle_redirect_from
+        Redirect { slug: Slug, visitor: Option<VisitorId> },
+
+        /// See [`UrlShortenerService::handle_redirect_with_password`].
+        ///
+        /// [`UrlShortenerService::handle_redirect_with_password`]: super::UrlShortenerService::handle_redirect_with_password
+        RedirectWithPassword { slug: Slug, password: String, visitor: Option<VisitorId> },
+
+        /// See [`UrlShortenerService::handle_redirect_with_context`].
+        ///
+        /// [`UrlShortenerService::handle_redirect_with_context`]: super::UrlShortenerService::handle_redirect_with_context
+        RedirectWithContext { slug: Slug, context: RedirectContext },
+
+        /// See [`UrlShortenerService::handle_redirect_with_outcome`].
+        ///
+        /// [`UrlShortenerService::handle_redirect_with_outcome`]: super::UrlShortenerService::handle_redirect_with_outcome
+        RedirectWithOutcome { slug: Slug, password: Option<String>, visitor: Option<VisitorId> },
+
+        /// See [`UrlShortenerService::handle_reserve_slug`].
+        ///
+        /// [`UrlShortenerService::handle_reserve_slug`]: super::UrlShortenerService::handle_reserve_slug
+        ReserveSlug { slug: Slug, timeout: Option<TimeDelta> },
+
+        /// See [`UrlShortenerService::handle_attach_url`].
+        ///
+        /// [`UrlShortenerService::handle_attach_url`]: super::UrlShortenerService::handle_attach_url
+        AttachUrl { slug: Slug, url: Url },
+
+        /// See [`UrlShortenerService::handle_prepare_link`].
+        ///
+        /// [`UrlShortenerService::handle_prepare_link`]: super::UrlShortenerService::handle_prepare_link
+        PrepareLink { url: Url, slug: Option<Slug>, options: LinkOptions, timeout: TimeDelta },
+
+        /// See [`UrlShortenerService::handle_activate_link`].
+        ///
+        /// [`UrlShortenerService::handle_activate_link`]: super::UrlShortenerService::handle_activate_link
+        ActivateLink { slug: Slug },
+
+        /// See [`UrlShortenerService::handle_expire_reservations`].
+        ///
+        /// [`UrlShortenerService::handle_expire_reservations`]: super::UrlShortenerService::handle_expire_reservations
+        ExpireReservations,
+
+        /// See [`UrlShortenerService::handle_rename_slug`].
+        ///
+        /// [`UrlShortenerService::handle_rename_slug`]: super::UrlShortenerService::handle_rename_slug
+        RenameSlug { old: Slug, new: Slug, old_slug: OldSlugPolicy },
+
+        /// See [`UrlShortenerService::handle_expire_aliases`].
+        ///
+        /// [`UrlShortenerService::handle_expire_aliases`]: super::UrlShortenerService::handle_expire_aliases
+        ExpireAliases,
+
+        /// See [`UrlShortenerService::handle_archive_inactive_links`].
+        ///
+        /// [`UrlShortenerService::handle_archive_inactive_links`]: super::UrlShortenerService::handle_archive_inactive_links
+        ArchiveInactiveLinks,
+
+        /// See [`UrlShortenerService::handle_unarchive_link`].
+        ///
+        /// [`UrlShortenerService::handle_unarchive_link`]: super::UrlShortenerService::handle_unarchive_link
+        UnarchiveLink { slug: Slug },
+
+        /// See [`UrlShortenerService::handle_import_redirects`].
+        ///
+        /// [`UrlShortenerService::handle_import_redirects`]: super::UrlShortenerService::handle_import_redirects
+        ImportRedirects { redirects: Vec<ImportedRedirect>, rules: MergeRules },
+
+        /// See [`UrlShortenerService::handle_reset_stats`].
+        ///
+        /// [`UrlShortenerService::handle_reset_stats`]: super::UrlShortenerService::handle_reset_stats
+        ResetStats { slug: Slug },
+
+        /// See [`UrlShortenerService::handle_set_password`].
+        ///
+        /// [`UrlShortenerService::handle_set_password`]: super::UrlShortenerService::handle_set_password
+        SetPassword { slug: Slug, password: String },
+
+        /// See [`UrlShortenerService::handle_remove_password`].
+        ///
+        /// [`UrlShortenerService::handle_remove_password`]: super::UrlShortenerService::handle_remove_password
+        RemovePassword { slug: Slug },
+
+        /// See [`UrlShortenerService::handle_tag_link`].
+        ///
+        /// [`UrlShortenerService::handle_tag_link`]: super::UrlShortenerService::handle_tag_link
+        TagLink { slug: Slug, tags: Vec<Tag> },
+
+        /// See [`UrlShortenerService::handle_untag_link`].
+        ///
+        /// [`UrlShortenerService::handle_untag_link`]: super::UrlShortenerService::handle_untag_link
+        UntagLink { slug: Slug, tags: Vec<Tag> },
+
+        /// See [`UrlShortenerService::handle_update_metadata`].
+        ///
+        /// [`UrlShortenerService::handle_update_metadata`]: super::UrlShortenerService::handle_update_metadata
+        UpdateMetadata { slug: Slug, metadata: LinkMetadata },
+
+        /// See [`UrlShortenerService::handle_add_alias`].
+        ///
+        /// [`UrlShortenerService::handle_add_alias`]: super::UrlShortenerService::handle_add_alias
+        AddAlias { slug: Slug, alias: Slug },
+
+        /// See [`UrlShortenerService::handle_clone_link`].
+        ///
+        /// [`UrlShortenerService::handle_clone_link`]: super::UrlShortenerService::handle_clone_link
+        CloneLink { slug: Slug, new_slug: Slug },
+
+        /// See [`UrlShortenerService::handle_merge_links`].
+        ///
+        /// [`UrlShortenerService::handle_merge_links`]: super::UrlShortenerService::handle_merge_links
+        MergeLinks { slug: Slug, duplicate: Slug },
+
+        /// See [`UrlShortenerService::handle_set_webhook`].
+        ///
+        /// [`UrlShortenerService::handle_set_webhook`]: super::UrlShortenerService::handle_set_webhook
+        SetWebhook { slug: Slug, webhook: LinkWebhook },
+
+        /// See [`UrlShortenerService::handle_remove_webhook`].
+        ///
+        /// [`UrlShortenerService::handle_remove_webhook`]: super::UrlShortenerService::handle_remove_webhook
+        RemoveWebhook { slug: Slug },
+
+        /// See [`UrlShortenerService::handle_set_redirect_type`].
+        ///
+        /// [`UrlShortenerService::handle_set_redirect_type`]: super::UrlShortenerService::handle_set_redirect_type
+        SetRedirectType { slug: Slug, redirect_type: RedirectType },
+
+        /// See [`UrlShortenerService::handle_set_interstitial`].
+        ///
+        /// [`UrlShortenerService::handle_set_interstitial`]: super::UrlShortenerService::handle_set_interstitial
+        SetInterstitial { slug: Slug, enabled: bool },
+
+        /// See [`UrlShortenerService::handle_set_query_params`].
+        ///
+        /// [`UrlShortenerService::handle_set_query_params`]: super::UrlShortenerService::handle_set_query_params
+        SetQueryParams { slug: Slug, params: Vec<QueryParam> },
+
+        /// See [`UrlShortenerService::handle_create_group`].
+        ///
+        /// [`UrlShortenerService::handle_create_group`]: super::UrlShortenerService::handle_create_group
+        CreateGroup { name: String },
+
+        /// See [`UrlShortenerService::handle_move_to_group`].
+        ///
+        /// [`UrlShortenerService::handle_move_to_group`]: super::UrlShortenerService::handle_move_to_group
+        MoveToGroup { slug: Slug, group: Option<GroupId> },
+
+        /// See [`UrlShortenerService::handle_conversion`].
+        ///
+        /// [`UrlShortenerService::handle_conversion`]: super::UrlShortenerService::handle_conversion
+        RecordConversion { slug: Slug, conversion_id: ConversionId },
+
+        /// See [`UrlShortenerService::handle_assign_campaign`].
+        ///
+        /// [`UrlShortenerService::handle_assign_campaign`]: super::UrlShortenerService::handle_assign_campaign
+        AssignCampaign { slug: Slug, campaign: Option<Campaign> },
+
+        /// See [`UrlShortenerService::handle_transfer_ownership`].
+        ///
+        /// [`UrlShortenerService::handle_transfer_ownership`]: super::UrlShortenerService::handle_transfer_ownership
+        TransferOwnership { slug: Slug, new_owner: OwnerId },
+
+        /// See [`UrlShortenerService::handle_disable_link`].
+        ///
+        /// [`UrlShortenerService::handle_disable_link`]: super::UrlShortenerService::handle_disable_link
+        DisableLink { slug: Slug },
+
+        /// See [`UrlShortenerService::handle_delete_link`].
+        ///
+        /// [`UrlShortenerService::handle_delete_link`]: super::UrlShortenerService::handle_delete_link
+        DeleteLink { slug: Slug },
+
+        /// See [`UrlShortenerService::handle_enable_link`].
+        ///
+        /// [`UrlShortenerService::handle_enable_link`]: super::UrlShortenerService::handle_enable_link
+        EnableLink { slug: Slug },
+
+        /// See [`UrlShortenerService::handle_schedule_command`]. The command
+        /// is executed on behalf of the caller scheduling it.
+        ///
+        /// [`UrlShortenerService::handle_schedule_command`]: super::UrlShortenerService::handle_schedule_command
+        ScheduleCommand { command: Box<Command>, due_at: DateTime<Utc> },
+
+        /// See [`UrlShortenerService::handle_cancel_scheduled_command`].
+        ///
+        /// [`UrlShortenerService::handle_cancel_scheduled_command`]: super::UrlShortenerService::handle_cancel_scheduled_command
+        CancelScheduledCommand { id: ScheduleId },
+
+        /// See [`UrlShortenerService::handle_run_due_commands`].
+        ///
+        /// [`UrlShortenerService::handle_run_due_commands`]: super::UrlShortenerService::handle_run_due_commands
+        RunDueCommands,
+
+        /// See [`UrlShortenerService::handle_create_namespace`].
+        ///
+        /// [`UrlShortenerService::handle_create_namespace`]: super::UrlShortenerService::handle_create_namespace
+        CreateNamespace { name: String },
+
+        /// See [`UrlShortenerService::handle_block_host`].
+        ///
+        /// [`UrlShortenerService::handle_block_host`]: super::UrlShortenerService::handle_block_host
+        BlockHost { host: String },
+
+        /// See [`UrlShortenerService::handle_recheck_destinations`].
+        ///
+        /// [`UrlShortenerService::handle_recheck_destinations`]: super::UrlShortenerService::handle_recheck_destinations
+        RecheckDestinations,
+
+        /// See [`UrlShortenerService::handle_check_destination_health`].
+        ///
+        /// [`UrlShortenerService::handle_check_destination_health`]: super::UrlShortenerService::handle_check_destination_health
+        CheckDestinationHealth,
+
+        /// See [`UrlShortenerService::handle_fetch_previews`].
+        ///
+        /// [`UrlShortenerService::handle_fetch_previews`]: super::UrlShortenerService::handle_fetch_previews
+        FetchPreviews,
+
+        /// See [`UrlShortenerService::handle_set_click_retention`].
+        ///
+        /// [`UrlShortenerService::handle_set_click_retention`]: super::UrlShortenerService::handle_set_click_retention
+        SetClickRetention { slug: Slug, retention: Option<TimeDelta> },
+
+        /// See [`UrlShortenerService::handle_prune_clicks`].
+        ///
+        /// [`UrlShortenerService::handle_prune_clicks`]: super::UrlShortenerService::handle_prune_clicks
+        PruneClicks,
+
+        /// See [`UrlShortenerService::handle_send_digest`].
+        ///
+        /// [`UrlShortenerService::handle_send_digest`]: super::UrlShortenerService::handle_send_digest
+        SendDigest { period: DigestPeriod, repeat: bool },
+
+        /// See [`UrlShortenerService::handle_detect_anomalies`].
+        ///
+        /// [`UrlShortenerService::handle_detect_anomalies`]: super::UrlShortenerService::handle_detect_anomalies
+        DetectAnomalies,
+
+        /// See [`UrlShortenerService::handle_create_api_key`].
+        ///
+        /// [`UrlShortenerService::handle_create_api_key`]: super::UrlShortenerService::handle_create_api_key
+        CreateApiKey { actor: ActorId, role: Role },
+
+        /// See [`UrlShortenerService::handle_revoke_api_key`].
+        ///
+        /// [`UrlShortenerService::handle_revoke_api_key`]: super::UrlShortenerService::handle_revoke_api_key
+        RevokeApiKey { key_id: ApiKeyId },
+
+        /// See [`UrlShortenerService::handle_register_webhook`].
+        ///
+        /// [`UrlShortenerService::handle_register_webhook`]: super::UrlShortenerService::handle_register_webhook
+        RegisterWebhook { webhook: EventWebhook },
+
+        /// See [`UrlShortenerService::handle_unregister_webhook`].
+        ///
+        /// [`UrlShortenerService::handle_unregister_webhook`]: super::UrlShortenerService::handle_unregister_webhook
+        UnregisterWebhook { webhook_id: WebhookId },
+
+        /// See [`UrlShortenerService::handle_deliver_webhooks`].
+        ///
+        /// [`UrlShortenerService::handle_deliver_webhooks`]: super::UrlShortenerService::handle_deliver_webhooks
+        DeliverWebhooks,
+
+        /// See [`UrlShortenerService::rebuild_projection`].
+        ///
+        /// [`UrlShortenerService::rebuild_projection`]: super::UrlShortenerService::rebuild_projection
+        RebuildProjection { name: String },
+
+        /// See [`UrlShortenerService::handle_compact_events`].
+        ///
+        /// [`UrlShortenerService::handle_compact_events`]: super::UrlShortenerService::handle_compact_events
+        CompactEvents,
+    }
+
+    impl Command {
+        /// Returns the name of the command, e.g. to label its metrics.
+        pub fn name(&self) -> &'static str {
+            match self {
+                Command::CreateShortLink { .. } => "create_short_link",
+                Command::CreateShortLinks { .. } => "create_short_links",
+                Command::CreateShortLinksAtomically { .. } => "create_short_links_atomically",
+                Command::ImportLinks { .. } => "import_links",
+                Command::Redirect { .. } => "redirect",
+                Command::RedirectWithPassword { .. } => "redirect_with_password",
+                Command::RedirectWithContext { .. } => "redirect_with_context",
+                Command::RedirectWithOutcome { .. } => "redirect_with_outcome",
+                Command::ReserveSlug { .. } => "reserve_slug",
+                Command::AttachUrl { .. } => "attach_url",
+                Command::PrepareLink { .. } => "prepare_link",
+                Command::ActivateLink { .. } => "activate_link",
+                Command::ExpireReservations => "expire_reservations",
+                Command::RenameSlug { .. } => "rename_slug",
+                Command::ExpireAliases => "expire_aliases",
+                Command::ArchiveInactiveLinks => "archive_inactive_links",
+                Command::UnarchiveLink { .. } => "unarchive_link",
+                Command::ImportRedirects { .. } => "import_redirects",
+                Command::ResetStats { .. } => "reset_stats",
+                Command::SetPassword { .. } => "set_password",
+                Command::RemovePassword { .. } => "remove_password",
+                Command::TagLink { .. } => "tag_link",
+                Command::UntagLink { .. } => "untag_link",
+                Command::UpdateMetadata { .. } => "update_metadata",
+                Command::AddAlias { .. } => "add_alias",
+                Command::CloneLink { .. } => "clone_link",
+                Command::MergeLinks { .. } => "merge_links",
+                Command::SetWebhook { .. } => "set_webhook",
+                Command::RemoveWebhook { .. } => "remove_webhook",
+                Command::SetRedirectType { .. } => "set_redirect_type",
+                Command::SetInterstitial { .. } => "set_interstitial",
+                Command::SetQueryParams { .. } => "set_query_params",
+                Command::CreateGroup { .. } => "create_group",
+                Command::MoveToGroup { .. } => "move_to_group",
+                Command::RecordConversion { .. } => "record_conversion",
+                Command::AssignCampaign { .. } => "assign_campaign",
+                Command::TransferOwnership { .. } => "transfer_ownership",
+                Command::DisableLink { .. } => "disable_link",
+                Command::DeleteLink { .. } => "delete_link",
+                Command::EnableLink { .. } => "enable_link",
+                Command::ScheduleCommand { .. } => "schedule_command",
+                Command::CancelScheduledCommand { .. } => "cancel_scheduled_command",
+                Command::RunDueCommands => "run_due_commands",
+                Command::CreateNamespace { .. } => "create_namespace",
+                Command::BlockHost { .. } => "block_host",
+                Command::RecheckDestinations => "recheck_destinations",
+                Command::CheckDestinationHealth => "check_destination_health",
+                Command::FetchPreviews => "fetch_previews",
+                Command::SetClickRetention { .. } => "set_click_retention",
+                Command::PruneClicks => "prune_clicks",
+                Command::SendDigest { .. } => "send_digest",
+                Command::DetectAnomalies => "detect_anomalies",
+                Command::CreateApiKey { .. } => "create_api_key",
+                Command::RevokeApiKey { .. } => "revoke_api_key",
+                Command::RegisterWebhook { .. } => "register_webhook",
+                Command::UnregisterWebhook { .. } => "unregister_webhook",
+                Command::DeliverWebhooks => "deliver_webhooks",
+                Command::RebuildProjection { .. } => "rebuild_projection",
+                Command::CompactEvents => "compact_events",
+            }
+        }
+
+        /// Returns the least [`Role`] allowed to dispatch the command.
+        /// Editors are allowed to modify only links they own.
+        pub fn role(&self) -> Role {
+            match self {
+                Command::Redirect { .. }
+                | Command::RedirectWithPassword { .. }
+                | Command::RedirectWithContext { .. }
+                | Command::RedirectWithOutcome { .. }
+                | Command::RecordConversion { .. } => Role::Viewer,
+                Command::ExpireReservations
+                | Command::ExpireAliases
+                | Command::ArchiveInactiveLinks
+                | Command::ImportRedirects { .. }
+                | Command::BlockHost { .. }
+                | Command::RecheckDestinations
+                | Command::CheckDestinationHealth
+                | Command::FetchPreviews
+                | Command::PruneClicks
+                | Command::SendDigest { .. }
+                | Command::DetectAnomalies
+                | Command::CreateApiKey { .. }
+                | Command::RevokeApiKey { .. }
+                | Command::RegisterWebhook { .. }
+                | Command::UnregisterWebhook { .. }
+                | Command::DeliverWebhooks
+                | Command::RebuildProjection { .. }
+                | Command::CompactEvents
+                | Command::CancelScheduledCommand { .. }
+                | Command::RunDueCommands => Role::Admin,
+                Command::ScheduleCommand { command, .. } => command.role(),
+                Command::CreateShortLink { .. }
+                | Command::CreateShortLinks { .. }
+                | Command::CreateShortLinksAtomically { .. }
+                | Command::ImportLinks { .. }
+                | Command::PrepareLink { .. }
+                | Command::ReserveSlug { .. }
+                | Command::AttachUrl { .. }
+                | Command::ActivateLink { .. }
+                | Command::ResetStats { .. }
+                | Command::SetPassword { .. }
+                | Command::RemovePassword { .. }
+                | Command::TagLink { .. }
+                | Command::UntagLink { .. }
+                | Command::UpdateMetadata { .. }
+                | Command::AddAlias { .. }
+                | Command::CloneLink { .. }
+                | Command::MergeLinks { .. }
+                | Command::SetWebhook { .. }
+                | Command::RemoveWebhook { .. }
+                | Command::SetRedirectType { .. }
+                | Command::SetInterstitial { .. }
+                | Command::SetQueryParams { .. }
+                | Command::MoveToGroup { .. }
+                | Command::AssignCampaign { .. }
+                | Command::SetClickRetention { .. }
+                | Command::TransferOwnership { .. }
+                | Command::DisableLink { .. }
+                | Command::DeleteLink { .. }
+                | Command::EnableLink { .. }
+                | Command::UnarchiveLink { .. }
+                | Command::RenameSlug { .. }
+                | Command::CreateGroup { .. }
+                | Command::CreateNamespace { .. } => Role::Editor,
+            }
+        }
+
+        /// Returns the [`Slug`] of the link the command targets.
+        pub fn target(&self) -> Option<&Slug> {
+            match self {
+                Command::CreateShortLink { slug, .. } | Command::PrepareLink { slug, .. } => slug.as_ref(),
+                Command::Redirect { slug, .. }
+                | Command::RedirectWithPassword { slug, .. }
+                | Command::RedirectWithContext { slug, .. }
+                | Command::RedirectWithOutcome { slug, .. }
+                | Command::RecordConversion { slug, .. }
+                | Command::ReserveSlug { slug, .. }
+                | Command::AttachUrl { slug, .. }
+                | Command::ActivateLink { slug }
+                | Command::ResetStats { slug }
+                | Command::SetPassword { slug, .. }
+                | Command::RemovePassword { slug }
+                | Command::TagLink { slug, .. }
+                | Command::UntagLink { slug, .. }
+                | Command::UpdateMetadata { slug, .. }
+                | Command::AddAlias { slug, .. }
+                | Command::CloneLink { slug, .. }
+                | Command::MergeLinks { slug, .. }
+                | Command::SetWebhook { slug, .. }
+                | Command::RemoveWebhook { slug }
+                | Command::SetRedirectType { slug, .. }
+                | Command::SetInterstitial { slug, .. }
+                | Command::SetQueryParams { slug, .. }
+                | Command::MoveToGroup { slug, .. }
+                | Command::AssignCampaign { slug, .. }
+                | Command::SetClickRetention { slug, .. }
+                | Command::TransferOwnership { slug, .. }
+                | Command::DisableLink { slug }
+                | Command::DeleteLink { slug }
+                | Command::EnableLink { slug }
+                | Command::UnarchiveLink { slug } => Some(slug),
+                Command::RenameSlug { old, .. } => Some(old),
+                Command::ScheduleCommand { command, .. } => command.target(),
+                Command::CreateShortLinks { .. }
+                | Command::CreateShortLinksAtomically { .. }
+                | Command::ImportLinks { .. }
+                | Command::ExpireReservations
+                | Command::ExpireAliases
+                | Command::ArchiveInactiveLinks
+                | Command::ImportRedirects { .. }
+                | Command::CreateGroup { .. }
+                | Command::CreateNamespace { .. }
+                | Command::BlockHost { .. }
+                | Command::RecheckDestinations
+                | Command::CheckDestinationHealth
+                | Command::FetchPreviews
+                | Command::PruneClicks
+                | Command::SendDigest { .. }
+                | Command::DetectAnomalies
+                | Command::CreateApiKey { .. }
+                | Command::RevokeApiKey { .. }
+                | Command::RegisterWebhook { .. }
+                | Command::UnregisterWebhook { .. }
+                | Command::DeliverWebhooks
+                | Command::RebuildProjection { .. }
+                | Command::CompactEvents
+                | Command::CancelScheduledCommand { .. }
+                | Command::RunDueCommands => None,
+            }
+        }
+
+        /// Checks if the command modifies the existing link it targets or
+        /// completes the reservation of its slug, so it is allowed only to the
+        /// owner of the link or the reservation.
+        pub fn modifies_link(&self) -> bool {
+            match self {
+                Command::RenameSlug { .. }
+                | Command::AttachUrl { .. }
+                | Command::ActivateLink { .. }
+                | Command::ResetStats { .. }
+                | Command::SetPassword { .. }
+                | Command::RemovePassword { .. }
+                | Command::TagLink { .. }
+                | Command::UntagLink { .. }
+                | Command::UpdateMetadata { .. }
+                | Command::AddAlias { .. }
+                | Command::MergeLinks { .. }
+                | Command::SetWebhook { .. }
+                | Command::RemoveWebhook { .. }
+                | Command::SetRedirectType { .. }
+                | Command::SetInterstitial { .. }
+                | Command::SetQueryParams { .. }
+                | Command::MoveToGroup { .. }
+                | Command::AssignCampaign { .. }
+                | Command::SetClickRetention { .. }
+                | Command::TransferOwnership { .. }
+                | Command::DisableLink { .. }
+                | Command::DeleteLink { .. }
+                | Command::EnableLink { .. }
+                | Command::UnarchiveLink { .. } => true,
+                Command::ScheduleCommand { command, .. } => command.modifies_link(),
+                Command::CreateShortLink { .. }
+                | Command::CreateShortLinks { .. }
+                | Command::CreateShortLinksAtomically { .. }
+                | Command::ImportLinks { .. }
+                | Command::Redirect { .. }
+                | Command::RedirectWithPassword { .. }
+                | Command::RedirectWithContext { .. }
+                | Command::RedirectWithOutcome { .. }
+                | Command::RecordConversion { .. }
+                | Command::ReserveSlug { .. }
+                | Command::PrepareLink { .. }
+                | Command::CloneLink { .. }
+                | Command::ExpireReservations
+                | Command::ExpireAliases
+                | Command::ArchiveInactiveLinks
+                | Command::ImportRedirects { .. }
+                | Command::CreateGroup { .. }
+                | Command::CreateNamespace { .. }
+                | Command::BlockHost { .. }
+                | Command::RecheckDestinations
+                | Command::CheckDestinationHealth
+                | Command::FetchPreviews
+                | Command::PruneClicks
+                | Command::SendDigest { .. }
+                | Command::DetectAnomalies
+                | Command::CreateApiKey { .. }
+                | Command::RevokeApiKey { .. }
+                | Command::RegisterWebhook { .. }
+                | Command::UnregisterWebhook { .. }
+                | Command::DeliverWebhooks
+                | Command::RebuildProjection { .. }
+                | Command::CompactEvents
+                | Command::CancelScheduledCommand { .. }
+                | Command::RunDueCommands => false,
+            }
+        }
+    }
+
+    /// Queries of the [`UrlShortenerService`], each one maps to its `get_*`
+    /// method.
+    ///
+    /// [`UrlShortenerService`]: super::UrlShortenerService
+    #[derive(Clone, Debug, PartialEq)]
+    pub enum Query {
+        /// See [`QueryHandler::get_stats`].
+        ///
+        /// [`QueryHandler::get_stats`]: super::queries::QueryHandler::get_stats
+        GetStats { slug: Slug },
+
+        /// See [`UrlShortenerService::get_link_id`].
+        ///
+        /// [`UrlShortenerService::get_link_id`]: super::UrlShortenerService::get_link_id
+        GetLinkId { slug: Slug },
+
+        /// See [`UrlShortenerService::get_link`].
+        ///
+        /// [`UrlShortenerService::get_link`]: super::UrlShortenerService::get_link
+        GetLink { slug: Slug },
+
+        /// See [`UrlShortenerService::get_stats_breakdown`].
+        ///
+        /// [`UrlShortenerService::get_stats_breakdown`]: super::UrlShortenerService::get_stats_breakdown
+        GetStatsBreakdown { slug: Slug },
+
+        /// See [`UrlShortenerService::get_clicks_by_country`].
+        ///
+        /// [`UrlShortenerService::get_clicks_by_country`]: super::UrlShortenerService::get_clicks_by_country
+        GetClicksByCountry { slug: Slug },
+
+        /// See [`UrlShortenerService::get_device_stats`].
+        ///
+        /// [`UrlShortenerService::get_device_stats`]: super::UrlShortenerService::get_device_stats
+        GetDeviceStats { slug: Slug },
+
+        /// See [`UrlShortenerService::get_redirects_over_time`].
+        ///
+        /// [`UrlShortenerService::get_redirects_over_time`]: super::UrlShortenerService::get_redirects_over_time
+        GetRedirectsOverTime { slug: Slug, from: DateTime<Utc>, to: DateTime<Utc> },
+
+        /// See [`UrlShortenerService::get_history`].
+        ///
+        /// [`UrlShortenerService::get_history`]: super::UrlShortenerService::get_history
+        GetHistory { slug: Slug },
+
+        /// See [`UrlShortenerService::get_events_for`].
+        ///
+        /// [`UrlShortenerService::get_events_for`]: super::UrlShortenerService::get_events_for
+        GetEventsFor { slug: Slug, from_version: u64 },
+
+        /// See [`UrlShortenerService::list_events`].
+        ///
+        /// [`UrlShortenerService::list_events`]: super::UrlShortenerService::list_events
+        ListEvents { after: u64, limit: usize },
+
+        /// See [`UrlShortenerService::get_changes_since`].
+        ///
+        /// [`UrlShortenerService::get_changes_since`]: super::UrlShortenerService::get_changes_since
+        GetChangesSince { cursor: SyncCursor },
+
+        /// See [`UrlShortenerService::list_links_by_tag`].
+        ///
+        /// [`UrlShortenerService::list_links_by_tag`]: super::UrlShortenerService::list_links_by_tag
+        ListLinksByTag { tag: Tag },
+
+        /// See [`UrlShortenerService::list_links`].
+        ///
+        /// [`UrlShortenerService::list_links`]: super::UrlShortenerService::list_links
+        ListLinks,
+
+        /// See [`UrlShortenerService::search_links`].
+        ///
+        /// [`UrlShortenerService::search_links`]: super::UrlShortenerService::search_links
+        SearchLinks { text: String },
+
+        /// See [`UrlShortenerService::list_archived_links`].
+        ///
+        /// [`UrlShortenerService::list_archived_links`]: super::UrlShortenerService::list_archived_links
+        ListArchivedLinks,
+
+        /// See [`UrlShortenerService::list_groups`].
+        ///
+        /// [`UrlShortenerService::list_groups`]: super::UrlShortenerService::list_groups
+        ListGroups,
+
+        /// See [`UrlShortenerService::list_links_in_group`].
+        ///
+        /// [`UrlShortenerService::list_links_in_group`]: super::UrlShortenerService::list_links_in_group
+        ListLinksInGroup { group: GroupId },
+
+        /// See [`UrlShortenerService::list_scheduled_commands`].
+        ///
+        /// [`UrlShortenerService::list_scheduled_commands`]: super::UrlShortenerService::list_scheduled_commands
+        ListScheduledCommands,
+
+        /// See [`UrlShortenerService::list_slug_suggestions`].
+        ///
+        /// [`UrlShortenerService::list_slug_suggestions`]: super::UrlShortenerService::list_slug_suggestions
+        ListSlugSuggestions { slug: Slug, count: usize },
+
+        /// See [`UrlShortenerService::list_namespaces`].
+        ///
+        /// [`UrlShortenerService::list_namespaces`]: super::UrlShortenerService::list_namespaces
+        ListNamespaces,
+
+        /// See [`UrlShortenerService::list_links_in_namespace`].
+        ///
+        /// [`UrlShortenerService::list_links_in_namespace`]: super::UrlShortenerService::list_links_in_namespace
+        ListLinksInNamespace { namespace: String },
+
+        /// See [`UrlShortenerService::get_system_stats`].
+        ///
+        /// [`UrlShortenerService::get_system_stats`]: super::UrlShortenerService::get_system_stats
+        GetSystemStats,
+
+        /// See [`UrlShortenerService::list_broken_links`].
+        ///
+        /// [`UrlShortenerService::list_broken_links`]: super::UrlShortenerService::list_broken_links
+        ListBrokenLinks,
+
+        /// See [`UrlShortenerService::list_flagged_links`].
+        ///
+        /// [`UrlShortenerService::list_flagged_links`]: super::UrlShortenerService::list_flagged_links
+        ListFlaggedLinks,
+
+        /// See [`UrlShortenerService::get_campaign_stats`].
+        ///
+        /// [`UrlShortenerService::get_campaign_stats`]: super::UrlShortenerService::get_campaign_stats
+        GetCampaignStats { campaign: Campaign },
+
+        /// See [`UrlShortenerService::get_webhook_deliveries`].
+        ///
+        /// [`UrlShortenerService::get_webhook_deliveries`]: super::UrlShortenerService::get_webhook_deliveries
+        GetWebhookDeliveries { webhook_id: WebhookId },
+    }
+
+    impl Query {
+        /// Returns the least [`Role`] allowed to dispatch the query.
+        pub fn role(&self) -> Role {
+            match self {
+                Query::GetHistory { .. }
+                | Query::GetEventsFor { .. }
+                | Query::ListEvents { .. }
+                | Query::GetChangesSince { .. }
+                | Query::ListScheduledCommands
+                | Query::GetSystemStats
+                | Query::GetWebhookDeliveries { .. } => Role::Admin,
+                Query::GetStats { .. }
+                | Query::GetLinkId { .. }
+                | Query::GetLink { .. }
+                | Query::GetStatsBreakdown { .. }
+                | Query::GetClicksByCountry { .. }
+                | Query::GetDeviceStats { .. }
+                | Query::GetRedirectsOverTime { .. }
+                | Query::ListLinksByTag { .. }
+                | Query::ListLinks
+                | Query::SearchLinks { .. }
+                | Query::ListArchivedLinks
+                | Query::ListGroups
+                | Query::ListLinksInGroup { .. }
+                | Query::ListSlugSuggestions { .. }
+                | Query::ListNamespaces
+                | Query::ListLinksInNamespace { .. }
+                | Query::ListBrokenLinks
+                | Query::ListFlaggedLinks
+                | Query::GetCampaignStats { .. } => Role::Viewer,
+            }
+        }
+
+        /// Returns the [`Slug`] of the link the query targets.
+        pub fn target(&self) -> Option<&Slug> {
+            match self {
+                Query::GetStats { slug }
+                | Query::GetLinkId { slug }
+                | Query::GetLink { slug }
+                | Query::GetStatsBreakdown { slug }
+                | Query::GetClicksByCountry { slug }
+                | Query::GetDeviceStats { slug }
+                | Query::GetRedirectsOverTime { slug, .. }
+                | Query::GetHistory { slug }
+                | Query::GetEventsFor { slug, .. } => Some(slug),
+                Query::ListEvents { .. }
+                | Query::GetChangesSince { .. }
+                | Query::ListLinksByTag { .. }
+                | Query::ListLinks
+                | Query::SearchLinks { .. }
+                | Query::ListArchivedLinks
+                | Query::ListGroups
+                | Query::ListLinksInGroup { .. }
+                | Query::ListScheduledCommands
+                | Query::ListSlugSuggestions { .. }
+                | Query::ListNamespaces
+                | Query::ListLinksInNamespace { .. }
+                | Query::GetSystemStats
+                | Query::ListBrokenLinks
+                | Query::ListFlaggedLinks
+                | Query::GetCampaignStats { .. }
+                | Query::GetWebhookDeliveries { .. } => None,
+            }
+        }
+    }
+
+    /// Result of a dispatched [`Command`] or [`Query`].
+    #[derive(Clone, Debug, PartialEq)]
+    pub enum Reply {
+        /// The command was handled and has no result.
+        Done,
+
+        /// A single link.
+        Link(ShortLink),
+
+        /// Outcomes of a batch of links.
+        Links(Vec<Result<ShortLink, ShortenerError>>),
+
+        /// Stats of a link.
+        Stats(Stats),
+
+        /// Identity of a link.
+        LinkId(LinkId),
+
+        /// Changes of links since a cursor.
+        Changes(Changes),
+
+        /// Draft link prepared for review.
+        Draft(Draft),
+
+        /// Stats of a link by its slugs.
+        StatsBreakdown(StatsBreakdown),
+
+        /// Current state of a link.
+        LinkInfo(Box<LinkInfo>),
+
+        /// Current state of links.
+        LinkInfos(Vec<LinkInfo>),
+
+        /// Recorded events.
+        Events(Vec<EventRecord>),
+
+        /// Recorded events of a link with their versions.
+        VersionedEvents(Vec<VersionedEvent>),
+
+        /// Identity of a scheduled command.
+        ScheduleId(ScheduleId),
+
+        /// Pending scheduled commands.
+        ScheduledCommands(Vec<ScheduledCommand>),
+
+        /// Outcome of an import.
+        ImportReport(ImportReport),
+
+        /// Outcome of an import of links.
+        LinkImportReport(LinkImportReport),
+
+        /// Outcome of a redirect.
+        RedirectOutcome(RedirectOutcome),
+
+        /// Identity of a group.
+        GroupId(GroupId),
+
+        /// Groups of links.
+        Groups(Vec<Group>),
+
+        /// Suggested or affected slugs.
+        Slugs(Vec<Slug>),
+
+        /// Namespaces of slugs.
+        Namespaces(Vec<Namespace>),
+
+        /// Overall stats of the service.
+        SystemStats(SystemStats),
+
+        /// Redirects of a link by countries.
+        CountryClicks(Vec<(CountryCode, u64)>),
+
+        /// Redirects of a link over time.
+        RedirectBuckets(Vec<RedirectBucket>),
+
+        /// Redirects of a link by devices and browsers.
+        DeviceStats(DeviceStats),
+
+        /// Stats of a campaign.
+        CampaignStats(CampaignStats),
+
+        /// Digest of the service over a period.
+        Digest(StatsDigest),
+
+        /// A new API key.
+        ApiKey(ApiKey),
+
+        /// Identity of a webhook of domain events.
+        WebhookId(WebhookId),
+
+        /// Deliveries to webhooks of domain events.
+        WebhookDeliveries(Vec<TrackedDelivery>),
+
+        /// Count of events dropped by compaction.
+        CompactedEvents(usize),
+    }
+
+    /// Operation an [`AuthorizationPolicy`] decides on.
+    #[derive(Clone, Copy, Debug, PartialEq)]
+    pub enum Operation<'a> {
+        /// A command is going to be handled.
+        Command(&'a Command),
+
+        /// A query is going to be answered.
+        Query(&'a Query),
+    }
+
+    /// Everything an [`AuthorizationPolicy`] knows about the request.
+    #[derive(Clone, Copy, Debug)]
+    pub struct AccessRequest<'a> {
+        /// Who makes the request.
+        pub context: &'a RequestContext,
+
+        /// What is requested.
+        pub operation: Operation<'a>,
+
+        /// [`Slug`] of the link the operation targets, if any.
+        pub target: Option<&'a Slug>,
+    }
+
+    /// Policy deciding whether a request is allowed. It is consulted for
+    /// every dispatched command and query, so deployments can plug in their
+    /// own access rules.
+    pub trait AuthorizationPolicy {
+        /// Returns `true` if the request is allowed.
+        fn authorize(&self, request: &AccessRequest<'_>) -> bool;
+    }
+
+    /// Policy allowing everything, used by default.
+    #[derive(Clone, Copy, Debug, Default)]
+    pub struct AllowAll;
+
+    impl AuthorizationPolicy for AllowAll {
+        fn authorize(&self, _request: &AccessRequest<'_>) -> bool {
+            true
+        }
+    }
+}
+
+/// Client of a remote [`UrlShortenerService`] over its HTTP API.
+///
+/// [`RemoteShortener`] implements the same [`CommandHandler`] and
+/// [`QueryHandler`] traits as the embedded service, so applications can switch
+/// between them with a type parameter.
+///
+/// [`UrlShortenerService`]: super::UrlShortenerService
+/// [`CommandHandler`]: super::commands::CommandHandler
+/// [`QueryHandler`]: super::queries::QueryHandler
+#[cfg(feature = "client")]
+pub mod client {
+    use reqwest::{blocking::{Client, Response}, redirect::Policy, header::LOCATION};
+    use serde::{Deserialize, Serialize};
+
+    use super::{
+        commands::CommandHandler, dispatch::API_KEY_HEADER, errors::ErrorPayload, queries::QueryHandler, LinkId,
+        LinkInfo, ShortLink, ShortenerError, Slug, Stats, StatsBreakdown, Tag, Url,
+    };
+
+    #[derive(Serialize)]
+    struct CreateLinkRequest<'a> {
+        url: &'a str,
+        slug: Option<&'a str>,
+    }
+
+    #[derive(Deserialize)]
+    struct LinkResponse {
+        slug: String,
+        url: String,
+    }
+
+    impl From<LinkResponse> for ShortLink {
+        fn from(link: LinkResponse) -> Self {
+            ShortLink { slug: Slug(link.slug), url: Url(link.url) }
+        }
+    }
+
+    #[derive(Deserialize)]
+    struct LinkInfoResponse {
+        link_id: String,
+        slug: String,
+        url: String,
+        tags: Vec<String>,
+        disabled: bool,
+        archived: bool,
+        redirects: u64,
+    }
+
+    impl From<LinkInfoResponse> for ListedLink {
+        fn from(info: LinkInfoResponse) -> Self {
+            ListedLink {
+                link_id: LinkId(info.link_id),
+                link: ShortLink { slug: Slug(info.slug), url: Url(info.url) },
+                tags: info.tags.into_iter().map(Tag).collect(),
+                disabled: info.disabled,
+                archived: info.archived,
+                redirects: info.redirects,
+            }
+        }
+    }
+
+    #[derive(Deserialize)]
+    struct StatsResponse {
+        link: LinkResponse,
+        redirects: u64,
+        // older services don't tell bots apart, all their redirects are human
+        #[serde(default)]
+        human_redirects: Option<u64>,
+        #[serde(default)]
+        unique_visitors: u64,
+        #[serde(default)]
+        conversions: u64,
+    }
+
+    #[derive(Deserialize)]
+    struct ErrorResponse {
+        code: String,
+        message: String,
+        field: Option<String>,
+        retry_after: Option<u64>,
+    }
+
+    /// Link listed by [`RemoteShortener::list_links`], with the part of its
+    /// [`LinkInfo`] the HTTP API lists.
+    #[derive(Clone, Debug, PartialEq)]
+    pub struct ListedLink {
+        /// Stable identity of the link.
+        pub link_id: LinkId,
+
+        /// The link with its current [`Slug`].
+        pub link: ShortLink,
+
+        /// Tags attached to the link.
+        pub tags: Vec<Tag>,
+
+        /// Whether the link is disabled.
+        pub disabled: bool,
+
+        /// Whether the link was archived for inactivity.
+        pub archived: bool,
+
+        /// Total number of redirects of the link.
+        pub redirects: u64,
+    }
+
+    impl From<LinkInfo> for ListedLink {
+        fn from(info: LinkInfo) -> Self {
+            ListedLink {
+                link_id: info.link_id,
+                link: info.link,
+                tags: info.tags.into_iter().collect(),
+                disabled: info.disabled,
+                archived: info.archived,
+                redirects: info.redirects,
+            }
+        }
+    }
+
+    /// Client of a remote service, implementing [`CommandHandler`] and
+    /// [`QueryHandler`] by calling its HTTP API.
+    pub struct RemoteShortener {
+        base_url: url::Url,
+        http: Client,
+        api_key: Option<String>,
+    }
+
+    impl RemoteShortener {
+        /// Creates a client of the service available at the base URL, e.g.
+        /// `https://sho.rt/`.
+        ///
+        /// ## Errors
+        ///
+        /// [`ShortenerError::InvalidUrl`] if the base URL can't be parsed.
+        pub fn new(base_url: &str) -> Result<Self, ShortenerError> {
+            let base_url = url::Url::parse(base_url).map_err(|_| ShortenerError::InvalidUrl)?;
+            // redirects are answered by the service itself, they must not be followed
+            let http = Client::builder()
+                .redirect(Policy::none())
+                .build()
+                .map_err(|_| ShortenerError::ServiceUnavailable)?;
+            Ok(Self { base_url, http, api_key: None })
+        }
+
+        /// Authenticates requests changing links with the API key.
+        pub fn with_api_key(mut self, api_key: impl Into<String>) -> Self {
+            self.api_key = Some(api_key.into());
+            self
+        }
+
+        fn endpoint(&self, segments: &[&str]) -> url::Url {
+            let mut url = self.base_url.clone();
+            url.path_segments_mut()
+                .expect("base URL is a valid HTTP URL")
+                .pop_if_empty()
+                .extend(segments);
+            url
+        }
+
+        /// Maps an unsuccessful response to the [`ShortenerError`] described
+        /// by its payload.
+        fn error(response: Response) -> ShortenerError {
+            response.json::<ErrorResponse>()
+                .ok()
+                .map(|error| ErrorPayload {
+                    code: error.code,
+                    message: error.message,
+                    field: error.field,
+                    retry_after: error.retry_after,
+                })
+                .and_then(|payload| payload.to_error())
+                .unwrap_or(ShortenerError::ServiceUnavailable)
+        }
+
+        /// Deletes the link, see [`UrlShortenerService::handle_delete_link`].
+        ///
+        /// ## Errors
+        ///
+        /// See [`ShortenerError`].
+        ///
+        /// [`UrlShortenerService::handle_delete_link`]: super::UrlShortenerService::handle_delete_link
+        pub fn delete_link(&self, slug: &Slug) -> Result<(), ShortenerError> {
+            let mut request = self.http.delete(self.endpoint(&["api", "links", &slug.0]));
+            if let Some(api_key) = &self.api_key {
+                request = request.header(API_KEY_HEADER, api_key);
+            }
+            let response = request.send().map_err(|_| ShortenerError::ServiceUnavailable)?;
+            if !response.status().is_success() {
+                return Err(Self::error(response));
+            }
+            Ok(())
+        }
+
+        /// Lists links, only the ones with the tag if given.
+        ///
+        /// ## Errors
+        ///
+        /// See [`ShortenerError`].
+        pub fn list_links(&self, tag: Option<&Tag>) -> Result<Vec<ListedLink>, ShortenerError> {
+            let mut request = self.http.get(self.endpoint(&["api", "links"]));
+            if let Some(tag) = tag {
+                request = request.query(&[("tag", &tag.0)]);
+            }
+            let response = request.send().map_err(|_| ShortenerError::ServiceUnavailable)?;
+            if !response.status().is_success() {
+                return Err(Self::error(response));
+            }
+
+            response.json::<Vec<LinkInfoResponse>>()
+                .map(|links| links.into_iter().map(ListedLink::from).collect())
+                .map_err(|_| ShortenerError::ServiceUnavailable)
+        }
+
+        /// Returns stats of the link with the part of their breakdown the HTTP
+        /// API reports, redirects by slug and refused redirects are left
+        /// empty.
+        ///
+        /// ## Errors
+        ///
+        /// See [`ShortenerError`].
+        pub fn get_stats_breakdown(&self, slug: &Slug) -> Result<StatsBreakdown, ShortenerError> {
+            let response = self.http.get(self.endpoint(&["api", "links", &slug.0, "stats"]))
+                .send()
+                .map_err(|_| ShortenerError::ServiceUnavailable)?;
+            if !response.status().is_success() {
+                return Err(Self::error(response));
+            }
+
+            response.json::<StatsResponse>()
+                .map(|StatsResponse { link, redirects, human_redirects, unique_visitors, conversions }| {
+                    let human_redirects = human_redirects.unwrap_or(redirects);
+                    StatsBreakdown {
+                        stats: Stats { link: link.into(), redirects },
+                        human_redirects,
+                        unique_visitors,
+                        conversions,
+                        by_slug: Vec::new(),
+                        refused: Vec::new(),
+                    }
+                })
+                .map_err(|_| ShortenerError::ServiceUnavailable)
+        }
+    }
+
+    impl CommandHandler for RemoteShortener {
+        fn handle_create_short_link(
+            &mut self,
+            url: Url,
+            slug: Option<Slug>,
+        ) -> Result<ShortLink, ShortenerError> {
+            let request = CreateLinkRequest { url: &url.0, slug: slug.as_ref().map(|slug| slug.0.as_str()) };
+            let mut request = self.http.post(self.endpoint(&["api", "links"])).json(&request);
+            if let Some(api_key) = &self.api_key {
+                request = request.header(API_KEY_HEADER, api_key);
+            }
+            let response = request.send().map_err(|_| ShortenerError::ServiceUnavailable)?;
+            if !response.status().is_success() {
+                return Err(Self::error(response));
+            }
+
+            response.json::<LinkResponse>()
+                .map(ShortLink::from)
+                .map_err(|_| ShortenerError::ServiceUnavailable)
+        }
+
+        fn handle_redirect(
+            &mut self,
+            slug: Slug,
+        ) -> Result<ShortLink, ShortenerError> {
+            let response = self.http.get(self.endpoint(&[&slug.0]))
+                .send()
+                .map_err(|_| ShortenerError::ServiceUnavailable)?;
+            if !response.status().is_redirection() {
+                return Err(Self::error(response));
+            }
+
+            let location = response.headers()
+                .get(LOCATION)
+                .and_then(|location| location.to_str().ok())
+                .ok_or(ShortenerError::ServiceUnavailable)?;
+            Ok(ShortLink { slug, url: Url(location.to_string()) })
+        }
+    }
+
+    impl QueryHandler for RemoteShortener {
+        fn get_stats(&self, slug: Slug) -> Result<Stats, ShortenerError> {
+            self.get_stats_breakdown(&slug).map(|breakdown| breakdown.stats)
+        }
+    }
+}
+
+/// `urlshort` command line tool managing links kept in a local
+/// [event log](event_log), or links of a remote service over its HTTP API
+/// with [`RemoteShortener`](client::RemoteShortener):
+///
+/// ```text
+/// urlshort create https://example.com/some/long/path --slug short
+/// urlshort list --tag promo
+/// urlshort --remote https://sho.rt/ --api-key <key> stats short
+/// urlshort export --clicks --output stats.csv
+/// urlshort import links.csv
+/// urlshort prune-clicks --retention-days 90
+/// urlshort --file imported.jsonl repl
+/// urlshort events cat --kind redirected --slug short --file urlshort.jsonl
+/// urlshort events verify
+/// urlshort doctor
+/// urlshort top
+/// ```
+///
+/// Local links are kept in `urlshort.jsonl` unless another `--file` is
+/// given, and the file is created with the first link. `prune-clicks` drops
+/// old raw redirects of the log, rewriting the file. `repl` keeps the
+/// service of the local event log alive for interactive commands, see
+/// [`REPL_HELP`](cli::REPL_HELP). `events` dumps records of the log, verifies
+/// their checksums, or replays them into a fresh service and prints the
+/// resulting stats. `doctor` verifies the log and runs the
+/// [integrity self-test](UrlShortenerService::check_integrity) of the service
+/// restored from it, failing if any check fails. With the `tui` feature `top`
+/// shows a live dashboard of the log, fed by subscriptions to the service
+/// while other processes append to the log.
+#[cfg(feature = "cli")]
+pub mod cli {
+    use std::{
+        collections::BTreeMap,
+        fmt,
+        fs::File,
+        io::{self, BufRead, BufReader, BufWriter, IsTerminal, Write},
+        path::{Path, PathBuf},
+        process::ExitCode,
+        time::Instant,
+    };
+
+    use chrono::TimeDelta;
+    use clap::{Args, Parser, Subcommand};
+
+    use super::{
+        client::{ListedLink, RemoteShortener},
+        commands::CommandHandler,
+        event_log::{self, EventLogError, EventLogFile},
+        events::EventKind,
+        subscriptions::EventFilter,
+        export::{self, ExportFilter},
+        import::{self, ImportedLink, LinkImportReport},
+        ServiceConfig, ShortenerError, Slug, Stats, StatsBreakdown, Tag, TenantId, Url, UrlShortenerService,
+    };
+
+    /// Commands of interactive sessions started by `urlshort repl`.
+    pub const REPL_HELP: &str = "\
+create <url> [slug]  creates a short link
+redirect <slug>      follows a link, counting the redirect
+stats <slug>         prints stats of a link
+history <slug>       prints events of a link
+help                 prints this help
+quit                 ends the session";
+
+    /// Creates, inspects and exports short links.
+    #[derive(Parser)]
+    #[command(name = "urlshort")]
+    struct Cli {
+        /// Event log keeping the links, unless `--remote` is given.
+        #[arg(long, global = true, default_value = "urlshort.jsonl", conflicts_with = "remote")]
+        file: PathBuf,
+
+        /// Base URL of a remote service managing the links, e.g. `https://sho.rt/`.
+        #[arg(long, global = true)]
+        remote: Option<String>,
+
+        /// API key of the remote service, required to create and delete links.
+        /// Ignored for local event logs.
+        #[arg(long, global = true, env = "URLSHORT_API_KEY")]
+        api_key: Option<String>,
+
+        #[command(subcommand)]
+        action: Action,
+    }
+
+    #[derive(Subcommand)]
+    enum Action {
+        /// Creates a short link, printing its slug and URL.
+        Create {
+            /// Destination of the link.
+            url: String,
+
+            /// Custom slug of the link, a random one is generated if not given.
+            #[arg(long)]
+            slug: Option<String>,
+        },
+
+        /// Deletes a link along with its stats.
+        Delete {
+            /// Slug of the link.
+            slug: String,
+        },
+
+        /// Prints stats of a link.
+        Stats {
+            /// Slug of the link.
+            slug: String,
+        },
+
+        /// Lists links, one per line with their slug, URL, redirects and tags.
+        List {
+            /// Lists only links with the tag.
+            #[arg(long)]
+            tag: Option<String>,
+        },
+
+        /// Exports stats of links as CSV.
+        Export {
+            /// Exports only links with the tag.
+            #[arg(long)]
+            tag: Option<String>,
+
+            /// Exports archived links too.
+            #[arg(long)]
+            include_archived: bool,
+
+            /// Exports raw clicks after stats of links, only available for
+            /// local event logs.
+            #[arg(long)]
+            clicks: bool,
+
+            /// File to write to instead of stdout.
+            #[arg(long, short)]
+            output: Option<PathBuf>,
+        },
+
+        /// Creates links from CSV with the columns `url,slug,tags`, printing
+        /// a summary of created links, conflicts and invalid links.
+        Import {
+            /// CSV file to read, tags are separated by commas or semicolons
+            /// within their field.
+            csv: PathBuf,
+        },
+
+        /// Drops raw redirects older than the retention of their links from
+        /// the local event log, keeping their counts, and prints slugs of
+        /// links whose redirects were dropped.
+        PruneClicks {
+            /// Days redirects of links without their own retention are kept,
+            /// they are kept forever if not given.
+            #[arg(long)]
+            retention_days: Option<u32>,
+        },
+
+        /// Starts an interactive session with links of the local event log,
+        /// reading commands until `quit` or the end of input.
+        Repl,
+
+        /// Verifies the local event log, restores it into a fresh service and
+        /// runs the integrity self-test, printing the report. Fails if any
+        /// check fails.
+        Doctor,
+
+        /// Inspects the local event log.
+        Events {
+            #[command(subcommand)]
+            action: EventsAction,
+        },
+
+        /// Shows a live dashboard of the local event log with top links,
+        /// their clicks per minute and recent events, following events
+        /// appended to the log by other processes until `q` is pressed.
+        #[cfg(feature = "tui")]
+        Top {
+            /// Milliseconds between reads of the event log.
+            #[arg(long, default_value_t = 1000)]
+            refresh_ms: u64,
+        },
+    }
+
+    #[derive(Subcommand)]
+    enum EventsAction {
+        /// Prints records matching the filters, one per line with their
+        /// sequence, time, actor and event.
+        Cat(CatArgs),
+
+        /// Verifies checksums and order of all records.
+        Verify,
+
+        /// Replays the records into a fresh service and prints the resulting
+        /// stats.
+        Replay,
+    }
+
+    #[derive(Args)]
+    struct CatArgs {
+        /// Prints only events of the kind, may be repeated.
+        #[arg(long, value_enum)]
+        kind: Vec<EventKind>,
+
+        /// Prints only events of the slug, may be repeated.
+        #[arg(long)]
+        slug: Vec<String>,
+
+        /// Prints only events of slugs starting with the prefix.
+        #[arg(long)]
+        slug_prefix: Option<String>,
+
+        /// Prints only events of links with the tag.
+        #[arg(long)]
+        tag: Option<String>,
+
+        /// Prints only events of the tenant.
+        #[arg(long)]
+        tenant: Option<String>,
+
+        /// Prints only events recorded after the sequence.
+        #[arg(long, default_value_t = 0)]
+        after: u64,
+
+        /// Prints records as JSON instead.
+        #[arg(long)]
+        json: bool,
+    }
+
+    /// Failure of a command.
+    #[derive(Debug)]
+    enum CliError {
+        Shortener(ShortenerError),
+        EventLog(EventLogError),
+        Usage(&'static str),
+        Corrupted(usize),
+        IntegrityFailed,
+    }
+
+    impl fmt::Display for CliError {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            match self {
+                Self::Shortener(error) => write!(f, "{error}"),
+                Self::EventLog(error) => write!(f, "{error}"),
+                Self::Usage(message) => write!(f, "{message}"),
+                Self::Corrupted(problems) => write!(f, "problems found in the event log: {problems}"),
+                Self::IntegrityFailed => write!(f, "integrity check of the restored service failed"),
+            }
+        }
+    }
+
+    impl From<ShortenerError> for CliError {
+        fn from(error: ShortenerError) -> Self {
+            Self::Shortener(error)
+        }
+    }
+
+    impl From<EventLogError> for CliError {
+        fn from(error: EventLogError) -> Self {
+            Self::EventLog(error)
+        }
+    }
+
+    impl From<io::Error> for CliError {
+        fn from(error: io::Error) -> Self {
+            Self::EventLog(EventLogError::Io(error))
+        }
+    }
+
+    /// Service the commands are run against.
+    enum Target {
+        Local { log: EventLogFile, service: Box<UrlShortenerService> },
+        Remote(RemoteShortener),
+    }
+
+    impl Target {
+        fn open(cli: &Cli) -> Result<Self, CliError> {
+            if let Some(base_url) = &cli.remote {
+                let mut remote = RemoteShortener::new(base_url)?;
+                if let Some(api_key) = &cli.api_key {
+                    remote = remote.with_api_key(api_key.clone());
+                }
+                return Ok(Self::Remote(remote));
+            }
+
+            let (log, records) = EventLogFile::open(&cli.file)?;
+            let click_retention = match cli.action {
+                Action::PruneClicks { retention_days } => retention_days.map(|days| TimeDelta::days(days.into())),
+                _ => None,
+            };
+            let config = ServiceConfig { quiet: true, click_retention, ..Default::default() };
+            Ok(Self::Local { log, service: Box::new(UrlShortenerService::restore(config, records)) })
+        }
+
+        /// Persists events recorded by the command. The log is rewritten once
+        /// redirects were pruned, so they don't stay in the file.
+        fn close(self) -> Result<(), CliError> {
+            if let Self::Local { mut log, service } = self {
+                if service.clicks_pruned_through() > 0 {
+                    log.rewrite(service.events())?;
+                } else {
+                    log.append(service.events())?;
+                }
+            }
+            Ok(())
+        }
+
+        fn list_links(&self, tag: Option<Tag>) -> Result<Vec<ListedLink>, CliError> {
+            let links = match (self, tag) {
+                (Self::Local { service, .. }, Some(tag)) => service.list_links_by_tag(&tag),
+                (Self::Local { service, .. }, None) => service.list_links(),
+                (Self::Remote(remote), tag) => return Ok(remote.list_links(tag.as_ref())?),
+            };
+            Ok(links.into_iter().map(ListedLink::from).collect())
+        }
+
+        fn export<W: Write>(&self, mut writer: W, filter: &ExportFilter) -> Result<(), CliError> {
+            let remote = match self {
+                Self::Local { service, .. } => {
+                    service.export_stats_csv(writer, filter)?;
+                    return Ok(());
+                },
+                Self::Remote(remote) => remote,
+            };
+            if filter.include_clicks {
+                return Err(CliError::Usage("raw clicks are exported only from local event logs"));
+            }
+
+            export::write_row(&mut writer, export::HEADER)?;
+            let mut links = remote.list_links(filter.tag.as_ref())?;
+            links.sort_by(|a, b| a.link_id.cmp(&b.link_id));
+            for link in links.into_iter().filter(|link| filter.include_archived || !link.archived) {
+                let StatsBreakdown { stats, human_redirects, unique_visitors, .. } =
+                    remote.get_stats_breakdown(&link.link.slug)?;
+                let row = [
+                    "link", &stats.link.slug.0, &stats.link.url.0, &stats.redirects.to_string(),
+                    &human_redirects.to_string(), &unique_visitors.to_string(), "", "", "", "", "", "",
+                ];
+                export::write_row(&mut writer, &row)?;
+            }
+            writer.flush()?;
+            Ok(())
+        }
+
+        fn import_links(&mut self, links: Vec<ImportedLink>) -> Result<LinkImportReport, CliError> {
+            let remote = match self {
+                Self::Local { service, .. } => return Ok(service.handle_import_links(links)),
+                Self::Remote(remote) => remote,
+            };
+            if links.iter().any(|link| !link.tags.is_empty()) {
+                return Err(CliError::Usage("tags are imported only into local event logs"));
+            }
+
+            let mut report = LinkImportReport::default();
+            for ImportedLink { line, url, slug, .. } in links {
+                match (remote.handle_create_short_link(url, slug.clone()), slug) {
+                    (Ok(link), _) => report.created.push(link),
+                    (Err(ShortenerError::SlugAlreadyInUse), Some(slug)) => report.conflicts.push((line, slug)),
+                    (Err(error), _) => report.invalid.push((line, error)),
+                }
+            }
+            Ok(report)
+        }
+    }
+
+    /// Runs the command given by arguments of the process, printing its
+    /// failure to stderr.
+    pub fn run() -> ExitCode {
+        match execute(Cli::parse()) {
+            Ok(()) => ExitCode::SUCCESS,
+            Err(error) => {
+                eprintln!("urlshort: {error}");
+                ExitCode::FAILURE
+            },
+        }
+    }
+
+    fn execute(cli: Cli) -> Result<(), CliError> {
+        if let Action::Events { action } = &cli.action {
+            if cli.remote.is_some() {
+                return Err(CliError::Usage("only local event logs can be inspected"));
+            }
+            return inspect_events(&cli.file, action);
+        }
+        if let Action::Doctor = cli.action {
+            if cli.remote.is_some() {
+                return Err(CliError::Usage("only local event logs can be checked"));
+            }
+            return doctor(&cli.file, io::stdout().lock());
+        }
+        #[cfg(feature = "tui")]
+        if let Action::Top { refresh_ms } = cli.action {
+            if cli.remote.is_some() {
+                return Err(CliError::Usage("only local event logs can be followed"));
+            }
+            return top::run(&cli.file, std::time::Duration::from_millis(refresh_ms));
+        }
+
+        let mut target = Target::open(&cli)?;
+        let mut stdout = io::stdout().lock();
+        match cli.action {
+            Action::Create { url, slug } => {
+                let link = match &mut target {
+                    Target::Local { service, .. } => service.handle_create_short_link(Url(url), slug.map(Slug))?,
+                    Target::Remote(remote) => remote.handle_create_short_link(Url(url), slug.map(Slug))?,
+                };
+                writeln!(stdout, "{}\t{}", link.slug.0, link.url.0)?;
+            },
+            Action::Delete { slug } => match &mut target {
+                Target::Local { service, .. } => service.handle_delete_link(Slug(slug))?,
+                Target::Remote(remote) => remote.delete_link(&Slug(slug))?,
+            },
+            Action::Stats { slug } => {
+                let breakdown = match &target {
+                    Target::Local { service, .. } => service.get_stats_breakdown(&Slug(slug))?,
+                    Target::Remote(remote) => remote.get_stats_breakdown(&Slug(slug))?,
+                };
+                write_stats(&mut stdout, &breakdown)?;
+            },
+            Action::List { tag } => {
+                for link in target.list_links(tag.map(Tag))? {
+                    let tags: Vec<_> = link.tags.iter().map(|tag| tag.0.as_str()).collect();
+                    let url = &link.link.url.0;
+                    writeln!(stdout, "{}\t{url}\t{}\t{}", link.link.slug.0, link.redirects, tags.join(","))?;
+                }
+            },
+            Action::Export { tag, include_archived, clicks, output } => {
+                let filter = ExportFilter {
+                    tag: tag.map(Tag),
+                    include_archived,
+                    include_clicks: clicks,
+                    clicks_since: None,
+                };
+                match output {
+                    Some(path) => target.export(BufWriter::new(File::create(path)?), &filter)?,
+                    None => target.export(&mut stdout, &filter)?,
+                }
+            },
+            Action::Import { csv } => {
+                let links = import::read_links_csv(File::open(csv)?)?;
+                let report = target.import_links(links)?;
+                writeln!(stdout, "created: {}", report.created.len())?;
+                writeln!(stdout, "conflicts: {}", report.conflicts.len())?;
+                writeln!(stdout, "invalid: {}", report.invalid.len())?;
+                for (line, slug) in &report.conflicts {
+                    writeln!(stdout, "line {line}: slug {} is already in use", slug.0)?;
+                }
+                for (line, error) in &report.invalid {
+                    writeln!(stdout, "line {line}: {error}")?;
+                }
+            },
+            Action::PruneClicks { .. } => {
+                let Target::Local { service, .. } = &mut target else {
+                    return Err(CliError::Usage("redirects are pruned only from local event logs"));
+                };
+                for slug in service.handle_prune_clicks() {
+                    writeln!(stdout, "{}", slug.0)?;
+                }
+            },
+            Action::Repl => {
+                let Target::Local { log, service } = &mut target else {
+                    return Err(CliError::Usage("interactive sessions are available only for local event logs"));
+                };
+                let stdin = io::stdin();
+                let prompt = stdin.is_terminal();
+                repl(service, log, stdin.lock(), &mut stdout, prompt)?;
+            },
+            Action::Doctor => unreachable!("the event log is checked without opening the target"),
+            Action::Events { .. } => unreachable!("events are inspected without opening the target"),
+            #[cfg(feature = "tui")]
+            Action::Top { .. } => unreachable!("the dashboard follows the log without opening the target"),
+        }
+        target.close()
+    }
+
+    fn inspect_events(path: &Path, action: &EventsAction) -> Result<(), CliError> {
+        let stdout = io::stdout().lock();
+        match action {
+            EventsAction::Cat(args) => cat_events(path, args, stdout),
+            EventsAction::Verify => verify_events(path, stdout),
+            EventsAction::Replay => replay_events(path, stdout),
+        }
+    }
+
+    fn cat_events<W: Write>(path: &Path, args: &CatArgs, mut output: W) -> Result<(), CliError> {
+        let mut filter = EventFilter::all().with_kinds(args.kind.iter().copied());
+        for slug in &args.slug {
+            filter = filter.with_slug(Slug(slug.clone()));
+        }
+        if let Some(prefix) = &args.slug_prefix {
+            filter = filter.with_slug_prefix(prefix.clone());
+        }
+        if let Some(tag) = &args.tag {
+            filter = filter.with_tag(Tag(tag.clone()));
+        }
+        if let Some(tenant) = &args.tenant {
+            filter = filter.with_tenant(TenantId(tenant.clone()));
+        }
+        let service = restore(path)?;
+        for record in service.events_matching(&filter).filter(|record| record.sequence > args.after) {
+            if args.json {
+                writeln!(output, "{}", serde_json::to_string(record).map_err(io::Error::from)?)?;
+                continue;
+            }
+            let actor = record.actor.as_ref().map_or("-", |actor| &actor.0);
+            let recorded_at = record.recorded_at.to_rfc3339();
+            writeln!(output, "{}\t{recorded_at}\t{actor}\t{:?}", record.sequence, record.event)?;
+        }
+        Ok(())
+    }
+
+    /// Restores a service from the log, which must exist.
+    fn restore(path: &Path) -> Result<UrlShortenerService, CliError> {
+        let (records, _) = event_log::read_records(BufReader::new(File::open(path)?))?;
+        let config = ServiceConfig { quiet: true, ..Default::default() };
+        Ok(UrlShortenerService::restore(config, records))
+    }
+
+    fn verify_events<W: Write>(path: &Path, mut output: W) -> Result<(), CliError> {
+        let verification = event_log::verify(BufReader::new(File::open(path)?))?;
+        for problem in &verification.problems {
+            writeln!(output, "{problem}")?;
+        }
+        writeln!(output, "{} valid records", verification.records)?;
+        if !verification.passed() {
+            return Err(CliError::Corrupted(verification.problems.len()));
+        }
+        Ok(())
+    }
+
+    fn doctor<W: Write>(path: &Path, mut output: W) -> Result<(), CliError> {
+        verify_events(path, &mut output)?;
+        let report = restore(path)?.check_integrity();
+        writeln!(output, "{report}")?;
+        if !report.passed() {
+            return Err(CliError::IntegrityFailed);
+        }
+        Ok(())
+    }
+
+    fn replay_events<W: Write>(path: &Path, mut output: W) -> Result<(), CliError> {
+        let started = Instant::now();
+        let service = restore(path)?;
+        let elapsed = started.elapsed();
+
+        let mut kinds = BTreeMap::new();
+        for record in service.events() {
+            *kinds.entry(format!("{:?}", record.event.kind())).or_insert(0) += 1;
+        }
+        let mut links = Vec::new();
+        for link in service.list_links() {
+            links.push(service.get_stats_breakdown(&link.link.slug)?);
+        }
+        links.sort_by(|a, b| {
+            b.stats.redirects.cmp(&a.stats.redirects).then_with(|| a.stats.link.slug.0.cmp(&b.stats.link.slug.0))
+        });
+
+        writeln!(output, "replayed {} events in {elapsed:?}", service.events().len())?;
+        for (kind, count) in &kinds {
+            writeln!(output, "    {kind}: {count}")?;
+        }
+        writeln!(output, "links: {}", links.len())?;
+        writeln!(output, "redirects: {}", links.iter().map(|breakdown| breakdown.stats.redirects).sum::<u64>())?;
+        for StatsBreakdown { stats: Stats { link, redirects }, human_redirects, unique_visitors, .. } in &links {
+            let (slug, url) = (&link.slug.0, &link.url.0);
+            writeln!(output, "{slug}\t{url}\t{redirects}\t{human_redirects}\t{unique_visitors}")?;
+        }
+        Ok(())
+    }
+
+    fn write_stats<W: Write>(mut output: W, breakdown: &StatsBreakdown) -> io::Result<()> {
+        let stats = &breakdown.stats;
+        writeln!(output, "slug: {}", stats.link.slug.0)?;
+        writeln!(output, "url: {}", stats.link.url.0)?;
+        writeln!(output, "redirects: {}", stats.redirects)?;
+        writeln!(output, "human redirects: {}", breakdown.human_redirects)?;
+        writeln!(output, "unique visitors: {}", breakdown.unique_visitors)?;
+        writeln!(output, "conversions: {}", breakdown.conversions)
+    }
+
+    /// Runs commands of [`REPL_HELP`] read line by line, until `quit` or the
+    /// end of input. Failed commands are reported to the output and the
+    /// session goes on, events are appended to the log after every command.
+    ///
+    /// ## Errors
+    ///
+    /// Errors of the input, the output and the log.
+    fn repl<R: BufRead, W: Write>(
+        service: &mut UrlShortenerService,
+        log: &mut EventLogFile,
+        input: R,
+        mut output: W,
+        prompt: bool,
+    ) -> Result<(), CliError> {
+        let mut lines = input.lines();
+        loop {
+            if prompt {
+                write!(output, "> ")?;
+                output.flush()?;
+            }
+            let Some(line) = lines.next().transpose()? else {
+                break;
+            };
+            let words: Vec<_> = line.split_whitespace().collect();
+            if matches!(words[..], ["quit" | "exit"]) {
+                break;
+            }
+            if let Err(error) = repl_command(service, &words, &mut output) {
+                writeln!(output, "error: {error}")?;
+            }
+            log.append(service.events())?;
+        }
+        Ok(())
+    }
+
+    fn repl_command<W: Write>(
+        service: &mut UrlShortenerService,
+        words: &[&str],
+        mut output: W,
+    ) -> Result<(), CliError> {
+        match *words {
+            [] => {},
+            ["help"] => writeln!(output, "{REPL_HELP}")?,
+            ["create", url] | ["create", url, _] => {
+                let slug = words.get(2).map(|slug| Slug(slug.to_string()));
+                let link = service.handle_create_short_link(Url(url.to_owned()), slug)?;
+                writeln!(output, "{}\t{}", link.slug.0, link.url.0)?;
+            },
+            ["redirect", slug] => {
+                let link = service.handle_redirect(Slug(slug.to_owned()))?;
+                writeln!(output, "{}", link.url.0)?;
+            },
+            ["stats", slug] => write_stats(&mut output, &service.get_stats_breakdown(&Slug((*slug).to_owned()))?)?,
+            ["history", slug] => {
+                for event in service.get_events_for(&Slug(slug.to_owned()), 1)? {
+                    let record = &event.record;
+                    let kind = record.event.kind();
+                    writeln!(output, "{}\t{}\t{kind:?}", event.version, record.recorded_at.to_rfc3339())?;
+                }
+            },
+            _ => return Err(CliError::Usage("unknown command, see `help`")),
+        }
+        Ok(())
+    }
+
+    /// Live dashboard of `urlshort top`.
+    #[cfg(feature = "tui")]
+    mod top {
+        use std::{
+            collections::{BTreeMap, VecDeque},
+            path::Path,
+            time::Duration,
+        };
+
+        use chrono::{DateTime, Local, TimeDelta, Utc};
+        use ratatui::{
+            crossterm::event::{self, Event, KeyCode, KeyEventKind, KeyModifiers},
+            layout::{Constraint, Layout, Rect},
+            style::{Modifier, Style},
+            text::Line,
+            widgets::{Block, Row, Sparkline, Table},
+            DefaultTerminal, Frame,
+        };
+
+        use super::CliError;
+        use super::super::{
+            event_log::EventLogFile,
+            events::EventRecord,
+            subscriptions::{Click, ClickFeed, EventFilter, Subscription},
+            LinkId, LinkInfo, ServiceConfig, UrlShortenerService,
+        };
+
+        /// Seconds of clicks the rates of links are counted over.
+        const RATE_WINDOW_SECONDS: i64 = 60;
+
+        /// Most recent events shown.
+        const RECENT_EVENTS: usize = 100;
+
+        /// Shows the dashboard of the log until `q`, `Esc` or `Ctrl-C` is
+        /// pressed, reading events appended to the log every `refresh`.
+        pub(super) fn run(path: &Path, refresh: Duration) -> Result<(), CliError> {
+            let mut dashboard = Dashboard::open(path)?;
+            let result = ratatui::try_init()
+                .map_err(CliError::from)
+                .and_then(|mut terminal| dashboard.follow(&mut terminal, refresh));
+            ratatui::restore();
+            result
+        }
+
+        /// Service restored from the log along with what it delivered to the
+        /// dashboard so far.
+        struct Dashboard {
+            log: EventLogFile,
+            service: UrlShortenerService,
+            events: Subscription,
+            clicks: ClickFeed,
+            // latest events, the most recent first
+            recent: VecDeque<EventRecord>,
+            // clicks of the rate window, in order they were delivered
+            window: VecDeque<Click>,
+        }
+
+        impl Dashboard {
+            fn open(path: &Path) -> Result<Self, CliError> {
+                let (log, records) = EventLogFile::open(path)?;
+                let config = ServiceConfig { quiet: true, ..Default::default() };
+                let mut service = UrlShortenerService::restore(config, records);
+                let recent = service.events().iter().rev().take(RECENT_EVENTS).cloned().collect();
+                let events = service.subscribe(EventFilter::all());
+                let clicks = service.subscribe_clicks(EventFilter::all());
+                Ok(Self { log, service, events, clicks, recent, window: VecDeque::new() })
+            }
+
+            fn follow(&mut self, terminal: &mut DefaultTerminal, refresh: Duration) -> Result<(), CliError> {
+                loop {
+                    let now = Utc::now();
+                    self.receive(now);
+                    terminal.draw(|frame| self.draw(frame, now))?;
+                    if event::poll(refresh)? && quits(&event::read()?) {
+                        return Ok(());
+                    }
+                    self.service.replicate(self.log.read_appended()?);
+                }
+            }
+
+            /// Takes events and clicks delivered since the last call, and
+            /// drops clicks which fell out of the rate window.
+            fn receive(&mut self, now: DateTime<Utc>) {
+                while let Ok(record) = self.events.events.try_recv() {
+                    self.recent.push_front(record);
+                }
+                self.recent.truncate(RECENT_EVENTS);
+
+                while let Some(click) = self.clicks.try_next() {
+                    self.window.push_back(click);
+                }
+                let start = now - TimeDelta::seconds(RATE_WINDOW_SECONDS);
+                self.window.retain(|click| click.at > start);
+            }
+
+            fn draw(&self, frame: &mut Frame, now: DateTime<Utc>) {
+                let [header, activity, body] = Layout::vertical([
+                    Constraint::Length(1),
+                    Constraint::Length(5),
+                    Constraint::Min(0),
+                ]).areas(frame.area());
+                let [links, events] = Layout::horizontal([Constraint::Percentage(60), Constraint::Percentage(40)])
+                    .areas(body);
+
+                let last_sequence = self.service.events().last().map_or(0, |record| record.sequence);
+                let summary = format!(
+                    " urlshort top  {}  events: {last_sequence}  clicks/min: {}  (q to quit)",
+                    self.log.path().display(),
+                    self.window.len(),
+                );
+                frame.render_widget(Line::from(summary).style(Style::new().add_modifier(Modifier::BOLD)), header);
+                self.draw_activity(frame, activity, now);
+                self.draw_links(frame, links);
+                self.draw_events(frame, events);
+            }
+
+            /// Draws clicks per second of the rate window, the latest on the
+            /// right.
+            fn draw_activity(&self, frame: &mut Frame, area: Rect, now: DateTime<Utc>) {
+                let mut per_second = [0; RATE_WINDOW_SECONDS as usize];
+                for click in &self.window {
+                    let age = (now - click.at).num_seconds().clamp(0, RATE_WINDOW_SECONDS - 1);
+                    per_second[(RATE_WINDOW_SECONDS - 1 - age) as usize] += 1;
+                }
+                let shown = per_second.len().saturating_sub(area.width.saturating_sub(2).into());
+                let sparkline = Sparkline::default()
+                    .block(Block::bordered().title(" Clicks per second "))
+                    .data(&per_second[shown..]);
+                frame.render_widget(sparkline, area);
+            }
+
+            /// Draws links ordered by their clicks in the rate window, then by
+            /// all their redirects.
+            fn draw_links(&self, frame: &mut Frame, area: Rect) {
+                let mut rates: BTreeMap<&LinkId, u64> = BTreeMap::new();
+                for click in &self.window {
+                    *rates.entry(&click.link_id).or_default() += 1;
+                }
+                let rate = |link: &LinkInfo| rates.get(&link.link_id).copied().unwrap_or(0);
+                let mut links = self.service.list_links();
+                links.sort_by(|a, b| {
+                    rate(b).cmp(&rate(a))
+                        .then(b.redirects.cmp(&a.redirects))
+                        .then_with(|| a.link.slug.0.cmp(&b.link.slug.0))
+                });
+
+                let rows = links.iter().take(area.height.into()).map(|link| {
+                    Row::new([
+                        link.link.slug.0.clone(),
+                        link.link.url.0.clone(),
+                        link.redirects.to_string(),
+                        rate(link).to_string(),
+                    ])
+                });
+                let widths = [Constraint::Max(16), Constraint::Fill(1), Constraint::Length(9), Constraint::Length(10)];
+                let header = Row::new(["slug", "url", "redirects", "clicks/min"]);
+                let table = Table::new(rows, widths)
+                    .header(header.style(Style::new().add_modifier(Modifier::BOLD)))
+                    .block(Block::bordered().title(" Top links "));
+                frame.render_widget(table, area);
+            }
+
+            fn draw_events(&self, frame: &mut Frame, area: Rect) {
+                let rows = self.recent.iter().take(area.height.into()).map(|record| {
+                    let slug = self.service.slug_of(&record.event).map_or("-", |slug| &slug.0);
+                    Row::new([
+                        record.sequence.to_string(),
+                        record.recorded_at.with_timezone(&Local).format("%H:%M:%S").to_string(),
+                        format!("{:?}", record.event.kind()),
+                        slug.to_owned(),
+                    ])
+                });
+                let widths = [Constraint::Length(8), Constraint::Length(8), Constraint::Fill(1), Constraint::Fill(1)];
+                let table = Table::new(rows, widths).block(Block::bordered().title(" Recent events "));
+                frame.render_widget(table, area);
+            }
+        }
+
+        /// Whether the terminal event ends the dashboard.
+        fn quits(event: &Event) -> bool {
+            let Event::Key(key) = event else {
+                return false;
+            };
+            let interrupted = key.code == KeyCode::Char('c') && key.modifiers.contains(KeyModifiers::CONTROL);
+            key.kind == KeyEventKind::Press && (matches!(key.code, KeyCode::Char('q') | KeyCode::Esc) || interrupted)
+        }
+    }
+}
+
+/// HTTP server of the [`UrlShortenerService`], serving redirects at
+/// `GET /{slug}` and the JSON API used by `RemoteShortener` of the `client`
+/// feature:
+///
+/// - `POST /api/links` creates a link from `{"url": ..., "slug": ...}`.
+/// - `POST /api/links:batch` creates up to
+///   [`MAX_BATCH_LINKS`](http::MAX_BATCH_LINKS) links from
+///   `{"links": [...], "atomic": ...}`, answering the outcome of each one.
+/// - `GET /api/links` lists links, only the ones with the `tag` if given.
+/// - `DELETE /api/links/{slug}` deletes a link.
+/// - `GET /api/links/{slug}/stats` returns stats of a link.
+/// - `POST /api/clicks/{slug}` counts a redirect served from a cache, if
+///   beacons are enabled by the [`CachePolicy`](http::CachePolicy).
+///
+/// Failures are answered with the status of [`ShortenerError::status_code`]
+/// and an [`ErrorPayload`](errors::ErrorPayload) as JSON.
+///
+/// Redirects carry `Cache-Control` and `Expires` headers of the
+/// [`CachePolicy`](http::CachePolicy) for their [`RedirectType`], so CDNs may
+/// serve permanent redirects without reaching the server. Refused redirects
+/// may be answered with a [`Fallback`](http::Fallback) instead of the JSON
+/// error, e.g. a redirect to the home page for unknown slugs. Links with an
+/// interstitial are answered with a page showing the domain of the
+/// destination and a link to continue there.
+///
+/// Endpoints changing links require an API key in the
+/// [`API_KEY_HEADER`](dispatch::API_KEY_HEADER), and act on behalf of the owner
+/// of the key, see [`UrlShortenerService::authenticate`]. Endpoints reading
+/// links act on behalf of the owner of the key too if one is given, and among
+/// links of the tenant of the requested host otherwise. Endpoints under
+/// `/api/admin` let admins page through raw events, view the stream of a
+/// link, rebuild projections and compact the event log.
+///
+/// Creation of links and redirects are limited by
+/// [`UrlShortenerService::check_rate_limit`] for the address of the client
+/// and its API key, and answered with `429 Too Many Requests` once the client
+/// exceeds its limit.
+///
+/// With the `openapi` feature the OpenAPI document of the API is served at
+/// `/api/openapi.json`, and Swagger UI browsing it at `/api/docs`.
+///
+/// With the `graphql` feature the [GraphQL API](graphql) is served at
+/// `/api/graphql`.
+///
+/// With the `tls` feature the server terminates TLS itself with the
+/// certificate of [`TlsConfig`](http::TlsConfig), so it can serve HTTPS
+/// without a reverse proxy.
+///
+/// The service isn't [`Send`], as its hooks don't have to be, so it runs on
+/// its own thread behind a [`ServiceHandle`](http::ServiceHandle), handling
+/// requests one at a time in order they come.
+#[cfg(feature = "http")]
+pub mod http {
+    #[cfg(feature = "tls")]
+    use std::path::PathBuf;
+    use std::{
+        collections::{BTreeMap, HashMap},
+        io,
+        net::SocketAddr,
+        sync::{mpsc, Arc},
+        thread,
+    };
+
+    use axum::{
+        extract::{rejection::JsonRejection, ConnectInfo, Query as QueryString, RawPathParams, State},
+        http::{header, Extensions, HeaderMap, HeaderName, HeaderValue, Method, StatusCode},
+        response::{AppendHeaders, Html, IntoResponse, Response},
+        routing::{delete, get, post},
+        Extension, Json, Router,
+    };
+    use chrono::{DateTime, TimeDelta, Utc};
+    use serde::{Deserialize, Serialize};
+    use tokio::{net::TcpListener, runtime::Runtime, sync::oneshot};
+    use tower_http::cors::{AllowOrigin, CorsLayer};
+
+    use super::{
+        dispatch::{Command, Query, Reply, RequestContext, TenantId, API_KEY_HEADER},
+        errors::ErrorPayload,
+        events::{EventRecord, VersionedEvent},
+        metrics::PrometheusRecorder,
+        rate_limits::{ClientKey, LimitedOperation},
+        slugs, LinkInfo, LinkOptions, RedirectContext, RedirectOutcome, RedirectRefusal, RedirectType, ShortLink,
+        ShortenerError, Slug, StatsBreakdown, Tag, Url, UrlShortenerService,
+    };
+
+    type Job = Box<dyn FnOnce(&mut UrlShortenerService) + Send>;
+
+    /// Handle of a [`UrlShortenerService`] running on its own thread. The
+    /// thread ends once all handles are dropped.
+    #[derive(Clone)]
+    pub struct ServiceHandle {
+        jobs: mpsc::Sender<Job>,
+    }
+
+    impl ServiceHandle {
+        /// Starts the thread owning the service created by `build`.
+        pub fn spawn<F>(build: F) -> Self
+        where
+            F: FnOnce() -> UrlShortenerService + Send + 'static,
+        {
+            let (jobs, receiver) = mpsc::channel::<Job>();
+            thread::spawn(move || {
+                let mut service = build();
+                for job in receiver {
+                    job(&mut service);
+                }
+            });
+            Self { jobs }
+        }
+
+        /// Runs the job on the service and returns its result.
+        ///
+        /// ## Errors
+        ///
+        /// [`ShortenerError::ServiceUnavailable`] if the thread of the service
+        /// has ended, e.g. a previous job panicked.
+        pub async fn call<T, F>(&self, job: F) -> Result<T, ShortenerError>
+        where
+            T: Send + 'static,
+            F: FnOnce(&mut UrlShortenerService) -> T + Send + 'static,
+        {
+            let (reply, result) = oneshot::channel();
+            self.jobs
+                .send(Box::new(move |service| {
+                    let _ = reply.send(job(service));
+                }))
+                .map_err(|_| ShortenerError::ServiceUnavailable)?;
+            result.await.map_err(|_| ShortenerError::ServiceUnavailable)
+        }
+    }
+
+    /// Configuration of the HTTP server.
+    #[derive(Clone, Debug, Default, PartialEq)]
+    pub struct HttpConfig {
+        /// Caching of redirects by browsers and CDNs.
+        pub cache: CachePolicy,
+
+        /// Responses to refused redirects by the reason of the refusal,
+        /// refusals without one are answered with the JSON error. Rate
+        /// limited redirects are always answered with the JSON error.
+        pub fallbacks: BTreeMap<RedirectRefusal, Fallback>,
+
+        /// Tenants by the hosts their links are served on, links of other
+        /// hosts are the ones of no tenant.
+        pub tenant_hosts: HashMap<String, TenantId>,
+
+        /// Cross-origin access to the JSON API, browsers allow only pages of
+        /// the same origin to call it if `None`.
+        pub cors: Option<CorsPolicy>,
+
+        /// Count of events projections may be behind the event log while the
+        /// server is ready, see `GET /readyz`.
+        pub max_projection_lag: u64,
+
+        /// Metrics served at `GET /metrics` in the Prometheus text format, the
+        /// service should record to a clone of the recorder. Metrics are not
+        /// served if `None`.
+        pub metrics: Option<PrometheusRecorder>,
+
+        /// Certificate and key the server terminates TLS with, connections
+        /// are served over plain HTTP if `None`.
+        #[cfg(feature = "tls")]
+        pub tls: Option<TlsConfig>,
+    }
+
+    /// Certificate and private key of the server, so small deployments can
+    /// serve HTTPS without a reverse proxy in front of it.
+    #[cfg(feature = "tls")]
+    #[derive(Clone, Debug, PartialEq)]
+    pub struct TlsConfig {
+        /// PEM file with the certificate chain, the certificate of the server
+        /// first.
+        pub cert_path: PathBuf,
+
+        /// PEM file with the private key of the certificate.
+        pub key_path: PathBuf,
+    }
+
+    /// Cross-origin access to the JSON API under `/api`, so browser-based
+    /// dashboards can call it directly. Redirects are not affected.
+    #[derive(Clone, Debug, PartialEq)]
+    pub struct CorsPolicy {
+        /// Origins allowed to call the API, e.g. `https://dashboard.example.com`,
+        /// any origin if empty.
+        pub allowed_origins: Vec<String>,
+
+        /// Methods allowed in cross-origin requests.
+        pub allowed_methods: Vec<Method>,
+
+        /// Headers allowed in cross-origin requests.
+        pub allowed_headers: Vec<HeaderName>,
+
+        /// Whether requests may carry cookies and authorization headers, any
+        /// origin is then the origin of the request.
+        pub allow_credentials: bool,
+
+        /// How long browsers may cache answers to preflight requests.
+        pub max_age: Option<TimeDelta>,
+    }
+
+    impl Default for CorsPolicy {
+        fn default() -> Self {
+            Self {
+                allowed_origins: Vec::new(),
+                allowed_methods: vec![Method::GET, Method::POST, Method::DELETE],
+                allowed_headers: vec![header::CONTENT_TYPE, HeaderName::from_static(API_KEY_HEADER)],
+                allow_credentials: false,
+                max_age: Some(TimeDelta::hours(1)),
+            }
+        }
+    }
+
+    impl CorsPolicy {
+        /// Returns the layer answering preflight requests and adding CORS
+        /// headers to responses. Origins which aren't valid header values are
+        /// skipped.
+        pub fn layer(&self) -> CorsLayer {
+            let origins = match (self.allowed_origins.is_empty(), self.allow_credentials) {
+                // browsers reject the wildcard origin in credentialed responses
+                (true, true) => AllowOrigin::mirror_request(),
+                (true, false) => AllowOrigin::any(),
+                (false, _) => AllowOrigin::list(
+                    self.allowed_origins.iter().filter_map(|origin| HeaderValue::from_str(origin).ok()),
+                ),
+            };
+            let layer = CorsLayer::new()
+                .allow_origin(origins)
+                .allow_methods(self.allowed_methods.clone())
+                .allow_headers(self.allowed_headers.clone())
+                .allow_credentials(self.allow_credentials)
+                .expose_headers([header::RETRY_AFTER]);
+            match self.max_age.and_then(|max_age| max_age.to_std().ok()) {
+                Some(max_age) => layer.max_age(max_age),
+                None => layer,
+            }
+        }
+    }
+
+    /// Response to a refused redirect meant for visitors rather than API
+    /// clients.
+    #[derive(Clone, Debug, PartialEq)]
+    pub enum Fallback {
+        /// Temporary redirect to the URL, e.g. the home page.
+        Redirect(Url),
+
+        /// HTML page, e.g. a branded 404, served with the status of the
+        /// refusal.
+        Page(String),
+    }
+
+    impl Fallback {
+        fn response(&self, refusal: RedirectRefusal) -> Response {
+            match self {
+                Fallback::Redirect(url) => {
+                    let headers = [(header::LOCATION, url.0.as_str()), (header::CACHE_CONTROL, "no-store")];
+                    (StatusCode::FOUND, headers).into_response()
+                },
+                Fallback::Page(html) => {
+                    let status = StatusCode::from_u16(refusal.status_code()).unwrap_or(StatusCode::NOT_FOUND);
+                    (status, Html(html.clone())).into_response()
+                },
+            }
+        }
+    }
+
+    /// How long browsers and CDNs may cache redirects, by their
+    /// [`RedirectType`]. Redirects served from caches never reach the server,
+    /// so they are counted only if the cache reports them with a beacon.
+    #[derive(Clone, Debug, PartialEq)]
+    pub struct CachePolicy {
+        /// How long temporary redirects may be cached, by default they
+        /// aren't.
+        pub temporary: Option<TimeDelta>,
+
+        /// How long permanent redirects may be cached, a day by default.
+        pub permanent: Option<TimeDelta>,
+
+        /// Whether redirects are counted from beacons at
+        /// `POST /api/clicks/{slug}`, e.g. sent asynchronously by CDN edges
+        /// for redirects served from their cache.
+        pub beacons: bool,
+    }
+
+    impl Default for CachePolicy {
+        fn default() -> Self {
+            Self { temporary: None, permanent: Some(TimeDelta::days(1)), beacons: false }
+        }
+    }
+
+    impl CachePolicy {
+        /// Returns the caching headers of a redirect of the type served at
+        /// the time.
+        pub fn headers(&self, redirect_type: RedirectType, now: DateTime<Utc>) -> Vec<(HeaderName, String)> {
+            let max_age = match redirect_type {
+                RedirectType::Temporary => self.temporary,
+                RedirectType::Permanent => self.permanent,
+            };
+            match max_age.filter(|max_age| *max_age > TimeDelta::zero()) {
+                Some(max_age) => vec![
+                    (header::CACHE_CONTROL, format!("public, max-age={}", max_age.num_seconds())),
+                    (header::EXPIRES, (now + max_age).format("%a, %d %b %Y %H:%M:%S GMT").to_string()),
+                ],
+                None => vec![(header::CACHE_CONTROL, String::from("no-store"))],
+            }
+        }
+    }
+
+    #[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+    #[derive(Deserialize)]
+    struct CreateLinkRequest {
+        url: String,
+        slug: Option<String>,
+    }
+
+    #[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+    #[derive(Deserialize)]
+    struct CreateLinksRequest {
+        /// Links to create, at most [`MAX_BATCH_LINKS`].
+        links: Vec<CreateLinkRequest>,
+
+        /// Whether links are created only if all of them are valid.
+        #[serde(default)]
+        atomic: bool,
+    }
+
+    #[cfg_attr(feature = "openapi", derive(utoipa::IntoParams), into_params(parameter_in = Query))]
+    #[derive(Deserialize)]
+    struct ListLinksRequest {
+        /// Lists only links with the tag.
+        tag: Option<String>,
+    }
+
+    #[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+    #[derive(Serialize)]
+    struct LinkResponse {
+        slug: String,
+        url: String,
+    }
+
+    impl From<ShortLink> for LinkResponse {
+        fn from(link: ShortLink) -> Self {
+            Self { slug: link.slug.0, url: link.url.0 }
+        }
+    }
+
+    #[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+    #[derive(Serialize)]
+    struct LinkInfoResponse {
+        link_id: String,
+        slug: String,
+        url: String,
+        tags: Vec<String>,
+        disabled: bool,
+        archived: bool,
+        redirects: u64,
+    }
+
+    impl From<LinkInfo> for LinkInfoResponse {
+        fn from(info: LinkInfo) -> Self {
+            Self {
+                link_id: info.link_id.0,
+                slug: info.link.slug.0,
+                url: info.link.url.0,
+                tags: info.tags.into_iter().map(|tag| tag.0).collect(),
+                disabled: info.disabled,
+                archived: info.archived,
+                redirects: info.redirects,
+            }
+        }
+    }
+
+    #[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+    #[derive(Serialize)]
+    struct StatsResponse {
+        link: LinkResponse,
+        redirects: u64,
+        human_redirects: u64,
+        unique_visitors: u64,
+        conversions: u64,
+    }
+
+    impl From<StatsBreakdown> for StatsResponse {
+        fn from(StatsBreakdown { stats, human_redirects, unique_visitors, conversions, .. }: StatsBreakdown) -> Self {
+            Self {
+                link: stats.link.into(),
+                redirects: stats.redirects,
+                human_redirects,
+                unique_visitors,
+                conversions,
+            }
+        }
+    }
+
+    #[cfg_attr(feature = "openapi", derive(utoipa::IntoParams), into_params(parameter_in = Query))]
+    #[derive(Deserialize)]
+    struct ListEventsRequest {
+        /// Lists events recorded after the sequence, from the start of the
+        /// event log by default.
+        #[serde(default)]
+        after: u64,
+
+        /// Maximum count of listed events, at most [`MAX_EVENTS_PAGE`].
+        limit: Option<usize>,
+    }
+
+    #[cfg_attr(feature = "openapi", derive(utoipa::IntoParams), into_params(parameter_in = Query))]
+    #[derive(Deserialize)]
+    struct LinkEventsRequest {
+        /// Lists events of the link starting from the version.
+        #[serde(default)]
+        from_version: u64,
+    }
+
+    #[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+    #[derive(Serialize)]
+    struct EventResponse {
+        sequence: u64,
+        recorded_at: String,
+        actor: Option<String>,
+        tenant: Option<String>,
+        kind: String,
+        /// The event as it is represented in Rust, meant for humans.
+        event: String,
+    }
+
+    impl From<EventRecord> for EventResponse {
+        fn from(record: EventRecord) -> Self {
+            Self {
+                sequence: record.sequence,
+                recorded_at: record.recorded_at.to_rfc3339(),
+                actor: record.actor.map(|actor| actor.0),
+                tenant: record.tenant.map(|tenant| tenant.0),
+                kind: format!("{:?}", record.event.kind()),
+                event: format!("{:?}", record.event),
+            }
+        }
+    }
+
+    #[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+    #[derive(Serialize)]
+    struct EventPageResponse {
+        events: Vec<EventResponse>,
+        /// Sequence to list the next page after, `None` once the end of the
+        /// event log is reached.
+        next_after: Option<u64>,
+    }
+
+    #[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+    #[derive(Serialize)]
+    struct VersionedEventResponse {
+        version: u64,
+        #[serde(flatten)]
+        record: EventResponse,
+    }
+
+    impl From<VersionedEvent> for VersionedEventResponse {
+        fn from(event: VersionedEvent) -> Self {
+            Self { version: event.version, record: event.record.into() }
+        }
+    }
+
+    #[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+    #[derive(Serialize)]
+    struct CompactionResponse {
+        compacted: usize,
+    }
+
+    #[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+    #[derive(Serialize)]
+    struct BatchItemResponse {
+        /// Status the entry would be answered with by `POST /api/links`.
+        status: u16,
+        link: Option<LinkResponse>,
+        error: Option<ErrorResponse>,
+    }
+
+    impl From<Result<ShortLink, ShortenerError>> for BatchItemResponse {
+        fn from(outcome: Result<ShortLink, ShortenerError>) -> Self {
+            match outcome {
+                Ok(link) => Self { status: StatusCode::CREATED.as_u16(), link: Some(link.into()), error: None },
+                Err(error) => Self {
+                    status: error.status_code(),
+                    link: None,
+                    error: Some(ErrorPayload::from(&error).into()),
+                },
+            }
+        }
+    }
+
+    #[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+    #[derive(Serialize)]
+    struct BatchResponse {
+        /// Outcomes of the links in the order of the request.
+        results: Vec<BatchItemResponse>,
+    }
+
+    #[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+    #[derive(Serialize)]
+    struct ErrorResponse {
+        code: String,
+        message: String,
+        field: Option<String>,
+        retry_after: Option<u64>,
+    }
+
+    impl From<ErrorPayload> for ErrorResponse {
+        fn from(payload: ErrorPayload) -> Self {
+            Self {
+                code: payload.code,
+                message: payload.message,
+                field: payload.field,
+                retry_after: payload.retry_after,
+            }
+        }
+    }
+
+    /// OpenAPI document of the routes of the server.
+    #[cfg(feature = "openapi")]
+    #[derive(utoipa::OpenApi)]
+    #[openapi(
+        info(title = "URL shortener"),
+        paths(
+            redirect, create_link, create_links, list_links, delete_link, get_stats, count_click, list_events, list_link_events,
+            rebuild_projection, compact_events,
+        ),
+        components(schemas(
+            CreateLinkRequest, CreateLinksRequest, LinkResponse, BatchItemResponse, BatchResponse, LinkInfoResponse, StatsResponse, ErrorResponse, EventResponse,
+            EventPageResponse, VersionedEventResponse, CompactionResponse,
+        )),
+    )]
+    pub struct ApiDoc;
+
+    /// Returns the routes of the server.
+    pub fn router(service: ServiceHandle, config: HttpConfig) -> Router {
+        let mut router = Router::new()
+            .route("/api/links", get(list_links).post(create_link))
+            .route("/api/links:batch", post(create_links))
+            .route("/api/links/{slug}", delete(delete_link))
+            .route("/api/links/{slug}/stats", get(get_stats))
+            .route("/api/admin/events", get(list_events))
+            .route("/api/admin/links/{slug}/events", get(list_link_events))
+            .route("/api/admin/projections/{name}/rebuild", post(rebuild_projection))
+            .route("/api/admin/compaction", post(compact_events));
+        if config.cache.beacons {
+            router = router.route("/api/clicks/{slug}", post(count_click));
+        }
+        #[cfg(feature = "openapi")]
+        let router = {
+            use utoipa::OpenApi;
+            use utoipa_swagger_ui::SwaggerUi;
+
+            router.merge(SwaggerUi::new("/api/docs").url("/api/openapi.json", ApiDoc::openapi()))
+        };
+        #[cfg(feature = "graphql")]
+        let router = router.merge(super::graphql::router(service.clone()));
+        let router = match &config.cors {
+            Some(cors) => router.layer(cors.layer()),
+            None => router,
+        };
+        let router = match config.metrics.clone() {
+            Some(metrics) => router.route("/metrics", get(move || async move { metrics.render() })),
+            None => router,
+        };
+        router
+            .route("/healthz", get(healthz))
+            .route("/readyz", get(readyz))
+            .route("/{slug}", get(redirect))
+            .layer(Extension(Arc::new(config)))
+            .with_state(service)
+    }
+
+    /// Serves connections accepted by the listener, addresses of visitors are
+    /// taken from the connections.
+    ///
+    /// ## Errors
+    ///
+    /// Returns the I/O error which stopped the server.
+    pub async fn serve(listener: TcpListener, service: ServiceHandle, config: HttpConfig) -> io::Result<()> {
+        #[cfg(feature = "tls")]
+        if let Some(tls) = config.tls.clone() {
+            return serve_tls(listener, router(service, config), &tls).await;
+        }
+        axum::serve(listener, router(service, config).into_make_service_with_connect_info::<SocketAddr>()).await
+    }
+
+    /// Serves connections accepted by the listener over TLS.
+    ///
+    /// ## Errors
+    ///
+    /// Returns the I/O error which stopped the server, e.g. if the
+    /// certificate or the key can't be read.
+    #[cfg(feature = "tls")]
+    async fn serve_tls(listener: TcpListener, router: Router, tls: &TlsConfig) -> io::Result<()> {
+        // the provider is installed once per process, later calls fail harmlessly
+        let _ = rustls::crypto::ring::default_provider().install_default();
+        let rustls = axum_server::tls_rustls::RustlsConfig::from_pem_file(&tls.cert_path, &tls.key_path).await?;
+        axum_server::from_tcp_rustls(listener.into_std()?, rustls)
+            .serve(router.into_make_service_with_connect_info::<SocketAddr>())
+            .await
+    }
+
+    /// Serves the address on a new runtime, blocking the current thread.
+    ///
+    /// ## Errors
+    ///
+    /// Returns the I/O error which stopped the server, e.g. if the address
+    /// can't be bound.
+    pub fn run(addr: SocketAddr, service: ServiceHandle, config: HttpConfig) -> io::Result<()> {
+        Runtime::new()?.block_on(async { serve(TcpListener::bind(addr).await?, service, config).await })
+    }
+
+    #[cfg_attr(feature = "openapi", utoipa::path(
+        get,
+        path = "/{slug}",
+        params(("slug" = String, Path, description = "Percent-encoded slug of the link")),
+        responses(
+            (status = 301, description = "Permanent redirect to the destination in `Location`"),
+            (status = 302, description = "Temporary redirect to the destination in `Location`"),
+            (status = 200, description = "Interstitial page with a link to the destination"),
+            (status = "4XX", description = "The redirect was refused", body = ErrorResponse),
+        ),
+    ))]
+    async fn redirect(
+        State(service): State<ServiceHandle>,
+        Extension(config): Extension<Arc<HttpConfig>>,
+        params: RawPathParams,
+        headers: HeaderMap,
+        extensions: Extensions,
+    ) -> Response {
+        let clients = clients(&headers, &extensions);
+        let outcome = match handle_redirect(&service, &config, &params, &headers, &extensions, clients).await {
+            Ok(outcome) => outcome,
+            Err(error) => return error_response(&error),
+        };
+
+        match outcome {
+            RedirectOutcome::Served { link, interstitial: true, .. } => interstitial(&link),
+            RedirectOutcome::Served { link, redirect_type, .. } => {
+                let status = StatusCode::from_u16(redirect_type.status_code()).unwrap_or(StatusCode::FOUND);
+                let cache = AppendHeaders(config.cache.headers(redirect_type, Utc::now()));
+                (status, [(header::LOCATION, link.url.0)], cache).into_response()
+            },
+            // clients over their rate limit get the error with `Retry-After` instead of a fallback
+            RedirectOutcome::Refused(refusal @ RedirectRefusal::RateLimited { .. }) => error_response(&refusal.error()),
+            RedirectOutcome::Refused(refusal) => match config.fallbacks.get(&refusal) {
+                Some(fallback) => fallback.response(refusal),
+                None => error_response(&refusal.error()),
+            },
+        }
+    }
+
+    /// Page showing the domain of the destination with a button to continue
+    /// there. It is never cached, so visitors see it every time.
+    fn interstitial(link: &ShortLink) -> Response {
+        let domain = url::Url::parse(&link.url.0)
+            .ok()
+            .and_then(|url| url.host_str().map(str::to_string))
+            .unwrap_or_else(|| link.url.0.clone());
+        let html = format!(
+            concat!(
+                "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n",
+                "<meta name=\"robots\" content=\"noindex\">\n<title>Leaving for {domain}</title>\n</head>\n",
+                "<body>\n<p>This link leads to <strong>{domain}</strong>:</p>\n<p><code>{url}</code></p>\n",
+                "<p><a href=\"{url}\" rel=\"noopener noreferrer\">Continue</a></p>\n</body>\n</html>\n",
+            ),
+            domain = escape_html(&domain),
+            url = escape_html(&link.url.0),
+        );
+        (StatusCode::OK, [(header::CACHE_CONTROL, "no-store")], Html(html)).into_response()
+    }
+
+    fn escape_html(text: &str) -> String {
+        text.replace('&', "&amp;")
+            .replace('<', "&lt;")
+            .replace('>', "&gt;")
+            .replace('"', "&quot;")
+            .replace('\'', "&#39;")
+    }
+
+    #[cfg_attr(feature = "openapi", utoipa::path(
+        post,
+        path = "/api/clicks/{slug}",
+        params(("slug" = String, Path, description = "Percent-encoded slug of the link")),
+        responses(
+            (status = 204, description = "The redirect served from a cache was counted"),
+            (status = "4XX", description = "The redirect would have been refused", body = ErrorResponse),
+        ),
+    ))]
+    async fn count_click(
+        State(service): State<ServiceHandle>,
+        Extension(config): Extension<Arc<HttpConfig>>,
+        params: RawPathParams,
+        headers: HeaderMap,
+        extensions: Extensions,
+    ) -> Response {
+        // beacons come from caches on behalf of many visitors, so they aren't rate limited
+        match handle_redirect(&service, &config, &params, &headers, &extensions, Vec::new()).await {
+            Ok(RedirectOutcome::Served { .. }) => StatusCode::NO_CONTENT.into_response(),
+            Ok(RedirectOutcome::Refused(refusal)) => error_response(&refusal.error()),
+            Err(error) => error_response(&error),
+        }
+    }
+
+    /// Handles the redirect of the slug in the path among links of the tenant
+    /// of the requested host, recording details of the request. Redirects of
+    /// clients over their rate limit are refused.
+    async fn handle_redirect(
+        service: &ServiceHandle,
+        config: &HttpConfig,
+        params: &RawPathParams,
+        headers: &HeaderMap,
+        extensions: &Extensions,
+        clients: Vec<ClientKey>,
+    ) -> Result<RedirectOutcome, ShortenerError> {
+        let slug = slug(params)?;
+        let value = |name| headers.get(name).and_then(|value| value.to_str().ok()).map(str::to_string);
+        let context = RedirectContext {
+            referrer: value(header::REFERER),
+            user_agent: value(header::USER_AGENT),
+            ip: extensions.get::<ConnectInfo<SocketAddr>>().map(|ConnectInfo(addr)| addr.ip()),
+            ..Default::default()
+        };
+        let tenant = tenant(config, headers);
+        service
+            .call(move |service| {
+                if let Err(ShortenerError::RateLimited { retry_after }) =
+                    service.check_rate_limit(tenant.as_ref(), LimitedOperation::Redirect, &clients)
+                {
+                    return RedirectOutcome::Refused(RedirectRefusal::RateLimited { retry_after });
+                }
+                let acting = RequestContext { tenant, ..Default::default() };
+                service.act_as(&acting, |service| service.handle_redirect_request(slug, context, None))
+            })
+            .await
+    }
+
+    /// Returns the tenant whose links are served on the requested host, see
+    /// [`HttpConfig::tenant_hosts`].
+    fn tenant(config: &HttpConfig, headers: &HeaderMap) -> Option<TenantId> {
+        let host = headers.get(header::HOST).and_then(|value| value.to_str().ok())?;
+        let host = host.rsplit_once(':').map_or(host, |(host, _)| host);
+        config.tenant_hosts.get(host).cloned()
+    }
+
+    /// Returns keys of the client of the request, see [`ClientKey`].
+    fn clients(headers: &HeaderMap, extensions: &Extensions) -> Vec<ClientKey> {
+        let ip = extensions.get::<ConnectInfo<SocketAddr>>().map(|ConnectInfo(addr)| ClientKey::Ip(addr.ip()));
+        ip.into_iter().chain(api_key(headers).map(ClientKey::ApiKey)).collect()
+    }
+
+    /// Returns the API key of the request, if any.
+    pub(crate) fn api_key(headers: &HeaderMap) -> Option<String> {
+        headers.get(API_KEY_HEADER).and_then(|value| value.to_str().ok()).map(str::to_string)
+    }
+
+    #[cfg_attr(feature = "openapi", utoipa::path(
+        post,
+        path = "/api/links",
+        request_body = CreateLinkRequest,
+        responses(
+            (status = 201, description = "The link was created", body = LinkResponse),
+            (status = "4XX", description = "The link can't be created", body = ErrorResponse),
+        ),
+    ))]
+    async fn create_link(
+        State(service): State<ServiceHandle>,
+        headers: HeaderMap,
+        extensions: Extensions,
+        request: Result<Json<CreateLinkRequest>, JsonRejection>,
+    ) -> Response {
+        let Json(request) = match request {
+            Ok(request) => request,
+            Err(rejection) => return rejection_response(&rejection),
+        };
+        let command = Command::CreateShortLink {
+            url: Url(request.url),
+            slug: request.slug.map(Slug),
+            options: LinkOptions::default(),
+        };
+        let clients = clients(&headers, &extensions);
+        let api_key = api_key(&headers);
+        let reply = service.call(move |service| {
+            let context = service.authenticate(api_key.as_deref())?;
+            service.check_rate_limit(context.tenant.as_ref(), LimitedOperation::CreateLink, &clients)?;
+            service.dispatch_command(&context, command)
+        });
+        match reply.await.and_then(|reply| reply) {
+            Ok(Reply::Link(link)) => (StatusCode::CREATED, Json(LinkResponse::from(link))).into_response(),
+            reply => unexpected(reply),
+        }
+    }
+
+    /// Maximum count of links created by `POST /api/links:batch` at once.
+    pub const MAX_BATCH_LINKS: usize = 100;
+
+    #[cfg_attr(feature = "openapi", utoipa::path(
+        post,
+        path = "/api/links:batch",
+        request_body = CreateLinksRequest,
+        responses(
+            (status = 200, description = "Outcomes of the links in the order of the request", body = BatchResponse),
+            (status = 413, description = "The batch has too many links", body = ErrorResponse),
+            (status = "4XX", description = "The batch can't be created", body = ErrorResponse),
+        ),
+    ))]
+    async fn create_links(
+        State(service): State<ServiceHandle>,
+        headers: HeaderMap,
+        extensions: Extensions,
+        request: Result<Json<CreateLinksRequest>, JsonRejection>,
+    ) -> Response {
+        let Json(request) = match request {
+            Ok(request) => request,
+            Err(rejection) => return rejection_response(&rejection),
+        };
+        if request.links.len() > MAX_BATCH_LINKS {
+            return error_response(&ShortenerError::BatchTooLarge);
+        }
+
+        let links = request.links.into_iter().map(|link| (Url(link.url), link.slug.map(Slug))).collect();
+        let command = if request.atomic {
+            Command::CreateShortLinksAtomically { links }
+        } else {
+            Command::CreateShortLinks { links }
+        };
+        let clients = clients(&headers, &extensions);
+        let api_key = api_key(&headers);
+        let reply = service.call(move |service| {
+            let context = service.authenticate(api_key.as_deref())?;
+            service.check_rate_limit(context.tenant.as_ref(), LimitedOperation::CreateLink, &clients)?;
+            service.dispatch_command(&context, command)
+        });
+        match reply.await.and_then(|reply| reply) {
+            Ok(Reply::Links(outcomes)) => {
+                let results = outcomes.into_iter().map(BatchItemResponse::from).collect();
+                Json(BatchResponse { results }).into_response()
+            },
+            reply => unexpected(reply),
+        }
+    }
+
+    #[cfg_attr(feature = "openapi", utoipa::path(
+        get,
+        path = "/api/links",
+        params(ListLinksRequest),
+        responses(
+            (status = 200, description = "Links ordered by creation time", body = [LinkInfoResponse]),
+            (status = 401, description = "The API key is invalid", body = ErrorResponse),
+        ),
+    ))]
+    async fn list_links(
+        State(service): State<ServiceHandle>,
+        Extension(config): Extension<Arc<HttpConfig>>,
+        headers: HeaderMap,
+        request: QueryString<ListLinksRequest>,
+    ) -> Response {
+        let query = match request.0.tag {
+            Some(tag) => Query::ListLinksByTag { tag: Tag(tag) },
+            None => Query::ListLinks,
+        };
+        match dispatch_query(&service, &config, &headers, query).await {
+            Ok(Reply::LinkInfos(links)) => {
+                Json(links.into_iter().map(LinkInfoResponse::from).collect::<Vec<_>>()).into_response()
+            },
+            reply => unexpected(reply),
+        }
+    }
+
+    #[cfg_attr(feature = "openapi", utoipa::path(
+        delete,
+        path = "/api/links/{slug}",
+        params(("slug" = String, Path, description = "Percent-encoded slug of the link")),
+        responses(
+            (status = 204, description = "The link was deleted"),
+            (status = 401, description = "The API key is missing or invalid", body = ErrorResponse),
+            (status = 404, description = "The slug doesn't map to any link", body = ErrorResponse),
+        ),
+    ))]
+    async fn delete_link(State(service): State<ServiceHandle>, params: RawPathParams, headers: HeaderMap) -> Response {
+        let slug = match slug(&params) {
+            Ok(slug) => slug,
+            Err(error) => return error_response(&error),
+        };
+        match dispatch_command(&service, api_key(&headers), Command::DeleteLink { slug }).await {
+            Ok(Reply::Done) => StatusCode::NO_CONTENT.into_response(),
+            reply => unexpected(reply),
+        }
+    }
+
+    #[derive(Serialize)]
+    struct ReadinessResponse {
+        ready: bool,
+        last_sequence: u64,
+        lagging_projections: Vec<ProjectionLagResponse>,
+    }
+
+    #[derive(Serialize)]
+    struct ProjectionLagResponse {
+        name: String,
+        lag: u64,
+    }
+
+    /// Liveness probe: the event store lives in memory of the thread of the
+    /// service, so it is writable as long as the thread takes jobs.
+    async fn healthz(State(service): State<ServiceHandle>) -> Response {
+        match service.call(|_| ()).await {
+            Ok(()) => (StatusCode::OK, "ok").into_response(),
+            Err(error) => (StatusCode::SERVICE_UNAVAILABLE, error.to_string()).into_response(),
+        }
+    }
+
+    /// Readiness probe: the service is alive and its projections are within
+    /// [`HttpConfig::max_projection_lag`] of the event log.
+    async fn readyz(State(service): State<ServiceHandle>, Extension(config): Extension<Arc<HttpConfig>>) -> Response {
+        let lags = service.call(|service| (service.last_sequence, service.projection_lags())).await;
+        let Ok((last_sequence, lags)) = lags else {
+            return (StatusCode::SERVICE_UNAVAILABLE, Json(ReadinessResponse {
+                ready: false,
+                last_sequence: 0,
+                lagging_projections: Vec::new(),
+            })).into_response();
+        };
+
+        let lagging_projections: Vec<_> = lags
+            .into_iter()
+            .filter(|lag| lag.lag > config.max_projection_lag)
+            .map(|lag| ProjectionLagResponse { name: lag.name, lag: lag.lag })
+            .collect();
+        let ready = lagging_projections.is_empty();
+        let status = if ready { StatusCode::OK } else { StatusCode::SERVICE_UNAVAILABLE };
+        (status, Json(ReadinessResponse { ready, last_sequence, lagging_projections })).into_response()
+    }
+
+    #[cfg_attr(feature = "openapi", utoipa::path(
+        get,
+        path = "/api/links/{slug}/stats",
+        params(("slug" = String, Path, description = "Percent-encoded slug of the link")),
+        responses(
+            (status = 200, description = "Stats of the link", body = StatsResponse),
+            (status = 401, description = "The API key is invalid", body = ErrorResponse),
+            (status = 404, description = "The slug doesn't map to any link", body = ErrorResponse),
+        ),
+    ))]
+    async fn get_stats(
+        State(service): State<ServiceHandle>,
+        Extension(config): Extension<Arc<HttpConfig>>,
+        params: RawPathParams,
+        headers: HeaderMap,
+    ) -> Response {
+        let slug = match slug(&params) {
+            Ok(slug) => slug,
+            Err(error) => return error_response(&error),
+        };
+        match dispatch_query(&service, &config, &headers, Query::GetStatsBreakdown { slug }).await {
+            Ok(Reply::StatsBreakdown(breakdown)) => Json(StatsResponse::from(breakdown)).into_response(),
+            reply => unexpected(reply),
+        }
+    }
+
+    /// Maximum count of events listed by `GET /api/admin/events` at once.
+    pub const MAX_EVENTS_PAGE: usize = 1000;
+
+    #[cfg_attr(feature = "openapi", utoipa::path(
+        get,
+        path = "/api/admin/events",
+        params(ListEventsRequest),
+        responses(
+            (status = 200, description = "Page of raw events of the event log", body = EventPageResponse),
+            (status = 401, description = "The API key is missing or invalid", body = ErrorResponse),
+            (status = 403, description = "The API key doesn't grant the admin role", body = ErrorResponse),
+        ),
+    ))]
+    async fn list_events(
+        State(service): State<ServiceHandle>,
+        headers: HeaderMap,
+        request: QueryString<ListEventsRequest>,
+    ) -> Response {
+        let limit = request.limit.unwrap_or(100).min(MAX_EVENTS_PAGE);
+        let query = Query::ListEvents { after: request.after, limit };
+        match dispatch_query_as(&service, api_key(&headers), query).await {
+            Ok(Reply::Events(events)) => {
+                let next_after = events.last().map(|record| record.sequence).filter(|_| events.len() == limit);
+                let events = events.into_iter().map(EventResponse::from).collect();
+                Json(EventPageResponse { events, next_after }).into_response()
+            },
+            reply => unexpected(reply),
+        }
+    }
+
+    #[cfg_attr(feature = "openapi", utoipa::path(
+        get,
+        path = "/api/admin/links/{slug}/events",
+        params(("slug" = String, Path, description = "Percent-encoded slug of the link"), LinkEventsRequest),
+        responses(
+            (status = 200, description = "Stream of events of the link", body = [VersionedEventResponse]),
+            (status = 401, description = "The API key is missing or invalid", body = ErrorResponse),
+            (status = 403, description = "The API key doesn't grant the admin role", body = ErrorResponse),
+            (status = 404, description = "The slug doesn't map to any link", body = ErrorResponse),
+        ),
+    ))]
+    async fn list_link_events(
+        State(service): State<ServiceHandle>,
+        params: RawPathParams,
+        headers: HeaderMap,
+        request: QueryString<LinkEventsRequest>,
+    ) -> Response {
+        let slug = match slug(&params) {
+            Ok(slug) => slug,
+            Err(error) => return error_response(&error),
+        };
+        let query = Query::GetEventsFor { slug, from_version: request.from_version };
+        match dispatch_query_as(&service, api_key(&headers), query).await {
+            Ok(Reply::VersionedEvents(events)) => {
+                Json(events.into_iter().map(VersionedEventResponse::from).collect::<Vec<_>>()).into_response()
+            },
+            reply => unexpected(reply),
+        }
+    }
+
+    #[cfg_attr(feature = "openapi", utoipa::path(
+        post,
+        path = "/api/admin/projections/{name}/rebuild",
+        params(("name" = String, Path, description = "Name of the projection")),
+        responses(
+            (status = 204, description = "The projection was rebuilt from the event log"),
+            (status = 401, description = "The API key is missing or invalid", body = ErrorResponse),
+            (status = 403, description = "The API key doesn't grant the admin role", body = ErrorResponse),
+            (status = 404, description = "There is no projection with the name", body = ErrorResponse),
+            (status = 409, description = "Events were pruned and the projection has no snapshot", body = ErrorResponse),
+        ),
+    ))]
+    async fn rebuild_projection(State(service): State<ServiceHandle>, params: RawPathParams, headers: HeaderMap) -> Response {
+        let Some((_, name)) = params.iter().next() else {
+            return error_response(&ShortenerError::ProjectionNotFound);
+        };
+        let command = Command::RebuildProjection { name: name.to_owned() };
+        match dispatch_command(&service, api_key(&headers), command).await {
+            Ok(Reply::Done) => StatusCode::NO_CONTENT.into_response(),
+            reply => unexpected(reply),
+        }
+    }
+
+    #[cfg_attr(feature = "openapi", utoipa::path(
+        post,
+        path = "/api/admin/compaction",
+        responses(
+            (status = 200, description = "Events kept only for auditing were dropped", body = CompactionResponse),
+            (status = 401, description = "The API key is missing or invalid", body = ErrorResponse),
+            (status = 403, description = "The API key doesn't grant the admin role", body = ErrorResponse),
+        ),
+    ))]
+    async fn compact_events(State(service): State<ServiceHandle>, headers: HeaderMap) -> Response {
+        match dispatch_command(&service, api_key(&headers), Command::CompactEvents).await {
+            Ok(Reply::CompactedEvents(compacted)) => Json(CompactionResponse { compacted }).into_response(),
+            reply => unexpected(reply),
+        }
+    }
+
+    /// Decodes the slug of the request path, see
+    /// [`slugs::decode_path_segment`].
+    fn slug(params: &RawPathParams) -> Result<Slug, ShortenerError> {
+        let (_, segment) = params.iter().next().ok_or(ShortenerError::SlugNotFound)?;
+        slugs::decode_path_segment(segment).map_err(ShortenerError::InvalidSlug)
+    }
+
+    /// Dispatches the command on behalf of the owner of the API key.
+    async fn dispatch_command(
+        service: &ServiceHandle,
+        api_key: Option<String>,
+        command: Command,
+    ) -> Result<Reply, ShortenerError> {
+        service
+            .call(move |service| {
+                let context = service.authenticate(api_key.as_deref())?;
+                service.dispatch_command(&context, command)
+            })
+            .await?
+    }
+
+    /// Dispatches the query on behalf of the owner of the API key, if one is
+    /// given, or among links of the tenant of the requested host otherwise.
+    async fn dispatch_query(
+        service: &ServiceHandle,
+        config: &HttpConfig,
+        headers: &HeaderMap,
+        query: Query,
+    ) -> Result<Reply, ShortenerError> {
+        let api_key = api_key(headers);
+        let tenant = tenant(config, headers);
+        service
+            .call(move |service| {
+                let context = match api_key {
+                    Some(api_key) => service.authenticate(Some(&api_key))?,
+                    None => RequestContext { tenant, ..Default::default() },
+                };
+                service.dispatch_query(&context, query)
+            })
+            .await?
+    }
+
+    /// Dispatches the query on behalf of the owner of the API key.
+    async fn dispatch_query_as(
+        service: &ServiceHandle,
+        api_key: Option<String>,
+        query: Query,
+    ) -> Result<Reply, ShortenerError> {
+        service
+            .call(move |service| {
+                let context = service.authenticate(api_key.as_deref())?;
+                service.dispatch_query(&context, query)
+            })
+            .await?
+    }
+
+    fn unexpected(reply: Result<Reply, ShortenerError>) -> Response {
+        match reply {
+            Ok(reply) => {
+                let payload = ErrorPayload::internal(format!("unexpected reply {reply:?}"));
+                payload_response(StatusCode::INTERNAL_SERVER_ERROR, payload)
+            },
+            Err(error) => error_response(&error),
+        }
+    }
+
+    fn error_response(error: &ShortenerError) -> Response {
+        let status = StatusCode::from_u16(error.status_code()).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR);
+        payload_response(status, ErrorPayload::from(error))
+    }
+
+    fn rejection_response(rejection: &JsonRejection) -> Response {
+        payload_response(rejection.status(), ErrorPayload::internal(rejection.body_text()))
+    }
+
+    fn payload_response(status: StatusCode, payload: ErrorPayload) -> Response {
+        let retry_after = payload.retry_after.map(|seconds| [(header::RETRY_AFTER, seconds.to_string())]);
+        (status, retry_after, Json(ErrorResponse::from(payload))).into_response()
+    }
+}
+
+/// gRPC server of the [`UrlShortenerService`] for service-to-service
+/// integrations, defined by `proto/shortener.proto`. Like the HTTP server, it
+/// runs the service behind a [`ServiceHandle`](http::ServiceHandle), and
+/// requires an API key in the [`API_KEY_HEADER`](dispatch::API_KEY_HEADER)
+/// metadata to create links.
+///
+/// Failures are answered with the status matching
+/// [`ShortenerError::status_code`], and the stable
+/// [`ShortenerError::code`] in the [`ERROR_CODE_METADATA`](grpc::ERROR_CODE_METADATA)
+/// metadata.
+#[cfg(feature = "grpc")]
+pub mod grpc {
+    use std::{io, net::SocketAddr};
+
+    use tokio::{net::TcpListener, runtime::Runtime, sync::mpsc};
+    use tokio_stream::wrappers::{ReceiverStream, TcpListenerStream};
+    use tonic::{transport::Server, Code, Request, Response, Status};
+
+    use super::{
+        dispatch::{Command, Query, Reply, RequestContext, API_KEY_HEADER},
+        http::ServiceHandle,
+        rate_limits::{ClientKey, LimitedOperation},
+        subscriptions::{Click, EventFilter},
+        LinkOptions, RedirectContext, RedirectOutcome, RedirectRefusal, ShortLink, ShortenerError, Slug, StatsBreakdown,
+        Url, VisitorId,
+    };
+
+    /// Messages and services generated from `proto/shortener.proto`.
+    pub mod proto {
+        tonic::include_proto!("shortener.v1");
+    }
+
+    use proto::shortener_server::{Shortener, ShortenerServer};
+
+    /// Metadata key of the [`ShortenerError::code`] of failed calls.
+    pub const ERROR_CODE_METADATA: &str = "x-shortener-error";
+
+    /// Most clicks buffered for a slow watcher before the feed waits for it.
+    const CLICK_BUFFER: usize = 64;
+
+    /// Implementation of the `Shortener` gRPC service.
+    #[derive(Clone)]
+    pub struct GrpcShortener {
+        service: ServiceHandle,
+    }
+
+    impl GrpcShortener {
+        /// Creates the gRPC service over the running service.
+        pub fn new(service: ServiceHandle) -> Self {
+            Self { service }
+        }
+
+        /// Dispatches the query on behalf of the owner of the API key, if one
+        /// is given.
+        async fn dispatch_query(&self, api_key: Option<String>, query: Query) -> Result<Reply, Status> {
+            self.service
+                .call(move |service| {
+                    let context = match api_key {
+                        Some(api_key) => service.authenticate(Some(&api_key))?,
+                        None => RequestContext::default(),
+                    };
+                    service.dispatch_query(&context, query)
+                })
+                .await
+                .and_then(|reply| reply)
+                .map_err(|error| status(&error))
+        }
+    }
+
+    #[tonic::async_trait]
+    impl Shortener for GrpcShortener {
+        type WatchClicksStream = ReceiverStream<Result<proto::Click, Status>>;
+
+        async fn create_short_link(
+            &self,
+            request: Request<proto::CreateShortLinkRequest>,
+        ) -> Result<Response<proto::ShortLink>, Status> {
+            let clients = clients(&request);
+            let api_key = api_key(&request);
+            let request = request.into_inner();
+            let command = Command::CreateShortLink {
+                url: Url(request.url),
+                slug: request.slug.map(Slug),
+                options: LinkOptions::default(),
+            };
+            let reply = self.service
+                .call(move |service| {
+                    let context = service.authenticate(api_key.as_deref())?;
+                    service.check_rate_limit(context.tenant.as_ref(), LimitedOperation::CreateLink, &clients)?;
+                    service.dispatch_command(&context, command)
+                })
+                .await
+                .and_then(|reply| reply)
+                .map_err(|error| status(&error))?;
+            match reply {
+                Reply::Link(link) => Ok(Response::new(link.into())),
+                reply => Err(unexpected(&reply)),
+            }
+        }
+
+        async fn redirect(
+            &self,
+            request: Request<proto::RedirectRequest>,
+        ) -> Result<Response<proto::RedirectResponse>, Status> {
+            let ip = request.remote_addr().map(|addr| addr.ip());
+            let clients = clients(&request);
+            let request = request.into_inner();
+            let slug = Slug(request.slug);
+            let context = RedirectContext {
+                visitor: request.visitor.map(VisitorId),
+                referrer: request.referrer,
+                user_agent: request.user_agent,
+                ip,
+                ..Default::default()
+            };
+            let outcome = self.service
+                .call(move |service| {
+                    if let Err(ShortenerError::RateLimited { retry_after }) =
+                        service.check_rate_limit(None, LimitedOperation::Redirect, &clients)
+                    {
+                        return RedirectOutcome::Refused(RedirectRefusal::RateLimited { retry_after });
+                    }
+                    service.handle_redirect_request(slug, context, None)
+                })
+                .await
+                .map_err(|error| status(&error))?;
+            match outcome {
+                RedirectOutcome::Served { link, redirect_type, interstitial } => {
+                    Ok(Response::new(proto::RedirectResponse {
+                        link: Some(link.into()),
+                        status_code: redirect_type.status_code().into(),
+                        interstitial,
+                    }))
+                },
+                RedirectOutcome::Refused(refusal) => Err(status(&refusal.error())),
+            }
+        }
+
+        async fn get_stats(&self, request: Request<proto::GetStatsRequest>) -> Result<Response<proto::Stats>, Status> {
+            let api_key = api_key(&request);
+            let slug = Slug(request.into_inner().slug);
+            match self.dispatch_query(api_key, Query::GetStatsBreakdown { slug }).await? {
+                Reply::StatsBreakdown(breakdown) => Ok(Response::new(breakdown.into())),
+                reply => Err(unexpected(&reply)),
+            }
+        }
+
+        async fn watch_clicks(
+            &self,
+            request: Request<proto::WatchClicksRequest>,
+        ) -> Result<Response<Self::WatchClicksStream>, Status> {
+            let filter = request.into_inner()
+                .slugs
+                .into_iter()
+                .fold(EventFilter::all(), |filter, slug| filter.with_slug(Slug(slug)));
+            let mut feed = self.service
+                .call(move |service| service.subscribe_clicks(filter))
+                .await
+                .map_err(|error| status(&error))?;
+
+            // the feed is dropped, and so unsubscribed, once the watcher goes away
+            let (clicks, stream) = mpsc::channel(CLICK_BUFFER);
+            tokio::spawn(async move {
+                while let Some(click) = feed.next_click().await {
+                    if clicks.send(Ok(click.into())).await.is_err() {
+                        break;
+                    }
+                }
+            });
+            Ok(Response::new(ReceiverStream::new(stream)))
+        }
+    }
+
+    impl From<ShortLink> for proto::ShortLink {
+        fn from(link: ShortLink) -> Self {
+            Self { slug: link.slug.0, url: link.url.0 }
+        }
+    }
+
+    impl From<StatsBreakdown> for proto::Stats {
+        fn from(StatsBreakdown { stats, human_redirects, unique_visitors, conversions, .. }: StatsBreakdown) -> Self {
+            Self {
+                link: Some(stats.link.into()),
+                redirects: stats.redirects,
+                human_redirects,
+                unique_visitors,
+                conversions,
+            }
+        }
+    }
+
+    impl From<Click> for proto::Click {
+        fn from(click: Click) -> Self {
+            Self {
+                slug: click.slug.0,
+                link_id: click.link_id.0,
+                at: click.at.to_rfc3339(),
+                visitor: click.visitor.map(|visitor| visitor.0),
+                bot: click.bot,
+            }
+        }
+    }
+
+    /// Serves connections accepted by the listener.
+    ///
+    /// ## Errors
+    ///
+    /// Returns the transport error which stopped the server.
+    pub async fn serve(listener: TcpListener, service: ServiceHandle) -> Result<(), tonic::transport::Error> {
+        Server::builder()
+            .add_service(ShortenerServer::new(GrpcShortener::new(service)))
+            .serve_with_incoming(TcpListenerStream::new(listener))
+            .await
+    }
+
+    /// Serves the address on a new runtime, blocking the current thread.
+    ///
+    /// ## Errors
+    ///
+    /// Returns the error which stopped the server, e.g. if the address can't
+    /// be bound.
+    pub fn run(addr: SocketAddr, service: ServiceHandle) -> io::Result<()> {
+        Runtime::new()?.block_on(async {
+            serve(TcpListener::bind(addr).await?, service).await.map_err(io::Error::other)
+        })
+    }
+
+    /// Returns the status of calls failing with the error.
+    pub fn status(error: &ShortenerError) -> Status {
+        let code = match error.status_code() {
+            400 => Code::InvalidArgument,
+            401 => Code::Unauthenticated,
+            403 => Code::PermissionDenied,
+            404 => Code::NotFound,
+            409 => Code::AlreadyExists,
+            410 => Code::FailedPrecondition,
+            429 => Code::ResourceExhausted,
+            503 => Code::Unavailable,
+            504 => Code::DeadlineExceeded,
+            _ => Code::Internal,
+        };
+        let mut status = Status::new(code, error.to_string());
+        if let Ok(value) = error.code().parse() {
+            status.metadata_mut().insert(ERROR_CODE_METADATA, value);
+        }
+        status
+    }
+
+    /// Returns keys of the client of the request, see [`ClientKey`].
+    fn clients<T>(request: &Request<T>) -> Vec<ClientKey> {
+        let ip = request.remote_addr().map(|addr| ClientKey::Ip(addr.ip()));
+        ip.into_iter().chain(api_key(request).map(ClientKey::ApiKey)).collect()
+    }
+
+    /// Returns the API key of the request from the [`API_KEY_HEADER`]
+    /// metadata, if any.
+    fn api_key<T>(request: &Request<T>) -> Option<String> {
+        request.metadata().get(API_KEY_HEADER).and_then(|value| value.to_str().ok()).map(str::to_string)
+    }
+
+    fn unexpected(reply: &Reply) -> Status {
+        Status::internal(format!("unexpected reply {reply:?}"))
+    }
+}
+
+/// GraphQL API of the [`UrlShortenerService`] for dashboards, so they fetch
+/// exactly the fields they need in one request. Queries read links, their
+/// stats and redirects over time, mutations are mapped to
+/// [`Command`](dispatch::Command)s. Like the REST API, it is served by the
+/// HTTP server, at `POST /api/graphql`, with GraphiQL at `GET /api/graphql`.
+/// Mutations require an API key, like the endpoints of the REST API changing
+/// links.
+///
+/// Failed fields carry the message of the [`ShortenerError`] and its stable
+/// [`ShortenerError::code`] in the `code` extension.
+#[cfg(feature = "graphql")]
+pub mod graphql {
+    use async_graphql::{
+        http::GraphiQLSource, ComplexObject, Context, EmptySubscription, Enum, Error, ErrorExtensions, Object,
+        SimpleObject,
+    };
+    use axum::{
+        http::HeaderMap,
+        response::{Html, IntoResponse},
+        routing::get,
+        Extension, Json, Router,
+    };
+    use chrono::{DateTime, Utc};
+
+    use super::{
+        analytics::{RedirectBucket, Resolution},
+        dispatch::{Command, Query, Reply, RequestContext},
+        http::{self, ServiceHandle},
+        LinkInfo, LinkOptions, ShortenerError, Slug, StatsBreakdown, Tag, Url,
+    };
+
+    /// Path the API and GraphiQL are served at.
+    pub const PATH: &str = "/api/graphql";
+
+    /// Schema of the API.
+    pub type Schema = async_graphql::Schema<QueryRoot, MutationRoot, EmptySubscription>;
+
+    /// Returns the schema resolving fields with the running service.
+    pub fn schema(service: ServiceHandle) -> Schema {
+        Schema::build(QueryRoot, MutationRoot, EmptySubscription).data(service).finish()
+    }
+
+    /// Returns the routes serving the API and GraphiQL.
+    pub fn router(service: ServiceHandle) -> Router<ServiceHandle> {
+        Router::new().route(PATH, get(graphiql).post(execute).layer(Extension(schema(service))))
+    }
+
+    async fn execute(
+        Extension(schema): Extension<Schema>,
+        headers: HeaderMap,
+        Json(request): Json<async_graphql::Request>,
+    ) -> Json<async_graphql::Response> {
+        Json(schema.execute(request.data(Credentials(http::api_key(&headers)))).await)
+    }
+
+    /// API key of the request, mutations are dispatched on behalf of its
+    /// owner.
+    struct Credentials(Option<String>);
+
+    async fn graphiql() -> impl IntoResponse {
+        Html(GraphiQLSource::build().endpoint(PATH).finish())
+    }
+
+    /// Fields readable from the root of queries.
+    pub struct QueryRoot;
+
+    #[Object]
+    impl QueryRoot {
+        /// Link the slug maps to, if any.
+        async fn link(&self, ctx: &Context<'_>, slug: String) -> async_graphql::Result<Option<LinkObject>> {
+            match dispatch_query(ctx, Query::GetLink { slug: Slug(slug) }).await {
+                Ok(Reply::LinkInfo(info)) => Ok(Some((*info).into())),
+                Err(ShortenerError::SlugNotFound) => Ok(None),
+                reply => Err(unexpected(reply)),
+            }
+        }
+
+        /// All links ordered by creation time, only the ones with the tag if
+        /// given.
+        async fn links(&self, ctx: &Context<'_>, tag: Option<String>) -> async_graphql::Result<Vec<LinkObject>> {
+            let query = match tag {
+                Some(tag) => Query::ListLinksByTag { tag: Tag(tag) },
+                None => Query::ListLinks,
+            };
+            list(ctx, query).await
+        }
+
+        /// Links whose slug, destination, title or description contains the
+        /// text, ignoring case.
+        async fn search(&self, ctx: &Context<'_>, text: String) -> async_graphql::Result<Vec<LinkObject>> {
+            list(ctx, Query::SearchLinks { text }).await
+        }
+
+        /// Links flagged for anomalous traffic.
+        async fn flagged_links(&self, ctx: &Context<'_>) -> async_graphql::Result<Vec<LinkObject>> {
+            list(ctx, Query::ListFlaggedLinks).await
+        }
+    }
+
+    /// Fields changing links.
+    pub struct MutationRoot;
+
+    #[Object]
+    impl MutationRoot {
+        /// Creates a link to the URL, with a generated slug if none is given.
+        async fn create_link(
+            &self,
+            ctx: &Context<'_>,
+            url: String,
+            slug: Option<String>,
+            #[graphql(default)] tags: Vec<String>,
+        ) -> async_graphql::Result<LinkObject> {
+            let options = LinkOptions { tags: tags.into_iter().map(Tag).collect(), ..Default::default() };
+            let command = Command::CreateShortLink { url: Url(url), slug: slug.map(Slug), options };
+            let slug = match dispatch_command(ctx, command).await {
+                Ok(Reply::Link(link)) => link.slug,
+                reply => return Err(unexpected(reply)),
+            };
+            match dispatch_query(ctx, Query::GetLink { slug }).await {
+                Ok(Reply::LinkInfo(info)) => Ok((*info).into()),
+                reply => Err(unexpected(reply)),
+            }
+        }
+
+        /// Deletes the link.
+        async fn delete_link(&self, ctx: &Context<'_>, slug: String) -> async_graphql::Result<bool> {
+            done(ctx, Command::DeleteLink { slug: Slug(slug) }).await
+        }
+
+        /// Disables redirects of the link.
+        async fn disable_link(&self, ctx: &Context<'_>, slug: String) -> async_graphql::Result<bool> {
+            done(ctx, Command::DisableLink { slug: Slug(slug) }).await
+        }
+
+        /// Enables redirects of the disabled link.
+        async fn enable_link(&self, ctx: &Context<'_>, slug: String) -> async_graphql::Result<bool> {
+            done(ctx, Command::EnableLink { slug: Slug(slug) }).await
+        }
+
+        /// Attaches the tags to the link.
+        async fn tag_link(&self, ctx: &Context<'_>, slug: String, tags: Vec<String>) -> async_graphql::Result<bool> {
+            let tags = tags.into_iter().map(Tag).collect();
+            done(ctx, Command::TagLink { slug: Slug(slug), tags }).await
+        }
+
+        /// Detaches the tags from the link.
+        async fn untag_link(&self, ctx: &Context<'_>, slug: String, tags: Vec<String>) -> async_graphql::Result<bool> {
+            let tags = tags.into_iter().map(Tag).collect();
+            done(ctx, Command::UntagLink { slug: Slug(slug), tags }).await
+        }
+    }
+
+    /// Current state of a link.
+    #[derive(SimpleObject)]
+    #[graphql(name = "Link", complex)]
+    pub struct LinkObject {
+        link_id: String,
+        slug: String,
+        url: String,
+        title: Option<String>,
+        description: Option<String>,
+        tags: Vec<String>,
+        aliases: Vec<String>,
+        disabled: bool,
+        archived: bool,
+        redirects: u64,
+    }
+
+    #[ComplexObject]
+    impl LinkObject {
+        /// Stats of the link.
+        async fn stats(&self, ctx: &Context<'_>) -> async_graphql::Result<StatsObject> {
+            match dispatch_query(ctx, Query::GetStatsBreakdown { slug: Slug(self.slug.clone()) }).await {
+                Ok(Reply::StatsBreakdown(breakdown)) => Ok(breakdown.into()),
+                reply => Err(unexpected(reply)),
+            }
+        }
+
+        /// Redirects of the link between `from` and `to`, counted by the
+        /// finest resolution still kept for the period.
+        async fn redirects_over_time(
+            &self,
+            ctx: &Context<'_>,
+            from: DateTime<Utc>,
+            to: DateTime<Utc>,
+        ) -> async_graphql::Result<Vec<BucketObject>> {
+            let query = Query::GetRedirectsOverTime { slug: Slug(self.slug.clone()), from, to };
+            match dispatch_query(ctx, query).await {
+                Ok(Reply::RedirectBuckets(buckets)) => Ok(buckets.into_iter().map(BucketObject::from).collect()),
+                reply => Err(unexpected(reply)),
+            }
+        }
+
+        /// Redirects of the link by country of the visitor, most first.
+        async fn clicks_by_country(&self, ctx: &Context<'_>) -> async_graphql::Result<Vec<CountryClicksObject>> {
+            match dispatch_query(ctx, Query::GetClicksByCountry { slug: Slug(self.slug.clone()) }).await {
+                Ok(Reply::CountryClicks(clicks)) => Ok(clicks
+                    .into_iter()
+                    .map(|(country, redirects)| CountryClicksObject { country: country.0, redirects })
+                    .collect()),
+                reply => Err(unexpected(reply)),
+            }
+        }
+    }
+
+    impl From<LinkInfo> for LinkObject {
+        fn from(info: LinkInfo) -> Self {
+            Self {
+                link_id: info.link_id.0,
+                slug: info.link.slug.0,
+                url: info.link.url.0,
+                title: info.metadata.title,
+                description: info.metadata.description,
+                tags: info.tags.into_iter().map(|tag| tag.0).collect(),
+                aliases: info.aliases.into_iter().map(|alias| alias.0).collect(),
+                disabled: info.disabled,
+                archived: info.archived,
+                redirects: info.redirects,
+            }
+        }
+    }
+
+    /// Stats of a link.
+    #[derive(SimpleObject)]
+    #[graphql(name = "Stats")]
+    pub struct StatsObject {
+        redirects: u64,
+        human_redirects: u64,
+        unique_visitors: u64,
+        conversions: u64,
+        conversion_rate: f64,
+    }
+
+    impl From<StatsBreakdown> for StatsObject {
+        fn from(breakdown: StatsBreakdown) -> Self {
+            Self {
+                conversion_rate: breakdown.conversion_rate(),
+                redirects: breakdown.stats.redirects,
+                human_redirects: breakdown.human_redirects,
+                unique_visitors: breakdown.unique_visitors,
+                conversions: breakdown.conversions,
+            }
+        }
+    }
+
+    /// Count of redirects in a period.
+    #[derive(SimpleObject)]
+    #[graphql(name = "RedirectBucket")]
+    pub struct BucketObject {
+        start: DateTime<Utc>,
+        resolution: ResolutionObject,
+        redirects: u64,
+    }
+
+    impl From<RedirectBucket> for BucketObject {
+        fn from(bucket: RedirectBucket) -> Self {
+            let resolution = match bucket.resolution {
+                Resolution::Minute => ResolutionObject::Minute,
+                Resolution::Hour => ResolutionObject::Hour,
+                Resolution::Day => ResolutionObject::Day,
+            };
+            Self { start: bucket.start, resolution, redirects: bucket.redirects }
+        }
+    }
+
+    /// Length of the period of a redirect bucket.
+    #[derive(Clone, Copy, PartialEq, Eq, Enum)]
+    #[graphql(name = "Resolution")]
+    pub enum ResolutionObject {
+        Minute,
+        Hour,
+        Day,
+    }
+
+    /// Redirects from a country.
+    #[derive(SimpleObject)]
+    #[graphql(name = "CountryClicks")]
+    pub struct CountryClicksObject {
+        country: String,
+        redirects: u64,
+    }
+
+    async fn dispatch_command(ctx: &Context<'_>, command: Command) -> Result<Reply, ShortenerError> {
+        let api_key = ctx.data_opt::<Credentials>().and_then(|credentials| credentials.0.clone());
+        ctx.data_unchecked::<ServiceHandle>()
+            .call(move |service| {
+                let context = service.authenticate(api_key.as_deref())?;
+                service.dispatch_command(&context, command)
+            })
+            .await
+            .and_then(|reply| reply)
+    }
+
+    /// Dispatches the query on behalf of the owner of the API key, if one is
+    /// given.
+    async fn dispatch_query(ctx: &Context<'_>, query: Query) -> Result<Reply, ShortenerError> {
+        let api_key = ctx.data_opt::<Credentials>().and_then(|credentials| credentials.0.clone());
+        ctx.data_unchecked::<ServiceHandle>()
+            .call(move |service| {
+                let context = match api_key {
+                    Some(api_key) => service.authenticate(Some(&api_key))?,
+                    None => RequestContext::default(),
+                };
+                service.dispatch_query(&context, query)
+            })
+            .await
+            .and_then(|reply| reply)
+    }
+
+    async fn list(ctx: &Context<'_>, query: Query) -> async_graphql::Result<Vec<LinkObject>> {
+        match dispatch_query(ctx, query).await {
+            Ok(Reply::LinkInfos(links)) => Ok(links.into_iter().map(LinkObject::from).collect()),
+            reply => Err(unexpected(reply)),
+        }
+    }
+
+    async fn done(ctx: &Context<'_>, command: Command) -> async_graphql::Result<bool> {
+        match dispatch_command(ctx, command).await {
+            Ok(Reply::Done) => Ok(true),
+            reply => Err(unexpected(reply)),
+        }
+    }
+
+    /// Returns the error of a field failing with the error.
+    pub fn error(error: &ShortenerError) -> Error {
+        Error::new(error.to_string()).extend_with(|_, extensions| extensions.set("code", error.code()))
+    }
+
+    fn unexpected(reply: Result<Reply, ShortenerError>) -> Error {
+        match reply {
+            Ok(reply) => Error::new(format!("unexpected reply {reply:?}")),
+            Err(e) => error(&e),
+        }
+    }
+}
+
+/// Partitioning of slugs between several instances of the service.
+///
+/// Every slug is owned by exactly one instance chosen by a
+/// [`PartitionRouter`]. [`PartitionedShortener`] handles requests for slugs
+/// owned by the local instance itself and forwards the rest to their owners,
+/// e.g. through `RemoteShortener` of the `client` feature, so writes scale out
+/// beyond one process.
+pub mod partitioning {
+    use std::collections::HashMap;
+
+    use sha2::{Digest, Sha256};
+
+    use super::{
+        commands::CommandHandler, queries::QueryHandler, slugs, ShortLink, ShortenerError, Slug, Stats, Url, SLUG_LEN,
+    };
+
+    /// Identifier of an instance of the service.
+    #[derive(Clone, Debug, PartialEq, Eq, Hash)]
+    pub struct InstanceId(pub String);
+
+    /// Assignment of slugs to instances. All instances must use the same
+    /// assignment.
+    pub trait PartitionRouter {
+        /// Returns the instance owning the slug.
+        fn owner(&self, slug: &Slug) -> Option<&InstanceId>;
+    }
+
+    /// Returns the hash partitions are assigned by. It is stable across
+    /// processes and versions. Slugs are hashed by their key with homoglyphs
+    /// folded and letters lowercased, see [`slugs::key`], so slugs which are
+    /// the same slug under any settings of the service have the same owner.
+    pub fn slug_hash(slug: &Slug) -> u64 {
+        hash(&partition_key(slug))
+    }
+
+    fn partition_key(slug: &Slug) -> String {
+        slugs::key(&slug.0, true, true)
+    }
+
+    fn hash(key: &str) -> u64 {
+        let digest = Sha256::digest(key.as_bytes());
+        u64::from_be_bytes(digest[..8].try_into().expect("digest is longer than 8 bytes"))
+    }
+
+    /// Static assignment of slug hash ranges to instances.
+    #[derive(Clone, Debug, Default, PartialEq)]
+    pub struct StaticRanges {
+        // inclusive upper bounds of ranges with their owners, ordered by bounds
+        ranges: Vec<(u64, InstanceId)>,
+    }
+
+    impl StaticRanges {
+        /// Creates an assignment from inclusive upper bounds of ranges. Slugs
+        /// whose hash is above all bounds have no owner.
+        pub fn new(mut ranges: Vec<(u64, InstanceId)>) -> Self {
+            ranges.sort_by_key(|(bound, _)| *bound);
+            Self { ranges }
+        }
+
+        /// Splits the whole hash space into equal ranges, one per instance.
+        pub fn even(instances: Vec<InstanceId>) -> Self {
+            let count = instances.len() as u64;
+            let ranges = instances.into_iter()
+                .enumerate()
+                .map(|(index, instance)| {
+                    let index = index as u64;
+                    let bound = if index + 1 == count { u64::MAX } else { (u64::MAX / count) * (index + 1) };
+                    (bound, instance)
+                })
+                .collect();
+            Self { ranges }
+        }
+    }
+
+    impl PartitionRouter for StaticRanges {
+        fn owner(&self, slug: &Slug) -> Option<&InstanceId> {
+            let hash = slug_hash(slug);
+            let index = self.ranges.partition_point(|(bound, _)| *bound < hash);
+            self.ranges.get(index).map(|(_, instance)| instance)
+        }
+    }
+
+    /// Assignment by rendezvous (highest random weight) hashing, adding or
+    /// removing an instance moves only the slugs it gains or loses.
+    #[derive(Clone, Debug, Default, PartialEq)]
+    pub struct RendezvousRouter {
+        /// Instances slugs are spread between.
+        pub instances: Vec<InstanceId>,
+    }
+
+    impl PartitionRouter for RendezvousRouter {
+        fn owner(&self, slug: &Slug) -> Option<&InstanceId> {
+            let key = partition_key(slug);
+            self.instances.iter().max_by_key(|instance| hash(&format!("{}\0{key}", instance.0)))
+        }
+    }
+
+    /// Count of random slugs drawn for a link created without a slug before
+    /// the creation fails, as all of them were in use.
+    pub const CREATE_ATTEMPTS: usize = 8;
+
+    // handler of both commands and queries, local or remote
+    trait Shortener: CommandHandler + QueryHandler {}
+
+    impl<T: CommandHandler + QueryHandler> Shortener for T {}
+
+    /// Service handling slugs owned by the local instance and forwarding the
+    /// rest to their owners.
+    ///
+    /// Links created without a slug get a random slug, and are created by its
+    /// owner. A slug already in use is drawn again, up to [`CREATE_ATTEMPTS`]
+    /// times in total. Uniqueness of URLs is checked by each instance only
+    /// among its own links.
+    pub struct PartitionedShortener<L, R> {
+        local_id: InstanceId,
+        local: L,
+        remotes: HashMap<InstanceId, R>,
+        router: Box<dyn PartitionRouter>,
+    }
+
+    impl<L: CommandHandler + QueryHandler, R: CommandHandler + QueryHandler> PartitionedShortener<L, R> {
+        /// Creates the partitioned service of the local instance.
+        pub fn new<P: PartitionRouter + 'static>(local_id: InstanceId, local: L, router: P) -> Self {
+            Self { local_id, local, remotes: HashMap::new(), router: Box::new(router) }
+        }
+
+        /// Adds the handler requests are forwarded to for slugs owned by the
+        /// remote instance.
+        pub fn with_remote(mut self, id: InstanceId, remote: R) -> Self {
+            self.remotes.insert(id, remote);
+            self
+        }
+
+        /// Returns the handler of the local instance.
+        pub fn local(&self) -> &L {
+            &self.local
+        }
+
+        /// Returns the handler of the remote instance.
+        pub fn remote(&self, id: &InstanceId) -> Option<&R> {
+            self.remotes.get(id)
+        }
+
+        fn handler(&self, slug: &Slug) -> Result<&dyn Shortener, ShortenerError> {
+            match self.router.owner(slug) {
+                Some(owner) if *owner == self.local_id => Some(&self.local as &dyn Shortener),
+                Some(owner) => self.remotes.get(owner).map(|remote| remote as &dyn Shortener),
+                None => None,
+            }
+            .ok_or(ShortenerError::ServiceUnavailable)
+        }
+
+        fn handler_mut(&mut self, slug: &Slug) -> Result<&mut dyn Shortener, ShortenerError> {
+            match self.router.owner(slug) {
+                Some(owner) if *owner == self.local_id => Some(&mut self.local as &mut dyn Shortener),
+                Some(owner) => self.remotes.get_mut(owner).map(|remote| remote as &mut dyn Shortener),
+                None => None,
+            }
+            .ok_or(ShortenerError::ServiceUnavailable)
+        }
+    }
+
+    impl<L: CommandHandler + QueryHandler, R: CommandHandler + QueryHandler> CommandHandler for PartitionedShortener<L, R> {
+        fn handle_create_short_link(
+            &mut self,
+            url: Url,
+            slug: Option<Slug>,
+        ) -> Result<ShortLink, ShortenerError> {
+            if let Some(slug) = slug {
+                return self.handler_mut(&slug)?.handle_create_short_link(url, Some(slug));
+            }
+
+            // the slug must be known to find its owner
+            let mut attempts = CREATE_ATTEMPTS;
+            loop {
+                let slug = Slug(format!("{:016x}", rand::random::<u64>())[..SLUG_LEN].to_string());
+                match self.handler_mut(&slug)?.handle_create_short_link(url.clone(), Some(slug)) {
+                    Err(ShortenerError::SlugAlreadyInUse) if attempts > 1 => attempts -= 1,
+                    created => return created,
+                }
+            }
+        }
+
+        fn handle_redirect(
+            &mut self,
+            slug: Slug,
+        ) -> Result<ShortLink, ShortenerError> {
+            self.handler_mut(&slug)?.handle_redirect(slug)
+        }
+    }
+
+    impl<L: CommandHandler + QueryHandler, R: CommandHandler + QueryHandler> QueryHandler for PartitionedShortener<L, R> {
+        fn get_stats(&self, slug: Slug) -> Result<Stats, ShortenerError> {
+            self.handler(&slug)?.get_stats(slug)
+        }
+    }
+}
+
+/// Test doubles for applications embedding the service.
+///
+/// [`MockShortener`] implements the [`CommandHandler`] and [`QueryHandler`]
+/// traits with scripted responses and records every call, so handlers generic
+/// over these traits can be unit tested without a [`UrlShortenerService`].
+///
+/// [`UrlShortenerService`]: super::UrlShortenerService
+/// [`CommandHandler`]: super::commands::CommandHandler
+/// [`QueryHandler`]: super::queries::QueryHandler
+#[cfg(feature = "testing")]
+pub mod testing {
+    use std::{cell::RefCell, collections::VecDeque};
+
+    use super::{commands::CommandHandler, queries::QueryHandler, ShortLink, ShortenerError, Slug, Stats, Url};
+
+    /// Call received by a [`MockShortener`].
+    #[derive(Clone, Debug, PartialEq)]
+    pub enum Call {
+        /// See [`CommandHandler::handle_create_short_link`].
+        CreateShortLink { url: Url, slug: Option<Slug> },
+
+        /// See [`CommandHandler::handle_redirect`].
+        Redirect { slug: Slug },
+
+        /// See [`QueryHandler::get_stats`].
+        GetStats { slug: Slug },
+    }
+
+    /// Scriptable [`CommandHandler`] and [`QueryHandler`].
+    ///
+    /// Responses are returned in the order they were scripted, separately for
+    /// each method. A call without a scripted response panics, so unexpected
+    /// calls fail the test.
+    #[derive(Debug, Default)]
+    pub struct MockShortener {
+        creations: VecDeque<Result<ShortLink, ShortenerError>>,
+        redirects: VecDeque<Result<ShortLink, ShortenerError>>,
+        // queries take `&self`
+        stats: RefCell<VecDeque<Result<Stats, ShortenerError>>>,
+        calls: RefCell<Vec<Call>>,
+    }
+
+    impl MockShortener {
+        /// Creates a mock without scripted responses.
+        pub fn new() -> Self {
+            Self::default()
+        }
+
+        /// Scripts the response of the next unanswered
+        /// [`CommandHandler::handle_create_short_link`] call.
+        pub fn expect_create_short_link(mut self, response: Result<ShortLink, ShortenerError>) -> Self {
+            self.creations.push_back(response);
+            self
+        }
+
+        /// Scripts the response of the next unanswered
+        /// [`CommandHandler::handle_redirect`] call.
+        pub fn expect_redirect(mut self, response: Result<ShortLink, ShortenerError>) -> Self {
+            self.redirects.push_back(response);
+            self
+        }
+
+        /// Scripts the response of the next unanswered
+        /// [`QueryHandler::get_stats`] call.
+        pub fn expect_get_stats(self, response: Result<Stats, ShortenerError>) -> Self {
+            self.stats.borrow_mut().push_back(response);
+            self
+        }
+
+        /// Returns all received calls in order.
+        pub fn calls(&self) -> Vec<Call> {
+            self.calls.borrow().clone()
+        }
+
+        /// Checks if all scripted responses were consumed.
+        pub fn is_done(&self) -> bool {
+            self.creations.is_empty() && self.redirects.is_empty() && self.stats.borrow().is_empty()
+        }
+
+        fn respond<T>(responses: &mut VecDeque<T>, call: &Call) -> T {
+            responses.pop_front().unwrap_or_else(|| panic!("No response scripted for {call:?}"))
+        }
+    }
+
+    impl CommandHandler for MockShortener {
+        fn handle_create_short_link(
+            &mut self,
+            url: Url,
+            slug: Option<Slug>,
+        ) -> Result<ShortLink, ShortenerError> {
+            let call = Call::CreateShortLink { url, slug };
+            let response = Self::respond(&mut self.creations, &call);
+            self.calls.get_mut().push(call);
+            response
+        }
+
+        fn handle_redirect(
+            &mut self,
+            slug: Slug,
+        ) -> Result<ShortLink, ShortenerError> {
+            let call = Call::Redirect { slug };
+            let response = Self::respond(&mut self.redirects, &call);
+            self.calls.get_mut().push(call);
+            response
+        }
+    }
+
+    impl QueryHandler for MockShortener {
+        fn get_stats(&self, slug: Slug) -> Result<Stats, ShortenerError> {
+            let call = Call::GetStats { slug };
+            let response = Self::respond(&mut self.stats.borrow_mut(), &call);
+            self.calls.borrow_mut().push(call);
+            response
+        }
+    }
+}
+
+/// Built-in read model of the [`UrlShortenerService`], it is always in sync
+/// with the event log.
+#[derive(Clone, Default, PartialEq)]
+struct ReadModel {
+    // links by their ids
+    links: HashMap<LinkId, LinkState>,
+    // ids of links by their slugs (including old slugs forwarding to renamed links), so we can find link in O(1)
+    // instead of scanning all creation events
+    slugs: HashMap<String, LinkId>,
+    // all shortened urls, because we can have only one slug for url (unless the link is cloned explicitly)
+    urls: HashSet<String>,
+    // expiration time of deprecated old slugs of renamed links
+    alias_expirations: HashMap<String, SlugHold<DateTime<Utc>>>,
+    // expiration time of reserved slugs, which are not attached to any url yet
+    reservations: HashMap<String, SlugHold<Option<DateTime<Utc>>>>,
+    // time of the last counted redirect by link and visitor, used for redirect deduplication
+    last_counted_redirects: HashMap<(LinkId, VisitorId), DateTime<Utc>>,
+    // interned slugs referenced by redirect events
+    slug_ids: SlugIds,
+    // destinations and settings of draft links by their reserved slugs
+    drafts: HashMap<String, (Url, LinkOptions)>,
+    // pending scheduled commands by their ids
+    scheduled: BTreeMap<ScheduleId, ScheduledCommand>,
+    // id given to the next scheduled command
+    next_schedule_id: u64,
+    // ids of links merged into other links, mapped to the links they were merged into
+    merged_links: HashMap<LinkId, LinkId>,
+    // ids of deleted links, their events are still in the log
+    deleted_links: HashSet<LinkId>,
+    // groups of links by their ids
+    groups: HashMap<GroupId, GroupState>,
+    // names and owners of namespaces by their keys
+    namespaces: HashMap<String, (String, Option<OwnerId>)>,
+    // hosts links can't point to, including their subdomains
+    blocked_hosts: BTreeSet<String>,
+    // number the next sequential slug is tried with
+    slug_sequence: u64,
+    // ids of API keys which aren't revoked with the contexts they authenticate, by hashes of their secrets
+    api_keys: HashMap<String, (ApiKeyId, RequestContext)>,
+    // tenant whose slugs and urls are looked up, the tenant of the caller or of the applied event
+    tenant: Option<TenantId>,
+    // webhooks of domain events with their tenants by their ids
+    event_webhooks: BTreeMap<WebhookId, (EventWebhook, Option<TenantId>)>,
+    // id given to the next registered webhook of domain events
+    next_webhook_id: u64,
+    // whether slugs differing only in case are the same slug, see `ServiceConfig::case_insensitive_slugs`
+    case_insensitive: bool,
+    // whether slugs differing only in homoglyphs are the same slug, see `ServiceConfig::fold_homoglyph_slugs`
+    fold_homoglyphs: bool,
+}
+
+/// Group of links as seen by the [`ReadModel`].
+#[derive(Clone, PartialEq)]
+struct GroupState {
+    name: String,
+    owner: Option<OwnerId>,
+    tenant: Option<TenantId>,
+    // ids of links in the group, ordered by creation time of links
+    links: BTreeSet<LinkId>,
+}
+
+impl ReadModel {
+    /// Returns id and state of the link the slug maps to.
+    fn info(&self, link_id: &LinkId) -> Option<LinkInfo> {
+        let state = self.links.get(link_id)?;
+        Some(LinkInfo {
+            link_id: link_id.clone(),
+            link: state.link.clone(),
+            original_url: state.original_url.clone(),
+            disabled: state.disabled,
+            password_protected: state.password.is_some(),
+            owner: state.owner.clone(),
+            tags: state.tags.clone(),
+            metadata: state.metadata.clone(),
+            aliases: state.aliases.clone(),
+            one_time: state.one_time,
+            consumed: state.consumed,
+            redirect_type: state.redirect_type,
+            interstitial: state.interstitial,
+            query_params: state.query_params.clone(),
+            webhook_url: state.webhook.as_ref().map(|webhook| webhook.url.clone()),
+            archived: state.archived,
+            group: state.group.clone(),
+            campaign: state.campaign.clone(),
+            blocked: state.blocked.clone(),
+            health: state.health.clone(),
+            preview: state.preview.clone(),
+            click_retention: state.click_retention,
+            anomaly: state.anomaly.clone().map(|(anomaly, _)| anomaly),
+            redirects: state.redirects,
+            tenant: state.tenant.clone(),
+        })
+    }
+
+    fn find(&self, slug: &str) -> Option<(&LinkId, &LinkState)> {
+        let slug = self.key(slug);
+        if self.alias_expirations.get(&slug).is_some_and(|alias| alias.expires_at <= Utc::now()) {
+            return None;
+        }
+
+        let link_id = self.slugs.get(&slug)?;
+        Some((link_id, &self.links[link_id]))
+    }
+
+    /// Returns the key the slug is stored under, slugs with the same key are
+    /// the same slug.
+    fn key(&self, slug: &str) -> String {
+        self.key_in(self.tenant.as_ref(), slug)
+    }
+
+    /// Returns the key the slug of the tenant is stored under.
+    fn key_in(&self, tenant: Option<&TenantId>, slug: &str) -> String {
+        self.scoped(tenant, slugs::key(slug, self.fold_homoglyphs, self.case_insensitive))
+    }
+
+    /// Returns the key the url of the tenant's link is stored under.
+    fn url_key(&self, url: &Url) -> String {
+        self.scoped(self.tenant.as_ref(), url.0.clone())
+    }
+
+    // keys of tenants are prefixed by the tenant and a separator, which slugs and urls can't contain
+    fn scoped(&self, tenant: Option<&TenantId>, key: String) -> String {
+        match tenant {
+            Some(tenant) => format!("{}\u{1f}{key}", tenant.0),
+            None => key,
+        }
+    }
+
+    /// Returns links of the tenant the model is scoped to.
+    fn tenant_links(&self) -> impl Iterator<Item = (&LinkId, &LinkState)> {
+        self.links.iter().filter(|(_, state)| state.tenant == self.tenant)
+    }
+
+    /// Returns the link, if it belongs to the tenant the model is scoped to.
+    fn tenant_link(&self, link_id: &LinkId) -> Option<&LinkState> {
+        self.links.get(link_id).filter(|state| state.tenant == self.tenant)
+    }
+
+    /// Returns the group, if it belongs to the tenant the model is scoped to.
+    fn tenant_group(&self, group_id: &GroupId) -> Option<&GroupState> {
+        self.groups.get(group_id).filter(|group| group.tenant == self.tenant)
+    }
+
+    /// Returns the owner of the reservation of the slug, if it is reserved.
+    fn reservation_owner(&self, slug: &str) -> Option<&OwnerId> {
+        self.reservations.get(&self.key(slug)).and_then(|hold| hold.owner.as_ref())
+    }
+
+    /// Returns the id of the slug of the link, even if the slug is typed in
+    /// another case than it was interned with.
+    fn slug_id(&self, link_id: &LinkId, slug: &Slug) -> Option<SlugId> {
+        self.slug_ids.id(link_id, slug).or_else(|| {
+            let key = self.key(&slug.0);
+            self.slug_ids.slugs_of(link_id).find(|(_, interned)| self.key(&interned.0) == key).map(|(id, _)| id)
+        })
+    }
+
+    fn apply(&mut self, record: &EventRecord) {
+        let scope = std::mem::replace(&mut self.tenant, record.tenant.clone());
+        self.apply_scoped(record);
+        self.tenant = scope;
+    }
+
+    fn apply_scoped(&mut self, record: &EventRecord) {
+        self.slug_ids.apply(record);
+        match &record.event {
+            Event::LinkCreated { link_id, slug, url, original_url, owner, options } => {
+                self.reservations.remove(&self.key(&slug.0));
+                self.drafts.remove(&self.key(&slug.0));
+                self.urls.insert(self.url_key(url));
+                self.slugs.insert(self.key(&slug.0), link_id.clone());
+                self.links.insert(link_id.clone(), LinkState {
+                    link: ShortLink { slug: slug.clone(), url: url.clone() },
+                    original_url: original_url.clone(),
+                    disabled: false,
+                    password: None,
+                    owner: owner.clone(),
+                    tags: options.tags.iter().cloned().collect(),
+                    metadata: options.metadata.clone(),
+                    one_time: options.one_time,
+                    consumed: false,
+                    redirect_type: options.redirect_type,
+                    interstitial: false,
+                    query_params: Vec::new(),
+                    aliases: Vec::new(),
+                    webhook: None,
+                    redirects: 0,
+                    bot_redirects: 0,
+                    conversions: 0,
+                    conversion_ids: HashSet::new(),
+                    visitors: HyperLogLog::default(),
+                    redirects_by_slug: HashMap::new(),
+                    refused: BTreeMap::new(),
+                    archived: false,
+                    group: None,
+                    campaign: None,
+                    click_retention: None,
+                    anomaly: None,
+                    blocked: None,
+                    health: DestinationHealth::Unchecked,
+                    preview: None,
+                    last_active_at: record.recorded_at,
+                    tenant: record.tenant.clone(),
+                });
+            },
+            Event::Redirected { slug_id, visitor, context, bot } => {
+                let Some((link_id, _)) = self.slug_ids.resolve(*slug_id) else {
+                    return;
+                };
+                if let Some(state) = self.links.get_mut(link_id) {
+                    match (visitor, context.as_deref()) {
+                        (Some(visitor), _) => state.visitors.insert(&visitor.0),
+                        (None, Some(RedirectContext { ip: Some(ip), user_agent, .. })) => {
+                            state.visitors.insert(&(ip, user_agent));
+                        },
+                        _ => {},
+                    }
+                    state.redirects += 1;
+                    state.bot_redirects += u64::from(*bot);
+                    *state.redirects_by_slug.entry(*slug_id).or_default() += 1;
+                    state.last_active_at = record.recorded_at;
+                }
+                let visitor = visitor.clone().or_else(|| context.as_deref().and_then(analytics::fingerprint));
+                if let Some(visitor) = visitor {
+                    self.last_counted_redirects.insert((link_id.clone(), visitor), record.recorded_at);
+                }
+            },
+            Event::RedirectDeduplicated { .. } => {},
+            Event::RedirectsAggregated { link_id, redirects, bot_redirects, visitors, .. } => {
+                if let Some(state) = self.links.get_mut(link_id) {
+                    state.visitors.merge(visitors);
+                    for (slug_id, redirects) in redirects {
+                        state.redirects += redirects;
+                        *state.redirects_by_slug.entry(*slug_id).or_default() += redirects;
+                    }
+                    state.bot_redirects += bot_redirects;
+                    state.last_active_at = record.recorded_at;
+                }
+            },
+            Event::LinkConsumed { link_id, .. } => {
+                if let Some(state) = self.links.get_mut(link_id) {
+                    state.consumed = true;
+                }
+            },
+            Event::SlugReserved { slug, owner, expires_at } => {
+                let tenant = self.tenant.clone();
+                let hold = SlugHold { tenant, owner: owner.clone(), slug: slug.clone(), expires_at: *expires_at };
+                self.reservations.insert(self.key(&slug.0), hold);
+            },
+            Event::LinkPrepared { slug, owner, url, options, expires_at } => {
+                let tenant = self.tenant.clone();
+                let hold = SlugHold { tenant, owner: owner.clone(), slug: slug.clone(), expires_at: Some(*expires_at) };
+                self.reservations.insert(self.key(&slug.0), hold);
+                self.drafts.insert(self.key(&slug.0), (url.clone(), options.clone()));
+            },
+            Event::SlugReservationExpired { slug } => {
+                self.reservations.remove(&self.key(&slug.0));
+                self.drafts.remove(&self.key(&slug.0));
+            },
+            Event::SlugRenamed { link_id, old_slug, new_slug, old_slug_released, old_slug_expires_at } => {
+                if *old_slug_released {
+                    self.slugs.remove(&self.key(&old_slug.0));
+                }
+                if let Some(expires_at) = old_slug_expires_at {
+                    let hold = SlugHold {
+                        tenant: self.tenant.clone(),
+                        owner: self.links.get(link_id).and_then(|state| state.owner.clone()),
+                        slug: old_slug.clone(),
+                        expires_at: *expires_at,
+                    };
+                    self.alias_expirations.insert(self.key(&old_slug.0), hold);
+                }
+                self.alias_expirations.remove(&self.key(&new_slug.0));
+                self.slugs.insert(self.key(&new_slug.0), link_id.clone());
+                if let Some(state) = self.links.get_mut(link_id) {
+                    state.link.slug = new_slug.clone();
+                    state.aliases.retain(|alias| alias != new_slug);
+                }
+            },
+            Event::AliasAdded { link_id, alias, .. } => {
+                self.reservations.remove(&self.key(&alias.0));
+                self.slugs.insert(self.key(&alias.0), link_id.clone());
+                if let Some(state) = self.links.get_mut(link_id) {
+                    state.aliases.push(alias.clone());
+                }
+            },
+            Event::SlugAliasExpired { slug, .. } => {
+                self.alias_expirations.remove(&self.key(&slug.0));
+                self.slugs.remove(&self.key(&slug.0));
+            },
+            Event::RedirectRefused { link_id, reason, .. } => {
+                if let Some(state) = self.links.get_mut(link_id) {
+                    *state.refused.entry(*reason).or_default() += 1;
+                }
+            },
+            Event::RedirectsImported { link_id, slug, at, redirects } => {
+                let slug_id = self.slug_id(link_id, slug);
+                if let Some(state) = self.links.get_mut(link_id) {
+                    state.redirects += redirects;
+                    if let Some(slug_id) = slug_id {
+                        *state.redirects_by_slug.entry(slug_id).or_default() += redirects;
+                    }
+                    state.last_active_at = state.last_active_at.max(*at);
+                }
+            },
+            Event::StatsReset { link_id, .. } => {
+                if let Some(state) = self.links.get_mut(link_id) {
+                    state.redirects = 0;
+                    state.bot_redirects = 0;
+                    state.conversions = 0;
+                    state.visitors.clear();
+                    state.redirects_by_slug.clear();
+                    state.refused.clear();
+                }
+            },
+            Event::PasswordSet { link_id, password, .. } => {
+                if let Some(state) = self.links.get_mut(link_id) {
+                    state.password = Some(password.clone());
+                }
+            },
+            Event::PasswordRemoved { link_id, .. } => {
+                if let Some(state) = self.links.get_mut(link_id) {
+                    state.password = None;
+                }
+            },
+            Event::PasswordAttemptFailed { .. } => {},
+            Event::LinkTagged { link_id, tags, .. } => {
+                if let Some(state) = self.links.get_mut(link_id) {
+                    state.tags.extend(tags.iter().cloned());
+                }
+            },
+            Event::LinkUntagged { link_id, tags, .. } => {
+                if let Some(state) = self.links.get_mut(link_id) {
+                    state.tags.retain(|tag| !tags.contains(tag));
+                }
+            },
+            Event::MetadataUpdated { link_id, metadata, .. } => {
+                if let Some(state) = self.links.get_mut(link_id) {
+                    state.metadata = metadata.clone();
+                }
+            },
+            Event::LinksMerged { link_id, merged_link_id, .. } => {
+                let Some(merged) = self.links.remove(merged_link_id) else {
+                    return;
+                };
+                // clones of the duplicate may still point to its url
+                if self.links.values().all(|state| state.link.url != merged.link.url) {
+                    let url = self.url_key(&merged.link.url);
+                    self.urls.remove(&url);
+                }
+                if let Some(group) = merged.group.as_ref().and_then(|group_id| self.groups.get_mut(group_id)) {
+                    group.links.remove(merged_link_id);
+                }
+                self.merged_links.insert(merged_link_id.clone(), link_id.clone());
+                for target in self.slugs.values_mut().filter(|target| *target == merged_link_id) {
+                    *target = link_id.clone();
+                }
+
+                let visitors: Vec<_> = self.last_counted_redirects
+                    .keys()
+                    .filter(|(visited, _)| visited == merged_link_id)
+                    .cloned()
+                    .collect();
+                for key in visitors {
+                    let Some(visited_at) = self.last_counted_redirects.remove(&key) else {
+                        continue;
+                    };
+                    let last = self.last_counted_redirects.entry((link_id.clone(), key.1)).or_insert(visited_at);
+                    *last = (*last).max(visited_at);
+                }
+
+                if let Some(state) = self.links.get_mut(link_id) {
+                    state.redirects += merged.redirects;
+                    state.bot_redirects += merged.bot_redirects;
+                    state.conversions += merged.conversions;
+                    state.conversion_ids.extend(merged.conversion_ids);
+                    state.visitors.merge(&merged.visitors);
+                    for (reason, refused) in merged.refused {
+                        *state.refused.entry(reason).or_default() += refused;
+                    }
+                    for (slug_id, redirects) in merged.redirects_by_slug {
+                        // slugs of the duplicate are interned again for the link it was merged into
+                        let slug_id = self.slug_ids.resolve(slug_id)
+                            .and_then(|(_, slug)| self.slug_ids.id(link_id, slug))
+                            .unwrap_or(slug_id);
+                        *state.redirects_by_slug.entry(slug_id).or_default() += redirects;
+                    }
+                    state.aliases.push(merged.link.slug);
+                    state.aliases.extend(merged.aliases);
+                    state.last_active_at = state.last_active_at.max(merged.last_active_at);
+                }
+            },
+            Event::WebhookSet { link_id, webhook, .. } => {
+                if let Some(state) = self.links.get_mut(link_id) {
+                    state.webhook = Some(webhook.clone());
+                }
+            },
+            Event::WebhookRemoved { link_id, .. } => {
+                if let Some(state) = self.links.get_mut(link_id) {
+                    state.webhook = None;
+                }
+            },
+            Event::RedirectTypeSet { link_id, redirect_type, .. } => {
+                if let Some(state) = self.links.get_mut(link_id) {
+                    state.redirect_type = *redirect_type;
+                }
+            },
+            Event::InterstitialSet { link_id, enabled, .. } => {
+                if let Some(state) = self.links.get_mut(link_id) {
+                    state.interstitial = *enabled;
+                }
+            },
+            Event::QueryParamsSet { link_id, params, .. } => {
+                if let Some(state) = self.links.get_mut(link_id) {
+                    state.query_params = params.clone();
+                }
+            },
+            Event::GroupCreated { group_id, name, owner } => {
+                self.groups.insert(group_id.clone(), GroupState {
+                    name: name.clone(),
+                    owner: owner.clone(),
+                    tenant: self.tenant.clone(),
+                    links: BTreeSet::new(),
+                });
+            },
+            Event::NamespaceCreated { name, owner } => {
+                self.namespaces.insert(self.key(name), (name.clone(), owner.clone()));
+            },
+            Event::LinkMovedToGroup { link_id, previous_group, group, .. } => {
+                if let Some(previous) = previous_group.as_ref().and_then(|group_id| self.groups.get_mut(group_id)) {
+                    previous.links.remove(link_id);
+                }
+                if let Some(next) = group.as_ref().and_then(|group_id| self.groups.get_mut(group_id)) {
+                    next.links.insert(link_id.clone());
+                }
+                if let Some(state) = self.links.get_mut(link_id) {
+                    state.group = group.clone();
+                }
+            },
+            Event::OwnershipTransferred { link_id, new_owner, .. } => {
+                if let Some(state) = self.links.get_mut(link_id) {
+                    state.owner = Some(new_owner.clone());
+                }
+            },
+            Event::LinkDisabled { link_id, .. } => {
+                if let Some(state) = self.links.get_mut(link_id) {
+                    state.disabled = true;
+                }
+            },
+            Event::LinkEnabled { link_id, .. } => {
+                if let Some(state) = self.links.get_mut(link_id) {
+                    state.disabled = false;
+                }
+            },
+            Event::SlugSequenceAdvanced { next } => {
+                self.slug_sequence = self.slug_sequence.max(*next);
+            },
+            Event::CommandScheduled { scheduled } => {
+                self.next_schedule_id = self.next_schedule_id.max(scheduled.id.0 + 1);
+                self.scheduled.insert(scheduled.id, scheduled.clone());
+            },
+            Event::ScheduledCommandCancelled { id } | Event::ScheduledCommandExecuted { id, .. } => {
+                self.scheduled.remove(id);
+            },
+            Event::LinkArchived { link_id, .. } => {
+                if let Some(state) = self.links.get_mut(link_id) {
+                    state.archived = true;
+                }
+            },
+            Event::LinkUnarchived { link_id, .. } => {
+                if let Some(state) = self.links.get_mut(link_id) {
+                    state.archived = false;
+                    state.last_active_at = record.recorded_at;
+                }
+            },
+            Event::HostBlocked { host } => {
+                self.blocked_hosts.insert(host.clone());
+            },
+            Event::ApiKeyCreated { key_id, actor, role, secret_hash } => {
+                let context = RequestContext {
+                    actor: Some(actor.clone()),
+                    tenant: record.tenant.clone(),
+                    role: Some(*role),
+                };
+                self.api_keys.insert(secret_hash.clone(), (key_id.clone(), context));
+            },
+            Event::ApiKeyRevoked { key_id } => {
+                self.api_keys.retain(|_, (id, _)| id != key_id);
+            },
+            Event::WebhookRegistered { webhook_id, webhook } => {
+                self.event_webhooks.insert(*webhook_id, (webhook.clone(), record.tenant.clone()));
+                self.next_webhook_id = self.next_webhook_id.max(webhook_id.0 + 1);
+            },
+            Event::WebhookUnregistered { webhook_id } => {
+                self.event_webhooks.remove(webhook_id);
+            },
+            Event::LinkBlocked { link_id, reason, .. } => {
+                if let Some(state) = self.links.get_mut(link_id) {
+                    state.blocked = Some(reason.clone());
+                }
+            },
+            Event::DestinationHealthy { link_id, .. } => {
+                if let Some(state) = self.links.get_mut(link_id) {
+                    state.health = DestinationHealth::Healthy { checked_at: record.recorded_at };
+                }
+            },
+            Event::DestinationBroken { link_id, reason, .. } => {
+                if let Some(state) = self.links.get_mut(link_id) {
+                    state.health = DestinationHealth::Broken { checked_at: record.recorded_at, reason: reason.clone() };
+                }
+            },
+            Event::MetadataFetched { link_id, preview, .. } => {
+                if let Some(state) = self.links.get_mut(link_id) {
+                    state.preview = Some(preview.clone());
+                }
+            },
+            Event::CampaignAssigned { link_id, campaign, .. } => {
+                if let Some(state) = self.links.get_mut(link_id) {
+                    state.campaign = campaign.clone();
+                }
+            },
+            Event::ClickRetentionSet { link_id, retention, .. } => {
+                if let Some(state) = self.links.get_mut(link_id) {
+                    state.click_retention = *retention;
+                }
+            },
+            Event::LinkDeleted { link_id, .. } => {
+                let Some(deleted) = self.links.remove(link_id) else {
+                    return;
+                };
+                // clones of the link may still point to its url
+                if self.links.values().all(|state| state.link.url != deleted.link.url) {
+                    let url = self.url_key(&deleted.link.url);
+                    self.urls.remove(&url);
+                }
+                if let Some(group) = deleted.group.as_ref().and_then(|group_id| self.groups.get_mut(group_id)) {
+                    group.links.remove(link_id);
+                }
+                // the slug, aliases and old slugs of the link are free again
+                let slugs: Vec<_> = self.slugs
+                    .iter()
+                    .filter(|(_, target)| *target == link_id)
+                    .map(|(slug, _)| slug.clone())
+                    .collect();
+                for slug in slugs {
+                    self.slugs.remove(&slug);
+                    self.alias_expirations.remove(&slug);
+                }
+                self.last_counted_redirects.retain(|(visited, _), _| visited != link_id);
+                self.deleted_links.insert(link_id.clone());
+            },
+            Event::AnomalyDetected { link_id, anomaly, .. } => {
+                if let Some(state) = self.links.get_mut(link_id) {
+                    state.anomaly = Some((anomaly.clone(), record.recorded_at));
+                }
+            },
+            Event::ConversionRecorded { link_id, conversion_id, .. } => {
+                if let Some(state) = self.links.get_mut(link_id) {
+                    state.conversions += 1;
+                    state.conversion_ids.insert(conversion_id.clone());
+                }
+            },
+        }
+    }
+}
+
+/// Per-link index of the event log, so events of a single link are found
+/// without scanning the whole log.
+#[derive(Default)]
+struct EventIndex {
+    streams: HashMap<LinkId, LinkStream>,
+}
+
+#[derive(Default)]
+struct LinkStream {
+    // version of the last recorded event of the link
+    version: u64,
+    // version and sequence number of retained events of the link
+    events: Vec<(u64, u64)>,
+}
+
+impl EventIndex {
+    fn push(&mut self, link_id: &LinkId, sequence: u64) {
+        let stream = self.streams.entry(link_id.clone()).or_default();
+        stream.version += 1;
+        stream.events.push((stream.version, sequence));
+    }
+
+    /// Returns versions and sequence numbers of retained events of the link,
+    /// starting from the version.
+    fn events_from(&self, link_id: &LinkId, from_version: u64) -> &[(u64, u64)] {
+        self.streams.get(link_id).map_or(&[], |stream| {
+            let from = stream.events.partition_point(|(version, _)| *version < from_version);
+            &stream.events[from..]
+        })
+    }
+
+    /// Drops events which are not in the log anymore.
+    fn retain(&mut self, log: &[EventRecord]) {
+        for stream in self.streams.values_mut() {
+            stream.events.retain(|(_, sequence)| log.binary_search_by_key(sequence, |record| record.sequence).is_ok());
+        }
+    }
+}
+
+/// Configuration of the [`UrlShortenerService`].
+#[derive(Clone, Debug, Default)]
+pub struct ServiceConfig {
+    /// Repeated redirects of the same visitor within this window are served,
+    /// but not counted in stats. Visitors are told apart by their
+    /// [`VisitorId`], or by the fingerprint of their request if it has none,
+    /// see [`analytics::fingerprint`]. Deduplication is disabled if `None`.
+    pub redirect_dedup_window: Option<TimeDelta>,
+
+    /// Bound of the in-memory event log. The log grows unbounded if `None`.
+    pub event_window: Option<EventWindow>,
+
+    /// How addresses of visitors are anonymized before they are recorded with
+    /// redirects. Countries of visitors are resolved from raw addresses
+    /// before they are anonymized, see
+    /// [`UrlShortenerService::set_geo_resolver`].
+    pub ip_anonymization: IpAnonymization,
+
+    /// How long raw redirect events are kept for links without their own
+    /// retention, see [`UrlShortenerService::handle_prune_clicks`]. They are
+    /// kept forever if `None`.
+    pub click_retention: Option<TimeDelta>,
+
+    /// Thresholds of suspicious traffic flagged by
+    /// [`UrlShortenerService::handle_detect_anomalies`].
+    pub anomaly_rules: AnomalyRules,
+
+    /// Links without redirects for this long are archived by
+    /// [`UrlShortenerService::handle_archive_inactive_links`]. Links are never
+    /// archived if `None`.
+    pub archive_after: Option<TimeDelta>,
+
+    /// Strategy generating slugs of links created without a custom one.
+    pub slug_generator: SlugGenerator,
+
+    /// Slugs which can't be used by links, aliases or reservations, e.g.
+    /// [`slugs::SERVICE_ROUTES`]. Generated slugs skip them too.
+    pub blocked_slugs: HashSet<String>,
+
+    /// Rules custom slugs of links, aliases and reservations must follow.
+    pub slug_rules: SlugRules,
+
+    /// Optional steps of normalization applied to destination URLs before
+    /// they are checked for uniqueness and stored.
+    pub url_normalization: UrlNormalization,
+
+    /// What happens when a URL which was already shortened is shortened
+    /// again.
+    pub duplicate_urls: DuplicateUrlPolicy,
+
+    /// Limits destination URLs must follow before they are normalized.
+    pub url_limits: UrlLimits,
+
+    /// Whether destinations are checked against the blocklist of hosts and
+    /// the [`UrlPolicy`] on every redirect, not only when links are created
+    /// or rechecked, so links are blocked as soon as the policy changes.
+    pub check_urls_on_redirect: bool,
+
+    /// Schemes of destination URLs which may be shortened.
+    pub allowed_schemes: SchemeAllowlist,
+
+    /// Hosts short links are served from, e.g. `sho.rt`. Destinations on them
+    /// point back at the service.
+    pub own_hosts: HashSet<String>,
+
+    /// Most redirects of destinations followed when links are created, so
+    /// links point at final destinations without extra hops. Redirects are
+    /// followed by [`UrlShortenerService::set_redirect_follower`], and not at
+    /// all if it is zero.
+    pub max_resolved_redirects: usize,
+
+    /// Whether destinations may point at [`ServiceConfig::own_hosts`]. Chains
+    /// of short links are followed then, and links leading back to
+    /// themselves are rejected. Such destinations are rejected if not set.
+    pub allow_own_links: bool,
+
+    /// Destinations checked more recently than this are skipped by
+    /// [`UrlShortenerService::handle_check_destination_health`], zero checks
+    /// all of them every time.
+    pub health_check_interval: TimeDelta,
+
+    /// Whether slugs differing only in case, like `MyLink` and `mylink`, are
+    /// the same slug, both when checking uniqueness and when resolving
+    /// links. Links keep the casing their slugs were created with.
+    pub case_insensitive_slugs: bool,
+
+    /// Whether slugs differing only in letters looking the same, like latin
+    /// `a` and cyrillic `а`, are the same slug. See [`slugs::fold_homoglyphs`].
+    /// Slugs are compared in NFC regardless of this setting.
+    pub fold_homoglyph_slugs: bool,
+
+    /// Whether generated slugs containing words of [`slugs::DENYLIST`] are
+    /// skipped, so the generator tries another one. Custom slugs are not
+    /// filtered.
+    pub filter_profanity: bool,
+
+    /// Rate limits of clients checked by
+    /// [`UrlShortenerService::check_rate_limit`], unless their tenant has its
+    /// own in [`ServiceConfig::tenant_rate_limits`].
+    pub rate_limits: RateLimits,
+
+    /// Rate limits of clients of the tenants.
+    pub tenant_rate_limits: HashMap<TenantId, RateLimits>,
+
+    /// Retries of failed deliveries to webhooks of domain events, see
+    /// [`UrlShortenerService::handle_deliver_webhooks`].
+    pub webhook_retries: RetryPolicy,
+
+    /// Whether visitors of links flagged by
+    /// [`UrlShortenerService::handle_detect_anomalies`] are shown an
+    /// interstitial page, like visitors of links with
+    /// [`UrlShortenerService::handle_set_interstitial`].
+    pub interstitial_for_flagged: bool,
+
+    /// Whether the service keeps its log to itself instead of printing it to
+    /// stdout, e.g. when stdout carries the output of a command line tool.
+    pub quiet: bool,
+}
+
+/// Bound of the in-memory event log of the [`UrlShortenerService`]. Limits
+/// must not be zero.
+///
+/// Once the log outgrows any of the limits, it is compacted by dropping
+/// state-neutral events, and then the oldest events are pruned, as they are
+/// applied to the [`Snapshot`] of the read model and projections. The log is
+/// cut down to three quarters of the limits, so pruning doesn't run on every
+/// recorded event. The log is left as it is while a projection without a
+/// copy in the snapshot is registered, see [`Projection::snapshot`].
+///
+/// Pruned events are no longer returned by [`UrlShortenerService::events`].
+/// Projections are rebuilt from the snapshot and the events after it, and
+/// clients syncing from a cursor before pruned events get all links as
+/// upserts.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct EventWindow {
+    /// Maximum number of events kept in memory.
+    pub max_events: Option<usize>,
+
+    /// Maximum memory taken by events kept in memory, approximated by the
+    /// size of event records plus the length of their debug representation,
+    /// which covers their strings and other heap-allocated data.
+    pub max_bytes: Option<usize>,
+}
+
+impl EventWindow {
+    /// Whether `len` events taking `bytes` fit the window.
+    fn fits(&self, len: usize, bytes: usize) -> bool {
+        self.max_events.is_none_or(|max| len <= max) && self.max_bytes.is_none_or(|max| bytes <= max)
+    }
+
+    /// Returns the window the log is cut down to once it outgrows this one.
+    fn pruning_target(&self) -> EventWindow {
+        EventWindow {
+            max_events: self.max_events.map(|max| max - max / 4),
+            max_bytes: self.max_bytes.map(|max| max - max / 4),
+        }
+    }
+
+    /// Returns approximate memory taken by the record, or zero if the window
+    /// doesn't limit memory.
+    fn record_size(&self, record: &EventRecord) -> usize {
+        struct Counter(usize);
+
+        impl std::fmt::Write for Counter {
+            fn write_str(&mut self, text: &str) -> std::fmt::Result {
+                self.0 += text.len();
+                Ok(())
+            }
+        }
+
+        if self.max_bytes.is_none() {
+            return 0;
+        }
+        let mut counter = Counter(std::mem::size_of::<EventRecord>());
+        let _ = std::fmt::Write::write_fmt(&mut counter, format_args!("{record:?}"));
+        counter.0
+    }
+}
+
+/// State of the read model and projections of the [`UrlShortenerService`]
+/// after an event of its log, rolled forward by the events pruned to fit the
+/// [`EventWindow`]. The state of the service is rebuilt from the snapshot and
+/// the events after it, see [`UrlShortenerService::restore_from_snapshot`].
+pub struct Snapshot {
+    // sequence number of the last event reflected in the snapshot
+    through: u64,
+    read_model: ReadModel,
+    // copies of projections supporting `Projection::snapshot`
+    projections: ProjectionRunner,
+}
+
+impl Snapshot {
+    /// Returns the sequence number of the last event reflected in the
+    /// snapshot.
+    pub fn through(&self) -> u64 {
+        self.through
+    }
+}
+
+impl Clone for Snapshot {
+    fn clone(&self) -> Self {
+        Snapshot {
+            through: self.through,
+            read_model: self.read_model.clone(),
+            projections: self.projections.snapshot(),
+        }
+    }
+}
+
+/// CQRS and Event Sourcing-based service implementation
+pub struct UrlShortenerService {
+    config: ServiceConfig,
+    // append-only log of events, the only source of truth of the service
+    // together with the read model once old events are pruned
+    events: Vec<EventRecord>,
+    // sequence number of the last recorded event
+    last_sequence: u64,
+    // sequence number of the last pruned event, events up to it are only
+    // reflected in the snapshot
+    pruned_through: u64,
+    // state of the service before the events which are kept
+    snapshot: Snapshot,
+    // approximate memory taken by the event log, tracked only if the event window limits it
+    event_bytes: usize,
+    // sequence number of the last pruned redirect event, redirects up to it
+    // may be only reflected in the read model and projections
+    clicks_pruned_through: u64,
+    // events of each link
+    index: EventIndex,
+    // state built from events, used to validate commands and answer queries
+    read_model: ReadModel,
+    // user-defined projections
+    projections: ProjectionRunner,
+    // subscribers of recorded events
+    bus: EventBus,
+    // policy consulted for dispatched commands and queries
+    policy: Box<dyn AuthorizationPolicy>,
+    // context of the command being dispatched, anonymous outside of dispatching
+    acting: RequestContext,
+    // transport of per-link webhooks
+    webhooks: Box<dyn WebhookSender>,
+    // backend answering queries from its own indexes
+    backend: Box<dyn QueryBackend>,
+    // counters of slug generation, updated while commands are validated
+    slug_generation: Cell<SlugGenerationStats>,
+    // policy consulted for destinations besides the blocklist of hosts
+    url_policy: Box<dyn UrlPolicy>,
+    // check of destinations, they are not checked without one
+    health_probe: Option<Box<dyn HealthProbe>>,
+    // validators of destinations by their lowercase schemes
+    scheme_validators: HashMap<String, Box<dyn SchemeValidator>>,
+    // source of previews of destinations, they are not fetched without one
+    preview_fetcher: Option<Box<dyn PreviewFetcher>>,
+    // source of redirects of destinations, they are not resolved without one
+    redirect_follower: Option<Box<dyn RedirectFollower>>,
+    // source of countries of visitors, they are not resolved without one
+    geo_resolver: Option<Box<dyn GeoResolver>>,
+    // classifier of redirects made by bots
+    bot_classifier: Box<dyn BotClassifier>,
+    // destination of digests, they are produced but not delivered without one
+    digest_sink: Option<Box<dyn DigestSink>>,
+    // token buckets of clients, they are not recorded as events
+    rate_limiter: RateLimiter,
+    // transport of webhooks of domain events, they are queued but not delivered without one
+    webhook_transport: Option<Box<dyn WebhookTransport>>,
+    // deliveries to webhooks of domain events by their ids, they are not recorded as events
+    webhook_deliveries: BTreeMap<DeliveryId, TrackedDelivery>,
+    last_delivery_id: u64,
+    // redirect thresholds reached by links, by webhooks of domain events they already fired for
+    reached_thresholds: HashSet<(WebhookId, LinkId, u64)>,
+    // backend of metrics, they are dropped without one
+    metrics: Box<dyn MetricsRecorder>,
+}
+
+impl Default for UrlShortenerService {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl UrlShortenerService {
+    /// Creates a new instance of the service
+    pub fn new() -> Self {
+        Self::with_config(ServiceConfig::default())
+    }
+
+    /// Creates a new instance of the service with the given configuration.
+    ///
+    /// ## Panics
+    ///
+    /// If a limit of the [`ServiceConfig::event_window`] is zero, which would
+    /// prune the whole event log on every recorded event.
+    pub fn with_config(config: ServiceConfig) -> Self {
+        if let Some(window) = config.event_window {
+            assert!(window.max_events != Some(0) && window.max_bytes != Some(0), "event window must not be empty");
+        }
+        let read_model = ReadModel {
+            case_insensitive: config.case_insensitive_slugs,
+            fold_homoglyphs: config.fold_homoglyph_slugs,
+            ..Default::default()
+        };
+        let snapshot = Snapshot { through: 0, read_model: read_model.clone(), projections: ProjectionRunner::default() };
+        let mut service = Self {
+            config,
+            events: Vec::new(),
+            last_sequence: 0,
+            pruned_through: 0,
+            snapshot,
+            event_bytes: 0,
+            clicks_pruned_through: 0,
+            index: EventIndex::default(),
+            read_model,
+            projections: ProjectionRunner::default(),
+            bus: EventBus::default(),
+            policy: Box::new(AllowAll),
+            acting: RequestContext::default(),
+            webhooks: Box::new(NoWebhooks),
+            backend: Box::new(InMemory),
+            slug_generation: Cell::default(),
+            url_policy: Box::new(AllowAllUrls),
+            health_probe: None,
+            scheme_validators: HashMap::new(),
+            preview_fetcher: None,
+            redirect_follower: None,
+            geo_resolver: None,
+            bot_classifier: Box::new(UserAgentHeuristics),
+            digest_sink: None,
+            rate_limiter: RateLimiter::default(),
+            webhook_transport: None,
+            webhook_deliveries: BTreeMap::new(),
+            last_delivery_id: 0,
+            reached_thresholds: HashSet::new(),
+            metrics: Box::new(NoMetrics),
+        };
+        service.set_scheme_validator("mailto", MailtoValidator);
+        service.set_scheme_validator("tel", TelValidator);
+        service.set_scheme_validator("magnet", MagnetValidator);
+        service.register_builtin(TagIndex::default());
+        service.register_builtin(CountryClicks::default());
+        service.register_builtin(RedirectRollups::default());
+        service.register_builtin(DeviceClicks::default());
+        service.register_builtin(CampaignIndex::default());
+        service
+    }
+
+    /// Registers the built-in projection along with its copy in the snapshot.
+    fn register_builtin<P: Projection + 'static>(&mut self, projection: P) {
+        self.snapshot.projections.register_copy(&projection);
+        self.projections.register(projection, &[]);
+    }
+
+    /// Creates an instance of the service with the state of the recorded
+    /// events, e.g. ones read from a persisted [event log](event_log). The
+    /// records must be in order of their sequences, as they were recorded,
+    /// starting from the first one, and the service continues the log after
+    /// the last one. Events are replayed into the read model and projections
+    /// only, they are not published to subscribers and don't trigger webhooks
+    /// again. Logs of services which pruned their events are restored with
+    /// [`Self::restore_from_snapshot`].
+    pub fn restore(config: ServiceConfig, records: Vec<EventRecord>) -> Self {
+        let mut service = Self::with_config(config);
+        service.replicate(records);
+        service.log(format!("Restored {} events", service.events.len()));
+        service
+    }
+
+    /// Creates an instance of the service with the state of the snapshot,
+    /// and replays the recorded events after it like [`Self::restore`].
+    /// Records up to the snapshot are skipped. Projections without a copy in
+    /// the snapshot can only be registered if their checkpoint is past it.
+    pub fn restore_from_snapshot(config: ServiceConfig, snapshot: Snapshot, records: Vec<EventRecord>) -> Self {
+        let mut service = Self::with_config(config);
+        let Snapshot { through, read_model, projections } = snapshot.clone();
+        service.read_model = read_model;
+        service.projections = projections;
+        service.last_sequence = through;
+        service.pruned_through = through;
+        service.snapshot = snapshot;
+        service.replicate(records);
+        service.log(format!("Restored {} events after snapshot at sequence {through}", service.events.len()));
+        service
+    }
+
+    /// Returns a copy of the snapshot after the last event pruned to fit the
+    /// [`EventWindow`], if events were pruned, e.g. to persist it along with
+    /// [`Self::events`] for [`Self::restore_from_snapshot`].
+    pub fn snapshot(&self) -> Option<Snapshot> {
+        (self.pruned_through > 0).then(|| self.snapshot.clone())
+    }
+
+    /// Applies events recorded by another instance of the service after the
+    /// last event of this one, e.g. ones appended to a shared
+    /// [event log](event_log) since it was restored. Events are applied to
+    /// the read model and projections, and published to subscribers, but
+    /// don't trigger webhooks. Events up to the last one are skipped.
+    pub fn replicate(&mut self, records: Vec<EventRecord>) {
+        let no_tags = BTreeSet::new();
+        for record in records {
+            if record.sequence <= self.last_sequence {
+                continue;
+            }
+            self.read_model.apply(&record);
+            self.projections.dispatch(&record);
+
+            let slug_ids = &self.read_model.slug_ids;
+            let link_id = record.event.link_id(slug_ids);
+            let state = link_id.and_then(|link_id| self.read_model.links.get(link_id));
+            let link_tags = state.map_or(&no_tags, |state| &state.tags);
+            self.bus.publish(&record, record.event.slug(slug_ids), link_id, link_tags);
+            if let Some(link_id) = link_id {
+                self.index.push(link_id, record.sequence);
+            }
+            self.last_sequence = record.sequence;
+            self.event_bytes += self.config.event_window.map_or(0, |window| window.record_size(&record));
+            self.events.push(record);
+        }
+        self.enforce_event_window();
+    }
+
+    /// Returns recorded events in order. Events dropped to fit the
+    /// [`EventWindow`] are not returned.
+    pub fn events(&self) -> &[EventRecord] {
+        &self.events
+    }
+
+    /// Returns the sequence number of the last redirect event dropped by
+    /// [`Self::handle_prune_clicks`], 0 if none was dropped. Persisted logs
+    /// holding records up to it must be replaced with [`Self::events`],
+    /// since aggregates of dropped redirects take their places in the log.
+    pub fn clicks_pruned_through(&self) -> u64 {
+        self.clicks_pruned_through
+    }
+
+    /// Returns the slug the event is related to, with slugs of redirects
+    /// resolved, e.g. to display events.
+    pub fn slug_of<'a>(&'a self, event: &'a Event) -> Option<&'a Slug> {
+        event.slug(&self.read_model.slug_ids)
+    }
+
+    /// Returns recorded events matching the filter in order, e.g. to inspect
+    /// the event log. Events are matched like for subscribers, but with the
+    /// current tags of their links.
+    pub fn events_matching<'a>(&'a self, filter: &'a EventFilter) -> impl Iterator<Item = &'a EventRecord> {
+        const NO_TAGS: &BTreeSet<Tag> = &BTreeSet::new();
+        let slug_ids = &self.read_model.slug_ids;
+        self.events.iter().filter(move |record| {
+            let link_id = record.event.link_id(slug_ids);
+            let state = link_id.and_then(|link_id| self.read_model.links.get(link_id));
+            let tags = state.map_or(NO_TAGS, |state| &state.tags);
+            filter.matches(record, record.event.slug(slug_ids), link_id, tags)
+        })
+    }
+
+    /// Returns recorded events of the tenant in order, e.g. to export data of
+    /// a single tenant. Events of no tenant are returned for `None`.
+    pub fn events_of_tenant<'a>(&'a self, tenant: Option<&'a TenantId>) -> impl Iterator<Item = &'a EventRecord> {
+        self.events.iter().filter(move |record| record.tenant.as_ref() == tenant)
+    }
+
+    /// Registers a user-defined [`Projection`]. The projection is caught up
+    /// with already recorded events (starting after its
+    /// [`Projection::checkpoint`]) and then receives every new event.
+    ///
+    /// ## Errors
+    ///
+    /// - [`ShortenerError::ProjectionAlreadyRegistered`] if there is already
+    ///   a projection with the same name.
+    /// - [`ShortenerError::HistoryPruned`] if events after the checkpoint of
+    ///   the projection were already pruned to fit the [`EventWindow`].
+    pub fn register_projection<P: Projection + 'static>(&mut self, projection: P) -> Result<(), ShortenerError> {
+        if self.projections.contains(projection.name()) {
+            self.log(format!("Failed to register projection {}: name is already in use", projection.name()));
+            return Err(ShortenerError::ProjectionAlreadyRegistered);
+        }
+        if projection.checkpoint() < self.pruned_through {
+            self.log(format!("Failed to register projection {}: events were pruned", projection.name()));
+            return Err(ShortenerError::HistoryPruned);
+        }
+
+        self.log(format!("Registered projection {}", projection.name()));
+        self.snapshot.projections.register_copy(&projection);
+        self.projections.register(projection, &self.events);
+        Ok(())
+    }
+
+    /// Returns the registered projection of the given type.
+    pub fn projection<P: Projection + 'static>(&self) -> Option<&P> {
+        self.projections.get::<P>()
+    }
+
+    /// Returns the sequence number of the last event applied to the
+    /// projection with the given name.
+    pub fn projection_checkpoint(&self, name: &str) -> Option<u64> {
+        self.projections.checkpoint(name)
+    }
+
+    /// Returns how far every registered projection is behind the event log,
+    /// in the order they were registered.
+    pub fn projection_lags(&self) -> Vec<ProjectionLag> {
+        self.projections.lags(self.last_sequence)
+    }
+
+    /// Drops the state of the projection with the given name and replays the
+    /// whole event log into it. Projections supporting
+    /// [`Projection::snapshot`] are restored from their copy in the
+    /// [`Snapshot`] instead and the events after it are replayed, so they can
+    /// be rebuilt once events were pruned to fit the [`EventWindow`].
+    ///
+    /// ## Errors
+    ///
+    /// - [`ShortenerError::ProjectionNotFound`] if there is no projection
+    ///   with such name.
+    /// - [`ShortenerError::HistoryPruned`] if events were pruned, but the
+    ///   projection has no copy in the snapshot.
+    pub fn rebuild_projection(&mut self, name: &str) -> Result<(), ShortenerError> {
+        let pruned = self.pruned_through > 0;
+        if let Err(error) = self.projections.rebuild(name, &self.snapshot.projections, pruned, &self.events) {
+            self.log(format!("Failed to rebuild projection {name}: {error}"));
+            return Err(error);
+        }
+
+        self.log(format!("Rebuilt projection {name}"));
+        Ok(())
+    }
+
+    /// Replaces the backend queries are pushed down to. All queries are
+    /// answered from memory by default.
+    pub fn set_query_backend<B: QueryBackend + 'static>(&mut self, backend: B) {
+        self.backend = Box::new(backend);
+    }
+
+    /// Returns interned slugs referenced by redirect events.
+    pub fn slug_ids(&self) -> &SlugIds {
+        &self.read_model.slug_ids
+    }
+
+    /// Replaces the policy consulted for destinations of new links, and of
+    /// followed links if [`ServiceConfig::check_urls_on_redirect`] is set.
+    /// All destinations not on the blocklist of hosts are allowed by default.
+    /// Existing links are checked against the new policy by
+    /// [`Self::handle_recheck_destinations`].
+    pub fn set_url_policy<P: UrlPolicy + 'static>(&mut self, policy: P) {
+        self.url_policy = Box::new(policy);
+    }
+
+    /// Sets the validator of destinations with the scheme, replacing the
+    /// previous one. The scheme must be allowed by
+    /// [`ServiceConfig::allowed_schemes`] as well.
+    pub fn set_scheme_validator<V: SchemeValidator + 'static>(&mut self, scheme: &str, validator: V) {
+        self.scheme_validators.insert(scheme.to_ascii_lowercase(), Box::new(validator));
+    }
+
+    /// Sets the follower resolving redirects of destinations of new links,
+    /// see [`ServiceConfig::max_resolved_redirects`].
+    pub fn set_redirect_follower<F: RedirectFollower + 'static>(&mut self, follower: F) {
+        self.redirect_follower = Some(Box::new(follower));
+    }
+
+    /// Sets the resolver of countries of visitors following links, see
+    /// [`Self::get_clicks_by_country`]. Countries are not resolved until it
+    /// is set.
+    pub fn set_geo_resolver<R: GeoResolver + 'static>(&mut self, resolver: R) {
+        self.geo_resolver = Some(Box::new(resolver));
+    }
+
+    /// Replaces the classifier of redirects made by bots, which are counted
+    /// apart in [`StatsBreakdown::human_redirects`]. [`UserAgentHeuristics`]
+    /// are used by default.
+    pub fn set_bot_classifier<C: BotClassifier + 'static>(&mut self, classifier: C) {
+        self.bot_classifier = Box::new(classifier);
+    }
+
+    /// Sets the sink receiving digests of [`Self::handle_send_digest`].
+    /// Digests are not delivered until it is set.
+    pub fn set_digest_sink<S: DigestSink + 'static>(&mut self, sink: S) {
+        self.digest_sink = Some(Box::new(sink));
+    }
+
+    /// Sets the fetcher used by [`Self::handle_fetch_previews`]. Previews are
+    /// not fetched until it is set.
+    pub fn set_preview_fetcher<F: PreviewFetcher + 'static>(&mut self, fetcher: F) {
+        self.preview_fetcher = Some(Box::new(fetcher));
+    }
+
+    /// Sets the probe used by [`Self::handle_check_destination_health`].
+    /// Destinations are not checked until it is set.
+    pub fn set_health_probe<P: HealthProbe + 'static>(&mut self, probe: P) {
+        self.health_probe = Some(Box::new(probe));
+    }
+
+    /// Replaces the transport of per-link webhooks. Deliveries are dropped by
+    /// default.
+    pub fn set_webhook_sender<S: WebhookSender + 'static>(&mut self, sender: S) {
+        self.webhooks = Box::new(sender);
+    }
+
+    /// Replaces the backend of metrics of the service: counts of created links,
+    /// redirects and errors, latency of commands, size of the event log and
+    /// lag of projections. Metrics are dropped by default.
+    pub fn set_metrics_recorder<R: MetricsRecorder + 'static>(&mut self, recorder: R) {
+        self.metrics = Box::new(recorder);
+    }
+
+    /// Sets the transport used by [`Self::handle_deliver_webhooks`].
+    /// Deliveries to webhooks of domain events are queued, but not delivered
+    /// until it is set.
+    pub fn set_webhook_transport<T: WebhookTransport + 'static>(&mut self, transport: T) {
+        self.webhook_transport = Some(Box::new(transport));
+    }
+
+    /// Subscribes to events matching the filter. Only events recorded after
+    /// the subscription are delivered. Subscription is dropped automatically
+    /// once its receiver is dropped.
+    pub fn subscribe(&mut self, filter: EventFilter) -> Subscription {
+        let subscription = self.bus.subscribe(filter);
+        self.log(format!("Created subscription {:?}", subscription.id));
+        subscription
+    }
+
+    /// Subscribes to redirects matching the filter, e.g. of a slug with
+    /// [`EventFilter::with_slug`] or of links with a tag with
+    /// [`EventFilter::with_tag`]. Kinds of events in the filter are ignored.
+    /// Only redirects recorded after the subscription are delivered, counted
+    /// ones only. Subscription is dropped automatically once the feed is
+    /// dropped.
+    pub fn subscribe_clicks(&mut self, filter: EventFilter) -> ClickFeed {
+        let feed = self.bus.subscribe_clicks(filter);
+        self.log(format!("Created click feed {:?}", feed.id));
+        feed
+    }
+
+    /// Cancels the subscription. Returns `false` if there is no such
+    /// subscription.
+    pub fn unsubscribe(&mut self, id: SubscriptionId) -> bool {
+        self.bus.unsubscribe(id)
+    }
+
+    /// Returns a future resolving with stats of the link once it reaches
+    /// `threshold` redirects, counting the ones it already has. The future is
+    /// driven by the event bus, so it doesn't poll the service.
+    ///
+    /// ## Errors
+    ///
+    /// [`ShortenerError::SlugNotFound`] if the [`Slug`] doesn't map to any
+    /// short link. The future resolves with:
+    /// - [`ShortenerError::TimedOut`] if the threshold isn't reached within
+    ///   `timeout`.
+    /// - [`ShortenerError::ServiceUnavailable`] if the service is dropped
+    ///   before the threshold is reached.
+    pub fn wait_for_redirects(
+        &mut self,
+        slug: &Slug,
+        threshold: u64,
+        timeout: TimeDelta,
+    ) -> Result<WaitForRedirects, ShortenerError> {
+        let Some((link_id, state)) = self.read_model.find(&slug.0) else {
+            self.log(format!("Failed to wait for redirects of slug {slug:?}: slug not found"));
+            return Err(ShortenerError::SlugNotFound);
+        };
+
+        let stats = state.stats();
+        let filter = EventFilter::all()
+            .with_kinds([
+                EventKind::Redirected,
+                EventKind::RedirectsImported,
+                EventKind::StatsReset,
+                EventKind::SlugRenamed,
+            ])
+            .with_link(link_id.clone());
+        let subscription = self.subscribe(filter);
+        let deadline = Instant::now() + timeout.to_std().unwrap_or_default();
+        self.log(format!("Waiting for {threshold} redirects of slug {slug:?}"));
+        Ok(WaitForRedirects::new(subscription, stats, threshold, deadline))
+    }
+
+    /// Records the event, applies it to all projections and delivers it to
+    /// subscribers.
+    fn record(&mut self, event: Event) {
+        self.last_sequence += 1;
+        let tenant = match event.link_id(&self.read_model.slug_ids).and_then(|id| self.read_model.links.get(id)) {
+            Some(state) => state.tenant.clone(),
+            None => self.acting.tenant.clone(),
+        };
+        let record = EventRecord {
+            sequence: self.last_sequence,
+            recorded_at: Utc::now(),
+            actor: self.acting.actor.clone(),
+            tenant,
+            event,
+        };
+        self.read_model.apply(&record);
+        self.projections.dispatch(&record);
+
+        let no_tags = BTreeSet::new();
+        let slug_ids = &self.read_model.slug_ids;
+        let link_tags = record.event.link_id(slug_ids)
+            .and_then(|link_id| self.read_model.links.get(link_id))
+            .map_or(&no_tags, |state| &state.tags);
+        self.bus.publish(&record, record.event.slug(slug_ids), record.event.link_id(slug_ids), link_tags);
+        self.notify_webhook(&record);
+        self.queue_event_webhooks(&record);
+        if let Some(link_id) = record.event.link_id(&self.read_model.slug_ids) {
+            self.index.push(link_id, record.sequence);
+        }
+        self.record_metrics(&record);
+        self.event_bytes += self.config.event_window.map_or(0, |window| window.record_size(&record));
+        self.events.push(record);
+        self.enforce_event_window();
+        self.metrics.set_gauge("shortener_event_store_size", &[], self.events.len() as f64);
+    }
+
+    /// Counts the recorded event in metrics and updates lags of projections.
+    fn record_metrics(&self, record: &EventRecord) {
+        match &record.event {
+            Event::LinkCreated { .. } => self.metrics.increment_counter("shortener_links_created_total", &[], 1),
+            Event::Redirected { .. } => self.metrics.increment_counter("shortener_redirects_total", &[], 1),
+            Event::RedirectRefused { reason, .. } => {
+                let reason = format!("{reason:?}");
+                self.metrics.increment_counter("shortener_redirects_refused_total", &[("reason", &reason)], 1);
+            },
+            _ => {},
+        }
+        for lag in self.projections.lags(record.sequence) {
+            self.metrics.set_gauge("shortener_projection_lag", &[("projection", &lag.name)], lag.lag as f64);
+        }
+    }
+
+    /// Delivers the redirect to the webhook of the link, if it is sampled.
+    fn notify_webhook(&self, record: &EventRecord) {
+        let Event::Redirected { slug_id, visitor, .. } = &record.event else {
+            return;
+        };
+        let Some((link_id, slug)) = self.read_model.slug_ids.resolve(*slug_id) else {
+            return;
+        };
+        let Some(state) = self.read_model.links.get(link_id) else {
+            return;
+        };
+        let Some(webhook) = &state.webhook else {
+            return;
+        };
+        if rand::random::<f64>() >= webhook.sample_rate {
+            return;
+        }
+
+        let delivery = WebhookDelivery::redirect(webhook, record, link_id, slug, &state.link.url, visitor.as_ref());
+        self.webhooks.send(delivery);
+    }
+
+    /// Queues deliveries of the event to webhooks of domain events of its
+    /// tenant, which are triggered by it.
+    fn queue_event_webhooks(&mut self, record: &EventRecord) {
+        let slug_ids = &self.read_model.slug_ids;
+        let (Some(link_id), Some(slug)) = (record.event.link_id(slug_ids), record.event.slug(slug_ids)) else {
+            return;
+        };
+        let Some(state) = self.read_model.links.get(link_id) else {
+            return;
+        };
+
+        for (webhook_id, (webhook, tenant)) in &self.read_model.event_webhooks {
+            if *tenant != record.tenant {
+                continue;
+            }
+            let trigger = webhook.triggers.iter().copied().find(|trigger| match (trigger, &record.event) {
+                (WebhookTrigger::LinkCreated, Event::LinkCreated { .. }) => true,
+                // counts may jump past the threshold, and fall below it on reset, so it fires only once
+                (
+                    WebhookTrigger::RedirectsReached(redirects),
+                    Event::Redirected { .. }
+                    | Event::RedirectsImported { .. }
+                    | Event::RedirectsAggregated { .. }
+                    | Event::LinksMerged { .. },
+                ) => {
+                    state.redirects >= *redirects
+                        && self.reached_thresholds.insert((*webhook_id, link_id.clone(), *redirects))
+                },
+                (WebhookTrigger::LinkExpired, Event::LinkConsumed { .. } | Event::SlugAliasExpired { .. }) => true,
+                _ => false,
+            });
+            let Some(trigger) = trigger else {
+                continue;
+            };
+
+            self.last_delivery_id += 1;
+            let id = DeliveryId(self.last_delivery_id);
+            self.webhook_deliveries.insert(id, TrackedDelivery {
+                id,
+                webhook_id: *webhook_id,
+                trigger,
+                delivery: WebhookDelivery::event(webhook, trigger, record, link_id, slug, state.redirects),
+                status: DeliveryStatus::Pending { next_attempt_at: record.recorded_at },
+                attempts: 0,
+                last_error: None,
+            });
+        }
+    }
+
+    /// Compacts and prunes the event log once it outgrows the
+    /// [`EventWindow`].
+    fn enforce_event_window(&mut self) {
+        let Some(window) = self.config.event_window else {
+            return;
+        };
+        if window.fits(self.events.len(), self.event_bytes) {
+            return;
+        }
+        // projections without a copy in the snapshot couldn't be rebuilt once events are pruned
+        if self.projections.names().any(|name| !self.snapshot.projections.contains(name)) {
+            return;
+        }
+
+        let compacted = self.compact_events();
+        let target = window.pruning_target();
+        let mut pruned = 0;
+        while pruned < self.events.len() && !target.fits(self.events.len() - pruned, self.event_bytes) {
+            let record = &self.events[pruned];
+            self.event_bytes -= window.record_size(record);
+            self.snapshot.read_model.apply(record);
+            self.snapshot.projections.dispatch(record);
+            pruned += 1;
+        }
+        if pruned > 0 {
+            self.pruned_through = self.events[pruned - 1].sequence;
+            self.snapshot.through = self.pruned_through;
+            self.events.drain(..pruned);
+        }
+        self.index.retain(&self.events);
+        self.log(format!(
+            "Compacted {compacted} and pruned {pruned} events, kept events after sequence {}",
+            self.pruned_through,
+        ));
+    }
+
+    /// Drops events which are only kept for auditing, see
+    /// [`Event::is_state_neutral`], so the event log takes less memory. The
+    /// log is otherwise compacted only once it outgrows the [`EventWindow`].
+    /// Returns the count of dropped events.
+    pub fn handle_compact_events(&mut self) -> usize {
+        let compacted = self.compact_events();
+        self.index.retain(&self.events);
+        self.metrics.set_gauge("shortener_event_store_size", &[], self.events.len() as f64);
+        self.log(format!("Compacted {compacted} events"));
+        compacted
+    }
+
+    fn compact_events(&mut self) -> usize {
+        let window = self.config.event_window.unwrap_or_default();
+        let len = self.events.len();
+        let mut compacted_bytes = 0;
+        self.events.retain(|record| {
+            let neutral = record.event.is_state_neutral();
+            if neutral {
+                compacted_bytes += window.record_size(record);
+            }
+            !neutral
+        });
+        self.event_bytes -= compacted_bytes;
+        len - self.events.len()
+    }
+
+    fn log(&self, message: String) {
+        if self.config.quiet {
+            return;
+        }
+        let now = Local::now().format("%Y-%m-%d %H:%M:%S");
+        println!("[{}] {message}", now);
+    }
+}
+
+/// Redirects of a link dropped by [`UrlShortenerService::handle_prune_clicks`]
+/// since the last other event of the log, counted together.
+struct ClickAggregate {
+    // the last of the redirects, whose place in the log the aggregate takes
+    last: (u64, DateTime<Utc>, Option<ActorId>, Option<TenantId>),
+    slug: Slug,
+    redirects: BTreeMap<SlugId, u64>,
+    bot_redirects: u64,
+    visitors: HyperLogLog,
+    countries: BTreeMap<CountryCode, u64>,
+    devices: BTreeMap<DeviceClass, u64>,
+    browsers: BTreeMap<BrowserFamily, u64>,
+    minutes: BTreeMap<DateTime<Utc>, u64>,
+}
+
+impl ClickAggregate {
+    fn new(record: &EventRecord, slug: Slug) -> Self {
+        let mut aggregate = ClickAggregate {
+            last: (record.sequence, record.recorded_at, record.actor.clone(), record.tenant.clone()),
+            slug,
+            redirects: BTreeMap::new(),
+            bot_redirects: 0,
+            visitors: HyperLogLog::default(),
+            countries: BTreeMap::new(),
+            devices: BTreeMap::new(),
+            browsers: BTreeMap::new(),
+            minutes: BTreeMap::new(),
+        };
+        aggregate.add(record);
+        aggregate
+    }
+
+    /// Counts the redirect like the read model and built-in projections do.
+    fn add(&mut self, record: &EventRecord) {
+        let Event::Redirected { slug_id, visitor, context, bot } = &record.event else {
+            return;
+        };
+        self.last = (record.sequence, record.recorded_at, record.actor.clone(), record.tenant.clone());
+        *self.redirects.entry(*slug_id).or_default() += 1;
+        self.bot_redirects += u64::from(*bot);
+        match (visitor, context.as_deref()) {
+            (Some(visitor), _) => self.visitors.insert(&visitor.0),
+            (None, Some(RedirectContext { ip: Some(ip), user_agent, .. })) => self.visitors.insert(&(ip, user_agent)),
+            _ => {},
+        }
+
+        let context = context.as_deref();
+        if let Some(country) = context.and_then(|context| context.country.as_ref()) {
+            *self.countries.entry(country.clone()).or_default() += 1;
+        }
+        if let Some(user_agent) = context.and_then(|context| context.user_agent.as_deref()) {
+            let (device, browser) = analytics::parse_user_agent(user_agent);
+            let device = if *bot { DeviceClass::Bot } else { device };
+            *self.devices.entry(device).or_default() += 1;
+            *self.browsers.entry(browser).or_default() += 1;
+        }
+        let at = context.and_then(|context| context.requested_at).unwrap_or(record.recorded_at);
+        *self.minutes.entry(Resolution::Minute.truncate(at)).or_default() += 1;
+    }
+
+    fn into_record(self, link_id: LinkId) -> EventRecord {
+        let (sequence, recorded_at, actor, tenant) = self.last;
+        EventRecord {
+            sequence,
+            recorded_at,
+            actor,
+            tenant,
+            event: Event::RedirectsAggregated {
+                link_id,
+                slug: self.slug,
+                redirects: self.redirects,
+                bot_redirects: self.bot_redirects,
+                visitors: self.visitors,
+                countries: self.countries,
+                devices: self.devices,
+                browsers: self.browsers,
+                minutes: self.minutes,
+            },
+        }
+    }
+
+    /// Moves records of the runs to the log, returning the memory they take
+    /// as measured by the window.
+    fn flush(runs: &mut BTreeMap<LinkId, ClickAggregate>, window: &EventWindow, log: &mut Vec<EventRecord>) -> usize {
+        let mut bytes = 0;
+        for (link_id, run) in std::mem::take(runs) {
+            let record = run.into_record(link_id);
+            bytes += window.record_size(&record);
+            log.push(record);
+        }
+        bytes
+    }
+}
+
+/// Links accepted in the current batch, but not recorded yet.
+#[derive(Default)]
+struct PendingLinks {
+    slugs: HashSet<String>,
+    // slugs of pending links by their urls
+    urls: HashMap<String, Slug>,
+}
+
+impl UrlShortenerService {
+    /// Validates the new link against the recorded and pending links, and
+    /// generates its slug if needed.
+    fn prepare_short_link(
+        &self,
+        url: Url,
+        slug: Option<Slug>,
+        pending: &PendingLinks,
+    ) -> Result<ShortLink, ShortenerError> {
+        let url = self.check_url(url, pending, slug.is_none() && self.is_canonical())?;
+
+        let slug_taken = |slug: &str| {
+            self.is_slug_in_use(slug) || pending.slugs.contains(&self.read_model.key(slug)) || self.is_blocked(slug)
+        };
+
+        // Function that generates slug using hash of url
+        fn generate_slug_from_url(url: &str) -> String {
+            let mut hasher = DefaultHasher::new();
+            url.hash(&mut hasher);
+            let hash = hasher.finish();
+        
+            format!("{:x}", hash).chars().take(SLUG_LEN).collect()
+        }
+
+        let link = match slug {
+            Some(slug) => {
+                self.check_custom_slug(&slug)?;
+                if slug_taken(&slug.0) {
+                    self.log(format!("Failed to create short link: slug {slug:?} is already in use"));
+                    return Err(ShortenerError::SlugAlreadyInUse);
+                }
+                ShortLink { slug, url }
+            },
+            None => {
+                // offensive slugs are skipped like taken ones, so the next candidate is tried
+                let unusable = |slug: &str| slug_taken(slug) || self.is_offensive(slug);
+                let mut tried = 0;
+                let slug = match self.config.slug_generator {
+                    SlugGenerator::Sequential => {
+                        let number = self.next_slug_number(unusable);
+                        tried = number - self.read_model.slug_sequence + 1;
+                        Some(slugs::base62(number))
+                    },
+                    SlugGenerator::Random { length, attempts } => {
+                        (0..attempts).map(|_| slugs::random(length)).find(|slug| {
+                            tried += 1;
+                            !unusable(slug)
+                        })
+                    },
+                    // hash of the url is always the same, so there is no point in retrying
+                    SlugGenerator::Canonical { length } => {
+                        let hash = slugs::canonical(&url.0);
+                        (length.min(hash.len())..=hash.len()).map(|length| &hash[..length]).find(|slug| {
+                            tried += 1;
+                            !unusable(slug)
+                        }).map(String::from)
+                    },
+                    SlugGenerator::UrlHash => {
+                        tried = 1;
+                        Some(generate_slug_from_url(&url.0)).filter(|slug| !unusable(slug))
+                    },
+                };
+
+                let mut stats = self.slug_generation.get();
+                stats.attempts += tried;
+                stats.collisions += tried - u64::from(slug.is_some());
+                stats.failures += u64::from(slug.is_none());
+                self.slug_generation.set(stats);
+
+                let Some(slug) = slug else {
+                    self.log(format!("Failed to create short link: no free slug generated for URL {url:?}"));
+                    return Err(ShortenerError::SlugGenerationFailed);
+                };
+                ShortLink { slug: Slug(slug), url }
+            },
+        };
+
+        self.check_redirect_chain(&link, pending)?;
+        Ok(link)
+    }
+
+    /// Returns the first number of the slug sequence whose slug is not taken.
+    fn next_slug_number(&self, taken: impl Fn(&str) -> bool) -> u64 {
+        let mut number = self.read_model.slug_sequence;
+        while taken(&slugs::base62(number)) {
+            number += 1;
+        }
+        number
+    }
+
+    /// Records that the slug took the next number of the slug sequence, if it
+    /// is the next sequential slug. Must be called before the slug is taken.
+    fn advance_slug_sequence(&mut self, slug: &Slug) {
+        if self.config.slug_generator != SlugGenerator::Sequential {
+            return;
+        }
+
+        let unusable = |slug: &str| self.is_slug_in_use(slug) || self.is_blocked(slug) || self.is_offensive(slug);
+        let number = self.next_slug_number(unusable);
+        if slugs::base62(number) == slug.0 {
+            self.record(Event::SlugSequenceAdvanced { next: number + 1 });
+        }
+    }
+
+    /// Checks that the URL is valid and wasn't shortened yet. Returns the URL
+    /// normalized by [`ServiceConfig::url_normalization`], which is stored
+    /// instead of the given one.
+    fn check_url(&self, url: Url, pending: &PendingLinks, canonical: bool) -> Result<Url, ShortenerError> {
+        let url = Url(self.clean_url(&url)?);
+
+        let parsed = baseUrl::parse(&url.0).map_err(|_| ShortenerError::InvalidUrl)?;
+        if !self.config.allowed_schemes.allows(&parsed) {
+            self.log(format!("Failed to create short link: scheme of URL {url:?} is not allowed"));
+            return Err(ShortenerError::SchemeNotAllowed);
+        }
+
+        if let Some(validator) = self.scheme_validators.get(parsed.scheme()) {
+            if let Err(reason) = validator.validate(&parsed) {
+                self.log(format!("Failed to create short link: URL {url:?} is invalid, {reason}"));
+                return Err(ShortenerError::InvalidUrl);
+            }
+        }
+
+        if let Some(reason) = self.destination_block_reason(&url) {
+            self.log(format!("Failed to create short link: URL {url:?} is blocked, {reason}"));
+            return Err(ShortenerError::UrlBlocked);
+        }
+
+        if !self.config.allow_own_links && urls::own_path(&url.0, &self.config.own_hosts).is_some() {
+            self.log(format!("Failed to create short link: URL {url:?} points at the shortener"));
+            return Err(ShortenerError::SelfReference);
+        }
+
+        // We need to make sure that our new slug doesn't match any of existing slugs
+        // It is equal to finding out if we already processed url because we can have only one slug for url
+        // URLs shortened with custom slugs still get their canonical links
+        let duplicate = self.read_model.urls.contains(&self.read_model.url_key(&url))
+            || pending.urls.contains_key(&url.0);
+        if duplicate && !canonical && self.config.duplicate_urls != DuplicateUrlPolicy::AllowDuplicates {
+            self.log(format!("Failed to create short link: URL {url:?} already exists"));
+            return Err(ShortenerError::SlugAlreadyInUse);
+        }
+
+        Ok(url)
+    }
+
+    /// Follows redirects of the destination with the follower set by
+    /// [`Self::set_redirect_follower`], up to
+    /// [`ServiceConfig::max_resolved_redirects`] hops. Returns the final
+    /// destination, and the given one if it was resolved. Only destinations
+    /// which may be shortened are requested, and resolving stops at
+    /// destinations which can't be reached, so they are checked as given.
+    fn resolve_destination(&self, url: Url) -> (Url, Option<Url>) {
+        let Some(follower) = self.redirect_follower.as_ref().filter(|_| self.config.max_resolved_redirects > 0) else {
+            return (url, None);
+        };
+        let Ok(original) = self.clean_url(&url) else {
+            return (url, None);
+        };
+
+        let mut current = original.clone();
+        for _ in 0..self.config.max_resolved_redirects {
+            let Ok(parsed) = baseUrl::parse(&current) else {
+                break;
+            };
+            let web = matches!(parsed.scheme(), "http" | "https") && self.config.allowed_schemes.allows(&parsed);
+            if !web || self.destination_block_reason(&Url(current.clone())).is_some() {
+                break;
+            }
+
+            match follower.next_hop(&parsed) {
+                Ok(Some(next)) => match self.clean_url(&Url(next.into())) {
+                    Ok(next) if next != current => current = next,
+                    _ => break,
+                },
+                Ok(None) => break,
+                Err(reason) => {
+                    self.log(format!("Failed to resolve redirect of URL {current:?}: {reason}"));
+                    break;
+                },
+            }
+        }
+
+        if current == original {
+            return (url, None);
+        }
+        self.log(format!("Resolved URL {original:?} to {current:?}"));
+        (Url(current), Some(Url(original)))
+    }
+
+    /// Follows the chain of short links the destination of the new link
+    /// points at, recorded or pending, and checks that it doesn't lead back to
+    /// the new link.
+    fn check_redirect_chain(&self, link: &ShortLink, pending: &PendingLinks) -> Result<(), ShortenerError> {
+        let start = self.read_model.key(&link.slug.0);
+        let mut url = link.url.clone();
+        for _ in 0..=urls::MAX_OWN_REDIRECTS {
+            let Some(slug) = urls::own_path(&url.0, &self.config.own_hosts)
+                .and_then(|path| slugs::decode_path_segment(&path).ok())
+            else {
+                return Ok(());
+            };
+
+            let key = self.read_model.key(&slug.0);
+            if key == start {
+                self.log(format!("Failed to create short link {link:?}: destination leads back to it"));
+                return Err(ShortenerError::RedirectLoop);
+            }
+
+            let next = self.read_model.find(&slug.0).map(|(_, state)| state.link.url.clone()).or_else(|| {
+                let mut pending = pending.urls.iter();
+                pending.find(|(_, pending)| self.read_model.key(&pending.0) == key).map(|(url, _)| Url(url.clone()))
+            });
+            // links to missing slugs or other pages of the service end the chain
+            let Some(next) = next else {
+                return Ok(());
+            };
+            url = next;
+        }
+
+        self.log(format!("Failed to create short link {link:?}: destination resolves through too many short links"));
+        Err(ShortenerError::RedirectLoop)
+    }
+
+    /// Sanitizes and normalizes the URL, returning the form which is stored.
+    fn clean_url(&self, url: &Url) -> Result<String, ShortenerError> {
+        let sanitized = urls::sanitize(&url.0, &self.config.url_limits).map_err(|violation| {
+            self.log(format!("Rejected URL: URL breaks limit {violation:?} of {:?}", self.config.url_limits));
+            ShortenerError::UrlRejected(violation)
+        })?;
+        urls::normalize(&sanitized, &self.config.url_normalization).map_err(|_| ShortenerError::InvalidUrl)
+    }
+
+    /// Returns the link to the URL owned by the acting caller, recorded or
+    /// pending, which is handed back instead of a new one by
+    /// [`DuplicateUrlPolicy::ReturnExisting`].
+    fn existing_link(&self, url: &Url, slug: Option<&Slug>, pending: &PendingLinks) -> Option<ShortLink> {
+        if slug.is_none() && self.is_canonical() {
+            return self.canonical_link(url, pending);
+        }
+
+        if self.config.duplicate_urls != DuplicateUrlPolicy::ReturnExisting {
+            return None;
+        }
+
+        let url = Url(self.clean_url(url).ok()?);
+        if let Some(slug) = pending.urls.get(&url.0) {
+            return Some(ShortLink { slug: slug.clone(), url });
+        }
+
+        let acting = self.acting.actor.as_ref().map(OwnerId::from);
+        self.read_model.tenant_links()
+            .filter(|(_, state)| state.link.url == url && state.owner == acting && state.blocked.is_none())
+            .min_by(|a, b| a.0.cmp(b.0))
+            .map(|(_, state)| state.link.clone())
+    }
+
+    /// Returns the link to the URL with a slug generated by
+    /// [`SlugGenerator::Canonical`], recorded or pending, regardless of its
+    /// owner.
+    fn canonical_link(&self, url: &Url, pending: &PendingLinks) -> Option<ShortLink> {
+        let url = Url(self.clean_url(url).ok()?);
+        let hash = slugs::canonical(&url.0);
+        if let Some(slug) = pending.urls.get(&url.0).filter(|slug| hash.starts_with(&slug.0)) {
+            return Some(ShortLink { slug: slug.clone(), url });
+        }
+
+        self.read_model.tenant_links()
+            .map(|(_, state)| state)
+            .filter(|state| state.link.url == url && hash.starts_with(&state.link.slug.0) && state.blocked.is_none())
+            .min_by_key(|state| state.link.slug.0.len())
+            .map(|state| state.link.clone())
+    }
+
+    fn is_canonical(&self) -> bool {
+        matches!(self.config.slug_generator, SlugGenerator::Canonical { .. })
+    }
+
+    /// Returns why the destination is blocked by the blocklist of hosts or
+    /// the [`UrlPolicy`], if it is.
+    fn destination_block_reason(&self, url: &Url) -> Option<String> {
+        let parsed = baseUrl::parse(&url.0).ok()?;
+        if let Some(host) = parsed.host_str() {
+            let blocked = self.read_model.blocked_hosts.iter().find(|blocked| urls::host_matches(host, blocked));
+            if let Some(blocked) = blocked {
+                return Some(format!("host {blocked} is blocked"));
+            }
+        }
+        self.url_policy.blocks(&parsed)
+    }
+
+    /// Checks that the slug chosen by the caller may be used.
+    fn check_custom_slug(&self, slug: &Slug) -> Result<(), ShortenerError> {
+        let local = match slugs::split_namespace(&slug.0) {
+            Some((namespace, local)) => {
+                self.check_namespace(namespace)?;
+                local
+            },
+            None => &slug.0,
+        };
+
+        if let Err(violation) = self.config.slug_rules.check(local) {
+            self.log(format!("Rejected slug {slug:?}: slug breaks rule {violation:?} of {:?}", self.config.slug_rules));
+            return Err(ShortenerError::InvalidSlug(violation));
+        }
+
+        if self.is_blocked(&slug.0) {
+            self.log(format!("Rejected slug {slug:?}: slug is blocked"));
+            return Err(ShortenerError::SlugBlocked);
+        }
+
+        Ok(())
+    }
+
+    /// Checks that the namespace exists and the acting caller may use slugs
+    /// in it.
+    fn check_namespace(&self, namespace: &str) -> Result<(), ShortenerError> {
+        let Some((_, owner)) = self.read_model.namespaces.get(&self.read_model.key(namespace)) else {
+            self.log(format!("Rejected namespace {namespace:?}: namespace not found"));
+            return Err(ShortenerError::NamespaceNotFound);
+        };
+
+        let acting = self.acting.actor.as_ref().map(OwnerId::from);
+        if owner.is_some() && *owner != acting {
+            self.log(format!("Rejected namespace {namespace:?}: namespace is owned by {owner:?}"));
+            return Err(ShortenerError::AccessDenied);
+        }
+
+        Ok(())
+    }
+
+    /// Checks if the slug is one of [`ServiceConfig::blocked_slugs`].
+    fn is_blocked(&self, slug: &str) -> bool {
+        let slug = self.read_model.key(slug);
+        self.config.blocked_slugs.iter().any(|blocked| self.read_model.key(blocked) == slug)
+    }
+
+    /// Checks if the generated slug must be skipped by
+    /// [`ServiceConfig::filter_profanity`].
+    fn is_offensive(&self, slug: &str) -> bool {
+        self.config.filter_profanity && slugs::is_offensive(slug)
+    }
+
+    /// Checks if the slug is reserved and the reservation hasn't expired yet.
+    fn is_reserved(&self, slug: &str) -> bool {
+        self.read_model.reservations
+            .get(&self.read_model.key(slug))
+            .is_some_and(|hold| hold.expires_at.is_none_or(|expires_at| expires_at > Utc::now()))
+    }
+
+    /// Checks if the slug maps to a link or is reserved.
+    fn is_slug_in_use(&self, slug: &str) -> bool {
+        self.read_model.find(slug).is_some() || self.is_reserved(slug)
+    }
+
+    /// Records expiration of the slug reservation or the deprecated slug alias
+    /// if they have expired, but are not recorded as expired yet. Must be
+    /// called before the slug is taken by another link or reservation.
+    fn expire_slug(&mut self, slug: &Slug) {
+        let key = self.read_model.key(&slug.0);
+        if self.read_model.reservations.contains_key(&key) && !self.is_reserved(&slug.0) {
+            self.record(Event::SlugReservationExpired { slug: slug.clone() });
+        }
+
+        if self.read_model.alias_expirations.contains_key(&key) && self.read_model.find(&slug.0).is_none() {
+            if let Some(link_id) = self.read_model.slugs.get(&key).cloned() {
+                self.record(Event::SlugAliasExpired { link_id, slug: slug.clone() });
+            }
+        }
+    }
+
+    /// Records creation of the validated link, owned by the acting caller.
+    /// Expired reservation or alias of its slug is recorded as expired first.
+    fn record_link_created(&mut self, short_link: &ShortLink, original_url: Option<Url>, options: LinkOptions) {
+        let owner = self.acting.actor.as_ref().map(OwnerId::from);
+        self.record_link_created_for(owner, short_link, original_url, options);
+    }
+
+    fn record_link_created_for(
+        &mut self,
+        owner: Option<OwnerId>,
+        short_link: &ShortLink,
+        original_url: Option<Url>,
+        options: LinkOptions,
+    ) {
+        self.expire_slug(&short_link.slug);
+        self.advance_slug_sequence(&short_link.slug);
+        self.record(Event::LinkCreated {
+            link_id: LinkId::generate(),
+            slug: short_link.slug.clone(),
+            url: short_link.url.clone(),
+            original_url,
+            owner,
+            options,
+        });
+    }
+
+    /// Creates a new short link with the given settings, the same way as
+    /// [`CommandHandler::handle_create_short_link`].
+    ///
+    /// ## Errors
+    ///
+    /// See [`ShortenerError`].
+    pub fn handle_create_short_link_with(
+        &mut self,
+        url: Url,
+        slug: Option<Slug>,
+        options: LinkOptions,
+    ) -> Result<ShortLink, ShortenerError> {
+        let (url, original_url) = self.resolve_destination(url);
+        if let Some(existing) = self.existing_link(&url, slug.as_ref(), &PendingLinks::default()) {
+            self.log(format!("Returned existing short link {existing:?}"));
+            return Ok(existing);
+        }
+
+        let short_link = self.prepare_short_link(url, slug, &PendingLinks::default())?;
+
+        // Create event for new slug
+        self.record_link_created(&short_link, original_url, options);
+        self.log(format!("Successfully created short link {short_link:?}"));
+        Ok(short_link)
+    }
+
+    /// Creates many short links at once. Every entry is validated the same way
+    /// as in [`CommandHandler::handle_create_short_link`], including conflicts
+    /// with other entries of the batch. Returns the outcome of every entry in
+    /// the same order, successfully validated links are recorded together
+    /// after the whole batch is validated.
+    pub fn handle_create_short_links(
+        &mut self,
+        links: Vec<(Url, Option<Slug>)>,
+    ) -> Vec<Result<ShortLink, ShortenerError>> {
+        self.create_short_links(links, false)
+    }
+
+    /// Creates many short links at once like
+    /// [`Self::handle_create_short_links`], but only if every entry is valid.
+    /// Otherwise nothing is recorded, and valid entries fail with
+    /// [`ShortenerError::BatchAborted`].
+    pub fn handle_create_short_links_atomically(
+        &mut self,
+        links: Vec<(Url, Option<Slug>)>,
+    ) -> Vec<Result<ShortLink, ShortenerError>> {
+        self.create_short_links(links, true)
+    }
+
+    fn create_short_links(
+        &mut self,
+        links: Vec<(Url, Option<Slug>)>,
+        atomic: bool,
+    ) -> Vec<Result<ShortLink, ShortenerError>> {
+        let mut pending = PendingLinks::default();
+        let mut existing = HashSet::new();
+        let mut original_urls = HashMap::new();
+        let outcomes: Vec<_> = links
+            .into_iter()
+            .enumerate()
+            .map(|(index, (url, slug))| {
+                let (url, original_url) = self.resolve_destination(url);
+                if let Some(original_url) = original_url {
+                    original_urls.insert(index, original_url);
+                }
+                if let Some(link) = self.existing_link(&url, slug.as_ref(), &pending) {
+                    existing.insert(index);
+                    return Ok(link);
+                }
+
+                let short_link = self.prepare_short_link(url, slug, &pending)?;
+                pending.slugs.insert(self.read_model.key(&short_link.slug.0));
+                pending.urls.insert(short_link.url.0.clone(), short_link.slug.clone());
+                Ok(short_link)
+            })
+            .collect();
+
+        let failed = outcomes.iter().filter(|outcome| outcome.is_err()).count();
+        if atomic && failed > 0 {
+            self.log(format!("Aborted batch of {} short links: {failed} of them failed", outcomes.len()));
+            return outcomes.into_iter().map(|outcome| outcome.and(Err(ShortenerError::BatchAborted))).collect();
+        }
+
+        let created: Vec<_> = outcomes.iter()
+            .enumerate()
+            .filter(|(index, _)| !existing.contains(index))
+            .filter_map(|(index, outcome)| Some((outcome.as_ref().ok()?, original_urls.remove(&index))))
+            .collect();
+        self.log(format!("Created {} of {} short links in batch", created.len(), outcomes.len()));
+        self.events.reserve(created.len());
+        for (short_link, original_url) in created {
+            self.record_link_created(short_link, original_url, LinkOptions::default());
+        }
+
+        outcomes
+    }
+}
+
+impl commands::CommandHandler for UrlShortenerService {
+    fn handle_create_short_link(
+        &mut self,
+        url: Url,
+        slug: Option<Slug>,
+    ) -> Result<ShortLink, ShortenerError> {
+        self.handle_create_short_link_with(url, slug, LinkOptions::default())
+    }
+
+    fn handle_redirect(
+        &mut self,
+        slug: Slug,
+    ) -> Result<ShortLink, ShortenerError> {
+        self.handle_redirect_with_outcome(slug, None, None).into_result()
+    }
+}
+
+impl UrlShortenerService {
+    /// Processes a redirection by [`Slug`] made by the known visitor. Repeated
+    /// redirects of the same visitor within
+    /// [`ServiceConfig::redirect_dedup_window`] are served, but not counted in
+    /// stats.
+    ///
+    /// ## Errors
+    ///
+    /// See [`CommandHandler::handle_redirect`].
+    pub fn handle_redirect_from(
+        &mut self,
+        slug: Slug,
+        visitor: VisitorId,
+    ) -> Result<ShortLink, ShortenerError> {
+        self.handle_redirect_with_outcome(slug, Some(visitor), None).into_result()
+    }
+
+    /// Processes a redirection by [`Slug`] of a password-protected link. Wrong
+    /// passwords are recorded as failed attempts. Links without a password
+    /// are followed regardless of the given password.
+    ///
+    /// ## Errors
+    ///
+    /// - [`ShortenerError::InvalidPassword`] if the password doesn't match.
+    /// - See [`CommandHandler::handle_redirect`].
+    pub fn handle_redirect_with_password(
+        &mut self,
+        slug: Slug,
+        password: &str,
+        visitor: Option<VisitorId>,
+    ) -> Result<ShortLink, ShortenerError> {
+        self.handle_redirect_with_outcome(slug, visitor, Some(password)).into_result()
+    }
+
+    /// Processes a redirection by [`Slug`] like
+    /// [`Self::handle_redirect_with_password`], but reports the precise
+    /// outcome, e.g. for the HTTP layer to pick the response. Refusals of
+    /// existing links are recorded, so they are counted in
+    /// [`StatsBreakdown::refused`].
+    pub fn handle_redirect_with_outcome(
+        &mut self,
+        slug: Slug,
+        visitor: Option<VisitorId>,
+        password: Option<&str>,
+    ) -> RedirectOutcome {
+        let context = RedirectContext { visitor, ..Default::default() };
+        self.redirect(slug, context, password)
+    }
+
+    /// Processes a redirection by [`Slug`] like
+    /// [`Self::handle_redirect_with_outcome`], recording details of the
+    /// request with the redirect like [`Self::handle_redirect_with_context`],
+    /// e.g. for the HTTP layer which knows both.
+    pub fn handle_redirect_request(
+        &mut self,
+        slug: Slug,
+        context: RedirectContext,
+        password: Option<&str>,
+    ) -> RedirectOutcome {
+        self.redirect(slug, context, password)
+    }
+
+    /// Takes a token of the operation from the bucket of every key of the
+    /// client, limited by the [`ServiceConfig::rate_limits`] of the tenant.
+    /// API layers check it before handling requests of clients.
+    ///
+    /// ## Errors
+    ///
+    /// [`ShortenerError::RateLimited`] if the client exceeded the limit,
+    /// with the seconds after which all of its buckets have a token again.
+    pub fn check_rate_limit(
+        &mut self,
+        tenant: Option<&TenantId>,
+        operation: LimitedOperation,
+        clients: &[ClientKey],
+    ) -> Result<(), ShortenerError> {
+        let limits = tenant
+            .and_then(|tenant| self.config.tenant_rate_limits.get(tenant))
+            .unwrap_or(&self.config.rate_limits);
+        let Some(limit) = limits.limit(operation) else {
+            return Ok(());
+        };
+
+        self.rate_limiter.take(tenant, operation, clients, limit, Utc::now()).map_err(|wait| {
+            let retry_after = (wait + TimeDelta::milliseconds(999)).num_seconds().max(1) as u64;
+            self.log(format!("Rate limited {operation:?} of {clients:?}: retry after {retry_after}s"));
+            ShortenerError::RateLimited { retry_after }
+        })
+    }
+
+    /// Returns the context of requests made with the API key. API layers
+    /// dispatch commands changing the service in it, so they are recorded on
+    /// behalf of the owner of the key.
+    ///
+    /// ## Errors
+    ///
+    /// - [`ShortenerError::ApiKeyRequired`] if no key is given.
+    /// - [`ShortenerError::InvalidApiKey`] if the key doesn't exist or was
+    ///   revoked.
+    pub fn authenticate(&self, api_key: Option<&str>) -> Result<RequestContext, ShortenerError> {
+        let api_key = api_key.ok_or(ShortenerError::ApiKeyRequired)?;
+        let Some((_, context)) = self.read_model.api_keys.get(&ApiKey::hash(api_key)) else {
+            self.log(String::from("Failed to authenticate request: API key is invalid"));
+            return Err(ShortenerError::InvalidApiKey);
+        };
+        Ok(context.clone())
+    }
+
+    /// Creates an API key authenticating requests of the actor with the role
+    /// in the tenant of the acting caller. The secret of the key is returned
+    /// only now, just its hash is recorded.
+    pub fn handle_create_api_key(&mut self, actor: ActorId, role: Role) -> ApiKey {
+        let key = ApiKey::generate();
+        self.log(format!("Created API key {:?} of {actor:?} with role {role:?}", key.id));
+        let secret_hash = ApiKey::hash(&key.secret);
+        self.record(Event::ApiKeyCreated { key_id: key.id.clone(), actor, role, secret_hash });
+        key
+    }
+
+    /// Revokes the API key, so requests made with it are rejected.
+    ///
+    /// ## Errors
+    ///
+    /// [`ShortenerError::ApiKeyNotFound`] if the key doesn't exist or was
+    /// already revoked.
+    pub fn handle_revoke_api_key(&mut self, key_id: &ApiKeyId) -> Result<(), ShortenerError> {
+        if !self.read_model.api_keys.values().any(|(id, _)| id == key_id) {
+            self.log(format!("Failed to revoke API key {key_id:?}: key not found"));
+            return Err(ShortenerError::ApiKeyNotFound);
+        }
+
+        self.log(format!("Revoked API key {key_id:?}"));
+        self.record(Event::ApiKeyRevoked { key_id: key_id.clone() });
+        Ok(())
+    }
+
+    /// Registers the webhook for domain events of links of the tenant of the
+    /// acting caller. Every triggering event queues a signed delivery, sent
+    /// by [`Self::handle_deliver_webhooks`].
+    ///
+    /// ## Errors
+    ///
+    /// [`ShortenerError::InvalidUrl`] if the webhook URL is not valid.
+    pub fn handle_register_webhook(&mut self, webhook: EventWebhook) -> Result<WebhookId, ShortenerError> {
+        if baseUrl::parse(&webhook.url.0).is_err() {
+            self.log(format!("Failed to register webhook: invalid URL {:?}", webhook.url));
+            return Err(ShortenerError::InvalidUrl);
+        }
+
+        let webhook_id = WebhookId(self.read_model.next_webhook_id.max(1));
+        self.log(format!("Registered webhook {webhook_id:?} at {:?}", webhook.url));
+        self.record(Event::WebhookRegistered { webhook_id, webhook });
+        Ok(webhook_id)
+    }
+
+    /// Unregisters the webhook of domain events. Its queued deliveries are
+    /// still attempted.
+    ///
+    /// ## Errors
+    ///
+    /// [`ShortenerError::WebhookNotFound`] if the webhook isn't registered in
+    /// the tenant of the acting caller.
+    pub fn handle_unregister_webhook(&mut self, webhook_id: WebhookId) -> Result<(), ShortenerError> {
+        if self.tenant_webhook(webhook_id).is_none() {
+            self.log(format!("Failed to unregister webhook {webhook_id:?}: webhook not found"));
+            return Err(ShortenerError::WebhookNotFound);
+        }
+
+        self.log(format!("Unregistered webhook {webhook_id:?}"));
+        self.record(Event::WebhookUnregistered { webhook_id });
+        Ok(())
+    }
+
+    /// Attempts due deliveries to webhooks of domain events with the
+    /// transport set by [`Self::set_webhook_transport`]. Failed deliveries
+    /// are retried by [`ServiceConfig::webhook_retries`]. Returns the
+    /// attempted deliveries with their new status. Nothing is delivered
+    /// without a transport. It is meant to be run periodically, away from
+    /// serving redirects, as the transport may block.
+    pub fn handle_deliver_webhooks(&mut self) -> Vec<TrackedDelivery> {
+        let Some(transport) = &self.webhook_transport else {
+            return Vec::new();
+        };
+
+        let now = Utc::now();
+        let retries = self.config.webhook_retries;
+        let mut attempted = Vec::new();
+        for tracked in self.webhook_deliveries.values_mut() {
+            if !matches!(tracked.status, DeliveryStatus::Pending { next_attempt_at } if next_attempt_at <= now) {
+                continue;
+            }
+            tracked.attempts += 1;
+            match transport.post(&tracked.delivery) {
+                Ok(()) => {
+                    tracked.status = DeliveryStatus::Delivered { at: now };
+                    tracked.last_error = None;
+                },
+                Err(error) => {
+                    tracked.status = if tracked.attempts < retries.max_attempts {
+                        DeliveryStatus::Pending { next_attempt_at: now + retries.delay(tracked.attempts) }
+                    } else {
+                        DeliveryStatus::Failed
+                    };
+                    tracked.last_error = Some(error);
+                },
+            }
+            attempted.push(tracked.clone());
+        }
+
+        for tracked in &attempted {
+            match &tracked.last_error {
+                None => self.log(format!("Delivered {:?} to webhook {:?}", tracked.id, tracked.webhook_id)),
+                Some(error) => self.log(format!(
+                    "Failed to deliver {:?} to webhook {:?}, attempt {}: {error}",
+                    tracked.id, tracked.webhook_id, tracked.attempts,
+                )),
+            }
+        }
+        attempted
+    }
+
+    /// Returns deliveries to the webhook of domain events, ordered by the
+    /// time they were queued.
+    ///
+    /// ## Errors
+    ///
+    /// [`ShortenerError::WebhookNotFound`] if the webhook isn't registered in
+    /// the tenant of the acting caller.
+    pub fn get_webhook_deliveries(&self, webhook_id: WebhookId) -> Result<Vec<TrackedDelivery>, ShortenerError> {
+        if self.tenant_webhook(webhook_id).is_none() {
+            self.log(format!("Failed to retrieve deliveries of webhook {webhook_id:?}: webhook not found"));
+            return Err(ShortenerError::WebhookNotFound);
+        }
+
+        Ok(self.webhook_deliveries
+            .values()
+            .filter(|tracked| tracked.webhook_id == webhook_id)
+            .cloned()
+            .collect())
+    }
+
+    /// Returns the webhook of domain events, if it is registered in the
+    /// tenant of the acting caller.
+    fn tenant_webhook(&self, webhook_id: WebhookId) -> Option<&EventWebhook> {
+        self.read_model.event_webhooks
+            .get(&webhook_id)
+            .filter(|(_, tenant)| *tenant == self.read_model.tenant)
+            .map(|(webhook, _)| webhook)
+    }
+
+    /// Processes a redirection by [`Slug`] like [`Self::handle_redirect_from`],
+    /// recording details of the request with the redirect, so they can be
+    /// analyzed later. Repeated redirects of anonymous visitors are
+    /// deduplicated by the fingerprint of their request.
+    ///
+    /// ## Errors
+    ///
+    /// See [`CommandHandler::handle_redirect`].
+    pub fn handle_redirect_with_context(
+        &mut self,
+        slug: Slug,
+        context: RedirectContext,
+    ) -> Result<ShortLink, ShortenerError> {
+        self.redirect(slug, context, None).into_result()
+    }
+
+    /// Serves the redirect, the context is recorded only if it has more
+    /// details than the visitor.
+    fn redirect(&mut self, slug: Slug, mut context: RedirectContext, password: Option<&str>) -> RedirectOutcome {
+        let visitor = context.visitor.take();
+        if let (None, Some(ip), Some(resolver)) = (&context.country, context.ip, &self.geo_resolver) {
+            context.country = resolver.country(ip);
+        }
+        // raw addresses never leave this function
+        context.ip = context.ip.map(|ip| self.config.ip_anonymization.apply(ip));
+        let bot = self.bot_classifier.is_bot(&context);
+        let dedup_key = visitor.clone().or_else(|| analytics::fingerprint(&context));
+        let context = (context != RedirectContext::default()).then(|| Box::new(context));
+
+        // Check if slug exists
+        let Some((link_id, state)) = self.read_model.find(&slug.0) else {
+            // Deprecated aliases still map to their links until their expiration is recorded
+            if let Some(link_id) = self.read_model.slugs.get(&self.read_model.key(&slug.0)).cloned() {
+                self.log(format!("Failed to handle redirect of slug {slug:?}: slug has expired"));
+                return self.refuse(link_id, slug, RedirectRefusal::Expired);
+            }
+
+            self.log(format!("Failed to handle redirect of slug {slug:?}: slug not found"));
+            return RedirectOutcome::Refused(RedirectRefusal::NotFound);
+        };
+        let link_id = link_id.clone();
+
+        // Disabled links keep their stats, but can't be followed
+        if state.disabled {
+            self.log(format!("Failed to handle redirect of slug {slug:?}: link is disabled"));
+            return self.refuse(link_id, slug, RedirectRefusal::Disabled);
+        }
+
+        // One-time links can be followed only once
+        if state.consumed {
+            self.log(format!("Failed to handle redirect of slug {slug:?}: link is already used"));
+            return self.refuse(link_id, slug, RedirectRefusal::Consumed);
+        }
+
+        // Links to malicious destinations can't be followed
+        if state.blocked.is_some() {
+            self.log(format!("Failed to handle redirect of slug {slug:?}: destination is blocked"));
+            return self.refuse(link_id, slug, RedirectRefusal::Blocked);
+        }
+        if self.config.check_urls_on_redirect {
+            if let Some(reason) = self.destination_block_reason(&state.link.url) {
+                self.log(format!("Failed to handle redirect of slug {slug:?}: destination is blocked, {reason}"));
+                let link_slug = state.link.slug.clone();
+                self.record(Event::LinkBlocked { link_id: link_id.clone(), slug: link_slug, reason });
+                return self.refuse(link_id, slug, RedirectRefusal::Blocked);
+            }
+        }
+
+        let link = state.link.clone();
+        let redirect_type = state.redirect_type;
+        let interstitial = state.interstitial || (self.config.interstitial_for_flagged && state.anomaly.is_some());
+        let one_time = state.one_time;
+        let query_params = state.query_params.clone();
+        // slugs of links are interned as soon as they appear
+        let Some(slug_id) = self.read_model.slug_id(&link_id, &slug) else {
+            self.log(format!("Failed to handle redirect of slug {slug:?}: slug is not interned"));
+            return RedirectOutcome::Refused(RedirectRefusal::NotFound);
+        };
+
+        // Protected links are followed only with the right password
+        match (&state.password, password) {
+            (None, _) => {},
+            (Some(_), None) => {
+                self.log(format!("Failed to handle redirect of slug {slug:?}: password required"));
+                return self.refuse(link_id, slug, RedirectRefusal::PasswordRequired);
+            },
+            (Some(hash), Some(password)) => if !hash.verify(password) {
+                self.log(format!("Failed to handle redirect of slug {slug:?}: invalid password"));
+                self.record(Event::PasswordAttemptFailed { link_id, slug, visitor });
+                return RedirectOutcome::Refused(RedirectRefusal::InvalidPassword);
+            },
+        }
+
+        // Destination is templated for every redirect, the stored one stays the same
+        let link = if query_params.is_empty() {
+            link
+        } else {
+            let click_id = slugs::random(16);
+            let url = Url(urls::apply_params(&link.url.0, &query_params, &slug.0, &click_id));
+            ShortLink { url, ..link }
+        };
+
+        // Ok, we found it, create redirect event, unless the same visitor has just been counted
+        match dedup_key {
+            Some(key) if self.is_duplicate_redirect(&link_id, &key) => {
+                self.log(format!("Handled duplicate redirect of slug {slug:?} by visitor {key:?}"));
+                self.record(Event::RedirectDeduplicated { slug_id, visitor: key });
+            },
+            _ => {
+                self.log(format!("Handled redirect of slug {slug:?}"));
+                self.record(Event::Redirected { slug_id, visitor, context, bot });
+                if one_time {
+                    self.log(format!("Consumed one-time link of slug {slug:?}"));
+                    self.record(Event::LinkConsumed { link_id, slug });
+                }
+            },
+        }
+
+        RedirectOutcome::Served { link, redirect_type, interstitial }
+    }
+
+    fn refuse(&mut self, link_id: LinkId, slug: Slug, reason: RedirectRefusal) -> RedirectOutcome {
+        self.record(Event::RedirectRefused { link_id, slug, reason });
+        RedirectOutcome::Refused(reason)
+    }
+
+    fn is_duplicate_redirect(&self, link_id: &LinkId, visitor: &VisitorId) -> bool {
+        let Some(window) = self.config.redirect_dedup_window else {
+            return false;
+        };
+
+        self.read_model.last_counted_redirects
+            .get(&(link_id.clone(), visitor.clone()))
+            .is_some_and(|last| Utc::now() - *last < window)
+    }
+}
+
+impl UrlShortenerService {
+    /// Reserves a custom slug before the destination URL is known. Reserved
+    /// slugs can't be used by other links until the reservation is completed
+    /// by [`Self::handle_attach_url`] or it times out after `timeout`.
+    ///
+    /// ## Errors
+    ///
+    /// - [`ShortenerError::SlugBlocked`] if the slug is blocked.
+    /// - [`ShortenerError::NamespaceNotFound`] if the slug is in a namespace
+    ///   which doesn't exist.
+    /// - [`ShortenerError::SlugAlreadyInUse`] if the slug is used by a link or
+    ///   is already reserved.
+    pub fn handle_reserve_slug(&mut self, slug: Slug, timeout: Option<TimeDelta>) -> Result<(), ShortenerError> {
+        self.check_custom_slug(&slug)?;
+        if self.is_slug_in_use(&slug.0) {
+            self.log(format!("Failed to reserve slug {slug:?}: slug is already in use"));
+            return Err(ShortenerError::SlugAlreadyInUse);
+        }
+
+        self.expire_slug(&slug);
+
+        self.log(format!("Reserved slug {slug:?}"));
+        let expires_at = timeout.map(|timeout| Utc::now() + timeout);
+        let owner = self.acting.actor.as_ref().map(OwnerId::from);
+        self.record(Event::SlugReserved { slug, owner, expires_at });
+        Ok(())
+    }
+
+    /// Completes the reservation made by [`Self::handle_reserve_slug`] by
+    /// creating a short link with the reserved slug. The link is owned by the
+    /// owner of the reservation.
+    ///
+    /// ## Errors
+    ///
+    /// - [`ShortenerError::SlugNotReserved`] if the slug isn't reserved, the
+    ///   reservation has expired or the slug is reserved by a draft link.
+    /// - See [`CommandHandler::handle_create_short_link`] for URL errors.
+    pub fn handle_attach_url(&mut self, slug: Slug, url: Url) -> Result<ShortLink, ShortenerError> {
+        if !self.is_reserved(&slug.0) {
+            self.log(format!("Failed to attach URL to slug {slug:?}: slug is not reserved"));
+            return Err(ShortenerError::SlugNotReserved);
+        }
+
+        if self.read_model.drafts.contains_key(&self.read_model.key(&slug.0)) {
+            self.log(format!("Failed to attach URL to slug {slug:?}: slug is reserved by a draft link"));
+            return Err(ShortenerError::SlugNotReserved);
+        }
+
+        let (url, original_url) = self.resolve_destination(url);
+        let url = self.check_url(url, &PendingLinks::default(), false)?;
+
+        let owner = self.read_model.reservation_owner(&slug.0).cloned();
+        let short_link = ShortLink { slug, url };
+        self.check_redirect_chain(&short_link, &PendingLinks::default())?;
+        self.record_link_created_for(owner, &short_link, original_url, LinkOptions::default());
+        self.log(format!("Successfully attached URL to reserved slug {short_link:?}"));
+        Ok(short_link)
+    }
+
+    /// Prepares a draft link for review, the first phase of two-phase
+    /// creation. Its slug is reserved like by [`Self::handle_reserve_slug`],
+    /// but the link goes live only once [`Self::handle_activate_link`] is
+    /// called before the draft expires after `timeout`.
+    ///
+    /// ## Errors
+    ///
+    /// See [`CommandHandler::handle_create_short_link`].
+    pub fn handle_prepare_link(
+        &mut self,
+        url: Url,
+        slug: Option<Slug>,
+        options: LinkOptions,
+        timeout: TimeDelta,
+    ) -> Result<Draft, ShortenerError> {
+        let link = self.prepare_short_link(url, slug, &PendingLinks::default())?;
+
+        self.expire_slug(&link.slug);
+        self.advance_slug_sequence(&link.slug);
+        let expires_at = Utc::now() + timeout;
+        let owner = self.acting.actor.as_ref().map(OwnerId::from);
+        self.log(format!("Prepared draft link {link:?}"));
+        self.record(Event::LinkPrepared { slug: link.slug.clone(), owner, url: link.url.clone(), options, expires_at });
+        Ok(Draft { link, expires_at })
+    }
+
+    /// Publishes the draft link prepared by [`Self::handle_prepare_link`]. The
+    /// link is owned by the owner of the draft.
+    ///
+    /// ## Errors
+    ///
+    /// - [`ShortenerError::DraftNotFound`] if there is no draft with the slug
+    ///   or it has expired.
+    /// - See [`CommandHandler::handle_create_short_link`] for URL errors, the
+    ///   URL may have been shortened since the draft was prepared.
+    pub fn handle_activate_link(&mut self, slug: Slug) -> Result<ShortLink, ShortenerError> {
+        let draft = self.read_model.drafts.get(&self.read_model.key(&slug.0)).filter(|_| self.is_reserved(&slug.0));
+        let Some((url, options)) = draft.cloned() else {
+            self.log(format!("Failed to activate slug {slug:?}: draft not found"));
+            return Err(ShortenerError::DraftNotFound);
+        };
+
+        let (url, original_url) = self.resolve_destination(url);
+        let url = self.check_url(url, &PendingLinks::default(), false)?;
+
+        let owner = self.read_model.reservation_owner(&slug.0).cloned();
+        let short_link = ShortLink { slug, url };
+        self.check_redirect_chain(&short_link, &PendingLinks::default())?;
+        self.record_link_created_for(owner, &short_link, original_url, options);
+        self.log(format!("Activated draft link {short_link:?}"));
+        Ok(short_link)
+    }
+
+    /// Records expiration of all reservations which have timed out, including
+    /// reservations of draft links, of all tenants. Expired reservations
+    /// don't block their slugs even before this command, it only makes
+    /// expiration visible in the event log.
+    pub fn handle_expire_reservations(&mut self) {
+        let now = Utc::now();
+        let expired: Vec<_> = self.read_model.reservations
+            .values()
+            .filter(|hold| hold.expires_at.is_some_and(|expires_at| expires_at <= now))
+            .map(|hold| (hold.tenant.clone(), hold.slug.clone()))
+            .collect();
+
+        for (tenant, slug) in expired {
+            let context = RequestContext { tenant, ..self.acting.clone() };
+            self.act_as(&context, |service| {
+                service.log(format!("Reservation of slug {slug:?} expired"));
+                service.record(Event::SlugReservationExpired { slug });
+            });
+        }
+    }
+
+    /// Changes the slug of the link. `old` may be any slug resolving to the
+    /// link. The current slug of the link keeps forwarding to it, forwards as
+    /// a deprecated alias for a grace period or is released, depending on
+    /// `old_slug`. Renaming doesn't affect stats and
+    /// history of the link.
+    ///
+    /// ## Errors
+    ///
+    /// - [`ShortenerError::SlugNotFound`] if `old` doesn't map to any short
+    ///   link.
+    /// - [`ShortenerError::SlugBlocked`] if `new` is blocked.
+    /// - [`ShortenerError::NamespaceNotFound`] if `new` is in a namespace
+    ///   which doesn't exist.
+    /// - [`ShortenerError::SlugAlreadyInUse`] if `new` is used by another link
+    ///   or is reserved.
+    pub fn handle_rename_slug(&mut self, old: Slug, new: Slug, old_slug: OldSlugPolicy) -> Result<ShortLink, ShortenerError> {
+        let Some((link_id, state)) = self.read_model.find(&old.0) else {
+            self.log(format!("Failed to rename slug {old:?}: slug not found"));
+            return Err(ShortenerError::SlugNotFound);
+        };
+        let link_id = link_id.clone();
+        let old_slug_value = state.link.slug.clone();
+        self.check_custom_slug(&new)?;
+
+        // new slug may be an old slug of the same link, so it can be renamed back
+        let taken_by_other = self.read_model.find(&new.0).is_some_and(|(other, _)| *other != link_id);
+        if taken_by_other || self.is_reserved(&new.0) {
+            self.log(format!("Failed to rename slug {old:?}: slug {new:?} is already in use"));
+            return Err(ShortenerError::SlugAlreadyInUse);
+        }
+
+        self.expire_slug(&new);
+        self.log(format!("Renamed slug {old_slug_value:?} to {new:?}"));
+        let (old_slug_released, old_slug_expires_at) = match old_slug {
+            OldSlugPolicy::Forward => (false, None),
+            OldSlugPolicy::ForwardFor(grace_period) => (false, Some(Utc::now() + grace_period)),
+            OldSlugPolicy::Release => (true, None),
+        };
+        self.record(Event::SlugRenamed {
+            link_id: link_id.clone(),
+            old_slug: old_slug_value,
+            new_slug: new,
+            old_slug_released,
+            old_slug_expires_at,
+        });
+        Ok(self.read_model.links[&link_id].link.clone())
+    }
+
+    /// Adds an alias slug to the link. The alias resolves to the same link and
+    /// its redirects are counted in the stats of the link, while
+    /// [`Self::get_stats_breakdown`] shows them per slug.
+    ///
+    /// ## Errors
+    ///
+    /// - [`ShortenerError::SlugNotFound`] if `slug` doesn't map to any short
+    ///   link.
+    /// - [`ShortenerError::SlugBlocked`] if `alias` is blocked.
+    /// - [`ShortenerError::NamespaceNotFound`] if `alias` is in a namespace
+    ///   which doesn't exist.
+    /// - [`ShortenerError::SlugAlreadyInUse`] if `alias` is used by any link or
+    ///   is reserved.
+    pub fn handle_add_alias(&mut self, slug: Slug, alias: Slug) -> Result<ShortLink, ShortenerError> {
+        let Some((link_id, state)) = self.read_model.find(&slug.0) else {
+            self.log(format!("Failed to add alias to slug {slug:?}: slug not found"));
+            return Err(ShortenerError::SlugNotFound);
+        };
+        let link_id = link_id.clone();
+        let link = state.link.clone();
+        self.check_custom_slug(&alias)?;
+
+        if self.is_slug_in_use(&alias.0) {
+            self.log(format!("Failed to add alias to slug {slug:?}: slug {alias:?} is already in use"));
+            return Err(ShortenerError::SlugAlreadyInUse);
+        }
+
+        self.expire_slug(&alias);
+        self.log(format!("Added alias {alias:?} to slug {:?}", link.slug));
+        self.record(Event::AliasAdded { link_id, slug: link.slug.clone(), alias });
+        Ok(link)
+    }
+
+    /// Creates a new link owned by the acting caller with the same destination,
+    /// tags and metadata as the link, but with fresh stats. Unlike
+    /// [`CommandHandler::handle_create_short_link`], the destination may
+    /// already be shortened.
+    ///
+    /// ## Errors
+    ///
+    /// - [`ShortenerError::SlugNotFound`] if the [`Slug`] doesn't map to any
+    ///   short link.
+    /// - [`ShortenerError::SlugBlocked`] if the new [`Slug`] is blocked.
+    /// - [`ShortenerError::NamespaceNotFound`] if the new [`Slug`] is in a
+    ///   namespace which doesn't exist.
+    /// - [`ShortenerError::SlugAlreadyInUse`] if the new [`Slug`] is already
+    ///   in use.
+    pub fn handle_clone_link(&mut self, slug: Slug, new_slug: Slug) -> Result<ShortLink, ShortenerError> {
+        let Some((_, state)) = self.read_model.find(&slug.0) else {
+            self.log(format!("Failed to clone slug {slug:?}: slug not found"));
+            return Err(ShortenerError::SlugNotFound);
+        };
+        let options = LinkOptions {
+            tags: state.tags.iter().cloned().collect(),
+            metadata: state.metadata.clone(),
+            one_time: state.one_time,
+            redirect_type: state.redirect_type,
+        };
+        let short_link = ShortLink { slug: new_slug, url: state.link.url.clone() };
+        let original_url = state.original_url.clone();
+        self.check_custom_slug(&short_link.slug)?;
+
+        if let Some(reason) = self.destination_block_reason(&short_link.url) {
+            self.log(format!("Failed to clone slug {slug:?}: URL {:?} is blocked, {reason}", short_link.url));
+            return Err(ShortenerError::UrlBlocked);
+        }
+
+        if self.is_slug_in_use(&short_link.slug.0) {
+            self.log(format!("Failed to clone slug {slug:?}: slug {:?} is already in use", short_link.slug));
+            return Err(ShortenerError::SlugAlreadyInUse);
+        }
+
+        self.record_link_created(&short_link, original_url, options);
+        self.log(format!("Cloned slug {slug:?} as {short_link:?}"));
+        Ok(short_link)
+    }
+
+    /// Merges the duplicate link into the link, if both point to the same
+    /// destination once their URLs are normalized. Slugs of the duplicate
+    /// become aliases of the link, its redirects are added to the stats of
+    /// the link, and its own settings are dropped. Merging a link into itself
+    /// does nothing.
+    ///
+    /// ## Errors
+    ///
+    /// - [`ShortenerError::SlugNotFound`] if any of the [`Slug`]s doesn't map
+    ///   to any short link.
+    /// - [`ShortenerError::DestinationMismatch`] if the links point to
+    ///   different destinations.
+    /// - [`ShortenerError::AccessDenied`] if the links have different owners.
+    pub fn handle_merge_links(&mut self, slug: Slug, duplicate: Slug) -> Result<ShortLink, ShortenerError> {
+        let Some((link_id, state)) = self.read_model.find(&slug.0) else {
+            self.log(format!("Failed to merge slug {duplicate:?} into slug {slug:?}: slug {slug:?} not found"));
+            return Err(ShortenerError::SlugNotFound);
+        };
+        let link_id = link_id.clone();
+        let link = state.link.clone();
+        let owner = state.owner.clone();
+
+        let Some((merged_link_id, merged)) = self.read_model.find(&duplicate.0) else {
+            self.log(format!("Failed to merge slug {duplicate:?} into slug {slug:?}: slug {duplicate:?} not found"));
+            return Err(ShortenerError::SlugNotFound);
+        };
+
+        if *merged_link_id == link_id {
+            return Ok(link);
+        }
+
+        let normalize = |url: &Url| urls::normalize(&url.0, &self.config.url_normalization).ok();
+        if normalize(&merged.link.url) != normalize(&link.url) {
+            self.log(format!("Failed to merge slug {duplicate:?} into slug {slug:?}: destinations differ"));
+            return Err(ShortenerError::DestinationMismatch);
+        }
+
+        if merged.owner != owner {
+            self.log(format!("Failed to merge slug {duplicate:?} into slug {slug:?}: links have different owners"));
+            return Err(ShortenerError::AccessDenied);
+        }
+
+        let merged_link_id = merged_link_id.clone();
+        let merged_slug = merged.link.slug.clone();
+        self.log(format!("Merged slug {merged_slug:?} into slug {:?}", link.slug));
+        self.record(Event::LinksMerged { link_id, slug: link.slug.clone(), merged_link_id, merged_slug });
+        Ok(link)
+    }
+
+    /// Creates links exported from other systems, e.g. read by
+    /// [`import::read_links_csv`], each the same way as
+    /// [`Self::handle_create_short_link_with`] with its tags. Links failing
+    /// validation don't stop the import, they are reported along with the
+    /// created ones.
+    pub fn handle_import_links(&mut self, links: Vec<ImportedLink>) -> LinkImportReport {
+        let mut report = LinkImportReport::default();
+        for ImportedLink { line, url, slug, tags } in links {
+            let options = LinkOptions { tags, ..Default::default() };
+            match (self.handle_create_short_link_with(url, slug.clone(), options), slug) {
+                (Ok(link), _) => report.created.push(link),
+                (Err(ShortenerError::SlugAlreadyInUse), Some(slug)) => report.conflicts.push((line, slug)),
+                (Err(error), _) => report.invalid.push((line, error)),
+            }
+        }
+
+        self.log(format!(
+            "Imported {} links, {} conflicts and {} invalid links",
+            report.created.len(),
+            report.conflicts.len(),
+            report.invalid.len(),
+        ));
+        report
+    }
+
+    /// Imports links from CSV with the columns `url,slug,tags`, see
+    /// [`import::read_links_csv`] and [`Self::handle_import_links`].
+    ///
+    /// ## Errors
+    ///
+    /// Errors of the reader.
+    pub fn import_links_csv<R: io::Read>(&mut self, reader: R) -> io::Result<LinkImportReport> {
+        Ok(self.handle_import_links(import::read_links_csv(reader)?))
+    }
+
+    /// Imports redirect histories exported from other systems, reconciled
+    /// with the merge rules. Imported redirects are added to the stats of
+    /// the links, but are not delivered to webhooks.
+    pub fn handle_import_redirects(&mut self, redirects: Vec<ImportedRedirect>, rules: &MergeRules) -> ImportReport {
+        let (redirects, duplicates) = rules.apply(redirects);
+        let mut report = ImportReport { duplicates, ..Default::default() };
+
+        for ImportedRedirect { slug, at, count, .. } in redirects {
+            let Some((link_id, _)) = self.read_model.find(&slug.0) else {
+                if !report.unknown_slugs.contains(&slug) {
+                    report.unknown_slugs.push(slug);
+                }
+                continue;
+            };
+
+            let link_id = link_id.clone();
+            report.imported += count;
+            self.record(Event::RedirectsImported { link_id, slug, at, redirects: count });
+        }
+
+        self.log(format!("Imported redirects {report:?}"));
+        report
+    }
+
+    /// Resets stats of the link, so [`QueryHandler::get_stats`] counts only
+    /// redirects made after the reset. Recorded redirects remain in the event
+    /// log.
+    ///
+    /// ## Errors
+    ///
+    /// [`ShortenerError::SlugNotFound`] if the [`Slug`] doesn't map to any
+    /// short link.
+    pub fn handle_reset_stats(&mut self, slug: Slug) -> Result<(), ShortenerError> {
+        let Some((link_id, _)) = self.read_model.find(&slug.0) else {
+            self.log(format!("Failed to reset stats of slug {slug:?}: slug not found"));
+            return Err(ShortenerError::SlugNotFound);
+        };
+
+        let link_id = link_id.clone();
+        self.log(format!("Reset stats of slug {slug:?}"));
+        self.record(Event::StatsReset { link_id, slug });
+        Ok(())
+    }
+
+    /// Records expiration of all deprecated slug aliases of all tenants whose
+    /// grace period has ended. Expired aliases don't resolve even before this
+    /// command, it only makes expiration visible in the event log.
+    pub fn handle_expire_aliases(&mut self) {
+        let now = Utc::now();
+        let expired: Vec<_> = self.read_model.alias_expirations
+            .values()
+            .filter(|hold| hold.expires_at <= now)
+            .map(|hold| (hold.tenant.clone(), hold.slug.clone()))
+            .collect();
+
+        for (tenant, slug) in expired {
+            let context = RequestContext { tenant, ..self.acting.clone() };
+            self.act_as(&context, |service| {
+                service.log(format!("Deprecated slug {slug:?} expired"));
+                service.expire_slug(&slug);
+            });
+        }
+    }
+
+    /// Protects the link with a password, so it can be followed only by
+    /// [`Self::handle_redirect_with_password`]. Only a salted hash of the
+    /// password is recorded.
+    ///
+    /// ## Errors
+    ///
+    /// [`ShortenerError::SlugNotFound`] if the [`Slug`] doesn't map to any
+    /// short link.
+    pub fn handle_set_password(&mut self, slug: Slug, password: &str) -> Result<(), ShortenerError> {
+        let Some((link_id, _)) = self.read_model.find(&slug.0) else {
+            self.log(format!("Failed to set password of slug {slug:?}: slug not found"));
+            return Err(ShortenerError::SlugNotFound);
+        };
+
+        let link_id = link_id.clone();
+        self.log(format!("Set password of slug {slug:?}"));
+        self.record(Event::PasswordSet { link_id, slug, password: PasswordHash::new(password) });
+        Ok(())
+    }
+
+    /// Removes the password protection of the link. Removing it from a link
+    /// without a password does nothing.
+    ///
+    /// ## Errors
+    ///
+    /// [`ShortenerError::SlugNotFound`] if the [`Slug`] doesn't map to any
+    /// short link.
+    pub fn handle_remove_password(&mut self, slug: Slug) -> Result<(), ShortenerError> {
+        let Some((link_id, state)) = self.read_model.find(&slug.0) else {
+            self.log(format!("Failed to remove password of slug {slug:?}: slug not found"));
+            return Err(ShortenerError::SlugNotFound);
+        };
+
+        if state.password.is_none() {
+            return Ok(());
+        }
+
+        let link_id = link_id.clone();
+        self.log(format!("Removed password of slug {slug:?}"));
+        self.record(Event::PasswordRemoved { link_id, slug });
+        Ok(())
+    }
+
+    /// Attaches tags to the link. Tags the link already has are ignored.
+    ///
+    /// ## Errors
+    ///
+    /// [`ShortenerError::SlugNotFound`] if the [`Slug`] doesn't map to any
+    /// short link.
+    pub fn handle_tag_link(&mut self, slug: Slug, tags: Vec<Tag>) -> Result<(), ShortenerError> {
+        let Some((link_id, state)) = self.read_model.find(&slug.0) else {
+            self.log(format!("Failed to tag slug {slug:?}: slug not found"));
+            return Err(ShortenerError::SlugNotFound);
+        };
+
+        let mut tags: Vec<_> = tags.into_iter().filter(|tag| !state.tags.contains(tag)).collect();
+        tags.sort();
+        tags.dedup();
+        if tags.is_empty() {
+            return Ok(());
+        }
+
+        let link_id = link_id.clone();
+        self.log(format!("Tagged slug {slug:?} with {tags:?}"));
+        self.record(Event::LinkTagged { link_id, slug, tags });
+        Ok(())
+    }
+
+    /// Detaches tags from the link. Tags the link doesn't have are ignored.
+    ///
+    /// ## Errors
+    ///
+    /// [`ShortenerError::SlugNotFound`] if the [`Slug`] doesn't map to any
+    /// short link.
+    pub fn handle_untag_link(&mut self, slug: Slug, tags: Vec<Tag>) -> Result<(), ShortenerError> {
+        let Some((link_id, state)) = self.read_model.find(&slug.0) else {
+            self.log(format!("Failed to untag slug {slug:?}: slug not found"));
+            return Err(ShortenerError::SlugNotFound);
+        };
+
+        let mut tags: Vec<_> = tags.into_iter().filter(|tag| state.tags.contains(tag)).collect();
+        tags.sort();
+        tags.dedup();
+        if tags.is_empty() {
+            return Ok(());
+        }
+
+        let link_id = link_id.clone();
+        self.log(format!("Untagged slug {slug:?} from {tags:?}"));
+        self.record(Event::LinkUntagged { link_id, slug, tags });
+        Ok(())
+    }
+
+    /// Replaces metadata of the link.
+    ///
+    /// ## Errors
+    ///
+    /// [`ShortenerError::SlugNotFound`] if the [`Slug`] doesn't map to any
+    /// short link.
+    pub fn handle_update_metadata(&mut self, slug: Slug, metadata: LinkMetadata) -> Result<(), ShortenerError> {
+        let Some((link_id, state)) = self.read_model.find(&slug.0) else {
+            self.log(format!("Failed to update metadata of slug {slug:?}: slug not found"));
+            return Err(ShortenerError::SlugNotFound);
+        };
+
+        if state.metadata == metadata {
+            return Ok(());
+        }
+
+        let link_id = link_id.clone();
+        self.log(format!("Updated metadata of slug {slug:?} to {metadata:?}"));
+        self.record(Event::MetadataUpdated { link_id, slug, metadata });
+        Ok(())
+    }
+
+    /// Attaches the webhook to the link, replacing the previous one. The
+    /// webhook receives a signed POST for every sampled redirect of the link,
+    /// sent by the [`WebhookSender`] set with [`Self::set_webhook_sender`].
+    ///
+    /// ## Errors
+    ///
+    /// - [`ShortenerError::InvalidUrl`] if the webhook URL is not valid.
+    /// - [`ShortenerError::SlugNotFound`] if the [`Slug`] doesn't map to any
+    ///   short link.
+    pub fn handle_set_webhook(&mut self, slug: Slug, webhook: LinkWebhook) -> Result<(), ShortenerError> {
+        if baseUrl::parse(&webhook.url.0).is_err() {
+            self.log(format!("Failed to set webhook of slug {slug:?}: invalid URL {:?}", webhook.url));
+            return Err(ShortenerError::InvalidUrl);
+        }
+
+        let Some((link_id, _)) = self.read_model.find(&slug.0) else {
+            self.log(format!("Failed to set webhook of slug {slug:?}: slug not found"));
+            return Err(ShortenerError::SlugNotFound);
+        };
+
+        let link_id = link_id.clone();
+        self.log(format!("Set webhook {:?} of slug {slug:?}", webhook.url));
+        self.record(Event::WebhookSet { link_id, slug, webhook });
+        Ok(())
+    }
+
+    /// Detaches the webhook from the link. Links without a webhook are left
+    /// intact.
+    ///
+    /// ## Errors
+    ///
+    /// [`ShortenerError::SlugNotFound`] if the [`Slug`] doesn't map to any
+    /// short link.
+    pub fn handle_remove_webhook(&mut self, slug: Slug) -> Result<(), ShortenerError> {
+        let Some((link_id, state)) = self.read_model.find(&slug.0) else {
+            self.log(format!("Failed to remove webhook of slug {slug:?}: slug not found"));
+            return Err(ShortenerError::SlugNotFound);
+        };
+
+        if state.webhook.is_none() {
+            return Ok(());
+        }
+
+        let link_id = link_id.clone();
+        self.log(format!("Removed webhook of slug {slug:?}"));
+        self.record(Event::WebhookRemoved { link_id, slug });
+        Ok(())
+    }
+
+    /// Changes how redirects of the link are served. Setting the current type
+    /// does nothing.
+    ///
+    /// ## Errors
+    ///
+    /// [`ShortenerError::SlugNotFound`] if the [`Slug`] doesn't map to any
+    /// short link.
+    pub fn handle_set_redirect_type(&mut self, slug: Slug, redirect_type: RedirectType) -> Result<(), ShortenerError> {
+        let Some((link_id, state)) = self.read_model.find(&slug.0) else {
+            self.log(format!("Failed to set redirect type of slug {slug:?}: slug not found"));
+            return Err(ShortenerError::SlugNotFound);
+        };
+
+        if state.redirect_type == redirect_type {
+            return Ok(());
+        }
+
+        let link_id = link_id.clone();
+        self.log(format!("Set redirect type of slug {slug:?} to {redirect_type:?}"));
+        self.record(Event::RedirectTypeSet { link_id, slug, redirect_type });
+        Ok(())
+    }
+
+    /// Shows visitors of the link an interstitial page with its destination
+    /// and a button to continue there, instead of redirecting them right
+    /// away, e.g. so they can see where a link from an untrusted source
+    /// leads. Setting the current value does nothing.
+    ///
+    /// ## Errors
+    ///
+    /// [`ShortenerError::SlugNotFound`] if the [`Slug`] doesn't map to any
+    /// short link.
+    pub fn handle_set_interstitial(&mut self, slug: Slug, enabled: bool) -> Result<(), ShortenerError> {
+        let Some((link_id, state)) = self.read_model.find(&slug.0) else {
+            self.log(format!("Failed to set interstitial of slug {slug:?}: slug not found"));
+            return Err(ShortenerError::SlugNotFound);
+        };
+
+        if state.interstitial == enabled {
+            return Ok(());
+        }
+
+        let link_id = link_id.clone();
+        self.log(format!("Set interstitial of slug {slug:?} to {enabled}"));
+        self.record(Event::InterstitialSet { link_id, slug, enabled });
+        Ok(())
+    }
+
+    /// Replaces query parameters added to the destination of the link when it
+    /// is followed, e.g. UTM tags or a click id. Empty parameters remove
+    /// them. Setting the current parameters does nothing.
+    ///
+    /// ## Errors
+    ///
+    /// [`ShortenerError::SlugNotFound`] if the [`Slug`] doesn't map to any
+    /// short link.
+    pub fn handle_set_query_params(&mut self, slug: Slug, params: Vec<QueryParam>) -> Result<(), ShortenerError> {
+        let Some((link_id, state)) = self.read_model.find(&slug.0) else {
+            self.log(format!("Failed to set query parameters of slug {slug:?}: slug not found"));
+            return Err(ShortenerError::SlugNotFound);
+        };
+
+        if state.query_params == params {
+            return Ok(());
+        }
+
+        let link_id = link_id.clone();
+        self.log(format!("Set query parameters of slug {slug:?} to {params:?}"));
+        self.record(Event::QueryParamsSet { link_id, slug, params });
+        Ok(())
+    }
+
+    /// Creates an empty group of links owned by the acting caller.
+    pub fn handle_create_group(&mut self, name: String) -> GroupId {
+        let group_id = GroupId::generate();
+        let owner = self.acting.actor.as_ref().map(OwnerId::from);
+        self.log(format!("Created group {group_id:?} named {name:?}"));
+        self.record(Event::GroupCreated { group_id: group_id.clone(), name, owner });
+        group_id
+    }
+
+    /// Creates a namespace owned by the acting caller, so slugs like
+    /// `name/launch` can be used by its owner. Names of namespaces follow
+    /// [`ServiceConfig::slug_rules`], but don't conflict with slugs.
+    ///
+    /// ## Errors
+    ///
+    /// - [`ShortenerError::InvalidSlug`] if the name breaks the slug rules.
+    /// - [`ShortenerError::SlugBlocked`] if the name is blocked, so routes of
+    ///   the service can't be shadowed by namespaced slugs.
+    /// - [`ShortenerError::NamespaceAlreadyExists`] if there is a namespace
+    ///   with the name.
+    pub fn handle_create_namespace(&mut self, name: String) -> Result<(), ShortenerError> {
+        if let Err(violation) = self.config.slug_rules.check(&name) {
+            self.log(format!("Failed to create namespace {name:?}: name breaks rule {violation:?}"));
+            return Err(ShortenerError::InvalidSlug(violation));
+        }
+
+        if self.is_blocked(&name) {
+            self.log(format!("Failed to create namespace {name:?}: name is blocked"));
+            return Err(ShortenerError::SlugBlocked);
+        }
+
+        if self.read_model.namespaces.contains_key(&self.read_model.key(&name)) {
+            self.log(format!("Failed to create namespace {name:?}: namespace already exists"));
+            return Err(ShortenerError::NamespaceAlreadyExists);
+        }
+
+        let owner = self.acting.actor.as_ref().map(OwnerId::from);
+        self.log(format!("Created namespace {name:?}"));
+        self.record(Event::NamespaceCreated { name, owner });
+        Ok(())
+    }
+
+    /// Moves the link to the group, or out of its group if `group` is
+    /// `None`. Moving a link to its current group does nothing.
+    ///
+    /// ## Errors
+    ///
+    /// - [`ShortenerError::SlugNotFound`] if the [`Slug`] doesn't map to any
+    ///   short link.
+    /// - [`ShortenerError::GroupNotFound`] if there is no such group.
+    /// - [`ShortenerError::AccessDenied`] if the group is owned by someone
+    ///   other than the acting caller.
+    pub fn handle_move_to_group(&mut self, slug: Slug, group: Option<GroupId>) -> Result<(), ShortenerError> {
+        let Some((link_id, state)) = self.read_model.find(&slug.0) else {
+            self.log(format!("Failed to move slug {slug:?} to group {group:?}: slug not found"));
+            return Err(ShortenerError::SlugNotFound);
+        };
+
+        if state.group == group {
+            return Ok(());
+        }
+
+        if let Some(group_id) = &group {
+            let Some(group_state) = self.read_model.tenant_group(group_id) else {
+                self.log(format!("Failed to move slug {slug:?} to group {group_id:?}: group not found"));
+                return Err(ShortenerError::GroupNotFound);
+            };
+            let acting = self.acting.actor.as_ref().map(OwnerId::from);
+            if group_state.owner.is_some() && group_state.owner != acting {
+                let owner = &group_state.owner;
+                self.log(format!("Failed to move slug {slug:?} to group {group_id:?}: group is owned by {owner:?}"));
+                return Err(ShortenerError::AccessDenied);
+            }
+        }
+
+        let link_id = link_id.clone();
+        let previous_group = state.group.clone();
+        self.log(format!("Moved slug {slug:?} from group {previous_group:?} to group {group:?}"));
+        self.record(Event::LinkMovedToGroup { link_id, slug, previous_group, group });
+        Ok(())
+    }
+
+    /// Records that a redirect of the link led to a conversion reported by a
+    /// downstream system, e.g. a signup or a purchase. Conversions are counted
+    /// in [`StatsBreakdown::conversions`]. Reporting the same conversion again
+    /// does nothing, so reports can be retried.
+    ///
+    /// ## Errors
+    ///
+    /// [`ShortenerError::SlugNotFound`] if the [`Slug`] doesn't map to any
+    /// short link.
+    pub fn handle_conversion(&mut self, slug: Slug, conversion_id: ConversionId) -> Result<(), ShortenerError> {
+        let Some((link_id, state)) = self.read_model.find(&slug.0) else {
+            self.log(format!("Failed to record conversion {conversion_id:?} of slug {slug:?}: slug not found"));
+            return Err(ShortenerError::SlugNotFound);
+        };
+
+        if state.conversion_ids.contains(&conversion_id) {
+            self.log(format!("Ignored repeated conversion {conversion_id:?} of slug {slug:?}"));
+            return Ok(());
+        }
+
+        let link_id = link_id.clone();
+        self.log(format!("Recorded conversion {conversion_id:?} of slug {slug:?}"));
+        self.record(Event::ConversionRecorded { link_id, slug, conversion_id });
+        Ok(())
+    }
+
+    /// Assigns the link to the campaign, or removes it from its campaign if
+    /// `campaign` is `None`. Redirects are counted in stats of the campaign
+    /// the link is assigned to when they are made. Assigning a link to its
+    /// current campaign does nothing.
+    ///
+    /// ## Errors
+    ///
+    /// [`ShortenerError::SlugNotFound`] if the [`Slug`] doesn't map to any
+    /// short link.
+    pub fn handle_assign_campaign(&mut self, slug: Slug, campaign: Option<Campaign>) -> Result<(), ShortenerError> {
+        let Some((link_id, state)) = self.read_model.find(&slug.0) else {
+            self.log(format!("Failed to assign slug {slug:?} to campaign {campaign:?}: slug not found"));
+            return Err(ShortenerError::SlugNotFound);
+        };
+
+        if state.campaign == campaign {
+            return Ok(());
+        }
+
+        let link_id = link_id.clone();
+        self.log(format!("Assigned slug {slug:?} to campaign {campaign:?}"));
+        self.record(Event::CampaignAssigned { link_id, slug, campaign });
+        Ok(())
+    }
+
+    /// Transfers ownership of the link to the new owner.
+    ///
+    /// ## Errors
+    ///
+    /// [`ShortenerError::SlugNotFound`] if the [`Slug`] doesn't map to any
+    /// short link.
+    pub fn handle_transfer_ownership(&mut self, slug: Slug, new_owner: OwnerId) -> Result<(), ShortenerError> {
+        let Some((link_id, state)) = self.read_model.find(&slug.0) else {
+            self.log(format!("Failed to transfer ownership of slug {slug:?}: slug not found"));
+            return Err(ShortenerError::SlugNotFound);
+        };
+
+        let link_id = link_id.clone();
+        let previous_owner = state.owner.clone();
+        self.log(format!("Transferred ownership of slug {slug:?} from {previous_owner:?} to {new_owner:?}"));
+        self.record(Event::OwnershipTransferred { link_id, slug, previous_owner, new_owner });
+        Ok(())
+    }
+
+    /// Adds the host to the blocklist, so links to it or to its subdomains
+    /// can't be created anymore. Existing links to it are blocked, returns
+    /// slugs of newly blocked links. Blocking a blocked host only rechecks
+    /// destinations.
+    pub fn handle_block_host(&mut self, host: String) -> Vec<Slug> {
+        let host = host.to_lowercase();
+        if !self.read_model.blocked_hosts.contains(&host) {
+            self.log(format!("Blocked host {host:?}"));
+            self.record(Event::HostBlocked { host });
+        }
+        self.handle_recheck_destinations()
+    }
+
+    /// Checks destinations of all links, which are not blocked yet, against
+    /// the blocklist of hosts and the [`UrlPolicy`], e.g. after the policy
+    /// learned about new malicious destinations. Returns slugs of newly
+    /// blocked links.
+    pub fn handle_recheck_destinations(&mut self) -> Vec<Slug> {
+        let mut blocked: Vec<_> = self.read_model.links
+            .iter()
+            .filter(|(_, state)| state.blocked.is_none())
+            .filter_map(|(link_id, state)| {
+                let reason = self.destination_block_reason(&state.link.url)?;
+                Some((link_id.clone(), state.link.slug.clone(), reason))
+            })
+            .collect();
+        blocked.sort_by(|a, b| a.0.cmp(&b.0));
+
+        let mut slugs = Vec::new();
+        for (link_id, slug, reason) in blocked {
+            self.log(format!("Blocked slug {slug:?}: {reason}"));
+            slugs.push(slug.clone());
+            self.record(Event::LinkBlocked { link_id, slug, reason });
+        }
+        slugs
+    }
+
+    /// Probes destinations of links, which weren't checked for
+    /// [`ServiceConfig::health_check_interval`], with the probe set by
+    /// [`Self::set_health_probe`]. Returns slugs of links whose destinations
+    /// are broken. Nothing is checked without a probe, and destinations with
+    /// schemes other than `http` and `https` are never checked. It is meant
+    /// to be run periodically, away from serving redirects, as probes may
+    /// block.
+    pub fn handle_check_destination_health(&mut self) -> Vec<Slug> {
+        let Some(probe) = &self.health_probe else {
+            return Vec::new();
+        };
+
+        let checked_before = Utc::now() - self.config.health_check_interval;
+        let mut due: Vec<_> = self.read_model.links
+            .iter()
+            .filter(|(_, state)| state.link.url.0.starts_with("http:") || state.link.url.0.starts_with("https:"))
+            .filter(|(_, state)| state.health.checked_at().is_none_or(|checked_at| checked_at <= checked_before))
+            .map(|(link_id, state)| (link_id.clone(), state.link.slug.clone(), state.link.url.clone()))
+            .collect();
+        due.sort_by(|a, b| a.0.cmp(&b.0));
+        let checked: Vec<_> = due
+            .into_iter()
+            .map(|(link_id, slug, url)| (link_id, slug, probe.probe(&url)))
+            .collect();
+
+        let mut broken = Vec::new();
+        for (link_id, slug, result) in checked {
+            match result {
+                Ok(()) => {
+                    self.log(format!("Destination of slug {slug:?} is healthy"));
+                    self.record(Event::DestinationHealthy { link_id, slug });
+                },
+                Err(reason) => {
+                    self.log(format!("Destination of slug {slug:?} is broken: {reason}"));
+                    broken.push(slug.clone());
+                    self.record(Event::DestinationBroken { link_id, slug, reason });
+                },
+            }
+        }
+        broken
+    }
+
+    /// Drops raw redirect events older than the retention of their links,
+    /// [`ServiceConfig::click_retention`] unless the link sets its own.
+    /// Dropped redirects of each link between other events of the log are
+    /// replaced by a single [`Event::RedirectsAggregated`], so stats of links
+    /// and built-in projections keep counting them, even once they are
+    /// rebuilt from the log. Only details of single clicks are lost. Returns
+    /// slugs of links whose redirect events were dropped. It is meant to be
+    /// run periodically.
+    pub fn handle_prune_clicks(&mut self) -> Vec<Slug> {
+        let now = Utc::now();
+        let default_retention = self.config.click_retention;
+        let window = self.config.event_window.unwrap_or_default();
+        let mut kept = Vec::with_capacity(self.events.len());
+        // dropped redirects since the last other event, by their links
+        let mut run: BTreeMap<LinkId, ClickAggregate> = BTreeMap::new();
+        // times of dropped redirects counted for deduplication
+        let mut forgotten = Vec::new();
+        let mut pruned = 0;
+        let mut pruned_links = BTreeSet::new();
+        let mut pruned_through = self.clicks_pruned_through;
+        for record in std::mem::take(&mut self.events) {
+            let (Event::Redirected { slug_id, .. } | Event::RedirectDeduplicated { slug_id, .. }) = &record.event else {
+                self.event_bytes += ClickAggregate::flush(&mut run, &window, &mut kept);
+                kept.push(record);
+                continue;
+            };
+            let resolved = self.read_model.slug_ids.resolve(*slug_id);
+            let retention = resolved
+                .and_then(|(link_id, _)| self.read_model.links.get(link_id))
+                .and_then(|state| state.click_retention)
+                .or(default_retention);
+            if retention.is_none_or(|retention| record.recorded_at >= now - retention) {
+                kept.push(record);
+                continue;
+            }
+
+            pruned += 1;
+            pruned_through = pruned_through.max(record.sequence);
+            self.event_bytes -= window.record_size(&record);
+            let (Some((link_id, slug)), Event::Redirected { visitor, context, .. }) = (resolved, &record.event) else {
+                continue;
+            };
+            pruned_links.insert(link_id.clone());
+            if let Some(key) = visitor.clone().or_else(|| context.as_deref().and_then(analytics::fingerprint)) {
+                forgotten.push(((link_id.clone(), key), record.recorded_at));
+            }
+            match run.entry(link_id.clone()) {
+                btree_map::Entry::Occupied(mut entry) => entry.get_mut().add(&record),
+                btree_map::Entry::Vacant(entry) => {
+                    entry.insert(ClickAggregate::new(&record, slug.clone()));
+                },
+            }
+        }
+        self.event_bytes += ClickAggregate::flush(&mut run, &window, &mut kept);
+        // aggregates take places of their last redirects, which may precede kept redirects of the run
+        kept.sort_by_key(|record| record.sequence);
+        self.events = kept;
+        if pruned == 0 {
+            return Vec::new();
+        }
+
+        // replaying the log doesn't bring back deduplication of dropped redirects either
+        for (key, at) in forgotten {
+            if self.read_model.last_counted_redirects.get(&key) == Some(&at) {
+                self.read_model.last_counted_redirects.remove(&key);
+            }
+        }
+        self.clicks_pruned_through = pruned_through;
+        self.index.retain(&self.events);
+        let slugs: Vec<_> = pruned_links.iter()
+            .filter_map(|link_id| self.read_model.links.get(link_id))
+            .map(|state| state.link.slug.clone())
+            .collect();
+        self.log(format!("Pruned {pruned} redirect events of {} links", slugs.len()));
+        slugs
+    }
+
+    /// Flags links with suspicious traffic in the last window of
+    /// [`ServiceConfig::anomaly_rules`]: redirects spiking over the average of
+    /// the windows before it, or most of the traffic coming from a single
+    /// visitor. Every detection is recorded as [`Event::AnomalyDetected`], and
+    /// links flagged within the window are not flagged again. Returns slugs
+    /// of flagged links. It is meant to be run periodically, at least once a
+    /// window, and before [`Self::handle_prune_clicks`] drops the raw
+    /// redirect events visitors are told apart by.
+    pub fn handle_detect_anomalies(&mut self) -> Vec<Slug> {
+        let rules = self.config.anomaly_rules;
+        let now = Utc::now();
+        let recent_from = now - rules.window;
+        let baseline_from = recent_from - rules.window * rules.baseline_windows as i32;
+
+        // recent traffic of links, in total and by visitors
+        let mut traffic: HashMap<&LinkId, (u64, HashMap<VisitorId, u64>)> = HashMap::new();
+        for record in self.events.iter().rev().take_while(|record| record.recorded_at >= recent_from) {
+            let (slug_id, visitor) = match &record.event {
+                Event::Redirected { slug_id, visitor, context, .. } => {
+                    (slug_id, visitor.clone().or_else(|| context.as_deref().and_then(analytics::fingerprint)))
+                },
+                Event::RedirectDeduplicated { slug_id, visitor } => (slug_id, Some(visitor.clone())),
+                _ => continue,
+            };
+            let Some((link_id, _)) = self.read_model.slug_ids.resolve(*slug_id) else {
+                continue;
+            };
+            let (total, visitors) = traffic.entry(link_id).or_default();
+            *total += 1;
+            if let Some(visitor) = visitor {
+                *visitors.entry(visitor).or_default() += 1;
+            }
+        }
+
+        let rollups = self.projections.get::<RedirectRollups>();
+        let redirects_between = |link_id: &LinkId, from: DateTime<Utc>, to: DateTime<Utc>| -> u64 {
+            rollups.map_or(0, |rollups| {
+                rollups.range(link_id, from, to)
+                    .iter()
+                    .filter(|bucket| bucket.start >= from && bucket.start < to)
+                    .map(|bucket| bucket.redirects)
+                    .sum()
+            })
+        };
+        let mut detected: Vec<_> = self.read_model.links
+            .iter()
+            .filter(|(_, state)| state.anomaly.as_ref().is_none_or(|(_, detected_at)| *detected_at < recent_from))
+            .filter_map(|(link_id, state)| {
+                let dominant = traffic.get(link_id)
+                    .filter(|(total, _)| *total >= rules.min_redirects)
+                    .and_then(|(total, visitors)| {
+                        let (visitor, redirects) = visitors.iter().max_by_key(|(_, redirects)| **redirects)?;
+                        let share = *redirects as f64 / *total as f64;
+                        (share >= rules.max_visitor_share)
+                            .then(|| Anomaly::DominantVisitor { visitor: visitor.clone(), share })
+                    });
+                let spike = || {
+                    let redirects = redirects_between(link_id, recent_from, now);
+                    let baseline = redirects_between(link_id, baseline_from, recent_from) as f64
+                        / f64::from(rules.baseline_windows.max(1));
+                    let spiked = redirects >= rules.min_redirects
+                        && redirects as f64 > rules.spike_factor * baseline.max(1.0);
+                    spiked.then_some(Anomaly::Spike { redirects, baseline })
+                };
+                let anomaly = dominant.or_else(spike)?;
+                Some((link_id.clone(), state.link.slug.clone(), anomaly))
+            })
+            .collect();
+        detected.sort_by(|a, b| a.0.cmp(&b.0));
+
+        let mut slugs = Vec::new();
+        for (link_id, slug, anomaly) in detected {
+            self.log(format!("Detected anomaly of slug {slug:?}: {anomaly:?}"));
+            slugs.push(slug.clone());
+            self.record(Event::AnomalyDetected { link_id, slug, anomaly });
+        }
+        slugs
+    }
+
+    /// Produces the digest of the period ending now and hands it to the sink
+    /// set with [`Self::set_digest_sink`]. Redirects are counted from the
+    /// buckets of [`Self::get_redirects_over_time`] starting in the period,
+    /// so they stay available after raw clicks are pruned.
+    ///
+    /// If `repeat` is set, the same command is scheduled one period later on
+    /// behalf of the same caller, even if the delivery fails, so scheduling
+    /// the first [`Command::SendDigest`] keeps the digests coming.
+    ///
+    /// ## Errors
+    ///
+    /// [`ShortenerError::ServiceUnavailable`] if the sink fails to deliver
+    /// the digest.
+    pub fn handle_send_digest(&mut self, period: DigestPeriod, repeat: bool) -> Result<StatsDigest, ShortenerError> {
+        let to = Utc::now();
+        let digest = self.digest(period, to - period.duration(), to);
+        if repeat {
+            let command = Command::SendDigest { period, repeat };
+            self.handle_schedule_command(self.acting.clone(), command, to + period.duration());
+        }
+
+        if let Some(sink) = &self.digest_sink {
+            if let Err(error) = sink.deliver(&digest) {
+                self.log(format!("Failed to deliver {period:?} digest: {error}"));
+                return Err(ShortenerError::ServiceUnavailable);
+            }
+        }
+        self.log(format!("Sent {period:?} digest with {} redirects", digest.total_redirects));
+        Ok(digest)
+    }
+
+    fn digest(&self, period: DigestPeriod, from: DateTime<Utc>, to: DateTime<Utc>) -> StatsDigest {
+        let mut top_links: Vec<_> = self.read_model.tenant_links()
+            .filter_map(|(link_id, state)| {
+                let redirects: u64 = self.projections.get::<RedirectRollups>()?
+                    .range(link_id, from, to)
+                    .iter()
+                    .filter(|bucket| bucket.start >= from && bucket.start < to)
+                    .map(|bucket| bucket.redirects)
+                    .sum();
+                (redirects > 0).then(|| (link_id, state.link.slug.clone(), redirects))
+            })
+            .collect();
+        top_links.sort_by(|(a, _, a_redirects), (b, _, b_redirects)| {
+            b_redirects.cmp(a_redirects).then_with(|| a.cmp(b))
+        });
+        let new_links = self.events.iter()
+            .filter(|record| record.recorded_at >= from && record.recorded_at < to)
+            .filter_map(|record| match &record.event {
+                Event::LinkCreated { link_id, .. } => self.read_model.tenant_link(link_id),
+                _ => None,
+            })
+            .map(|state| state.link.clone())
+            .collect();
+        StatsDigest {
+            period,
+            from,
+            to,
+            total_redirects: top_links.iter().map(|(_, _, redirects)| redirects).sum(),
+            top_links: top_links.into_iter()
+                .take(StatsDigest::TOP_LINKS)
+                .map(|(_, slug, redirects)| (slug, redirects))
+                .collect(),
+            new_links,
+        }
+    }
+
+    /// Sets how long raw redirect events of the link are kept before
+    /// [`Self::handle_prune_clicks`] drops them, or resets it to
+    /// [`ServiceConfig::click_retention`] if `retention` is `None`. Setting
+    /// the current retention does nothing.
+    ///
+    /// ## Errors
+    ///
+    /// [`ShortenerError::SlugNotFound`] if the [`Slug`] doesn't map to any
+    /// short link.
+    pub fn handle_set_click_retention(
+        &mut self,
+        slug: Slug,
+        retention: Option<TimeDelta>,
+    ) -> Result<(), ShortenerError> {
+        let Some((link_id, state)) = self.read_model.find(&slug.0) else {
+            self.log(format!("Failed to set click retention of slug {slug:?}: slug not found"));
+            return Err(ShortenerError::SlugNotFound);
+        };
+
+        if state.click_retention == retention {
+            return Ok(());
+        }
+
+        let link_id = link_id.clone();
+        self.log(format!("Set click retention of slug {slug:?} to {retention:?}"));
+        self.record(Event::ClickRetentionSet { link_id, slug, retention });
+        Ok(())
+    }
+
+    /// Deletes the short link along with its stats. Its slug, aliases and old
+    /// slugs no longer resolve and may be used by other links, while its
+    /// history stays in the event log. Use [`Self::handle_disable_link`] to
+    /// keep the link and its stats.
+    ///
+    /// ## Errors
+    ///
+    /// [`ShortenerError::SlugNotFound`] if the [`Slug`] doesn't map to any
+    /// short link.
+    pub fn handle_delete_link(&mut self, slug: Slug) -> Result<(), ShortenerError> {
+        let Some((link_id, state)) = self.read_model.find(&slug.0) else {
+            self.log(format!("Failed to delete slug {slug:?}: slug not found"));
+            return Err(ShortenerError::SlugNotFound);
+        };
+
+        let link_id = link_id.clone();
+        let slug = state.link.slug.clone();
+        self.log(format!("Deleted slug {slug:?}"));
+        self.record(Event::LinkDeleted { link_id, slug });
+        Ok(())
+    }
+
+    /// Deactivates the short link without deleting it. Redirects to a disabled
+    /// link fail with [`ShortenerError::LinkDisabled`], while its stats and
+    /// history are retained. Disabling an already disabled link does nothing.
+    ///
+    /// ## Errors
+    ///
+    /// [`ShortenerError::SlugNotFound`] if the [`Slug`] doesn't map to any
+    /// short link.
+    pub fn handle_disable_link(&mut self, slug: Slug) -> Result<(), ShortenerError> {
+        let Some((link_id, state)) = self.read_model.find(&slug.0) else {
+            self.log(format!("Failed to disable slug {slug:?}: slug not found"));
+            return Err(ShortenerError::SlugNotFound);
+        };
+
+        if state.disabled {
+            return Ok(());
+        }
+
+        let link_id = link_id.clone();
+        self.log(format!("Disabled slug {slug:?}"));
+        self.record(Event::LinkDisabled { link_id, slug });
+        Ok(())
+    }
+
+    /// Reactivates the previously disabled short link. Enabling a link which
+    /// is not disabled does nothing.
+    ///
+    /// ## Errors
+    ///
+    /// [`ShortenerError::SlugNotFound`] if the [`Slug`] doesn't map to any
+    /// short link.
+    pub fn handle_enable_link(&mut self, slug: Slug) -> Result<(), ShortenerError> {
+        let Some((link_id, state)) = self.read_model.find(&slug.0) else {
+            self.log(format!("Failed to enable slug {slug:?}: slug not found"));
+            return Err(ShortenerError::SlugNotFound);
+        };
+
+        if !state.disabled {
+            return Ok(());
+        }
+
+        let link_id = link_id.clone();
+        self.log(format!("Enabled slug {slug:?}"));
+        self.record(Event::LinkEnabled { link_id, slug });
+        Ok(())
+    }
+
+    /// Archives all links which weren't followed since
+    /// [`ServiceConfig::archive_after`], counting from their creation or
+    /// restoring. Archived links still redirect, but are hidden from
+    /// listings until they are unarchived. It is meant to be run
+    /// periodically.
+    pub fn handle_archive_inactive_links(&mut self) {
+        let Some(archive_after) = self.config.archive_after else {
+            return;
+        };
+
+        let inactive_since = Utc::now() - archive_after;
+        let mut inactive: Vec<_> = self.read_model.links
+            .iter()
+            .filter(|(_, state)| !state.archived && state.last_active_at <= inactive_since)
+            .map(|(link_id, state)| (link_id.clone(), state.link.slug.clone()))
+            .collect();
+        inactive.sort_by(|(a, _), (b, _)| a.cmp(b));
+
+        for (link_id, slug) in inactive {
+            self.log(format!("Archived inactive slug {slug:?}"));
+            self.record(Event::LinkArchived { link_id, slug });
+        }
+    }
+
+    /// Restores the archived link, so it is listed again and its inactivity
+    /// period starts anew. Unarchiving a link which is not archived does
+    /// nothing.
+    ///
+    /// ## Errors
+    ///
+    /// [`ShortenerError::SlugNotFound`] if the [`Slug`] doesn't map to any
+    /// short link.
+    pub fn handle_unarchive_link(&mut self, slug: Slug) -> Result<(), ShortenerError> {
+        let Some((link_id, state)) = self.read_model.find(&slug.0) else {
+            self.log(format!("Failed to unarchive slug {slug:?}: slug not found"));
+            return Err(ShortenerError::SlugNotFound);
+        };
+
+        if !state.archived {
+            return Ok(());
+        }
+
+        let link_id = link_id.clone();
+        self.log(format!("Unarchived slug {slug:?}"));
+        self.record(Event::LinkUnarchived { link_id, slug });
+        Ok(())
+    }
+
+    /// Schedules the command to be executed on behalf of the caller once
+    /// `due_at` comes, see [`Self::handle_run_due_commands`]. The command is
+    /// kept in the event log as is, so secrets like passwords shouldn't be
+    /// scheduled.
+    pub fn handle_schedule_command(
+        &mut self,
+        context: RequestContext,
+        command: Command,
+        due_at: DateTime<Utc>,
+    ) -> ScheduleId {
+        let id = ScheduleId(self.read_model.next_schedule_id);
+        self.log(format!("Scheduled {command:?} as {id:?} at {due_at}"));
+        self.record(Event::CommandScheduled { scheduled: ScheduledCommand { id, due_at, context, command } });
+        id
+    }
+
+    /// Cancels the pending scheduled command.
+    ///
+    /// ## Errors
+    ///
+    /// [`ShortenerError::ScheduledCommandNotFound`] if there is no pending
+    /// command with the id, e.g. it was already executed or cancelled.
+    pub fn handle_cancel_scheduled_command(&mut self, id: ScheduleId) -> Result<(), ShortenerError> {
+        if !self.read_model.scheduled.contains_key(&id) {
+            self.log(format!("Failed to cancel {id:?}: scheduled command not found"));
+            return Err(ShortenerError::ScheduledCommandNotFound);
+        }
+
+        self.log(format!("Cancelled {id:?}"));
+        self.record(Event::ScheduledCommandCancelled { id });
+        Ok(())
+    }
+
+    /// Executes all scheduled commands which are due, in order of their
+    /// execution time. Each command goes through [`Self::dispatch_command`]
+    /// on behalf of the caller who scheduled it, so it is authorized at the
+    /// time of execution and produces the same events as if it was sent
+    /// directly. A failed command is not retried. It is meant to be run
+    /// periodically.
+    pub fn handle_run_due_commands(&mut self) {
+        let now = Utc::now();
+        let mut due: Vec<_> = self.read_model.scheduled
+            .values()
+            .filter(|scheduled| scheduled.due_at <= now)
+            .cloned()
+            .collect();
+        due.sort_by_key(|scheduled| (scheduled.due_at, scheduled.id));
+
+        for ScheduledCommand { id, context, command, .. } in due {
+            let error = self.dispatch_command(&context, command).err();
+            match &error {
+                None => self.log(format!("Executed {id:?}")),
+                Some(error) => self.log(format!("Failed to execute {id:?}: {error}")),
+            }
+            self.record(Event::ScheduledCommandExecuted { id, error });
+        }
+    }
+}
+
+impl UrlShortenerService {
+    /// Returns the [`LinkId`] of the link the [`Slug`] maps to.
+    ///
+    /// ## Errors
+    ///
+    /// [`ShortenerError::SlugNotFound`] if the [`Slug`] doesn't map to any
+    /// short link.
+    pub fn get_link_id(&self, slug: &Slug) -> Result<LinkId, ShortenerError> {
+        self.read_model.find(&slug.0)
+            .map(|(link_id, _)| link_id.clone())
+            .ok_or(ShortenerError::SlugNotFound)
+    }
+
+    /// Returns changes of the link the [`Slug`] maps to, ordered as they were
+    /// recorded. Redirect traffic is not part of the history, and events
+    /// dropped to fit the [`EventWindow`] are not returned.
+    ///
+    /// ## Errors
+    ///
+    /// [`ShortenerError::SlugNotFound`] if the [`Slug`] doesn't map to any
+    /// short link.
+    pub fn get_history(&self, slug: &Slug) -> Result<Vec<EventRecord>, ShortenerError> {
+        let Some((link_id, _)) = self.read_model.find(&slug.0) else {
+            self.log(format!("Failed to retrieve history of slug {slug:?}: slug not found"));
+            return Err(ShortenerError::SlugNotFound);
+        };
+
+        let history: Vec<_> = self.link_events(link_id, 1)
+            .map(|event| event.record)
+            .filter(|record| record.event.slug_id().is_none() && !record.event.is_state_neutral())
+            .collect();
+        self.log(format!("Retrieved {} history events of slug {slug:?}", history.len()));
+        Ok(history)
+    }
+
+    /// Returns events of the link the [`Slug`] maps to, starting from the
+    /// version, ordered as they were recorded. Versions count all events of
+    /// the link starting from `1`, so consumers can resume from the version
+    /// after the last one they have seen. Events dropped to fit the
+    /// [`EventWindow`] are skipped.
+    ///
+    /// ## Errors
+    ///
+    /// [`ShortenerError::SlugNotFound`] if the [`Slug`] doesn't map to any
+    /// short link.
+    pub fn get_events_for(&self, slug: &Slug, from_version: u64) -> Result<Vec<VersionedEvent>, ShortenerError> {
+        let Some((link_id, _)) = self.read_model.find(&slug.0) else {
+            self.log(format!("Failed to retrieve events of slug {slug:?}: slug not found"));
+            return Err(ShortenerError::SlugNotFound);
+        };
+
+        let events: Vec<_> = self.link_events(link_id, from_version).collect();
+        self.log(format!("Retrieved {} events of slug {slug:?} from version {from_version}", events.len()));
+        Ok(events)
+    }
+
+    /// Returns at most `limit` events of the acting tenant recorded after the
+    /// sequence, in order, so operators can page through the event log by
+    /// passing the sequence of the last event they have seen. Events dropped
+    /// to fit the [`EventWindow`] are skipped.
+    pub fn list_events(&self, after: u64, limit: usize) -> Vec<EventRecord> {
+        let start = self.events.partition_point(|record| record.sequence <= after);
+        let events: Vec<_> = self.events[start..]
+            .iter()
+            .filter(|record| record.tenant == self.acting.tenant)
+            .take(limit)
+            .cloned()
+            .collect();
+        self.log(format!("Listed {} events after sequence {after}", events.len()));
+        events
+    }
+
+    fn link_events<'a>(&'a self, link_id: &LinkId, from_version: u64) -> impl Iterator<Item = VersionedEvent> + 'a {
+        self.index.events_from(link_id, from_version)
+            .iter()
+            .filter_map(|(version, sequence)| {
+                let index = self.events.binary_search_by_key(sequence, |record| record.sequence).ok()?;
+                Some(VersionedEvent { version: *version, record: self.events[index].clone() })
+            })
+    }
+
+    /// Returns overall stats of the service, including how often generated
+    /// slugs collide with slugs in use.
+    pub fn get_system_stats(&self) -> SystemStats {
+        let stats = SystemStats {
+            links: self.read_model.links.len(),
+            redirects: self.read_model.links.values().map(|state| state.redirects).sum(),
+            events: self.last_sequence,
+            slug_generation: self.slug_generation.get(),
+        };
+        self.log(format!("Got system stats {stats:?}"));
+        stats
+    }
+
+    /// Returns stats of the link the [`Slug`] maps to together with redirects
+    /// counted by each of its slugs, including aliases and old slugs.
+    ///
+    /// ## Errors
+    ///
+    /// [`ShortenerError::SlugNotFound`] if the [`Slug`] doesn't map to any
+    /// short link.
+    pub fn get_stats_breakdown(&self, slug: &Slug) -> Result<StatsBreakdown, ShortenerError> {
+        let Some((link_id, state)) = self.read_model.find(&slug.0) else {
+            self.log(format!("Failed to retrieve stats breakdown of slug {slug:?}: slug not found"));
+            return Err(ShortenerError::SlugNotFound);
+        };
+
+        let by_slug = self.read_model.slug_ids.slugs_of(link_id)
+            .map(|(slug_id, slug)| (slug.clone(), state.redirects_by_slug.get(&slug_id).copied().unwrap_or(0)))
+            .collect();
+        let breakdown = StatsBreakdown {
+            stats: state.stats(),
+            human_redirects: state.human_redirects(),
+            unique_visitors: state.visitors.estimate(),
+            conversions: state.conversions,
+            by_slug,
+            refused: state.refused.iter().map(|(reason, refused)| (*reason, *refused)).collect(),
+        };
+        self.log(format!("Retrieved stats breakdown {breakdown:?}"));
+        Ok(breakdown)
+    }
+
+    /// Returns redirects of the link the [`Slug`] maps to by countries of
+    /// visitors, most frequent first. Only redirects whose country was given
+    /// or resolved by the [`GeoResolver`] are counted, see
+    /// [`Self::set_geo_resolver`].
+    ///
+    /// ## Errors
+    ///
+    /// [`ShortenerError::SlugNotFound`] if the [`Slug`] doesn't map to any
+    /// short link.
+    pub fn get_clicks_by_country(&self, slug: &Slug) -> Result<Vec<(CountryCode, u64)>, ShortenerError> {
+        let Some((link_id, _)) = self.read_model.find(&slug.0) else {
+            self.log(format!("Failed to retrieve clicks by country of slug {slug:?}: slug not found"));
+            return Err(ShortenerError::SlugNotFound);
+        };
+
+        let mut clicks: Vec<_> = self.projections.get::<CountryClicks>()
+            .into_iter()
+            .flat_map(|projection| projection.clicks(link_id))
+            .map(|(country, clicks)| (country.clone(), clicks))
+            .collect();
+        clicks.sort_by(|(a, a_clicks), (b, b_clicks)| b_clicks.cmp(a_clicks).then_with(|| a.cmp(b)));
+        self.log(format!("Retrieved clicks of slug {slug:?} from {} countries", clicks.len()));
+        Ok(clicks)
+    }
+
+    /// Writes stats of links matching the filter to the writer in CSV format,
+    /// see [`export::HEADER`], row by row as they are read. Raw clicks follow
+    /// stats of links if [`ExportFilter::include_clicks`] is set. Returns the
+    /// count of written rows besides the header.
+    ///
+    /// ## Errors
+    ///
+    /// Errors of the writer.
+    pub fn export_stats_csv<W: Write>(&self, mut writer: W, filter: &ExportFilter) -> io::Result<usize> {
+        let mut link_ids: Vec<_> = self.read_model.tenant_links()
+            .filter(|(_, state)| filter.include_archived || !state.archived)
+            .filter(|(_, state)| filter.tag.as_ref().is_none_or(|tag| state.tags.contains(tag)))
+            .map(|(link_id, _)| link_id)
+            .collect();
+        link_ids.sort();
+
+        export::write_row(&mut writer, export::HEADER)?;
+        for link_id in &link_ids {
+            let state = &self.read_model.links[*link_id];
+            let stats = state.stats();
+            let row = [
+                "link", &stats.link.slug.0, &stats.link.url.0, &stats.redirects.to_string(),
+                &state.human_redirects().to_string(), &state.visitors.estimate().to_string(), "", "", "", "", "", "",
+            ];
+            export::write_row(&mut writer, &row)?;
+        }
+        let mut rows = link_ids.len();
+
+        if filter.include_clicks {
+            let exported: HashSet<_> = link_ids.into_iter().collect();
+            for record in &self.events {
+                let Event::Redirected { slug_id, visitor, context, bot } = &record.event else {
+                    continue;
+                };
+                let Some((link_id, slug)) = self.read_model.slug_ids.resolve(*slug_id) else {
+                    continue;
+                };
+                let at = context.as_ref().and_then(|context| context.requested_at).unwrap_or(record.recorded_at);
+                if !exported.contains(link_id) || filter.clicks_since.is_some_and(|since| at < since) {
+                    continue;
+                }
+                let context = context.as_deref().cloned().unwrap_or_default();
+                let row = [
+                    "click", &slug.0, "", "", "", "",
+                    &at.to_rfc3339(),
+                    visitor.as_ref().map_or("", |visitor| &visitor.0),
+                    context.referrer.as_deref().unwrap_or(""),
+                    context.user_agent.as_deref().unwrap_or(""),
+                    context.country.as_ref().map_or("", |country| &country.0),
+                    if *bot { "true" } else { "false" },
+                ];
+                export::write_row(&mut writer, &row)?;
+                rows += 1;
+            }
+        }
+        writer.flush()?;
+        self.log(format!("Exported {rows} rows of stats"));
+        Ok(rows)
+    }
+
+    /// Returns stats of the campaign of the tenant of the caller aggregated
+    /// over its links.
+    ///
+    /// ## Errors
+    ///
+    /// [`ShortenerError::CampaignNotFound`] if no link of the tenant was ever
+    /// assigned to the campaign.
+    pub fn get_campaign_stats(&self, campaign: &Campaign) -> Result<CampaignStats, ShortenerError> {
+        let tenant = self.read_model.tenant.as_ref();
+        let totals = self.projections.get::<CampaignIndex>().and_then(|index| index.campaign(tenant, campaign));
+        let Some(totals) = totals else {
+            self.log(format!("Failed to retrieve stats of campaign {campaign:?}: campaign not found"));
+            return Err(ShortenerError::CampaignNotFound);
+        };
+
+        let mut top_links: Vec<_> = totals.redirects.iter()
+            .filter_map(|(link_id, redirects)| {
+                let state = self.read_model.tenant_link(link_id)?;
+                Some((link_id, state.link.slug.clone(), *redirects))
+            })
+            .collect();
+        top_links.sort_by(|(a, _, a_redirects), (b, _, b_redirects)| {
+            b_redirects.cmp(a_redirects).then_with(|| a.cmp(b))
+        });
+        let stats = CampaignStats {
+            campaign: campaign.clone(),
+            links: totals.links.len(),
+            redirects: totals.redirects.values().sum(),
+            unique_visitors: totals.visitors.estimate(),
+            top_links: top_links.into_iter()
+                .take(CampaignStats::TOP_LINKS)
+                .map(|(_, slug, redirects)| (slug, redirects))
+                .collect(),
+        };
+        self.log(format!("Retrieved campaign stats {stats:?}"));
+        Ok(stats)
+    }
+
+    /// Returns redirects of the link the [`Slug`] maps to by classes of
+    /// devices and families of browsers of visitors, with their percentages.
+    /// Only redirects with a known user agent are counted, redirects
+    /// classified as made by bots are counted as [`DeviceClass::Bot`].
+    ///
+    /// ## Errors
+    ///
+    /// [`ShortenerError::SlugNotFound`] if the [`Slug`] doesn't map to any
+    /// short link.
+    pub fn get_device_stats(&self, slug: &Slug) -> Result<DeviceStats, ShortenerError> {
+        let Some((link_id, _)) = self.read_model.find(&slug.0) else {
+            self.log(format!("Failed to retrieve device stats of slug {slug:?}: slug not found"));
+            return Err(ShortenerError::SlugNotFound);
+        };
+
+        let clicks = self.projections.get::<DeviceClicks>();
+        let devices = Share::of(clicks.into_iter().flat_map(|clicks| clicks.devices(link_id)));
+        let browsers = Share::of(clicks.into_iter().flat_map(|clicks| clicks.browsers(link_id)));
+        let stats = DeviceStats { redirects: devices.iter().map(|share| share.redirects).sum(), devices, browsers };
+        self.log(format!("Retrieved device stats {stats:?}"));
+        Ok(stats)
+    }
+
+    /// Returns counts of redirects of the link the [`Slug`] maps to in buckets
+    /// overlapping the range from `from` up to `to`, ordered by their start.
+    /// Recent redirects are counted by minutes, older ones by hours and then
+    /// by days, see [`analytics::Resolution`].
+    ///
+    /// ## Errors
+    ///
+    /// [`ShortenerError::SlugNotFound`] if the [`Slug`] doesn't map to any
+    /// short link.
+    pub fn get_redirects_over_time(
+        &self,
+        slug: &Slug,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+    ) -> Result<Vec<RedirectBucket>, ShortenerError> {
+        let Some((link_id, _)) = self.read_model.find(&slug.0) else {
+            self.log(format!("Failed to retrieve redirects over time of slug {slug:?}: slug not found"));
+            return Err(ShortenerError::SlugNotFound);
+        };
+
+        let buckets = self.projections.get::<RedirectRollups>()
+            .map(|rollups| rollups.range(link_id, from, to))
+            .unwrap_or_default();
+        self.log(format!("Retrieved {} buckets of redirects of slug {slug:?}", buckets.len()));
+        Ok(buckets)
+    }
+
+    /// Returns the current state of the link the [`Slug`] maps to, including
+    /// its tags and metadata.
+    ///
+    /// ## Errors
+    ///
+    /// [`ShortenerError::SlugNotFound`] if the [`Slug`] doesn't map to any
+    /// short link.
+    pub fn get_link(&self, slug: &Slug) -> Result<LinkInfo, ShortenerError> {
+        let Some(info) = self.read_model.find(&slug.0).and_then(|(link_id, _)| self.read_model.info(link_id)) else {
+            self.log(format!("Failed to retrieve link {slug:?}: slug not found"));
+            return Err(ShortenerError::SlugNotFound);
+        };
+
+        self.log(format!("Retrieved link {info:?}"));
+        Ok(info)
+    }
+}
+
+impl UrlShortenerService {
+    /// Returns links of the tenant of the caller with the tag, ordered by
+    /// their creation time, except archived ones. Links are found by the
+    /// [`QueryBackend`] or, if it can't answer, by the [`TagIndex`]
+    /// projection.
+    pub fn list_links_by_tag(&self, tag: &Tag) -> Vec<LinkInfo> {
+        let link_ids = self.backend.links_by_tag(tag).unwrap_or_else(|| {
+            self.projections.get::<TagIndex>()
+                .into_iter()
+                .flat_map(|index| index.links(tag))
+                .cloned()
+                .collect()
+        });
+        let links: Vec<_> = link_ids.iter()
+            .filter(|link_id| self.read_model.tenant_link(link_id).is_some())
+            .filter_map(|link_id| self.read_model.info(link_id))
+            .filter(|info| !info.archived)
+            .collect();
+        self.log(format!("Listed {} links tagged {tag:?}", links.len()));
+        links
+    }
+
+    /// Returns up to `count` available slugs close to the given one, e.g. to
+    /// offer them when creation fails with
+    /// [`ShortenerError::SlugAlreadyInUse`]. Suggested slugs follow
+    /// [`ServiceConfig::slug_rules`] and are neither blocked nor taken, but
+    /// are not reserved, so they may be taken before they are used.
+    pub fn list_slug_suggestions(&self, slug: &Slug, count: usize) -> Vec<Slug> {
+        let suggestions: Vec<_> = slugs::variants(&slug.0, Utc::now().year())
+            .filter(|variant| self.config.slug_rules.check(variant).is_ok())
+            .filter(|variant| !self.is_slug_in_use(variant) && !self.is_blocked(variant))
+            .take(count)
+            .map(Slug)
+            .collect();
+        self.log(format!("Suggested slugs {suggestions:?} for slug {slug:?}"));
+        suggestions
+    }
+
+    /// Returns groups of links of the tenant of the caller, ordered by their
+    /// creation time.
+    pub fn list_groups(&self) -> Vec<Group> {
+        let mut groups: Vec<_> = self.read_model.groups
+            .iter()
+            .filter(|(_, group)| group.tenant == self.read_model.tenant)
+            .map(|(group_id, group)| Group {
+                group_id: group_id.clone(),
+                name: group.name.clone(),
+                owner: group.owner.clone(),
+                links: group.links.len(),
+            })
+            .collect();
+        groups.sort_by(|a, b| a.group_id.cmp(&b.group_id));
+        self.log(format!("Listed {} groups", groups.len()));
+        groups
+    }
+
+    /// Returns links in the group, ordered by their creation time, except
+    /// archived ones.
+    ///
+    /// ## Errors
+    ///
+    /// [`ShortenerError::GroupNotFound`] if there is no such group.
+    pub fn list_links_in_group(&self, group_id: &GroupId) -> Result<Vec<LinkInfo>, ShortenerError> {
+        let Some(group) = self.read_model.tenant_group(group_id) else {
+            self.log(format!("Failed to list links in group {group_id:?}: group not found"));
+            return Err(ShortenerError::GroupNotFound);
+        };
+
+        let links: Vec<_> = group.links.iter()
+            .filter_map(|link_id| self.read_model.info(link_id))
+            .filter(|info| !info.archived)
+            .collect();
+        self.log(format!("Listed {} links in group {group_id:?}", links.len()));
+        Ok(links)
+    }
+
+    /// Returns namespaces of the tenant of the caller, ordered by their names.
+    pub fn list_namespaces(&self) -> Vec<Namespace> {
+        let mut namespaces: Vec<_> = self.read_model.namespaces
+            .iter()
+            // keys of namespaces of other tenants carry their prefix
+            .filter(|(key, (name, _))| self.read_model.key(name) == **key)
+            .map(|(key, (name, owner))| Namespace {
+                name: name.clone(),
+                owner: owner.clone(),
+                links: self.links_in_namespace(key).count(),
+            })
+            .collect();
+        namespaces.sort_by(|a, b| a.name.cmp(&b.name));
+        self.log(format!("Listed {} namespaces", namespaces.len()));
+        namespaces
+    }
+
+    /// Returns links whose slugs are in the namespace, ordered by their
+    /// creation time, except archived ones.
+    ///
+    /// ## Errors
+    ///
+    /// [`ShortenerError::NamespaceNotFound`] if there is no such namespace.
+    pub fn list_links_in_namespace(&self, namespace: &str) -> Result<Vec<LinkInfo>, ShortenerError> {
+        let key = self.read_model.key(namespace);
+        if !self.read_model.namespaces.contains_key(&key) {
+            self.log(format!("Failed to list links in namespace {namespace:?}: namespace not found"));
+            return Err(ShortenerError::NamespaceNotFound);
+        }
+
+        let mut links: Vec<_> = self.links_in_namespace(&key)
+            .filter_map(|link_id| self.read_model.info(link_id))
+            .filter(|info| !info.archived)
+            .collect();
+        links.sort_by(|a, b| a.link_id.cmp(&b.link_id));
+        self.log(format!("Listed {} links in namespace {namespace:?}", links.len()));
+        Ok(links)
+    }
+
+    /// Returns ids of links whose current slugs are in the namespace with the
+    /// key.
+    fn links_in_namespace<'a>(&'a self, key: &'a str) -> impl Iterator<Item = &'a LinkId> {
+        self.read_model.tenant_links()
+            .filter(move |(_, state)| {
+                slugs::split_namespace(&state.link.slug.0)
+                    .is_some_and(|(namespace, _)| self.read_model.key(namespace) == key)
+            })
+            .map(|(link_id, _)| link_id)
+    }
+
+    /// Returns pending scheduled commands, ordered by their execution time.
+    pub fn list_scheduled_commands(&self) -> Vec<ScheduledCommand> {
+        let mut scheduled: Vec<_> = self.read_model.scheduled.values().cloned().collect();
+        scheduled.sort_by_key(|scheduled| (scheduled.due_at, scheduled.id));
+        scheduled
+    }
+
+    /// Returns all links, archived ones included, ordered by their creation
+    /// time.
+    pub fn list_links(&self) -> Vec<LinkInfo> {
+        let mut links: Vec<_> = self.read_model.tenant_links()
+            .filter_map(|(link_id, _)| self.read_model.info(link_id))
+            .collect();
+        links.sort_by(|a, b| a.link_id.cmp(&b.link_id));
+        self.log(format!("Listed {} links", links.len()));
+        links
+    }
+
+    /// Returns links whose slug, destination, title or description contains
+    /// the text, ignoring case, ordered by their creation time.
+    pub fn search_links(&self, text: &str) -> Vec<LinkInfo> {
+        let text = text.to_lowercase();
+        let matches = |value: &str| value.to_lowercase().contains(&text);
+        let mut links: Vec<_> = self.read_model.tenant_links()
+            .filter_map(|(link_id, _)| self.read_model.info(link_id))
+            .filter(|info| {
+                matches(&info.link.slug.0)
+                    || matches(&info.link.url.0)
+                    || info.metadata.title.as_deref().is_some_and(matches)
+                    || info.metadata.description.as_deref().is_some_and(matches)
+            })
+            .collect();
+        links.sort_by(|a, b| a.link_id.cmp(&b.link_id));
+        self.log(format!("Found {} links matching {text:?}", links.len()));
+        links
+    }
+
+    /// Returns archived links, ordered by their creation time.
+    pub fn list_archived_links(&self) -> Vec<LinkInfo> {
+        let mut links: Vec<_> = self.read_model.tenant_links()
+            .filter(|(_, state)| state.archived)
+            .filter_map(|(link_id, _)| self.read_model.info(link_id))
+            .collect();
+        links.sort_by(|a, b| a.link_id.cmp(&b.link_id));
+        self.log(format!("Listed {} archived links", links.len()));
+        links
+    }
+
+    /// Fetches previews of destinations of links created since the last run
+    /// with the fetcher set by [`Self::set_preview_fetcher`]. Returns slugs
+    /// of links whose previews were fetched, failed fetches are retried on
+    /// the next run. Nothing is fetched without a fetcher. It is meant to be
+    /// run periodically, away from serving redirects, as fetchers may block.
+    pub fn handle_fetch_previews(&mut self) -> Vec<Slug> {
+        let Some(fetcher) = &self.preview_fetcher else {
+            return Vec::new();
+        };
+
+        let mut missing: Vec<_> = self.read_model.links
+            .iter()
+            .filter(|(_, state)| state.preview.is_none())
+            .filter_map(|(link_id, state)| {
+                let url = baseUrl::parse(&state.link.url.0).ok()?;
+                matches!(url.scheme(), "http" | "https").then(|| (link_id.clone(), state.link.slug.clone(), url))
+            })
+            .collect();
+        missing.sort_by(|a, b| a.0.cmp(&b.0));
+        let fetched: Vec<_> = missing
+            .into_iter()
+            .map(|(link_id, slug, url)| (link_id, slug, fetcher.fetch(&url)))
+            .collect();
+
+        let mut slugs = Vec::new();
+        for (link_id, slug, result) in fetched {
+            match result {
+                Ok(preview) => {
+                    self.log(format!("Fetched preview of slug {slug:?}: {preview:?}"));
+                    slugs.push(slug.clone());
+                    self.record(Event::MetadataFetched { link_id, slug, preview });
+                },
+                Err(reason) => self.log(format!("Failed to fetch preview of slug {slug:?}: {reason}")),
+            }
+        }
+        slugs
+    }
+
+    /// Returns links whose destinations failed their last health check,
+    /// ordered by their creation time.
+    pub fn list_broken_links(&self) -> Vec<LinkInfo> {
+        let mut links: Vec<_> = self.read_model.tenant_links()
+            .filter(|(_, state)| state.health.is_broken())
+            .filter_map(|(link_id, _)| self.read_model.info(link_id))
+            .collect();
+        links.sort_by(|a, b| a.link_id.cmp(&b.link_id));
+        self.log(format!("Listed {} broken links", links.len()));
+        links
+    }
+
+    /// Returns links whose suspicious traffic was detected by
+    /// [`Self::handle_detect_anomalies`], ordered by their creation time.
+    pub fn list_flagged_links(&self) -> Vec<LinkInfo> {
+        let mut links: Vec<_> = self.read_model.tenant_links()
+            .filter(|(_, state)| state.anomaly.is_some())
+            .filter_map(|(link_id, _)| self.read_model.info(link_id))
+            .collect();
+        links.sort_by(|a, b| a.link_id.cmp(&b.link_id));
+        self.log(format!("Listed {} flagged links", links.len()));
+        links
+    }
+
+    /// Returns changes of links recorded after the cursor, so a client can
+    /// keep a local copy of links in sync by applying them. Changed links are
+    /// returned as complete upserts, while links which were only followed are
+    /// returned as compact redirect counter deltas. If events after the
+    /// cursor were already pruned, including redirect events pruned by
+    /// [`Self::handle_prune_clicks`], all links are returned as upserts. Only
+    /// links of the tenant of the caller are returned.
+    pub fn get_changes_since(&self, cursor: SyncCursor) -> Changes {
+        let from = self.events.partition_point(|record| record.sequence <= cursor.0);
+
+        let mut upserted = Vec::new();
+        if cursor.0 < self.pruned_through.max(self.clicks_pruned_through) {
+            upserted.extend(self.read_model.tenant_links().map(|(link_id, _)| link_id.clone()));
+            upserted.sort();
+        }
+        let mut deletions = Vec::new();
+        let mut redirect_deltas: HashMap<LinkId, u64> = HashMap::new();
+        for record in self.events[from..].iter().filter(|record| record.tenant == self.acting.tenant) {
+            let Some(link_id) = record.event.link_id(&self.read_model.slug_ids) else {
+                continue;
+            };
+
+            match record.event {
+                Event::Redirected { .. } => *redirect_deltas.entry(link_id.clone()).or_default() += 1,
+                Event::LinkDeleted { .. } => deletions.push(link_id.clone()),
+                _ if record.event.is_state_neutral() => {},
+                _ => if !upserted.contains(link_id) {
+                    upserted.push(link_id.clone());
+                },
+            }
+        }
+
+        // upserts already carry the current counters, deleted links have none
+        redirect_deltas.retain(|link_id, _| !upserted.contains(link_id) && !deletions.contains(link_id));
+
+        let changes = Changes {
+            cursor: SyncCursor(self.last_sequence),
+            upserts: upserted.iter().filter_map(|link_id| self.read_model.info(link_id)).collect(),
+            deletions,
+            redirect_deltas,
+        };
+        self.log(format!(
+            "Retrieved {} upserts, {} deletions and {} redirect deltas since {cursor:?}",
+            changes.upserts.len(),
+            changes.deletions.len(),
+            changes.redirect_deltas.len(),
+        ));
+        changes
+    }
+}
+
+impl UrlShortenerService {
+    /// Verifies the service state before it is put into rotation: the event
+    /// log is ordered, replaying it into fresh read model and built-in
+    /// projections gives the current state, slugs are unique and every event
+    /// refers to an existing link. Events after the [`Snapshot`] are replayed
+    /// into its copies of the read model and projections, so events pruned to
+    /// fit the [`EventWindow`] are still accounted for.
+    pub fn check_integrity(&self) -> IntegrityReport {
+        let mut report = IntegrityReport::default();
+
+        let mut problems = Vec::new();
+        let mut previous = self.pruned_through;
+        for record in &self.events {
+            if record.sequence <= previous || record.sequence > self.last_sequence {
+                problems.push(format!("event {} is out of order after {previous}", record.sequence));
+            }
+            previous = record.sequence;
+        }
+        report.checks.push(IntegrityCheck { name: "event log order", problems, skipped: false });
+
+        let mut problems = Vec::new();
+        let mut read_model = self.snapshot.read_model.clone();
+        let mut tag_index = self.snapshot.projections.get::<TagIndex>().cloned().unwrap_or_default();
+        read_model.tenant = self.read_model.tenant.clone();
+        for record in self.events.iter().filter(|record| record.sequence > self.snapshot.through) {
+            read_model.apply(record);
+            tag_index.apply(record);
+        }
+        if read_model != self.read_model {
+            problems.push(String::from("replayed read model differs from the current one"));
+        }
+        if self.projections.get::<TagIndex>() != Some(&tag_index) {
+            problems.push(String::from("replayed tag index differs from the current one"));
+        }
+        report.checks.push(IntegrityCheck { name: "replay", problems, skipped: false });
+
+        let mut problems = Vec::new();
+        for (link_id, state) in &self.read_model.links {
+            let key = self.read_model.key_in(state.tenant.as_ref(), &state.link.slug.0);
+            if self.read_model.slugs.get(&key) != Some(link_id) {
+                problems.push(format!("slug {:?} of link {link_id:?} maps to another link", state.link.slug));
+            }
+        }
+        for (slug, link_id) in &self.read_model.slugs {
+            if !self.read_model.links.contains_key(link_id) {
+                problems.push(format!("slug {slug:?} maps to unknown link {link_id:?}"));
+            }
+        }
+        report.checks.push(IntegrityCheck { name: "unique slugs", problems, skipped: false });
+
+        let problems = self.events.iter()
+            .filter_map(|record| match record.event.link_id(&self.read_model.slug_ids) {
+                Some(link_id)
+                    if self.read_model.links.contains_key(link_id)
+                        || self.read_model.merged_links.contains_key(link_id)
+                        || self.read_model.deleted_links.contains(link_id) => None,
+                Some(link_id) => Some(format!("event {} refers to unknown link {link_id:?}", record.sequence)),
+                None => record.event.slug_id().map(|slug_id| {
+                    format!("event {} refers to unknown slug id {slug_id:?}", record.sequence)
+                }),
+            })
+            .collect();
+        report.checks.push(IntegrityCheck { name: "no orphan events", problems, skipped: false });
+
+        self.log(format!("Integrity check {}", if report.passed() { "passed" } else { "failed" }));
+        report
+    }
+}
+
+impl queries::QueryHandler for UrlShortenerService {
+    fn get_stats(&self, slug: Slug) -> Result<Stats, ShortenerError> {
+        // Check registered links to figure out if slug exists or not
+        if let Some((_, state)) = self.read_model.find(&slug.0) {
+            // Ok, we found registered slug, now we have to take redirects counted by read model
+            let stats = state.stats();
+            self.log(format!("Retrieved stats {stats:?}"));
+            
+            return Ok(stats);
+        }
+
+        self.log(format!("Failed to retrieve stat of slug {slug:?}: slug not found"));
+        Err(ShortenerError::SlugNotFound)
+    }
+}
+
+impl UrlShortenerService {
+    /// Replaces the policy consulted by [`Self::dispatch_command`] and
+    /// [`Self::dispatch_query`]. Everything is allowed by default.
+    pub fn set_authorization_policy<P: AuthorizationPolicy + 'static>(&mut self, policy: P) {
+        self.policy = Box::new(policy);
+    }
+
+    /// Handles the command on behalf of the caller, if the role of the caller
+    /// and the authorization policy allow it. Links created by the command
+    /// are owned by the caller, and commands modifying owned links are
+    /// allowed only to their owners and admins. Editors may modify only the
+    /// links they own.
+    ///
+    /// ## Errors
+    ///
+    /// - [`ShortenerError::AccessDenied`] if the role is below
+    ///   [`Command::role`], the policy denies the command or the caller
+    ///   doesn't own the modified link.
+    /// - Errors of the command handler.
+    pub fn dispatch_command(&mut self, context: &RequestContext, command: Command) -> Result<Reply, ShortenerError> {
+        let started_at = Instant::now();
+        let name = command.name();
+        let reply = self.act_as(context, |service| {
+            service.check_role(context, Operation::Command(&command), command.role())?;
+            service.authorize(context, Operation::Command(&command), command.target())?;
+            service.check_ownership(context, &command)?;
+            service.handle_command(command)
+        });
+        let latency = started_at.elapsed().as_secs_f64();
+        self.metrics.observe_histogram("shortener_command_duration_seconds", &[("command", name)], latency);
+        self.count_error(reply.as_ref().err());
+        reply
+    }
+
+    fn count_error(&self, error: Option<&ShortenerError>) {
+        if let Some(error) = error {
+            self.metrics.increment_counter("shortener_errors_total", &[("code", error.code())], 1);
+        }
+    }
+
+    /// Runs the job on behalf of the caller without authorizing it: links it
+    /// creates are owned by the caller, and slugs, listings and exports are
+    /// limited to links of the caller's tenant.
+    pub fn act_as<T>(&mut self, context: &RequestContext, job: impl FnOnce(&mut Self) -> T) -> T {
+        let previous = std::mem::replace(&mut self.acting, context.clone());
+        self.read_model.tenant = context.tenant.clone();
+        let result = job(self);
+        self.read_model.tenant = previous.tenant.clone();
+        self.acting = previous;
+        result
+    }
+
+    fn handle_command(&mut self, command: Command) -> Result<Reply, ShortenerError> {
+        match command {
+            Command::CreateShortLink { url, slug, options } => {
+                self.handle_create_short_link_with(url, slug, options).map(Reply::Link)
+            },
+            Command::CreateShortLinks { links } => Ok(Reply::Links(self.handle_create_short_links(links))),
+            Command::CreateShortLinksAtomically { links } => {
+                Ok(Reply::Links(self.handle_create_short_links_atomically(links)))
+            },
+            Command::ImportLinks { links } => Ok(Reply::LinkImportReport(self.handle_import_links(links))),
+            Command::Redirect { slug, visitor: None } => self.handle_redirect(slug).map(Reply::Link),
+            Command::Redirect { slug, visitor: Some(visitor) } => self.handle_redirect_from(slug, visitor).map(Reply::Link),
+            Command::RedirectWithPassword { slug, password, visitor } => {
+                self.handle_redirect_with_password(slug, &password, visitor).map(Reply::Link)
+            },
+            Command::RedirectWithContext { slug, context } => {
+                self.handle_redirect_with_context(slug, context).map(Reply::Link)
+            },
+            Command::RedirectWithOutcome { slug, password, visitor } => {
+                Ok(Reply::RedirectOutcome(self.handle_redirect_with_outcome(slug, visitor, password.as_deref())))
+            },
+            Command::ReserveSlug { slug, timeout } => self.handle_reserve_slug(slug, timeout).map(|_| Reply::Done),
+            Command::AttachUrl { slug, url } => self.handle_attach_url(slug, url).map(Reply::Link),
+            Command::PrepareLink { url, slug, options, timeout } => {
+                self.handle_prepare_link(url, slug, options, timeout).map(Reply::Draft)
+            },
+            Command::ActivateLink { slug } => self.handle_activate_link(slug).map(Reply::Link),
+            Command::ExpireReservations => {
+                self.handle_expire_reservations();
+                Ok(Reply::Done)
+            },
+            Command::RenameSlug { old, new, old_slug } => self.handle_rename_slug(old, new, old_slug).map(Reply::Link),
+            Command::ExpireAliases => {
+                self.handle_expire_aliases();
+                Ok(Reply::Done)
+            },
+            Command::ImportRedirects { redirects, rules } => {
+                Ok(Reply::ImportReport(self.handle_import_redirects(redirects, &rules)))
+            },
+            Command::ResetStats { slug } => self.handle_reset_stats(slug).map(|_| Reply::Done),
+            Command::SetPassword { slug, password } => self.handle_set_password(slug, &password).map(|_| Reply::Done),
+            Command::RemovePassword { slug } => self.handle_remove_password(slug).map(|_| Reply::Done),
+            Command::TagLink { slug, tags } => self.handle_tag_link(slug, tags).map(|_| Reply::Done),
+            Command::UntagLink { slug, tags } => self.handle_untag_link(slug, tags).map(|_| Reply::Done),
+            Command::UpdateMetadata { slug, metadata } => {
+                self.handle_update_metadata(slug, metadata).map(|_| Reply::Done)
+            },
+            Command::AddAlias { slug, alias } => self.handle_add_alias(slug, alias).map(Reply::Link),
+            Command::CloneLink { slug, new_slug } => self.handle_clone_link(slug, new_slug).map(Reply::Link),
+            Command::MergeLinks { slug, duplicate } => self.handle_merge_links(slug, duplicate).map(Reply::Link),
+            Command::SetWebhook { slug, webhook } => self.handle_set_webhook(slug, webhook).map(|_| Reply::Done),
+            Command::RemoveWebhook { slug } => self.handle_remove_webhook(slug).map(|_| Reply::Done),
+            Command::SetRedirectType { slug, redirect_type } => {
+                self.handle_set_redirect_type(slug, redirect_type).map(|_| Reply::Done)
+            },
+            Command::SetInterstitial { slug, enabled } => {
+                self.handle_set_interstitial(slug, enabled).map(|_| Reply::Done)
+            },
+            Command::SetQueryParams { slug, params } => self.handle_set_query_params(slug, params).map(|_| Reply::Done),
+            Command::CreateGroup { name } => Ok(Reply::GroupId(self.handle_create_group(name))),
+            Command::MoveToGroup { slug, group } => self.handle_move_to_group(slug, group).map(|_| Reply::Done),
+            Command::RecordConversion { slug, conversion_id } => {
+                self.handle_conversion(slug, conversion_id).map(|_| Reply::Done)
+            },
+            Command::AssignCampaign { slug, campaign } => {
+                self.handle_assign_campaign(slug, campaign).map(|_| Reply::Done)
+            },
+            Command::TransferOwnership { slug, new_owner } => {
+                self.handle_transfer_ownership(slug, new_owner).map(|_| Reply::Done)
+            },
+            Command::DisableLink { slug } => self.handle_disable_link(slug).map(|_| Reply::Done),
+            Command::DeleteLink { slug } => self.handle_delete_link(slug).map(|_| Reply::Done),
+            Command::EnableLink { slug } => self.handle_enable_link(slug).map(|_| Reply::Done),
+            Command::ArchiveInactiveLinks => {
+                self.handle_archive_inactive_links();
+                Ok(Reply::Done)
+            },
+            Command::UnarchiveLink { slug } => self.handle_unarchive_link(slug).map(|_| Reply::Done),
+            Command::ScheduleCommand { command, due_at } => {
+                Ok(Reply::ScheduleId(self.handle_schedule_command(self.acting.clone(), *command, due_at)))
+            },
+            Command::CancelScheduledCommand { id } => self.handle_cancel_scheduled_command(id).map(|_| Reply::Done),
+            Command::RunDueCommands => {
+                self.handle_run_due_commands();
+                Ok(Reply::Done)
+            },
+            Command::CreateNamespace { name } => self.handle_create_namespace(name).map(|_| Reply::Done),
+            Command::BlockHost { host } => Ok(Reply::Slugs(self.handle_block_host(host))),
+            Command::RecheckDestinations => Ok(Reply::Slugs(self.handle_recheck_destinations())),
+            Command::CheckDestinationHealth => Ok(Reply::Slugs(self.handle_check_destination_health())),
+            Command::FetchPreviews => Ok(Reply::Slugs(self.handle_fetch_previews())),
+            Command::SetClickRetention { slug, retention } => {
+                self.handle_set_click_retention(slug, retention).map(|_| Reply::Done)
+            },
+            Command::PruneClicks => Ok(Reply::Slugs(self.handle_prune_clicks())),
+            Command::SendDigest { period, repeat } => self.handle_send_digest(period, repeat).map(Reply::Digest),
+            Command::DetectAnomalies => Ok(Reply::Slugs(self.handle_detect_anomalies())),
+            Command::CreateApiKey { actor, role } => Ok(Reply::ApiKey(self.handle_create_api_key(actor, role))),
+            Command::RevokeApiKey { key_id } => self.handle_revoke_api_key(&key_id).map(|()| Reply::Done),
+            Command::RegisterWebhook { webhook } => self.handle_register_webhook(webhook).map(Reply::WebhookId),
+            Command::UnregisterWebhook { webhook_id } => {
+                self.handle_unregister_webhook(webhook_id).map(|()| Reply::Done)
+            },
+            Command::DeliverWebhooks => Ok(Reply::WebhookDeliveries(self.handle_deliver_webhooks())),
+            Command::RebuildProjection { name } => self.rebuild_projection(&name).map(|()| Reply::Done),
+            Command::CompactEvents => Ok(Reply::CompactedEvents(self.handle_compact_events())),
+        }
+    }
+
+    /// Answers the query on behalf of the caller, if the role of the caller
+    /// and the authorization policy allow it. Only links of the caller's
+    /// tenant are seen.
+    ///
+    /// ## Errors
+    ///
+    /// - [`ShortenerError::AccessDenied`] if the role is below
+    ///   [`Query::role`] or the policy denies the query.
+    /// - Errors of the query handler.
+    pub fn dispatch_query(&mut self, context: &RequestContext, query: Query) -> Result<Reply, ShortenerError> {
+        let reply = self.act_as(context, |service| {
+            service.check_role(context, Operation::Query(&query), query.role())?;
+            service.authorize(context, Operation::Query(&query), query.target())?;
+            service.handle_query(query)
+        });
+        self.count_error(reply.as_ref().err());
+        reply
+    }
+
+    fn handle_query(&self, query: Query) -> Result<Reply, ShortenerError> {
+        match query {
+            Query::GetStats { slug } => self.get_stats(slug).map(Reply::Stats),
+            Query::GetLinkId { slug } => self.get_link_id(&slug).map(Reply::LinkId),
+            Query::GetLink { slug } => self.get_link(&slug).map(|info| Reply::LinkInfo(Box::new(info))),
+            Query::GetStatsBreakdown { slug } => self.get_stats_breakdown(&slug).map(Reply::StatsBreakdown),
+            Query::GetClicksByCountry { slug } => self.get_clicks_by_country(&slug).map(Reply::CountryClicks),
+            Query::GetDeviceStats { slug } => self.get_device_stats(&slug).map(Reply::DeviceStats),
+            Query::GetRedirectsOverTime { slug, from, to } => {
+                self.get_redirects_over_time(&slug, from, to).map(Reply::RedirectBuckets)
+            },
+            Query::GetHistory { slug } => self.get_history(&slug).map(Reply::Events),
+            Query::GetEventsFor { slug, from_version } => {
+                self.get_events_for(&slug, from_version).map(Reply::VersionedEvents)
+            },
+            Query::ListEvents { after, limit } => Ok(Reply::Events(self.list_events(after, limit))),
+            Query::GetChangesSince { cursor } => Ok(Reply::Changes(self.get_changes_since(cursor))),
+            Query::ListLinksByTag { tag } => Ok(Reply::LinkInfos(self.list_links_by_tag(&tag))),
+            Query::ListLinks => Ok(Reply::LinkInfos(self.list_links())),
+            Query::SearchLinks { text } => Ok(Reply::LinkInfos(self.search_links(&text))),
+            Query::ListArchivedLinks => Ok(Reply::LinkInfos(self.list_archived_links())),
+            Query::ListGroups => Ok(Reply::Groups(self.list_groups())),
+            Query::ListLinksInGroup { group } => self.list_links_in_group(&group).map(Reply::LinkInfos),
+            Query::ListScheduledCommands => Ok(Reply::ScheduledCommands(self.list_scheduled_commands())),
+            Query::ListSlugSuggestions { slug, count } => Ok(Reply::Slugs(self.list_slug_suggestions(&slug, count))),
+            Query::ListNamespaces => Ok(Reply::Namespaces(self.list_namespaces())),
+            Query::GetSystemStats => Ok(Reply::SystemStats(self.get_system_stats())),
+            Query::ListBrokenLinks => Ok(Reply::LinkInfos(self.list_broken_links())),
+            Query::ListFlaggedLinks => Ok(Reply::LinkInfos(self.list_flagged_links())),
+            Query::GetCampaignStats { campaign } => self.get_campaign_stats(&campaign).map(Reply::CampaignStats),
+            Query::GetWebhookDeliveries { webhook_id } => {
+                self.get_webhook_deliveries(webhook_id).map(Reply::WebhookDeliveries)
+            },
+            Query::ListLinksInNamespace { namespace } => {
+                self.list_links_in_namespace(&namespace).map(Reply::LinkInfos)
+            },
+        }
     }
 
-    fn handle_redirect(
-        &mut self,
-        slug: Slug,
-    ) -> Result<ShortLink, ShortenerError> {
-        // Check if slug exists via iterating over url_events
-        if let Some(url_event) = self.url_events.iter().find(|url_event| url_event.slug == slug) {
-            // Ok, we found it, create redirect event
-            if let Some(stat) = self.redirect_events_by_slug.get_mut(&slug.0) {
-                stat.push(slug.clone());
-            } else {
-                self.redirect_events_by_slug.insert(slug.0.clone(), vec![slug.clone()]);
-            }
+    fn check_ownership(&self, context: &RequestContext, command: &Command) -> Result<(), ShortenerError> {
+        if !command.modifies_link() {
+            return Ok(());
+        }
 
-            self.log(format!("Handled redirect of slug {slug:?}"));
+        if context.role == Some(Role::Admin) {
+            return Ok(());
+        }
 
-            return Ok(ShortLink {
-                url: url_event.url.clone(),
-                slug,
-            });
+        let owner = command.target().and_then(|slug| match self.read_model.find(&slug.0) {
+            Some((_, state)) => state.owner.as_ref(),
+            None => self.read_model.reservation_owner(&slug.0),
+        });
+        match (owner, &context.actor) {
+            (None, _) if context.role != Some(Role::Editor) => Ok(()),
+            (Some(owner), Some(actor)) if *owner == OwnerId::from(actor) => Ok(()),
+            (None, actor) => {
+                self.log(format!("Denied {command:?} requested by editor {actor:?}: link isn't owned by anyone"));
+                Err(ShortenerError::AccessDenied)
+            },
+            (Some(owner), actor) => {
+                self.log(format!("Denied {command:?} requested by {actor:?}: link is owned by {owner:?}"));
+                Err(ShortenerError::AccessDenied)
+            },
         }
+    }
 
-        self.log(format!("Failed to handle redirect of slug {slug:?}: slug not found"));
-        Err(ShortenerError::SlugNotFound)
+    fn check_role(&self, context: &RequestContext, operation: Operation<'_>, role: Role) -> Result<(), ShortenerError> {
+        match context.role {
+            Some(granted) if granted < role => {
+                self.log(format!("Denied {operation:?} requested by {context:?}: role {role:?} is required"));
+                Err(ShortenerError::AccessDenied)
+            },
+            _ => Ok(()),
+        }
+    }
+
+    fn authorize(&self, context: &RequestContext, operation: Operation<'_>, target: Option<&Slug>) -> Result<(), ShortenerError> {
+        if !self.policy.authorize(&AccessRequest { context, operation, target }) {
+            self.log(format!("Denied {operation:?} requested by {context:?}"));
+            return Err(ShortenerError::AccessDenied);
+        }
+
+        Ok(())
     }
 }
 
-impl queries::QueryHandler for UrlShortenerService {
-    fn get_stats(&self, slug: Slug) -> Result<Stats, ShortenerError> {
-        // Check all link creation events to figure out if slug exists or not
-        if let Some(url_event) = self.url_events.iter().find(|url_event| url_event.slug == slug) {
-            // Ok, we found registered slug, now we have to count all redirects for this slug
-            let redirects = self.redirect_events_by_slug.get(&slug.0)
-                .map_or(0, |stat| stat.iter().filter(|&x| x.0 == slug.0).count()) as u64;
+/// Drives the future to completion on the current thread, parking it while
+/// the future is pending.
+fn block_on<F: std::future::Future>(future: F) -> F::Output {
+    struct ThreadWaker(std::thread::Thread);
 
-            let stats = Stats{link: url_event.clone(), redirects};
-            self.log(format!("Retrieved stats {stats:?}"));
-            
-            return Ok(stats);
+    impl std::task::Wake for ThreadWaker {
+        fn wake(self: std::sync::Arc<Self>) {
+            self.0.unpark();
         }
+    }
 
-        self.log(format!("Failed to retrieve stat of slug {slug:?}: slug not found"));
-        Err(ShortenerError::SlugNotFound)
+    let waker = std::sync::Arc::new(ThreadWaker(std::thread::current())).into();
+    let mut context = std::task::Context::from_waker(&waker);
+    let mut future = std::pin::pin!(future);
+    loop {
+        if let std::task::Poll::Ready(output) = future.as_mut().poll(&mut context) {
+            return output;
+        }
+        std::thread::park();
     }
 }
 
+/// Creates the service behind the servers, with an API key of the admin
+/// printed to be used by clients.
+#[cfg(feature = "http")]
+fn served_service() -> UrlShortenerService {
+    let mut service = UrlShortenerService::new();
+    let api_key = service.handle_create_api_key(ActorId(String::from("admin")), Role::Admin);
+    println!("API key of the admin: {}", api_key.secret);
+    service
+}
+
+#[allow(clippy::unnecessary_literal_unwrap)]
 fn main() {
+    // Serve the HTTP API instead of running the demo, e.g. `test_task serve 127.0.0.1:8080`, or over TLS with the
+    // `tls` feature, e.g. `test_task serve 127.0.0.1:8443 cert.pem key.pem`
+    #[cfg(feature = "http")]
+    if std::env::args().nth(1).as_deref() == Some("serve") {
+        let addr = std::env::args().nth(2).unwrap_or_else(|| String::from("127.0.0.1:8080"));
+        let addr = addr.parse().expect("Failed to parse the address to serve");
+        let metrics = metrics::PrometheusRecorder::default();
+        let recorder = metrics.clone();
+        let service = http::ServiceHandle::spawn(move || {
+            let mut service = served_service();
+            service.set_metrics_recorder(recorder);
+            service
+        });
+        #[cfg_attr(not(feature = "tls"), allow(unused_mut))]
+        let mut config = http::HttpConfig { metrics: Some(metrics), ..Default::default() };
+        #[cfg(feature = "tls")]
+        if let (Some(cert_path), Some(key_path)) = (std::env::args().nth(3), std::env::args().nth(4)) {
+            config.tls = Some(http::TlsConfig { cert_path: cert_path.into(), key_path: key_path.into() });
+        }
+        http::run(addr, service, config).expect("Failed to serve the HTTP API");
+        return;
+    }
+
+    // Serve the gRPC API instead of running the demo, e.g. `test_task serve-grpc 127.0.0.1:50051`
+    #[cfg(feature = "grpc")]
+    if std::env::args().nth(1).as_deref() == Some("serve-grpc") {
+        let addr = std::env::args().nth(2).unwrap_or_else(|| String::from("127.0.0.1:50051"));
+        let addr = addr.parse().expect("Failed to parse the address to serve");
+        grpc::run(addr, http::ServiceHandle::spawn(served_service)).expect("Failed to serve the gRPC API");
+        return;
+    }
+
     // Create service instance
     let mut service: UrlShortenerService = UrlShortenerService::new();
     let test_url = Url(String::from("http://relap.io/amazing-receipts-worldwide"));
@@ -324,4 +15508,1562 @@ fn main() {
         Ok(_) => panic!("We shoudn't receive stats for slug that doesn't exist!"),
         Err(error) => assert_eq!(error, ShortenerError::SlugNotFound),
     }
-}
\ No newline at end of file
+
+    // Custom projection which counts created links, defined outside of the service
+    #[derive(Default)]
+    struct CreatedLinksCounter(u64);
+
+    impl Projection for CreatedLinksCounter {
+        fn name(&self) -> &str {
+            "created_links_counter"
+        }
+
+        fn apply(&mut self, record: &EventRecord) {
+            if let Event::LinkCreated { .. } = record.event {
+                self.0 += 1;
+            }
+        }
+
+        fn reset(&mut self) {
+            self.0 = 0;
+        }
+    }
+
+    // Test projection registration - OK, projection is caught up with already recorded events
+    service.register_projection(CreatedLinksCounter::default()).expect("Failed to register projection");
+    assert_eq!(service.projection::<CreatedLinksCounter>().map(|p| p.0), Some(2));
+    assert_eq!(service.projection_checkpoint("created_links_counter"), Some(service.events().len() as u64));
+
+    // Test projection registration with the same name - FAIL
+    match service.register_projection(CreatedLinksCounter::default()) {
+        Ok(_) => panic!("We shouldn't register two projections with the same name!"),
+        Err(error) => assert_eq!(error, ShortenerError::ProjectionAlreadyRegistered),
+    }
+
+    // Test projection receives new events and can be rebuilt - OK
+    if let Err(error) = service.handle_create_short_link(Url(String::from("http://relap.io/amazing-receipts-worldwide3")), None) {
+        panic!("Failed to create short link: {:?}", error);
+    }
+    service.rebuild_projection("created_links_counter").expect("Failed to rebuild projection");
+    assert_eq!(service.projection::<CreatedLinksCounter>().map(|p| p.0), Some(3));
+
+    // Subscribe to lifecycle events of links - redirects must not be delivered
+    let lifecycle = service.subscribe(
+        EventFilter::all().with_kinds([EventKind::LinkDisabled, EventKind::LinkEnabled]),
+    );
+
+    // Test redirect of disabled link - FAIL, but stats are retained
+    service.handle_disable_link(short_link.slug.clone()).expect("Failed to disable link");
+    match service.handle_redirect(short_link.slug.clone()) {
+        Ok(_) => panic!("We shouldn't process redirect of disabled slug {:?}", short_link.slug),
+        Err(error) => assert_eq!(error, ShortenerError::LinkDisabled),
+    }
+    match service.get_stats(short_link.slug.clone()) {
+        Ok(stats) => assert_eq!(stats.redirects, short_link_redirects_count),
+        Err(error) => panic!("Failed to receive stats of disabled short link {:?}: {:?}", short_link, error),
+    }
+
+    // Test redirect of enabled again link - OK
+    service.handle_enable_link(short_link.slug.clone()).expect("Failed to enable link");
+    if let Err(error) = service.handle_redirect(short_link.slug.clone()) {
+        panic!("Failed to process redirect of enabled short link {:?}: {:?}", short_link.slug, error);
+    }
+
+    // Test routing by tags and tenants - events match if their link has any of the tags and they belong to the tenant
+    let filter = EventFilter::all()
+        .with_tag(Tag(String::from("promo")))
+        .with_tenant(TenantId(String::from("acme")));
+    let mut record = service.events().last().cloned().expect("No events were recorded");
+    let promo = BTreeSet::from([Tag(String::from("promo")), Tag(String::from("summer"))]);
+    assert!(!filter.matches(&record, None, None, &promo));
+    record.tenant = Some(TenantId(String::from("acme")));
+    assert!(filter.matches(&record, None, None, &promo));
+    assert!(!filter.matches(&record, None, None, &BTreeSet::new()));
+
+    // Test subscriber received only lifecycle events - OK
+    let kinds: Vec<_> = lifecycle.events.try_iter().map(|record| record.event.kind()).collect();
+    assert_eq!(kinds, vec![EventKind::LinkDisabled, EventKind::LinkEnabled]);
+
+    // Test bulk link creation - entries conflicting with each other or invalid ones FAIL, others are created
+    let outcomes = service.handle_create_short_links(vec![
+        (Url(String::from("http://relap.io/bulk-1")), Some(Slug(String::from("bulk")))),
+        (Url(String::from("http://relap.io/bulk-2")), Some(Slug(String::from("bulk")))),
+        (Url(String::from("not a url")), None),
+        (Url(String::from("http://relap.io/bulk-3")), None),
+    ]);
+    assert!(outcomes[0].is_ok() && outcomes[3].is_ok());
+    assert_eq!(outcomes[1], Err(ShortenerError::SlugAlreadyInUse));
+    assert_eq!(outcomes[2], Err(ShortenerError::InvalidUrl));
+
+    // Test atomic bulk link creation - one invalid entry aborts the whole batch, valid batches are created whole
+    let events = service.events().len();
+    let outcomes = service.handle_create_short_links_atomically(vec![
+        (Url(String::from("http://relap.io/atomic-1")), None),
+        (Url(String::from("not a url")), None),
+    ]);
+    assert_eq!(outcomes, [Err(ShortenerError::BatchAborted), Err(ShortenerError::InvalidUrl)]);
+    assert_eq!(service.events().len(), events);
+    let outcomes = service.handle_create_short_links_atomically(vec![
+        (Url(String::from("http://relap.io/atomic-1")), None),
+        (Url(String::from("http://relap.io/atomic-2")), None),
+    ]);
+    assert!(outcomes.iter().all(Result::is_ok));
+
+    // Test repeated redirects of the same visitor within dedup window - served, but counted once
+    let mut service = UrlShortenerService::with_config(ServiceConfig {
+        redirect_dedup_window: Some(TimeDelta::seconds(5)),
+        ..Default::default()
+    });
+    let short_link = service.handle_create_short_link(test_url.clone(), None).expect("Failed to create short link");
+    for visitor in ["alice", "alice", "bob"] {
+        if let Err(error) = service.handle_redirect_from(short_link.slug.clone(), VisitorId(String::from(visitor))) {
+            panic!("Failed to process redirect of short link {:?}: {:?}!", short_link.slug, error);
+        }
+    }
+    match service.get_stats(short_link.slug.clone()) {
+        Ok(stats) => assert_eq!(stats.redirects, 2),
+        Err(error) => panic!("Something went wrong while receiving stats for short link {:?}: {:?}", short_link, error),
+    }
+
+    // Test link identity is recorded in its events - OK
+    let link_id = service.get_link_id(&short_link.slug).expect("Failed to get link id");
+    assert!(service.events().iter().any(|record| record.event.link_id(service.slug_ids()) == Some(&link_id)));
+
+    // Test renamed link is available by both slugs and keeps its stats - OK
+    let renamed = service.handle_rename_slug(short_link.slug.clone(), Slug(String::from("renamed")), OldSlugPolicy::Forward)
+        .expect("Failed to rename slug");
+    match service.handle_redirect(short_link.slug.clone()) {
+        Ok(link) => assert_eq!(link, renamed),
+        Err(error) => panic!("Failed to process redirect of old slug {:?}: {:?}!", short_link.slug, error),
+    }
+    match service.get_stats(renamed.slug.clone()) {
+        Ok(stats) => assert_eq!(stats.redirects, 3),
+        Err(error) => panic!("Something went wrong while receiving stats for short link {:?}: {:?}", renamed, error),
+    }
+
+    // Test renaming with grace period - deprecated slug forwards until the grace period ends
+    let deprecated = renamed.slug.clone();
+    let renamed = service.handle_rename_slug(deprecated.clone(), Slug(String::from("renamed-2")), OldSlugPolicy::ForwardFor(TimeDelta::zero()))
+        .expect("Failed to rename slug");
+    match service.handle_redirect(deprecated.clone()) {
+        Ok(_) => panic!("Deprecated slug {:?} shouldn't forward after grace period", deprecated),
+        Err(error) => assert_eq!(error, ShortenerError::SlugNotFound),
+    }
+    service.handle_expire_aliases();
+    assert!(service.events().last().is_some_and(|record| record.event.kind() == EventKind::SlugAliasExpired));
+
+    // Test delta sync - renamed link is upserted, followed link is returned as counter delta
+    let other = service.handle_create_short_link(Url(String::from("http://relap.io/other")), None)
+        .expect("Failed to create short link");
+    let cursor = service.get_changes_since(SyncCursor::default()).cursor;
+    let renamed = service.handle_rename_slug(renamed.slug.clone(), Slug(String::from("renamed-3")), OldSlugPolicy::Forward)
+        .expect("Failed to rename slug");
+    service.handle_redirect(other.slug.clone()).expect("Failed to process redirect");
+    let changes = service.get_changes_since(cursor);
+    assert_eq!(changes.upserts.iter().map(|info| &info.link).collect::<Vec<_>>(), vec![&renamed]);
+    assert_eq!(changes.redirect_deltas.values().sum::<u64>(), 1);
+    assert!(service.get_changes_since(changes.cursor).upserts.is_empty());
+
+    // Test stats reset - only redirects after reset are counted
+    service.handle_reset_stats(renamed.slug.clone()).expect("Failed to reset stats");
+    if let Err(error) = service.handle_redirect(renamed.slug.clone()) {
+        panic!("Failed to process redirect of short link {:?}: {:?}!", renamed.slug, error);
+    }
+    match service.get_stats(renamed.slug.clone()) {
+        Ok(stats) => assert_eq!(stats.redirects, 1),
+        Err(error) => panic!("Something went wrong while receiving stats for short link {:?}: {:?}", renamed, error),
+    }
+
+    // Test password-protected link is followed only with the right password, failed attempts are recorded
+    service.handle_set_password(renamed.slug.clone(), "secret").expect("Failed to set password");
+    match service.handle_redirect(renamed.slug.clone()) {
+        Ok(_) => panic!("We shouldn't process redirect of protected slug {:?} without password", renamed.slug),
+        Err(error) => assert_eq!(error, ShortenerError::PasswordRequired),
+    }
+    match service.handle_redirect_with_password(renamed.slug.clone(), "wrong", None) {
+        Ok(_) => panic!("We shouldn't process redirect of protected slug {:?} with wrong password", renamed.slug),
+        Err(error) => assert_eq!(error, ShortenerError::InvalidPassword),
+    }
+    assert!(service.events().last().is_some_and(|record| record.event.kind() == EventKind::PasswordAttemptFailed));
+    if let Err(error) = service.handle_redirect_with_password(renamed.slug.clone(), "secret", None) {
+        panic!("Failed to process redirect of protected slug {:?}: {:?}", renamed.slug, error);
+    }
+    service.handle_remove_password(renamed.slug.clone()).expect("Failed to remove password");
+
+    // Test reserved slug can't be used by other links, but can be attached to url later - OK
+    let reserved_slug = Slug(String::from("coming-soon"));
+    service.handle_reserve_slug(reserved_slug.clone(), Some(TimeDelta::hours(1))).expect("Failed to reserve slug");
+    match service.handle_create_short_link(Url(String::from("http://relap.io/other")), Some(reserved_slug.clone())) {
+        Ok(_) => panic!("Something went wrong, slug {:?} is reserved!", reserved_slug),
+        Err(error) => assert_eq!(error, ShortenerError::SlugAlreadyInUse),
+    }
+    if let Err(error) = service.handle_attach_url(reserved_slug.clone(), Url(String::from("http://relap.io/launch"))) {
+        panic!("Failed to attach url to reserved slug {:?}: {:?}", reserved_slug, error);
+    }
+    match service.handle_attach_url(reserved_slug.clone(), Url(String::from("http://relap.io/launch-2"))) {
+        Ok(_) => panic!("Something went wrong, reservation of slug {:?} is already completed!", reserved_slug),
+        Err(error) => assert_eq!(error, ShortenerError::SlugNotReserved),
+    }
+
+    // Policy which allows anonymous callers only to follow links
+    struct AnonymousRedirectsOnly;
+
+    impl AuthorizationPolicy for AnonymousRedirectsOnly {
+        fn authorize(&self, request: &AccessRequest<'_>) -> bool {
+            request.context.actor.is_some() || matches!(request.operation, Operation::Command(Command::Redirect { .. }))
+        }
+    }
+
+    // Test dispatching with authorization policy - anonymous caller can follow link, but can't disable it
+    service.set_authorization_policy(AnonymousRedirectsOnly);
+    let anonymous = RequestContext::default();
+    let admin = RequestContext { actor: Some(ActorId(String::from("admin"))), ..Default::default() };
+    match service.dispatch_command(&anonymous, Command::Redirect { slug: reserved_slug.clone(), visitor: None }) {
+        Ok(reply) => assert!(matches!(reply, Reply::Link(_))),
+        Err(error) => panic!("Failed to dispatch redirect of slug {:?}: {:?}", reserved_slug, error),
+    }
+    match service.dispatch_command(&anonymous, Command::DisableLink { slug: reserved_slug.clone() }) {
+        Ok(_) => panic!("Anonymous caller shouldn't be able to disable slug {:?}", reserved_slug),
+        Err(error) => assert_eq!(error, ShortenerError::AccessDenied),
+    }
+    match service.dispatch_query(&admin, Query::GetStats { slug: reserved_slug.clone() }) {
+        Ok(Reply::Stats(stats)) => assert_eq!(stats.redirects, 1),
+        other => panic!("Failed to dispatch stats query of slug {:?}: {:?}", reserved_slug, other),
+    }
+
+    // Test link created by caller is owned by them - only owner can modify it until ownership is transferred
+    let editor = RequestContext { actor: Some(ActorId(String::from("editor"))), ..Default::default() };
+    let owned_slug = Slug(String::from("owned"));
+    let command = Command::CreateShortLink {
+        url: Url(String::from("http://relap.io/owned")),
+        slug: Some(owned_slug.clone()),
+        options: LinkOptions::default(),
+    };
+    service.dispatch_command(&admin, command).expect("Failed to dispatch link creation");
+    match service.dispatch_command(&editor, Command::DisableLink { slug: owned_slug.clone() }) {
+        Ok(_) => panic!("Only owner should be able to disable slug {:?}", owned_slug),
+        Err(error) => assert_eq!(error, ShortenerError::AccessDenied),
+    }
+    let command = Command::TransferOwnership { slug: owned_slug.clone(), new_owner: OwnerId(String::from("editor")) };
+    service.dispatch_command(&admin, command).expect("Failed to dispatch ownership transfer");
+    service.dispatch_command(&editor, Command::DisableLink { slug: owned_slug.clone() }).expect("Failed to dispatch disabling");
+
+    // Test error payload - errors are described by stable codes
+    let payload = ErrorPayload::from(&ShortenerError::SlugAlreadyInUse);
+    assert_eq!((payload.code.as_str(), payload.field.as_deref()), ("slug_already_in_use", Some("slug")));
+    assert_eq!(payload.to_error(), Some(ShortenerError::SlugAlreadyInUse));
+    assert_eq!(ErrorPayload::internal("storage is unavailable").to_error(), None);
+
+    // Test tags - links are found by tags given at creation and attached later
+    let promo = Tag(String::from("promo"));
+    let options = LinkOptions { tags: vec![promo.clone()], ..Default::default() };
+    let tagged = service
+        .handle_create_short_link_with(Url(String::from("http://relap.io/tagged")), None, options)
+        .expect("Failed to create tagged link");
+    service.handle_tag_link(reserved_slug.clone(), vec![promo.clone()]).expect("Failed to tag link");
+    let slugs: Vec<_> = service.list_links_by_tag(&promo).into_iter().map(|info| info.link.slug).collect();
+    assert_eq!(slugs, vec![reserved_slug.clone(), tagged.slug.clone()]);
+    service.handle_untag_link(tagged.slug.clone(), vec![promo.clone()]).expect("Failed to untag link");
+    assert_eq!(service.list_links_by_tag(&promo).len(), 1);
+
+    // Test integrity self-test - replayed state matches the current one
+    let report = service.check_integrity();
+    assert!(report.passed(), "Integrity check failed:\n{report}");
+
+    // Test event window - old events are pruned, but their effect is kept
+    let window = EventWindow { max_events: Some(4), max_bytes: None };
+    let mut service = UrlShortenerService::with_config(ServiceConfig { event_window: Some(window), ..Default::default() });
+    for index in 0..6 {
+        let url = Url(format!("http://relap.io/windowed-{index}"));
+        service.handle_create_short_link(url, None).expect("Failed to create windowed link");
+    }
+    assert!(service.events().len() <= 4);
+    let changes = service.get_changes_since(SyncCursor::default());
+    assert_eq!((changes.upserts.len(), changes.cursor), (6, SyncCursor(6)));
+
+    // Test snapshot - projections are rebuilt from the snapshot rolled forward by pruned events
+    service.rebuild_projection(TagIndex::NAME).expect("Failed to rebuild projection from snapshot");
+    let report = service.check_integrity();
+    assert!(report.passed() && report.checks.iter().all(|check| !check.skipped), "Integrity check failed:\n{report}");
+    let snapshot = service.snapshot().expect("Events were pruned without a snapshot");
+    let config = ServiceConfig { quiet: true, event_window: Some(window), ..Default::default() };
+    let restored = UrlShortenerService::restore_from_snapshot(config, snapshot, service.events().to_vec());
+    assert_eq!(restored.list_links(), service.list_links());
+    assert_eq!(service.register_projection(CreatedLinksCounter::default()), Err(ShortenerError::HistoryPruned));
+
+    // Test event window with a projection without snapshot - events are kept, so the projection can be rebuilt
+    let mut service = UrlShortenerService::with_config(ServiceConfig { event_window: Some(window), ..Default::default() });
+    service.register_projection(CreatedLinksCounter::default()).expect("Failed to register projection");
+    for index in 0..6 {
+        let url = Url(format!("http://relap.io/windowed-{index}"));
+        service.handle_create_short_link(url, None).expect("Failed to create windowed link");
+    }
+    assert_eq!(service.events().len(), 6);
+    service.rebuild_projection("created_links_counter").expect("Failed to rebuild projection");
+    assert_eq!(service.projection::<CreatedLinksCounter>().map(|p| p.0), Some(6));
+
+    // Test event window by memory - records are measured with their heap data, so long URLs are pruned sooner
+    let window = EventWindow { max_events: None, max_bytes: Some(4096) };
+    let mut service = UrlShortenerService::with_config(ServiceConfig { event_window: Some(window), ..Default::default() });
+    for index in 0..6 {
+        let url = Url(format!("http://relap.io/{}-{index}", "long".repeat(200)));
+        service.handle_create_short_link(url, None).expect("Failed to create windowed link");
+    }
+    assert!(service.events().len() < 6);
+    assert_eq!(service.event_bytes, service.events().iter().map(|record| window.record_size(record)).sum::<usize>());
+
+    // Test metadata - given at creation, replaced by update and returned with link
+    let metadata = LinkMetadata { title: Some(String::from("Relap")), ..Default::default() };
+    let options = LinkOptions { metadata, ..Default::default() };
+    let described = service
+        .handle_create_short_link_with(Url(String::from("http://relap.io/described")), None, options)
+        .expect("Failed to create described link");
+    let mut metadata = service.get_link(&described.slug).expect("Failed to get described link").metadata;
+    assert_eq!(metadata.title.as_deref(), Some("Relap"));
+    metadata.attributes.insert(String::from("campaign"), String::from("spring"));
+    service.handle_update_metadata(described.slug.clone(), metadata.clone()).expect("Failed to update metadata");
+    assert_eq!(service.get_link(&described.slug).map(|info| info.metadata), Ok(metadata));
+
+    // Test one-time link - the first redirect consumes it
+    let options = LinkOptions { one_time: true, ..Default::default() };
+    let one_time = service
+        .handle_create_short_link_with(Url(String::from("http://relap.io/once")), None, options)
+        .expect("Failed to create one-time link");
+    service.handle_redirect(one_time.slug.clone()).expect("Failed to follow one-time link");
+    assert_eq!(service.handle_redirect(one_time.slug.clone()), Err(ShortenerError::LinkConsumed));
+    assert!(service.get_link(&one_time.slug).is_ok_and(|info| info.consumed));
+
+    // Test webhook - redirects of the link are delivered signed
+    struct RecordingSender(std::rc::Rc<std::cell::RefCell<Vec<WebhookDelivery>>>);
+    impl WebhookSender for RecordingSender {
+        fn send(&self, delivery: WebhookDelivery) {
+            self.0.borrow_mut().push(delivery);
+        }
+    }
+    let deliveries = std::rc::Rc::default();
+    service.set_webhook_sender(RecordingSender(std::rc::Rc::clone(&deliveries)));
+    let webhook = LinkWebhook { url: Url(String::from("http://hooks.relap.io/clicks")), secret: String::from("Jefe"), sample_rate: 1.0 };
+    service.handle_set_webhook(described.slug.clone(), webhook).expect("Failed to set webhook");
+    service.handle_redirect(described.slug.clone()).expect("Failed to follow link with webhook");
+    let delivery = deliveries.borrow_mut().pop().expect("Webhook wasn't notified");
+    assert_eq!(delivery.signature, format!("sha256={}", webhooks::sign("Jefe", &delivery.body)));
+    assert_eq!(
+        webhooks::sign("Jefe", "what do ya want for nothing?"),
+        "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843",
+    );
+
+    // Test alias - both slugs resolve to the link and share its stats
+    let alias = Slug(String::from("described-alias"));
+    service.handle_add_alias(described.slug.clone(), alias.clone()).expect("Failed to add alias");
+    service.handle_redirect(alias.clone()).expect("Failed to follow alias");
+    let breakdown = service.get_stats_breakdown(&alias).expect("Failed to get stats breakdown");
+    assert_eq!(breakdown.stats.redirects, 2);
+    assert_eq!(breakdown.by_slug, vec![(described.slug.clone(), 1), (alias.clone(), 1)]);
+    assert_eq!(service.handle_add_alias(described.slug.clone(), alias.clone()), Err(ShortenerError::SlugAlreadyInUse));
+
+    // Test query pushdown - backend answers the query instead of in-memory index
+    struct EmptyBackend;
+    impl QueryBackend for EmptyBackend {
+        fn links_by_tag(&self, tag: &Tag) -> Option<Vec<LinkId>> {
+            Some(Vec::new())
+        }
+    }
+    service.handle_tag_link(alias.clone(), vec![promo.clone()]).expect("Failed to tag link");
+    assert_eq!(service.list_links_by_tag(&promo).len(), 1);
+    service.set_query_backend(EmptyBackend);
+    assert!(service.list_links_by_tag(&promo).is_empty());
+
+    // Test partitioning - links are created and followed on the instance owning their slug
+    let (local, remote) = (InstanceId(String::from("local")), InstanceId(String::from("remote")));
+    let router = StaticRanges::even(vec![local.clone(), remote.clone()]);
+    let rendezvous = RendezvousRouter { instances: vec![local.clone(), remote.clone()] };
+    let mut partitioned = PartitionedShortener::new(local.clone(), UrlShortenerService::new(), router.clone())
+        .with_remote(remote.clone(), UrlShortenerService::new());
+    for index in 0..4 {
+        let link = partitioned
+            .handle_create_short_link(Url(format!("http://relap.io/partitioned-{index}")), None)
+            .expect("Failed to create partitioned link");
+        partitioned.handle_redirect(link.slug.clone()).expect("Failed to follow partitioned link");
+        let owner = match router.owner(&link.slug) {
+            Some(owner) if *owner == local => partitioned.local(),
+            _ => partitioned.remote(&remote).expect("Remote instance is missing"),
+        };
+        assert_eq!(owner.get_stats(link.slug.clone()).map(|stats| stats.redirects), Ok(1));
+        assert!(rendezvous.owner(&link.slug).is_some());
+    }
+    // random slugs already in use are drawn again
+    struct Crowded {
+        service: UrlShortenerService,
+        taken: usize,
+    }
+    impl CommandHandler for Crowded {
+        fn handle_create_short_link(&mut self, url: Url, slug: Option<Slug>) -> Result<ShortLink, ShortenerError> {
+            if self.taken > 0 {
+                self.taken -= 1;
+                return Err(ShortenerError::SlugAlreadyInUse);
+            }
+            self.service.handle_create_short_link(url, slug)
+        }
+
+        fn handle_redirect(&mut self, slug: Slug) -> Result<ShortLink, ShortenerError> {
+            self.service.handle_redirect(slug)
+        }
+    }
+    impl QueryHandler for Crowded {
+        fn get_stats(&self, slug: Slug) -> Result<Stats, ShortenerError> {
+            self.service.get_stats(slug)
+        }
+    }
+    let crowded = || Crowded { service: UrlShortenerService::new(), taken: 2 };
+    let mut partitioned = PartitionedShortener::new(local.clone(), crowded(), router.clone())
+        .with_remote(remote.clone(), crowded());
+    let url = Url(String::from("http://relap.io/crowded"));
+    let taken = partitioned.handle_create_short_link(url.clone(), Some(Slug(String::from("taken"))));
+    assert_eq!(taken.map(|_| ()), Err(ShortenerError::SlugAlreadyInUse));
+    assert!(partitioned.handle_create_short_link(url, None).is_ok());
+    // slugs which may be the same slug are owned by the same instance
+    for (slug, lookalike) in [("promo", "PROMO"), ("promo", "prоmo"), ("promo", "ｐｒｏｍｏ")] {
+        let (slug, lookalike) = (Slug(String::from(slug)), Slug(String::from(lookalike)));
+        assert_eq!(router.owner(&slug), router.owner(&lookalike));
+        assert_eq!(rendezvous.owner(&slug), rendezvous.owner(&lookalike));
+    }
+
+    // Test history - changes of the link are listed in order, without redirects
+    let mut service = UrlShortenerService::new();
+    let link = service.handle_create_short_link(test_url.clone(), None).expect("Failed to create short link");
+    service.handle_redirect(link.slug.clone()).expect("Failed to follow link");
+    service.handle_disable_link(link.slug.clone()).expect("Failed to disable link");
+    service.handle_enable_link(link.slug.clone()).expect("Failed to enable link");
+    let history = service.get_history(&link.slug).expect("Failed to get history");
+    let kinds: Vec<_> = history.iter().map(|record| record.event.kind()).collect();
+    assert_eq!(kinds, vec![EventKind::LinkCreated, EventKind::LinkDisabled, EventKind::LinkEnabled]);
+
+    // Test event stream of a link - events are resumed from the version
+    let events = service.get_events_for(&link.slug, 2).expect("Failed to get events of link");
+    let versions: Vec<_> = events.iter().map(|event| (event.version, event.record.event.kind())).collect();
+    assert_eq!(versions, vec![(2, EventKind::Redirected), (3, EventKind::LinkDisabled), (4, EventKind::LinkEnabled)]);
+
+    // Test archiving - inactive links are hidden from listings until unarchived
+    let config = ServiceConfig { archive_after: Some(TimeDelta::zero()), ..Default::default() };
+    let mut service = UrlShortenerService::with_config(config);
+    let options = LinkOptions { tags: vec![promo.clone()], ..Default::default() };
+    let link = service
+        .handle_create_short_link_with(test_url.clone(), None, options)
+        .expect("Failed to create short link");
+    service.handle_archive_inactive_links();
+    assert!(service.list_links_by_tag(&promo).is_empty());
+    assert_eq!(service.list_archived_links().len(), 1);
+    service.handle_redirect(link.slug.clone()).expect("Archived link should still redirect");
+    service.handle_unarchive_link(link.slug.clone()).expect("Failed to unarchive link");
+    assert_eq!(service.list_links_by_tag(&promo).len(), 1);
+
+    // Test two-phase creation - draft goes live only when activated
+    let draft = service
+        .handle_prepare_link(Url(String::from("http://relap.io/draft")), None, LinkOptions::default(), TimeDelta::hours(1))
+        .expect("Failed to prepare draft link");
+    assert_eq!(service.handle_redirect(draft.link.slug.clone()), Err(ShortenerError::SlugNotFound));
+    let url = Url(String::from("http://relap.io/draft-taken"));
+    assert_eq!(service.handle_create_short_link(url.clone(), Some(draft.link.slug.clone())), Err(ShortenerError::SlugAlreadyInUse));
+    assert_eq!(service.handle_attach_url(draft.link.slug.clone(), url), Err(ShortenerError::SlugNotReserved));
+    assert_eq!(service.handle_activate_link(draft.link.slug.clone()), Ok(draft.link.clone()));
+    assert_eq!(service.handle_activate_link(draft.link.slug.clone()), Err(ShortenerError::DraftNotFound));
+
+    // Test scheduler - due commands are executed, pending ones can be cancelled
+    let context = RequestContext::default();
+    let disable = Command::DisableLink { slug: link.slug.clone() };
+    let enable = Command::EnableLink { slug: link.slug.clone() };
+    service.handle_schedule_command(context.clone(), disable, Utc::now() - TimeDelta::minutes(1));
+    let pending = service.handle_schedule_command(context, enable, Utc::now() + TimeDelta::days(7));
+    service.handle_run_due_commands();
+    assert_eq!(service.get_link(&link.slug).map(|info| info.disabled), Ok(true));
+    assert_eq!(service.list_scheduled_commands().len(), 1);
+    service.handle_cancel_scheduled_command(pending).expect("Failed to cancel scheduled command");
+    assert!(service.list_scheduled_commands().is_empty());
+    assert_eq!(service.handle_cancel_scheduled_command(pending), Err(ShortenerError::ScheduledCommandNotFound));
+
+    // Test waiting for redirects - resolves once the threshold is reached or times out
+    let url = Url(String::from("http://relap.io/awaited"));
+    let link = service.handle_create_short_link(url, None).expect("Failed to create short link");
+    service.handle_redirect(link.slug.clone()).expect("Failed to follow link");
+    let wait = service.wait_for_redirects(&link.slug, 2, TimeDelta::seconds(5)).expect("Failed to wait for redirects");
+    service.handle_redirect(link.slug.clone()).expect("Failed to follow link");
+    assert_eq!(block_on(wait).map(|stats| stats.redirects), Ok(2));
+    let wait = service.wait_for_redirects(&link.slug, 100, TimeDelta::milliseconds(10)).expect("Failed to wait for redirects");
+    assert_eq!(block_on(wait), Err(ShortenerError::TimedOut));
+
+    // Test merging - duplicate becomes an alias and its redirects are combined
+    let url = Url(String::from("http://relap.io/merged"));
+    let link = service.handle_create_short_link(url, None).expect("Failed to create short link");
+    // URLs are normalized, so only a clone can point to the same destination
+    let duplicate = service.handle_clone_link(link.slug.clone(), Slug(String::from("merged-copy")))
+        .expect("Failed to create duplicate link");
+    service.handle_redirect(link.slug.clone()).expect("Failed to follow link");
+    service.handle_redirect(duplicate.slug.clone()).expect("Failed to follow duplicate link");
+    assert_eq!(service.handle_merge_links(link.slug.clone(), duplicate.slug.clone()), Ok(link.clone()));
+    service.handle_redirect(duplicate.slug.clone()).expect("Failed to follow merged slug");
+    assert_eq!(service.get_stats(link.slug.clone()).map(|stats| stats.redirects), Ok(3));
+    assert_eq!(service.get_link(&duplicate.slug).map(|info| info.link), Ok(link.clone()));
+    let breakdown = service.get_stats_breakdown(&link.slug).expect("Failed to get stats breakdown");
+    assert_eq!(breakdown.by_slug, vec![(link.slug.clone(), 1), (duplicate.slug.clone(), 2)]);
+    assert!(service.check_integrity().passed());
+    assert_eq!(
+        service.handle_merge_links(link.slug.clone(), draft.link.slug.clone()),
+        Err(ShortenerError::DestinationMismatch),
+    );
+
+    // Test cloning - the clone keeps destination and tags, but starts with fresh stats
+    let options = LinkOptions { tags: vec![promo.clone()], ..Default::default() };
+    let url = Url(String::from("http://relap.io/campaign"));
+    let link = service.handle_create_short_link_with(url, None, options).expect("Failed to create short link");
+    service.handle_redirect(link.slug.clone()).expect("Failed to follow link");
+    let clone = service
+        .handle_clone_link(link.slug.clone(), Slug(String::from("campaign-2")))
+        .expect("Failed to clone link");
+    assert_eq!(clone.url, link.url);
+    assert_eq!(service.get_stats(clone.slug.clone()).map(|stats| stats.redirects), Ok(0));
+    assert_eq!(service.get_link(&clone.slug).map(|info| info.tags), service.get_link(&link.slug).map(|info| info.tags));
+    assert_eq!(service.handle_clone_link(link.slug.clone(), clone.slug.clone()), Err(ShortenerError::SlugAlreadyInUse));
+
+    // Test import - duplicates from several sources are dropped and counts are summed per bucket
+    let imported = |minute: u32, second: u32, count: u64| ImportedRedirect {
+        slug: clone.slug.clone(),
+        at: Utc.with_ymd_and_hms(2024, 1, 1, 12, minute, second).unwrap(),
+        visitor: None,
+        count,
+    };
+    let redirects = vec![imported(0, 10, 2), imported(0, 10, 2), imported(0, 40, 1), imported(5, 0, 3)];
+    let rules = MergeRules { drop_duplicates: true, bucket: Some(TimeDelta::minutes(1)) };
+    let report = service.handle_import_redirects(redirects, &rules);
+    assert_eq!((report.imported, report.duplicates), (6, 1));
+    assert_eq!(service.get_stats(clone.slug.clone()).map(|stats| stats.redirects), Ok(6));
+    let imports = service.get_history(&clone.slug).expect("Failed to get history")
+        .iter()
+        .filter(|record| record.event.kind() == EventKind::RedirectsImported)
+        .count();
+    assert_eq!(imports, 2);
+
+    // Test redirect type - temporary by default, settable at creation and later
+    assert_eq!(service.get_link(&clone.slug).map(|info| info.redirect_type), Ok(RedirectType::Temporary));
+    let options = LinkOptions { redirect_type: RedirectType::Permanent, ..Default::default() };
+    let url = Url(String::from("http://relap.io/permanent"));
+    let link = service.handle_create_short_link_with(url, None, options).expect("Failed to create short link");
+    assert_eq!(service.get_link(&link.slug).map(|info| info.redirect_type.status_code()), Ok(301));
+    service.handle_set_redirect_type(link.slug.clone(), RedirectType::Temporary).expect("Failed to set redirect type");
+    assert_eq!(service.get_link(&link.slug).map(|info| info.redirect_type), Ok(RedirectType::Temporary));
+
+    // Test redirect outcomes - refusals carry their reasons and are counted in stats
+    service.handle_disable_link(link.slug.clone()).expect("Failed to disable link");
+    let outcome = service.handle_redirect_with_outcome(link.slug.clone(), None, None);
+    assert_eq!(outcome, RedirectOutcome::Refused(RedirectRefusal::Disabled));
+    assert_eq!(outcome.status_code(), 403);
+    let outcome = service.handle_redirect_with_outcome(Slug(String::from("missing")), None, None);
+    assert_eq!(outcome.into_result(), Err(ShortenerError::SlugNotFound));
+    service.handle_enable_link(link.slug.clone()).expect("Failed to enable link");
+    let outcome = service.handle_redirect_with_outcome(link.slug.clone(), None, None);
+    let served = |interstitial| RedirectOutcome::Served {
+        link: link.clone(),
+        redirect_type: RedirectType::Temporary,
+        interstitial,
+    };
+    assert_eq!(outcome, served(false));
+    let breakdown = service.get_stats_breakdown(&link.slug).expect("Failed to get stats breakdown");
+    assert_eq!(breakdown.refused, vec![(RedirectRefusal::Disabled, 1)]);
+
+    // Test interstitial - redirects of the link are still served and counted, but shown the interstitial first
+    let command = Command::SetInterstitial { slug: link.slug.clone(), enabled: true };
+    assert_eq!(service.dispatch_command(&RequestContext::default(), command), Ok(Reply::Done));
+    assert_eq!(service.get_link(&link.slug).map(|info| info.interstitial), Ok(true));
+    assert_eq!(service.handle_redirect_with_outcome(link.slug.clone(), None, None), served(true));
+    service.handle_set_interstitial(link.slug.clone(), false).expect("Failed to remove interstitial");
+    assert_eq!(service.handle_redirect_with_outcome(link.slug.clone(), None, None), served(false));
+
+    // Test groups - links are organized into groups and moved between them
+    let group = service.handle_create_group(String::from("Campaigns"));
+    service.handle_move_to_group(clone.slug.clone(), Some(group.clone())).expect("Failed to move link to group");
+    service.handle_move_to_group(link.slug.clone(), Some(group.clone())).expect("Failed to move link to group");
+    let grouped = service.list_links_in_group(&group).expect("Failed to list links in group");
+    let slugs: Vec<_> = grouped.iter().map(|info| info.link.slug.clone()).collect();
+    assert_eq!(slugs, vec![clone.slug.clone(), link.slug.clone()]);
+    service.handle_move_to_group(link.slug.clone(), None).expect("Failed to move link out of group");
+    assert_eq!(service.list_groups().first().map(|group| group.links), Some(1));
+    let missing = GroupId::generate();
+    assert_eq!(service.handle_move_to_group(link.slug.clone(), Some(missing)), Err(ShortenerError::GroupNotFound));
+
+    // Test sequential slugs - short base62 slugs skipping the ones already in use
+    let config = ServiceConfig { slug_generator: SlugGenerator::Sequential, ..Default::default() };
+    let mut service = UrlShortenerService::with_config(config);
+    service.handle_create_short_link(Url(String::from("http://relap.io/custom")), Some(Slug(String::from("1"))))
+        .expect("Failed to create short link");
+    let links = service.handle_create_short_links(
+        (0..3).map(|index| (Url(format!("http://relap.io/sequential-{index}")), None)).collect(),
+    );
+    let slugs: Vec<_> = links.into_iter().map(|link| link.map(|link| link.slug.0)).collect();
+    assert_eq!(slugs, vec![Ok(String::from("0")), Ok(String::from("2")), Ok(String::from("3"))]);
+    assert_eq!(slugs::base62(62 * 62 - 1), "ZZ");
+
+    // Test random slugs - generation gives up once attempts are exhausted
+    let config = ServiceConfig { slug_generator: SlugGenerator::random(), ..Default::default() };
+    let mut service = UrlShortenerService::with_config(config);
+    let link = service.handle_create_short_link(test_url.clone(), None).expect("Failed to create short link");
+    assert!(link.slug.0.len() == SLUG_LEN && link.slug.0.chars().all(|char| char.is_ascii_alphanumeric()));
+    // the only possible slug of length 0 is taken by the first link
+    let slug_generator = SlugGenerator::Random { length: 0, attempts: 3 };
+    let mut service = UrlShortenerService::with_config(ServiceConfig { slug_generator, ..Default::default() });
+    service
+        .handle_create_short_link(Url(String::from("http://relap.io/a")), None)
+        .expect("Failed to create short link");
+    let failed = service.handle_create_short_link(Url(String::from("http://relap.io/b")), None);
+    assert_eq!(failed, Err(ShortenerError::SlugGenerationFailed));
+
+    // Test blocked slugs - routes of the service can't be taken by links
+    let blocked_slugs = slugs::SERVICE_ROUTES.iter().map(|route| route.to_string()).collect();
+    let mut service = UrlShortenerService::with_config(ServiceConfig { blocked_slugs, ..Default::default() });
+    let api = Slug(String::from("api"));
+    assert_eq!(service.handle_create_short_link(test_url.clone(), Some(api.clone())), Err(ShortenerError::SlugBlocked));
+    assert_eq!(service.handle_reserve_slug(api.clone(), None), Err(ShortenerError::SlugBlocked));
+    let link = service.handle_create_short_link(test_url.clone(), None).expect("Failed to create short link");
+    assert_eq!(service.handle_add_alias(link.slug.clone(), api), Err(ShortenerError::SlugBlocked));
+
+    // Test slug rules - custom slugs breaking them are rejected with the broken rule
+    let space = Slug(String::from("promo 2024"));
+    let error = service.handle_create_short_link(Url(String::from("https://example.com/promo")), Some(space));
+    assert_eq!(error, Err(ShortenerError::InvalidSlug(SlugViolation::InvalidCharacter)));
+    let dash = Slug(String::from("-promo"));
+    assert_eq!(service.handle_reserve_slug(dash, None), Err(ShortenerError::InvalidSlug(SlugViolation::EdgeDash)));
+    let payload = ErrorPayload::from(&ShortenerError::InvalidSlug(SlugViolation::TooLong));
+    assert_eq!(payload.to_error(), Some(ShortenerError::InvalidSlug(SlugViolation::TooLong)));
+
+    // Test case-insensitive slugs - slugs typed in another case resolve to the link, which keeps its casing
+    let config = ServiceConfig { case_insensitive_slugs: true, ..Default::default() };
+    let mut service = UrlShortenerService::with_config(config);
+    let my_link = Slug(String::from("MyLink"));
+    service.handle_create_short_link(test_url.clone(), Some(my_link.clone())).expect("Failed to create short link");
+    let redirected = service.handle_redirect(Slug(String::from("mylink"))).expect("Failed to redirect");
+    assert_eq!(redirected.slug, my_link);
+    assert_eq!(service.get_stats(Slug(String::from("MYLINK"))).map(|stats| stats.redirects), Ok(1));
+    let other_url = Url(String::from("https://example.com/my"));
+    let taken = service.handle_create_short_link(other_url, Some(Slug(String::from("myLINK"))));
+    assert_eq!(taken, Err(ShortenerError::SlugAlreadyInUse));
+
+    // Test unicode slugs - slugs are compared in NFC and lookalike letters are folded
+    let slug_rules = SlugRules { unicode: true, ..Default::default() };
+    let config = ServiceConfig { slug_rules, fold_homoglyph_slugs: true, ..Default::default() };
+    let mut service = UrlShortenerService::with_config(config);
+    let cafe = Slug(String::from("café"));
+    service.handle_create_short_link(test_url.clone(), Some(cafe.clone())).expect("Failed to create short link");
+    let decomposed = Slug(String::from("cafe\u{301}"));
+    assert_eq!(service.handle_redirect(decomposed).map(|link| link.slug), Ok(cafe.clone()));
+    let lookalike = Slug(String::from("\u{441}\u{430}fé"));
+    assert_eq!(service.handle_redirect(lookalike).map(|link| link.slug), Ok(cafe.clone()));
+    assert_eq!(slugs::decode_path_segment("caf%C3%A9"), Ok(cafe));
+    let emoji = Slug(String::from("🚀-launch"));
+    service.handle_create_short_link(Url(String::from("https://example.com/launch")), Some(emoji))
+        .expect("Failed to create short link");
+    assert_eq!(slugs::decode_path_segment("a%2Fb").map(|slug| SlugRules::default().check(&slug.0)),
+        Ok(Err(SlugViolation::InvalidCharacter)));
+
+    // Test profanity filter - generated slugs containing offensive words are skipped
+    assert!(slugs::is_offensive("xx5H1Txx") && !slugs::is_offensive("relap"));
+    let slug_generator = SlugGenerator::Sequential;
+    let mut service = UrlShortenerService::with_config(ServiceConfig {
+        slug_generator,
+        filter_profanity: true,
+        ..Default::default()
+    });
+    for index in 0..40 {
+        let link = service.handle_create_short_link(Url(format!("https://example.com/{index}")), None)
+            .expect("Failed to create short link");
+        assert!(!slugs::is_offensive(&link.slug.0));
+    }
+
+    // Test slug suggestions - taken slugs get available close variants
+    let mut service = UrlShortenerService::new();
+    let my_link = Slug(String::from("my-link"));
+    service.handle_create_short_link(test_url.clone(), Some(my_link.clone())).expect("Failed to create short link");
+    let year = Utc::now().year();
+    service.handle_reserve_slug(Slug(format!("my-link-{year}")), None).expect("Failed to reserve slug");
+    let suggestions = service.list_slug_suggestions(&my_link, 2);
+    assert_eq!(suggestions, vec![Slug(format!("my-link-{}", year + 1)), Slug(String::from("my-link-2"))]);
+    let query = Query::ListSlugSuggestions { slug: Slug(String::from("my-link-2")), count: 1 };
+    let suggested = service.dispatch_query(&RequestContext::default(), query);
+    assert!(matches!(suggested, Ok(Reply::Slugs(slugs)) if slugs == [Slug(format!("my-link-{}", year + 1))]));
+
+    // Test namespaces - slugs are unique within their namespaces, which must be created first
+    let mut service = UrlShortenerService::new();
+    let team_a = Slug(String::from("team-a/launch"));
+    let missing = service.handle_create_short_link(test_url.clone(), Some(team_a.clone()));
+    assert_eq!(missing, Err(ShortenerError::NamespaceNotFound));
+    service.handle_create_namespace(String::from("team-a")).expect("Failed to create namespace");
+    service.handle_create_namespace(String::from("team-b")).expect("Failed to create namespace");
+    let taken = service.handle_create_namespace(String::from("team-a"));
+    assert_eq!(taken, Err(ShortenerError::NamespaceAlreadyExists));
+    service.handle_create_short_link(test_url.clone(), Some(team_a.clone())).expect("Failed to create short link");
+    let team_b = Slug(String::from("team-b/launch"));
+    service.handle_create_short_link(Url(String::from("https://example.com/b")), Some(team_b))
+        .expect("Failed to create short link");
+    let links = service.list_links_in_namespace("team-a").expect("Failed to list links");
+    assert_eq!(links.iter().map(|info| &info.link.slug).collect::<Vec<_>>(), vec![&team_a]);
+    assert_eq!(service.list_namespaces().iter().map(|namespace| namespace.links).sum::<usize>(), 2);
+    let nested = Slug(String::from("team-a/launch/2"));
+    let nested = service.handle_create_short_link(Url(String::from("https://example.com/c")), Some(nested));
+    assert_eq!(nested, Err(ShortenerError::InvalidSlug(SlugViolation::InvalidCharacter)));
+
+    // Test slug collision metrics - rejected candidates are counted in system stats
+    let slug_generator = SlugGenerator::Random { length: 0, attempts: 3 };
+    let mut service = UrlShortenerService::with_config(ServiceConfig { slug_generator, ..Default::default() });
+    service.handle_create_short_link(test_url.clone(), None).expect("Failed to create short link");
+    let failed = service.handle_create_short_link(Url(String::from("http://relap.io/b")), None);
+    assert_eq!(failed, Err(ShortenerError::SlugGenerationFailed));
+    let stats = service.get_system_stats();
+    assert_eq!(stats.slug_generation, SlugGenerationStats { attempts: 4, collisions: 3, failures: 1 });
+    assert_eq!(stats.slug_generation.collision_rate(), 0.75);
+
+    // Test URL normalization - the same destination written differently is shortened once
+    let url_normalization = UrlNormalization { strip_trailing_slash: true, strip_tracking_params: true };
+    let mut service = UrlShortenerService::with_config(ServiceConfig { url_normalization, ..Default::default() });
+    let url = Url(String::from("HTTP://Example.com:80/a/../docs/?utm_source=mail&id=7"));
+    let link = service.handle_create_short_link(url, None).expect("Failed to create short link");
+    assert_eq!(link.url, Url(String::from("http://example.com/docs?id=7")));
+    let duplicate = service.handle_create_short_link(Url(String::from("http://example.com/docs?id=7&fbclid=x")), None);
+    assert_eq!(duplicate, Err(ShortenerError::SlugAlreadyInUse));
+
+    // Test scheme allowlist - only http and https URLs are shortened by default
+    let mut service = UrlShortenerService::new();
+    for url in ["javascript:alert(1)", "data:text/html,<script>alert(1)</script>", "file:///etc/passwd"] {
+        let rejected = service.handle_create_short_link(Url(String::from(url)), None);
+        assert_eq!(rejected, Err(ShortenerError::SchemeNotAllowed));
+    }
+    let allowed_schemes = SchemeAllowlist(HashSet::from([String::from("ftp")]));
+    let mut service = UrlShortenerService::with_config(ServiceConfig { allowed_schemes, ..Default::default() });
+    let ftp = Url(String::from("ftp://example.com/file"));
+    service.handle_create_short_link(ftp, None).expect("Failed to create short link");
+
+    // Test destination blocklist - blocked hosts can't be shortened and existing links to them are blocked
+    struct NoPhishing;
+    impl UrlPolicy for NoPhishing {
+        fn blocks(&self, url: &baseUrl) -> Option<String> {
+            url.path().contains("phishing").then(|| String::from("phishing page"))
+        }
+    }
+    let config = ServiceConfig { check_urls_on_redirect: true, ..Default::default() };
+    let mut service = UrlShortenerService::with_config(config);
+    let link = service.handle_create_short_link(Url(String::from("https://cdn.evil.example/x")), None)
+        .expect("Failed to create short link");
+    assert_eq!(service.handle_block_host(String::from("Evil.example")), vec![link.slug.clone()]);
+    assert_eq!(service.handle_redirect(link.slug.clone()), Err(ShortenerError::UrlBlocked));
+    let blocked = service.handle_create_short_link(Url(String::from("https://evil.example/y")), None);
+    assert_eq!(blocked, Err(ShortenerError::UrlBlocked));
+    let link = service.handle_create_short_link(Url(String::from("https://relap.io/phishing")), None)
+        .expect("Failed to create short link");
+    service.set_url_policy(NoPhishing);
+    let outcome = service.handle_redirect_with_outcome(link.slug.clone(), None, None);
+    assert_eq!(outcome, RedirectOutcome::Refused(RedirectRefusal::Blocked));
+    assert!(service.get_link(&link.slug).is_ok_and(|info| info.blocked.is_some()));
+    assert!(service.check_integrity().passed());
+
+    // Test duplicate URL policy - duplicates are rejected, allowed or resolved to the existing link
+    let duplicate_urls = DuplicateUrlPolicy::AllowDuplicates;
+    let mut service = UrlShortenerService::with_config(ServiceConfig { duplicate_urls, ..Default::default() });
+    let first = service.handle_create_short_link(test_url.clone(), None).expect("Failed to create short link");
+    let second = service.handle_create_short_link(test_url.clone(), Some(Slug(String::from("second"))))
+        .expect("Failed to create duplicate link");
+    assert!(first.slug != second.slug && first.url == second.url);
+    let duplicate_urls = DuplicateUrlPolicy::ReturnExisting;
+    let mut service = UrlShortenerService::with_config(ServiceConfig { duplicate_urls, ..Default::default() });
+    let first = service.handle_create_short_link(test_url.clone(), None).expect("Failed to create short link");
+    let again = service.handle_create_short_link(test_url.clone(), Some(Slug(String::from("again"))));
+    assert_eq!(again, Ok(first.clone()));
+    let other = Url(String::from("https://example.com/other"));
+    let batch = vec![(other.clone(), None), (other, None), (test_url.clone(), None)];
+    let outcomes = service.handle_create_short_links(batch);
+    assert!(outcomes[0].is_ok() && outcomes[0] == outcomes[1] && outcomes[2] == Ok(first));
+    assert_eq!(service.events().iter().filter(|record| record.event.kind() == EventKind::LinkCreated).count(), 2);
+
+    // Test URL limits - long URLs and line breaks are rejected, control characters are stripped
+    let mut service = UrlShortenerService::new();
+    let long = Url(format!("https://example.com/{}", "a".repeat(4096)));
+    assert_eq!(service.handle_create_short_link(long, None), Err(ShortenerError::UrlRejected(UrlViolation::TooLong)));
+    let injected = Url(String::from("https://example.com/\r\nSet-Cookie: a=b"));
+    let rejected = service.handle_create_short_link(injected, None);
+    assert_eq!(rejected, Err(ShortenerError::UrlRejected(UrlViolation::Newline)));
+    let link = service.handle_create_short_link(Url(String::from(" https://example.com/a\u{7}b ")), None)
+        .expect("Failed to create short link");
+    assert_eq!(link.url, Url(String::from("https://example.com/ab")));
+
+    // Test query parameters - per-link parameters are added to the destination on redirect
+    let mut service = UrlShortenerService::new();
+    let url = Url(String::from("https://example.com/sale?utm_source=old&id=1"));
+    let link = service.handle_create_short_link(url, None).expect("Failed to create short link");
+    let params = vec![
+        QueryParam::override_with("utm_source", "print"),
+        QueryParam::append("utm_campaign", QueryParam::SLUG),
+        QueryParam::append("click", QueryParam::CLICK_ID),
+    ];
+    service.handle_set_query_params(link.slug.clone(), params).expect("Failed to set query parameters");
+    let followed = service.handle_redirect(link.slug.clone()).expect("Failed to follow link");
+    let prefix = format!("https://example.com/sale?id=1&utm_source=print&utm_campaign={}&click=", link.slug.0);
+    assert!(followed.url.0.starts_with(&prefix) && followed.url.0.len() == prefix.len() + 16);
+    assert_eq!(service.get_link(&link.slug).map(|info| info.link), Ok(link));
+
+    // Test destination health - broken destinations are found by periodic checks
+    struct FakeProbe;
+    impl HealthProbe for FakeProbe {
+        fn probe(&self, url: &Url) -> Result<(), String> {
+            if url.0.contains("dead") { Err(String::from("Destination responded with 404 Not Found")) } else { Ok(()) }
+        }
+    }
+    let mut service = UrlShortenerService::new();
+    let alive = service.handle_create_short_link(Url(String::from("https://example.com/alive")), None).unwrap();
+    let dead = service.handle_create_short_link(Url(String::from("https://example.com/dead")), None).unwrap();
+    assert_eq!(service.handle_check_destination_health(), vec![]);
+    service.set_health_probe(FakeProbe);
+    assert_eq!(service.handle_check_destination_health(), vec![dead.slug.clone()]);
+    let broken = service.list_broken_links();
+    assert!(broken.len() == 1 && broken[0].link == dead && broken[0].health.is_broken());
+    assert!(matches!(service.get_link(&alive.slug).unwrap().health, DestinationHealth::Healthy { .. }));
+
+    // Test redirect loops - destinations can't point back at the shortener or loop through its links
+    let own_hosts = HashSet::from([String::from("sho.rt")]);
+    let config = ServiceConfig { own_hosts: own_hosts.clone(), ..Default::default() };
+    let mut service = UrlShortenerService::with_config(config);
+    let own = Url(String::from("https://sho.rt/abc"));
+    assert_eq!(service.handle_create_short_link(own, None), Err(ShortenerError::SelfReference));
+    let config = ServiceConfig { own_hosts, allow_own_links: true, ..Default::default() };
+    let mut service = UrlShortenerService::with_config(config);
+    let first = Url(String::from("https://sho.rt/second"));
+    service.handle_create_short_link(first, Some(Slug(String::from("first")))).expect("Failed to create short link");
+    let second = Url(String::from("https://sho.rt/third"));
+    service.handle_create_short_link(second, Some(Slug(String::from("second")))).expect("Failed to create short link");
+    let third = Url(String::from("https://sho.rt/first"));
+    let looped = service.handle_create_short_link(third, Some(Slug(String::from("third"))));
+    assert_eq!(looped, Err(ShortenerError::RedirectLoop));
+    let itself = Url(String::from("https://sho.rt/self"));
+    let itself = service.handle_create_short_link(itself, Some(Slug(String::from("self"))));
+    assert_eq!(itself, Err(ShortenerError::RedirectLoop));
+
+    // Test non-HTTP schemes - allowed schemes are validated by their validators
+    let allowed_schemes = SchemeAllowlist::default().with("mailto").with("tel").with("magnet").with("myapp");
+    let mut service = UrlShortenerService::with_config(ServiceConfig { allowed_schemes, ..Default::default() });
+    service.set_scheme_validator("myapp", |url: &baseUrl| match url.host_str() {
+        Some("open") => Ok(()),
+        _ => Err(String::from("unknown action")),
+    });
+    for url in ["mailto:team@example.com", "tel:+1-555-0100", "magnet:?xt=urn:btih:c12fe1", "myapp://open/item/42"] {
+        assert!(service.handle_create_short_link(Url(String::from(url)), None).is_ok(), "{url} was rejected");
+    }
+    for url in ["mailto:team", "tel:call-me", "magnet:?dn=file", "myapp://delete/item/42"] {
+        let rejected = service.handle_create_short_link(Url(String::from(url)), None);
+        assert_eq!(rejected, Err(ShortenerError::InvalidUrl), "{url} was accepted");
+    }
+
+    // Test previews - previews of destinations are fetched after creation and shown in link info
+    let html = r#"<html><head><title> Sale &amp; more </title>
+        <meta name="description" content='Everything at half price'><link rel="shortcut icon" href="/static/icon.png">
+        </head><body><title>Ignored</title></body></html>"#;
+    let page = baseUrl::parse("https://example.com/shop/sale").unwrap();
+    let preview = previews::parse_preview(html, &page);
+    assert_eq!(preview.title.as_deref(), Some("Sale & more"));
+    assert_eq!(preview.description.as_deref(), Some("Everything at half price"));
+    assert_eq!(preview.favicon.as_deref(), Some("https://example.com/static/icon.png"));
+    let bare = previews::parse_preview(r#"<meta property="og:title" content="Shop">"#, &page);
+    assert_eq!(bare.title.as_deref(), Some("Shop"));
+    assert_eq!(bare.favicon.as_deref(), Some("https://example.com/favicon.ico"));
+
+    struct FakeFetcher;
+    impl PreviewFetcher for FakeFetcher {
+        fn fetch(&self, url: &baseUrl) -> Result<DestinationPreview, String> {
+            let html = format!("<title>{}</title>", url.path());
+            Ok(previews::parse_preview(&html, url))
+        }
+    }
+    let mut service = UrlShortenerService::new();
+    let link = service.handle_create_short_link(Url(String::from("https://example.com/news")), None).unwrap();
+    assert!(service.handle_fetch_previews().is_empty());
+    service.set_preview_fetcher(FakeFetcher);
+    assert_eq!(service.handle_fetch_previews(), vec![link.slug.clone()]);
+    assert!(service.handle_fetch_previews().is_empty());
+    let preview = service.get_link(&link.slug).unwrap().preview.unwrap();
+    assert_eq!(preview.title.as_deref(), Some("/news"));
+
+    // Test canonical mode - independent instances converge on the same slug for the same URL
+    let config = ServiceConfig { slug_generator: SlugGenerator::canonical(), ..Default::default() };
+    let mut first = UrlShortenerService::with_config(config.clone());
+    let mut second = UrlShortenerService::with_config(config);
+    let url = Url(String::from("HTTPS://Example.com:443/docs"));
+    let link = first.handle_create_short_link(url.clone(), None).expect("Failed to create short link");
+    assert_eq!(second.handle_create_short_link(url.clone(), None), Ok(link.clone()));
+    assert!(slugs::canonical("https://example.com/docs").starts_with(&link.slug.0));
+    let acting = RequestContext { actor: Some(ActorId(String::from("someone"))), ..Default::default() };
+    let command = Command::CreateShortLink { url, slug: None, options: LinkOptions::default() };
+    assert_eq!(first.dispatch_command(&acting, command), Ok(Reply::Link(link)));
+
+    // Test resolved redirects - destinations are stored after their own redirects, keeping the original
+    struct FakeFollower;
+    impl RedirectFollower for FakeFollower {
+        fn next_hop(&self, url: &baseUrl) -> Result<Option<baseUrl>, String> {
+            match url.path() {
+                "/old" => Ok(Some(url.join("/moved").unwrap())),
+                "/moved" => Ok(Some(url.join("/final?from=old").unwrap())),
+                "/loop" => Ok(Some(url.join("/loop-back").unwrap())),
+                "/loop-back" => Ok(Some(url.join("/loop").unwrap())),
+                _ => Ok(None),
+            }
+        }
+    }
+    let config = ServiceConfig { max_resolved_redirects: 3, ..Default::default() };
+    let mut service = UrlShortenerService::with_config(config);
+    service.set_redirect_follower(FakeFollower);
+    let link = service.handle_create_short_link(Url(String::from("https://example.com/old")), None).unwrap();
+    assert_eq!(link.url, Url(String::from("https://example.com/final?from=old")));
+    let info = service.get_link(&link.slug).unwrap();
+    assert_eq!(info.original_url, Some(Url(String::from("https://example.com/old"))));
+    let looped = service.handle_create_short_link(Url(String::from("https://example.com/loop")), None).unwrap();
+    assert_eq!(looped.url, Url(String::from("https://example.com/loop-back")));
+    let direct = service.handle_create_short_link(Url(String::from("https://example.com/direct")), None).unwrap();
+    assert_eq!(service.get_link(&direct.slug).unwrap().original_url, None);
+
+    // Test redirect context - details of the request are recorded with the redirect
+    let mut service = UrlShortenerService::new();
+    let link = service.handle_create_short_link(Url(String::from("https://example.com/context")), None).unwrap();
+    let context = RedirectContext {
+        visitor: Some(VisitorId(String::from("visitor"))),
+        referrer: Some(String::from("https://news.example.org/")),
+        user_agent: Some(String::from("Mozilla/5.0")),
+        ip: Some(IpAddr::from([203, 0, 113, 7])),
+        requested_at: None,
+        country: None,
+    };
+    assert_eq!(service.handle_redirect_with_context(link.slug.clone(), context.clone()), Ok(link.clone()));
+    let last = service.events().last().map(|record| &record.event);
+    let Some(Event::Redirected { visitor, context: Some(recorded), .. }) = last else {
+        panic!("Redirect context was not recorded");
+    };
+    assert_eq!(visitor, &context.visitor);
+    assert_eq!(**recorded, RedirectContext { visitor: None, ..context });
+    service.handle_redirect(link.slug.clone()).unwrap();
+    assert!(matches!(service.events().last().map(|r| &r.event), Some(Event::Redirected { context: None, .. })));
+
+    // Test unique visitors - distinct visitors are estimated alongside redirects
+    let mut service = UrlShortenerService::new();
+    let link = service.handle_create_short_link(Url(String::from("https://example.com/unique")), None).unwrap();
+    for visitor in 0..1000 {
+        service.handle_redirect_from(link.slug.clone(), VisitorId(format!("visitor-{}", visitor % 400))).unwrap();
+    }
+    for ip in 0..100u8 {
+        let context = RedirectContext { ip: Some(IpAddr::from([198, 51, 100, ip])), ..Default::default() };
+        service.handle_redirect_with_context(link.slug.clone(), context).unwrap();
+    }
+    service.handle_redirect(link.slug.clone()).unwrap();
+    let breakdown = service.get_stats_breakdown(&link.slug).unwrap();
+    assert_eq!(breakdown.stats.redirects, 1101);
+    let unique_visitors = breakdown.unique_visitors;
+    assert!((475..=525).contains(&unique_visitors), "estimated {unique_visitors} unique visitors");
+    service.handle_reset_stats(link.slug.clone()).unwrap();
+    assert_eq!(service.get_stats_breakdown(&link.slug).unwrap().unique_visitors, 0);
+
+    // Test clicks by country - countries of visitors are resolved from their addresses
+    let mut service = UrlShortenerService::new();
+    service.set_geo_resolver(|ip: IpAddr| match ip {
+        IpAddr::V4(ip) if ip.octets()[0] == 203 => CountryCode::parse("de"),
+        IpAddr::V4(ip) if ip.octets()[0] == 198 => CountryCode::parse("FR"),
+        _ => None,
+    });
+    let link = service.handle_create_short_link(Url(String::from("https://example.com/geo")), None).unwrap();
+    for ip in [[203, 0, 113, 1], [203, 0, 113, 2], [198, 51, 100, 1], [192, 0, 2, 1]] {
+        let context = RedirectContext { ip: Some(IpAddr::from(ip)), ..Default::default() };
+        service.handle_redirect_with_context(link.slug.clone(), context).unwrap();
+    }
+    let context = RedirectContext { country: CountryCode::parse("jp"), ..Default::default() };
+    service.handle_redirect_with_context(link.slug.clone(), context).unwrap();
+    service.handle_redirect(link.slug.clone()).unwrap();
+    let country = |code: &str| CountryCode(String::from(code));
+    assert_eq!(
+        service.get_clicks_by_country(&link.slug),
+        Ok(vec![(country("DE"), 2), (country("FR"), 1), (country("JP"), 1)]),
+    );
+    service.handle_reset_stats(link.slug.clone()).unwrap();
+    assert_eq!(service.get_clicks_by_country(&link.slug), Ok(Vec::new()));
+
+    // Test bot detection - redirects made by bots are counted apart
+    let mut service = UrlShortenerService::new();
+    let link = service.handle_create_short_link(Url(String::from("https://example.com/bots")), None).unwrap();
+    for user_agent in ["Mozilla/5.0 (X11; Linux x86_64)", "Googlebot/2.1", "curl/8.5.0", ""] {
+        let context = RedirectContext { user_agent: Some(String::from(user_agent)), ..Default::default() };
+        service.handle_redirect_with_context(link.slug.clone(), context).unwrap();
+    }
+    service.handle_redirect(link.slug.clone()).unwrap();
+    let breakdown = service.get_stats_breakdown(&link.slug).unwrap();
+    assert_eq!((breakdown.stats.redirects, breakdown.human_redirects), (5, 2));
+    service.set_bot_classifier(|context: &RedirectContext| context.user_agent.is_none());
+    service.handle_redirect(link.slug.clone()).unwrap();
+    let breakdown = service.get_stats_breakdown(&link.slug).unwrap();
+    assert_eq!((breakdown.stats.redirects, breakdown.human_redirects), (6, 2));
+
+    // Test redirect rollups - older redirects are kept in coarser buckets
+    let mut service = UrlShortenerService::new();
+    let link = service.handle_create_short_link(Url(String::from("https://example.com/rollups")), None).unwrap();
+    let now = Utc::now();
+    for ago in [TimeDelta::days(40), TimeDelta::days(40), TimeDelta::days(2), TimeDelta::minutes(5)] {
+        let context = RedirectContext { requested_at: Some(now - ago), ..Default::default() };
+        service.handle_redirect_with_context(link.slug.clone(), context).unwrap();
+    }
+    let buckets = service.get_redirects_over_time(&link.slug, now - TimeDelta::days(60), now).unwrap();
+    let buckets: Vec<_> = buckets.iter().map(|bucket| (bucket.resolution, bucket.redirects)).collect();
+    assert_eq!(buckets, [(Resolution::Day, 2), (Resolution::Hour, 1), (Resolution::Minute, 1)]);
+    let recent = service.get_redirects_over_time(&link.slug, now - TimeDelta::hours(1), now).unwrap();
+    assert_eq!(recent.len(), 1);
+
+    // Test device stats - redirects are broken down by devices and browsers
+    let mut service = UrlShortenerService::new();
+    let link = service.handle_create_short_link(Url(String::from("https://example.com/devices")), None).unwrap();
+    let user_agents = [
+        "Mozilla/5.0 (iPhone; CPU iPhone OS 17_0 like Mac OS X) AppleWebKit/605.1.15 Version/17.0 Mobile Safari/604.1",
+        "Mozilla/5.0 (Linux; Android 14; Pixel 8) AppleWebKit/537.36 Chrome/120.0 Mobile Safari/537.36",
+        "Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 Chrome/120.0 Safari/537.36 Edg/120.0",
+        "Mozilla/5.0 (X11; Linux x86_64; rv:121.0) Gecko/20100101 Firefox/121.0",
+    ];
+    for user_agent in user_agents {
+        let context = RedirectContext { user_agent: Some(String::from(user_agent)), ..Default::default() };
+        service.handle_redirect_with_context(link.slug.clone(), context).unwrap();
+    }
+    service.handle_redirect(link.slug.clone()).unwrap();
+    let stats = service.get_device_stats(&link.slug).unwrap();
+    assert_eq!(stats.redirects, 4);
+    assert_eq!(stats.devices[0], Share { item: DeviceClass::Desktop, redirects: 2, percent: 50.0 });
+    assert_eq!(stats.devices[1], Share { item: DeviceClass::Mobile, redirects: 2, percent: 50.0 });
+    let browsers: Vec<_> = stats.browsers.iter().map(|share| share.item).collect();
+    assert_eq!(browsers, [BrowserFamily::Chrome, BrowserFamily::Firefox, BrowserFamily::Safari, BrowserFamily::Edge]);
+
+    // Test CSV export - stats and clicks of links are written row by row
+    let mut service = UrlShortenerService::new();
+    let link = service.handle_create_short_link(Url(String::from("https://example.com/a,b")), None).unwrap();
+    let context = RedirectContext { referrer: Some(String::from("say \"hi\"")), ..Default::default() };
+    service.handle_redirect_with_context(link.slug.clone(), context).unwrap();
+    let mut csv = Vec::new();
+    let filter = ExportFilter { include_clicks: true, ..Default::default() };
+    assert_eq!(service.export_stats_csv(&mut csv, &filter).unwrap(), 2);
+    let csv = String::from_utf8(csv).unwrap();
+    let lines: Vec<_> = csv.lines().collect();
+    assert_eq!(lines.len(), 3);
+    assert_eq!(lines[1], format!("link,{},\"https://example.com/a,b\",1,1,0,,,,,,", link.slug.0));
+    assert!(lines[2].starts_with(&format!("click,{},,,,,", link.slug.0)));
+    assert!(lines[2].ends_with(",,\"say \"\"hi\"\"\",,,false"));
+
+    // Test click feed - counted redirects are streamed to subscribers by slug and tag
+    let mut service = UrlShortenerService::new();
+    let watched = service.handle_create_short_link(Url(String::from("https://example.com/watched")), None).unwrap();
+    let other = service.handle_create_short_link(Url(String::from("https://example.com/other")), None).unwrap();
+    service.handle_tag_link(other.slug.clone(), vec![Tag(String::from("campaign"))]).unwrap();
+    let mut by_slug = service.subscribe_clicks(EventFilter::all().with_slug(watched.slug.clone()));
+    let by_tag = service.subscribe_clicks(EventFilter::all().with_tag(Tag(String::from("campaign"))));
+    service.handle_redirect_from(watched.slug.clone(), VisitorId(String::from("visitor"))).unwrap();
+    service.handle_redirect(other.slug.clone()).unwrap();
+    let click = block_on(by_slug.next_click()).unwrap();
+    assert_eq!((click.slug, click.visitor), (watched.slug.clone(), Some(VisitorId(String::from("visitor")))));
+    assert_eq!(by_slug.try_next(), None);
+    assert_eq!(by_tag.try_next().map(|click| click.slug), Some(other.slug.clone()));
+    drop(service);
+    assert_eq!(block_on(by_slug.next_click()), None);
+
+    // Test click deduplication - anonymous visitors are told apart by fingerprints of their requests
+    let config = ServiceConfig { redirect_dedup_window: Some(TimeDelta::seconds(30)), ..Default::default() };
+    let mut service = UrlShortenerService::with_config(config);
+    let link = service.handle_create_short_link(Url(String::from("https://example.com/refresh")), None).unwrap();
+    let request = |ip: u8, user_agent: &str| RedirectContext {
+        ip: Some(IpAddr::from([192, 0, 2, ip])),
+        user_agent: Some(String::from(user_agent)),
+        ..Default::default()
+    };
+    for context in [request(1, "Firefox"), request(1, "Firefox"), request(1, "Chrome"), request(2, "Firefox")] {
+        service.handle_redirect_with_context(link.slug.clone(), context).unwrap();
+    }
+    assert_eq!(service.get_stats(link.slug.clone()).unwrap().redirects, 3);
+    let suppressed = service.events().iter()
+        .filter(|record| matches!(record.event, Event::RedirectDeduplicated { .. }))
+        .count();
+    assert_eq!(suppressed, 1);
+
+    // Test campaigns - redirects of links of a campaign add up
+    let mut service = UrlShortenerService::new();
+    let spring = Campaign(String::from("spring-sale"));
+    let mut links = Vec::new();
+    for index in 0..3 {
+        let url = Url(format!("https://example.com/spring-{index}"));
+        let link = service.handle_create_short_link(url, None).unwrap();
+        service.handle_assign_campaign(link.slug.clone(), Some(spring.clone())).unwrap();
+        links.push(link);
+    }
+    for (index, link) in links.iter().enumerate() {
+        for visitor in 0..=index {
+            service.handle_redirect_from(link.slug.clone(), VisitorId(format!("visitor-{visitor}"))).unwrap();
+        }
+    }
+    service.handle_assign_campaign(links[0].slug.clone(), None).unwrap();
+    service.handle_redirect(links[0].slug.clone()).unwrap();
+    let stats = service.get_campaign_stats(&spring).unwrap();
+    assert_eq!((stats.links, stats.redirects, stats.unique_visitors), (2, 6, 3));
+    assert_eq!(stats.top_links[0], (links[2].slug.clone(), 3));
+    assert_eq!(service.get_link(&links[1].slug).unwrap().campaign, Some(spring));
+    let missing = Campaign(String::from("missing"));
+    assert_eq!(service.get_campaign_stats(&missing), Err(ShortenerError::CampaignNotFound));
+
+    // Test conversions - reported conversions are counted once and give a rate
+    let mut service = UrlShortenerService::new();
+    let link = service.handle_create_short_link(Url(String::from("https://example.com/signup")), None).unwrap();
+    for _ in 0..4 {
+        service.handle_redirect(link.slug.clone()).unwrap();
+    }
+    for order in ["order-1", "order-2", "order-1"] {
+        service.handle_conversion(link.slug.clone(), ConversionId(String::from(order))).unwrap();
+    }
+    let breakdown = service.get_stats_breakdown(&link.slug).unwrap();
+    assert_eq!((breakdown.conversions, breakdown.conversion_rate()), (2, 0.5));
+    let missing = Slug(String::from("missing-conversion"));
+    let conversion = ConversionId(String::from("order-3"));
+    assert_eq!(service.handle_conversion(missing, conversion), Err(ShortenerError::SlugNotFound));
+
+    // Test click retention - raw redirect events are pruned, while counters are kept
+    let config = ServiceConfig { click_retention: Some(TimeDelta::days(90)), ..Default::default() };
+    let mut service = UrlShortenerService::with_config(config);
+    let ephemeral = service.handle_create_short_link(Url(String::from("https://example.com/ephemeral")), None).unwrap();
+    let kept = service.handle_create_short_link(Url(String::from("https://example.com/kept")), None).unwrap();
+    service.handle_set_click_retention(ephemeral.slug.clone(), Some(TimeDelta::zero())).unwrap();
+    for slug in [&ephemeral.slug, &ephemeral.slug, &kept.slug] {
+        service.handle_redirect(slug.clone()).unwrap();
+    }
+    assert_eq!(service.handle_prune_clicks(), vec![ephemeral.slug.clone()]);
+    assert_eq!(service.handle_prune_clicks(), []);
+    let redirects = service.events().iter().filter(|record| matches!(record.event, Event::Redirected { .. })).count();
+    assert_eq!(redirects, 1);
+    assert_eq!(service.get_stats(ephemeral.slug.clone()).unwrap().redirects, 2);
+    assert_eq!(service.get_link(&ephemeral.slug).unwrap().click_retention, Some(TimeDelta::zero()));
+
+    // Test click aggregates - pruned redirects survive rebuilds of projections and restoring from the log
+    let kinds: Vec<_> = service.events().iter().map(|record| record.event.kind()).collect();
+    assert_eq!(kinds.iter().filter(|kind| **kind == EventKind::RedirectsAggregated).count(), 1);
+    let report = service.check_integrity();
+    assert!(report.passed() && report.checks.iter().all(|check| !check.skipped), "Integrity check failed:\n{report}");
+    let link_id = service.get_link_id(&ephemeral.slug).unwrap();
+    let rollups = |service: &UrlShortenerService| -> u64 {
+        let buckets = service.projection::<RedirectRollups>().unwrap().range(&link_id, Utc::now() - TimeDelta::hours(1), Utc::now());
+        buckets.iter().map(|bucket| bucket.redirects).sum()
+    };
+    service.rebuild_projection(RedirectRollups::NAME).unwrap();
+    assert_eq!(rollups(&service), 2);
+    let restored = UrlShortenerService::restore(ServiceConfig::default(), service.events().to_vec());
+    assert_eq!(restored.get_stats(ephemeral.slug.clone()), service.get_stats(ephemeral.slug.clone()));
+    assert_eq!(rollups(&restored), 2);
+
+    // Test IP anonymization - raw addresses of visitors never reach the event log
+    let recorded_ip = |anonymization: IpAnonymization, ip: IpAddr| {
+        let config = ServiceConfig { ip_anonymization: anonymization, ..Default::default() };
+        let mut service = UrlShortenerService::with_config(config);
+        service.set_geo_resolver(|ip: IpAddr| ip.is_ipv4().then(|| CountryCode(String::from("NL"))));
+        let link = service.handle_create_short_link(Url(String::from("https://example.com/private")), None).unwrap();
+        let context = RedirectContext { ip: Some(ip), ..Default::default() };
+        service.handle_redirect_with_context(link.slug.clone(), context).unwrap();
+        let Some(Event::Redirected { context: Some(context), .. }) = service.events().last().map(|r| &r.event) else {
+            panic!("Redirect context was not recorded");
+        };
+        assert_eq!(context.country, ip.is_ipv4().then(|| CountryCode(String::from("NL"))));
+        context.ip.unwrap()
+    };
+    let ipv4 = IpAddr::from([203, 0, 113, 77]);
+    let ipv6 = IpAddr::from([0x2001, 0xdb8, 0x1234, 0x5678, 0, 0, 0, 1]);
+    assert_eq!(recorded_ip(IpAnonymization::Off, ipv4), ipv4);
+    assert_eq!(recorded_ip(IpAnonymization::Truncate, ipv4), IpAddr::from([203, 0, 113, 0]));
+    assert_eq!(recorded_ip(IpAnonymization::Truncate, ipv6), IpAddr::from([0x2001, 0xdb8, 0x1234, 0, 0, 0, 0, 0]));
+    let salted = IpAnonymization::Hash { salt: String::from("secret") };
+    let pseudonym = recorded_ip(salted.clone(), ipv4);
+    assert!(matches!(pseudonym, IpAddr::V6(ip) if ip.octets()[0] == 0xfd));
+    assert_eq!(recorded_ip(salted, ipv4), pseudonym);
+
+    // Test digests - the scheduled digest is delivered to the sink and scheduled again
+    let mut service = UrlShortenerService::new();
+    let (digests, delivered) = std::sync::mpsc::channel();
+    service.set_digest_sink(move |digest: &StatsDigest| {
+        digests.send(digest.clone()).map_err(|error| error.to_string())
+    });
+    let popular = service.handle_create_short_link(Url(String::from("https://example.com/popular")), None).unwrap();
+    let quiet = service.handle_create_short_link(Url(String::from("https://example.com/quiet")), None).unwrap();
+    for slug in [&popular.slug, &quiet.slug, &popular.slug] {
+        service.handle_redirect(slug.clone()).unwrap();
+    }
+    let command = Command::SendDigest { period: DigestPeriod::Daily, repeat: true };
+    service.handle_schedule_command(RequestContext::default(), command.clone(), Utc::now());
+    service.handle_run_due_commands();
+    let digest = delivered.try_recv().unwrap();
+    assert_eq!(digest.total_redirects, 3);
+    assert_eq!(digest.top_links, [(popular.slug.clone(), 2), (quiet.slug.clone(), 1)]);
+    assert_eq!(digest.new_links, [popular.clone(), quiet.clone()]);
+    assert!(digest.to_json().contains(r#""period":"daily","#));
+    let scheduled = service.list_scheduled_commands();
+    assert!(matches!(scheduled.as_slice(), [next] if next.command == command && next.due_at > digest.to));
+
+    // Test anomaly detection - spikes and traffic of a single visitor flag links once
+    let rules = AnomalyRules { spike_factor: 2.0, min_redirects: 4, ..Default::default() };
+    let mut service = UrlShortenerService::with_config(ServiceConfig { anomaly_rules: rules, ..Default::default() });
+    let viral = service.handle_create_short_link(Url(String::from("https://example.com/viral")), None).unwrap();
+    let farmed = service.handle_create_short_link(Url(String::from("https://example.com/farmed")), None).unwrap();
+    let calm = service.handle_create_short_link(Url(String::from("https://example.com/calm")), None).unwrap();
+    for octet in 1..=4 {
+        let context = |ip: IpAddr| RedirectContext { ip: Some(ip), ..Default::default() };
+        service.handle_redirect_with_context(viral.slug.clone(), context(IpAddr::from([198, 51, 100, octet]))).unwrap();
+        service.handle_redirect_with_context(farmed.slug.clone(), context(IpAddr::from([198, 51, 100, 1]))).unwrap();
+    }
+    service.handle_redirect(calm.slug.clone()).unwrap();
+    assert_eq!(service.handle_detect_anomalies(), [viral.slug.clone(), farmed.slug.clone()]);
+    assert_eq!(service.handle_detect_anomalies(), []);
+    let flagged = service.list_flagged_links();
+    assert!(matches!(flagged[0].anomaly, Some(Anomaly::Spike { redirects: 4, .. })));
+    assert!(matches!(flagged[1].anomaly, Some(Anomaly::DominantVisitor { share, .. }) if share == 1.0));
+    assert_eq!(flagged.len(), 2);
+
+    // Test link deletion - the slug is freed and clients syncing changes are told about the deletion
+    let mut service = UrlShortenerService::new();
+    let doomed_slug = Slug(String::from("doomed"));
+    let url = Url(String::from("https://example.com/doomed"));
+    let doomed = service.handle_create_short_link(url.clone(), Some(doomed_slug.clone())).unwrap();
+    service.handle_redirect(doomed.slug.clone()).unwrap();
+    let cursor = service.get_changes_since(SyncCursor::default()).cursor;
+    let doomed_id = service.get_link_id(&doomed.slug).unwrap();
+    service.dispatch_command(&RequestContext::default(), Command::DeleteLink { slug: doomed.slug.clone() }).unwrap();
+    assert_eq!(service.handle_redirect(doomed.slug.clone()), Err(ShortenerError::SlugNotFound));
+    assert_eq!(service.get_changes_since(cursor).deletions, [doomed_id]);
+    let reborn = service.handle_create_short_link(url, Some(doomed_slug)).unwrap();
+    assert_eq!(service.get_stats(reborn.slug.clone()).unwrap().redirects, 0);
+    assert_eq!(service.list_links().len(), 1);
+    assert!(service.check_integrity().passed());
+    assert_eq!(ShortenerError::SlugAlreadyInUse.status_code(), 409);
+
+    // Test link search - slugs, destinations and titles match regardless of case
+    let mut service = UrlShortenerService::new();
+    let docs = service.handle_create_short_link(Url(String::from("https://example.com/docs")), None).unwrap();
+    let blog = service.handle_create_short_link(Url(String::from("https://example.org/blog")), None).unwrap();
+    let metadata = LinkMetadata { title: Some(String::from("Release Notes")), ..Default::default() };
+    service.handle_update_metadata(blog.slug.clone(), metadata).unwrap();
+    let slugs = |links: Vec<LinkInfo>| links.into_iter().map(|info| info.link.slug).collect::<Vec<_>>();
+    assert_eq!(slugs(service.search_links("EXAMPLE.")), [docs.slug, blog.slug.clone()]);
+    assert_eq!(slugs(service.search_links("release")), [blog.slug]);
+    assert!(service.search_links("missing").is_empty());
+
+    // Test rate limiting - clients get their burst, then wait for tokens refilled evenly, tenants have own limits
+    let mut limiter = RateLimiter::default();
+    let limit = RateLimit { capacity: 2, period: TimeDelta::seconds(10) };
+    let ip = ClientKey::Ip(IpAddr::from([203, 0, 113, 7]));
+    let key = ClientKey::ApiKey(String::from("key"));
+    let start = Utc::now();
+    let mut take = |clients: &[ClientKey], seconds| {
+        limiter.take(None, LimitedOperation::Redirect, clients, limit, start + TimeDelta::seconds(seconds))
+    };
+    assert_eq!(take(&[ip.clone(), key.clone()], 0), Ok(()));
+    assert_eq!(take(std::slice::from_ref(&ip), 0), Ok(()));
+    assert_eq!(take(&[ip.clone(), key.clone()], 0), Err(TimeDelta::seconds(5)));
+    assert_eq!(take(std::slice::from_ref(&key), 0), Ok(()));
+    assert_eq!(take(std::slice::from_ref(&ip), 5), Ok(()));
+    let tenant = TenantId(String::from("acme"));
+    let mut service = UrlShortenerService::with_config(ServiceConfig {
+        rate_limits: RateLimits { create_link: Some(RateLimit::per_minute(1)), redirect: None },
+        tenant_rate_limits: HashMap::from([(tenant.clone(), RateLimits::default())]),
+        ..Default::default()
+    });
+    let clients = [ip];
+    assert_eq!(service.check_rate_limit(None, LimitedOperation::CreateLink, &clients), Ok(()));
+    let limited = service.check_rate_limit(None, LimitedOperation::CreateLink, &clients).unwrap_err();
+    assert_eq!(limited, ShortenerError::RateLimited { retry_after: 60 });
+    assert_eq!((limited.status_code(), ErrorPayload::from(&limited).retry_after), (429, Some(60)));
+    let refusal = RedirectRefusal::RateLimited { retry_after: 60 };
+    assert_eq!((refusal.status_code(), refusal.error()), (429, limited));
+    assert_eq!(service.check_rate_limit(None, LimitedOperation::Redirect, &clients), Ok(()));
+    assert_eq!(service.check_rate_limit(Some(&tenant), LimitedOperation::CreateLink, &clients), Ok(()));
+
+    // Test API keys - keys authenticate their actor, who is recorded with events, until they are revoked
+    let mut service = UrlShortenerService::new();
+    let editor = ActorId(String::from("editor"));
+    let Ok(Reply::ApiKey(api_key)) = service.dispatch_command(
+        &RequestContext::default(),
+        Command::CreateApiKey { actor: editor.clone(), role: Role::Editor },
+    ) else {
+        panic!("API key wasn't created");
+    };
+    assert_eq!(service.authenticate(None), Err(ShortenerError::ApiKeyRequired));
+    assert_eq!(service.authenticate(Some("guess")), Err(ShortenerError::InvalidApiKey));
+    let context = service.authenticate(Some(&api_key.secret)).unwrap();
+    assert_eq!(context.actor.as_ref(), Some(&editor));
+    let url = Url(String::from("https://example.com/keyed"));
+    service.dispatch_command(&context, Command::CreateShortLink { url, slug: None, options: LinkOptions::default() })
+        .unwrap();
+    let recorded = service.events().last().unwrap();
+    assert!(matches!(recorded.event, Event::LinkCreated { .. }) && recorded.actor.as_ref() == Some(&editor));
+    assert!(!format!("{:?}", service.events()).contains(&api_key.secret));
+    assert_eq!(service.handle_revoke_api_key(&api_key.id), Ok(()));
+    assert_eq!(service.authenticate(Some(&api_key.secret)), Err(ShortenerError::InvalidApiKey));
+    assert_eq!(service.handle_revoke_api_key(&api_key.id), Err(ShortenerError::ApiKeyNotFound));
+    assert_eq!(ShortenerError::InvalidApiKey.status_code(), 401);
+
+    // Test multi-tenancy - slugs are unique per tenant, queries and listings see only links of the caller's tenant
+    let mut service = UrlShortenerService::new();
+    let acme = RequestContext { tenant: Some(TenantId(String::from("acme"))), ..Default::default() };
+    let globex = RequestContext { tenant: Some(TenantId(String::from("globex"))), ..Default::default() };
+    for (context, url) in [(&acme, "https://acme.example.com/"), (&globex, "https://globex.example.com/")] {
+        let command = Command::CreateShortLink {
+            url: Url(String::from(url)),
+            slug: Some(Slug(String::from("promo"))),
+            options: LinkOptions::default(),
+        };
+        assert!(service.dispatch_command(context, command).is_ok());
+    }
+    let Ok(Reply::LinkInfo(info)) = service.dispatch_query(&acme, Query::GetLink { slug: Slug(String::from("promo")) })
+    else {
+        panic!("link of the tenant wasn't found");
+    };
+    assert_eq!((info.link.url.0.as_str(), info.tenant.as_ref()), ("https://acme.example.com/", acme.tenant.as_ref()));
+    assert_eq!(service.get_link(&Slug(String::from("promo"))).map(|_| ()), Err(ShortenerError::SlugNotFound));
+    assert_eq!(service.act_as(&globex, |service| service.list_links().len()), 1);
+    assert_eq!(service.events_of_tenant(acme.tenant.as_ref()).count(), 1);
+    let promo = || Slug(String::from("promo"));
+    let (sale, launch) = (Tag(String::from("sale")), Campaign(String::from("launch")));
+    for context in [&acme, &globex] {
+        service.act_as(context, |service| {
+            service.handle_tag_link(promo(), vec![sale.clone()]).unwrap();
+            service.handle_assign_campaign(promo(), Some(launch.clone())).unwrap();
+            service.handle_create_namespace(String::from("team")).unwrap();
+        });
+    }
+    let group = service.act_as(&globex, |service| service.handle_create_group(String::from("globex links")));
+    service.act_as(&acme, |service| {
+        assert_eq!(service.list_links_by_tag(&sale).len(), 1);
+        assert_eq!(service.get_changes_since(SyncCursor::default()).upserts.len(), 1);
+        assert!(service.list_groups().is_empty());
+        assert_eq!(service.list_links_in_group(&group).map(|_| ()), Err(ShortenerError::GroupNotFound));
+        assert_eq!(service.handle_move_to_group(promo(), Some(group.clone())), Err(ShortenerError::GroupNotFound));
+        assert_eq!(service.list_namespaces().len(), 1);
+        assert_eq!(service.get_campaign_stats(&launch).map(|stats| stats.links), Ok(1));
+    });
+    assert_eq!(service.act_as(&globex, |service| service.list_groups().len()), 1);
+    let (held, offer) = (Slug(String::from("Held")), Slug(String::from("offer")));
+    let link_id = service.act_as(&acme, |service| {
+        let link_id = service.get_link_id(&promo()).unwrap();
+        service.handle_reserve_slug(held.clone(), Some(TimeDelta::zero())).unwrap();
+        service.handle_rename_slug(promo(), offer, OldSlugPolicy::ForwardFor(TimeDelta::zero())).unwrap();
+        link_id
+    });
+    let recorded = service.events().len();
+    for _ in 0..2 {
+        service.handle_expire_reservations();
+        service.handle_expire_aliases();
+    }
+    let expired: Vec<_> = service.events()[recorded..].iter()
+        .map(|record| (record.tenant.as_ref(), record.event.clone()))
+        .collect();
+    assert_eq!(expired, [
+        (acme.tenant.as_ref(), Event::SlugReservationExpired { slug: held }),
+        (acme.tenant.as_ref(), Event::SlugAliasExpired { link_id, slug: promo() }),
+    ]);
+    assert_eq!(service.check_integrity().checks.iter().map(|check| check.problems.len()).sum::<usize>(), 0);
+
+    // Test roles - viewers only query, editors modify only their own links, admins delete anything and read events
+    let mut service = UrlShortenerService::new();
+    let mut context_of = |name: &str, role| {
+        let api_key = service.handle_create_api_key(ActorId(String::from(name)), role);
+        service.authenticate(Some(&api_key.secret)).unwrap()
+    };
+    let viewer = context_of("viewer", Role::Viewer);
+    let editor = context_of("editor", Role::Editor);
+    let other_editor = context_of("other editor", Role::Editor);
+    let admin = context_of("admin", Role::Admin);
+    let slug = Slug(String::from("roles"));
+    let create = Command::CreateShortLink {
+        url: Url(String::from("https://example.com/roles")),
+        slug: Some(slug.clone()),
+        options: LinkOptions::default(),
+    };
+    assert_eq!(service.dispatch_command(&viewer, create.clone()), Err(ShortenerError::AccessDenied));
+    assert!(service.dispatch_command(&editor, create).is_ok());
+    assert!(matches!(service.dispatch_query(&viewer, Query::GetStats { slug: slug.clone() }), Ok(Reply::Stats(_))));
+    let events = Query::GetEventsFor { slug: slug.clone(), from_version: 0 };
+    assert_eq!(service.dispatch_query(&editor, events.clone()), Err(ShortenerError::AccessDenied));
+    assert!(service.dispatch_query(&admin, events).is_ok());
+    let disable = Command::DisableLink { slug: slug.clone() };
+    assert_eq!(service.dispatch_command(&other_editor, disable.clone()), Err(ShortenerError::AccessDenied));
+    assert_eq!(service.dispatch_command(&editor, disable), Ok(Reply::Done));
+    assert_eq!(service.dispatch_command(&admin, Command::DeleteLink { slug }), Ok(Reply::Done));
+    let (held, drafted) = (Slug(String::from("held-roles")), Slug(String::from("draft-roles")));
+    let url = Url(String::from("https://example.com/roles-reserved"));
+    service.dispatch_command(&editor, Command::ReserveSlug { slug: held.clone(), timeout: None }).unwrap();
+    let prepare = Command::PrepareLink {
+        url: Url(String::from("https://example.com/roles-draft")),
+        slug: Some(drafted.clone()),
+        options: LinkOptions::default(),
+        timeout: TimeDelta::hours(1),
+    };
+    service.dispatch_command(&editor, prepare).unwrap();
+    let attach = Command::AttachUrl { slug: held.clone(), url };
+    let activate = Command::ActivateLink { slug: drafted.clone() };
+    assert_eq!(service.dispatch_command(&other_editor, attach.clone()), Err(ShortenerError::AccessDenied));
+    assert_eq!(service.dispatch_command(&other_editor, activate.clone()), Err(ShortenerError::AccessDenied));
+    assert!(service.dispatch_command(&admin, attach).is_ok());
+    assert!(service.dispatch_command(&admin, activate).is_ok());
+    let owner = editor.actor.as_ref().map(OwnerId::from);
+    assert_eq!(service.get_link(&held).map(|info| info.owner), Ok(owner.clone()));
+    assert_eq!(service.get_link(&drafted).map(|info| info.owner), Ok(owner));
+
+    // Test event store inspection - admins page through raw events, compact them and rebuild projections
+    let page = Query::ListEvents { after: 2, limit: 2 };
+    assert_eq!(service.dispatch_query(&editor, page.clone()), Err(ShortenerError::AccessDenied));
+    match service.dispatch_query(&admin, page) {
+        Ok(Reply::Events(events)) => assert_eq!(events.iter().map(|event| event.sequence).collect::<Vec<_>>(), [3, 4]),
+        other => panic!("Failed to list events after sequence 2: {:?}", other),
+    }
+    let guarded = service.handle_create_short_link(Url(String::from("https://example.com/guarded")), None).unwrap();
+    service.handle_set_password(guarded.slug.clone(), "secret").unwrap();
+    assert!(service.handle_redirect_with_password(guarded.slug.clone(), "wrong", None).is_err());
+    let events = service.events().len();
+    assert_eq!(service.dispatch_command(&editor, Command::CompactEvents), Err(ShortenerError::AccessDenied));
+    assert_eq!(service.dispatch_command(&admin, Command::CompactEvents), Ok(Reply::CompactedEvents(1)));
+    assert_eq!(service.events().len(), events - 1);
+    let rebuild = Command::RebuildProjection { name: TagIndex::default().name().to_owned() };
+    assert_eq!(service.dispatch_command(&admin, rebuild), Ok(Reply::Done));
+    let rebuild = Command::RebuildProjection { name: String::from("missing") };
+    assert_eq!(service.dispatch_command(&admin, rebuild), Err(ShortenerError::ProjectionNotFound));
+
+    // Test webhooks of domain events - triggering events queue signed deliveries, failed ones are retried
+    struct FlakyTransport(Cell<u32>);
+
+    impl WebhookTransport for FlakyTransport {
+        fn post(&self, _delivery: &WebhookDelivery) -> Result<(), String> {
+            self.0.set(self.0.get() + 1);
+            if self.0.get() == 1 { Err(String::from("connection refused")) } else { Ok(()) }
+        }
+    }
+
+    let retries = RetryPolicy { backoff: TimeDelta::zero(), ..Default::default() };
+    let mut service =
+        UrlShortenerService::with_config(ServiceConfig { webhook_retries: retries, ..Default::default() });
+    service.set_webhook_transport(FlakyTransport(Cell::new(0)));
+    let webhook = EventWebhook {
+        url: Url(String::from("https://hooks.example.com/events")),
+        secret: String::from("secret"),
+        triggers: BTreeSet::from([WebhookTrigger::LinkCreated, WebhookTrigger::RedirectsReached(2)]),
+    };
+    let webhook_id = service.handle_register_webhook(webhook).unwrap();
+    let link = service.handle_create_short_link(Url(String::from("https://example.com/hooked")), None).unwrap();
+    for _ in 0..3 {
+        service.handle_redirect(link.slug.clone()).unwrap();
+    }
+    let attempted = service.handle_deliver_webhooks();
+    let triggers: Vec<_> = attempted.iter().map(|tracked| (tracked.trigger, tracked.status)).collect();
+    assert!(matches!(triggers[..], [
+        (WebhookTrigger::LinkCreated, DeliveryStatus::Pending { .. }),
+        (WebhookTrigger::RedirectsReached(2), DeliveryStatus::Delivered { .. }),
+    ]));
+    let delivery = &attempted[1].delivery;
+    assert_eq!(delivery.signature, format!("sha256={}", webhooks::sign("secret", &delivery.body)));
+    assert_eq!(service.handle_deliver_webhooks().len(), 1);
+    let deliveries = service.get_webhook_deliveries(webhook_id).unwrap();
+    assert!(deliveries.iter().all(|tracked| matches!(tracked.status, DeliveryStatus::Delivered { .. })));
+    assert_eq!(deliveries[0].attempts, 2);
+    service.handle_reset_stats(link.slug.clone()).unwrap();
+    for _ in 0..2 {
+        service.handle_redirect(link.slug.clone()).unwrap();
+    }
+    let jumped = service.handle_create_short_link(Url(String::from("https://example.com/jumped")), None).unwrap();
+    let imported = ImportedRedirect { slug: jumped.slug, at: Utc::now(), visitor: None, count: 5 };
+    service.handle_import_redirects(vec![imported], &MergeRules::default());
+    let deliveries = service.get_webhook_deliveries(webhook_id).unwrap();
+    let reached = deliveries.iter().filter(|tracked| tracked.trigger == WebhookTrigger::RedirectsReached(2)).count();
+    assert_eq!((deliveries.len(), reached), (4, 2));
+    assert_eq!(service.handle_unregister_webhook(webhook_id), Ok(()));
+    assert_eq!(service.get_webhook_deliveries(webhook_id), Err(ShortenerError::WebhookNotFound));
+
+    // Test readiness - projections are applied as events are recorded, so they never lag behind the event log
+    let lags = service.projection_lags();
+    assert!(lags.iter().any(|lag| lag.name == TagIndex::default().name()));
+    assert!(lags.iter().all(|lag| lag.lag == 0));
+
+    // Test metrics - redirects, creations, errors and command latency are recorded in the Prometheus text format
+    let metrics = metrics::PrometheusRecorder::default();
+    service.set_metrics_recorder(metrics.clone());
+    let command = Command::CreateShortLink {
+        url: Url(String::from("https://example.com/measured")),
+        slug: None,
+        options: LinkOptions::default(),
+    };
+    let Ok(Reply::Link(link)) = service.dispatch_command(&admin, command) else {
+        panic!("Failed to create a measured link");
+    };
+    service.handle_redirect(link.slug.clone()).unwrap();
+    let missing = Command::Redirect { slug: Slug(String::from("missing-measured")), visitor: None };
+    assert!(service.dispatch_command(&admin, missing).is_err());
+    let text = metrics.render();
+    assert!(text.contains("# TYPE shortener_links_created_total counter\nshortener_links_created_total 1\n"));
+    assert!(text.contains("shortener_redirects_total 1\n"));
+    assert!(text.contains("shortener_errors_total{code=\"slug_not_found\"} 1\n"));
+    assert!(text.contains("shortener_command_duration_seconds_count{command=\"create_short_link\"} 1\n"));
+    assert!(text.contains("shortener_event_store_size "));
+
+    // Test restoring - a service restored from recorded events has the same state and continues the event log
+    let config = ServiceConfig { quiet: true, ..Default::default() };
+    let mut restored = UrlShortenerService::restore(config, service.events().to_vec());
+    assert_eq!(restored.list_links(), service.list_links());
+    assert_eq!(restored.get_stats(link.slug.clone()), service.get_stats(link.slug.clone()));
+    assert!(restored.check_integrity().passed());
+    restored.handle_create_short_link(Url(String::from("https://example.com/restored")), None).unwrap();
+    let last_sequence = service.events().last().map(|record| record.sequence);
+    assert_eq!(restored.events().last().map(|record| record.sequence), last_sequence.map(|sequence| sequence + 1));
+
+    // Test replication - events after the last applied one are applied and published, earlier ones are skipped
+    let config = ServiceConfig { quiet: true, ..Default::default() };
+    let records = restored.events().to_vec();
+    let mut follower = UrlShortenerService::restore(config, records[..records.len() - 1].to_vec());
+    let subscription = follower.subscribe(EventFilter::all());
+    follower.replicate(records.clone());
+    assert_eq!(subscription.events.try_iter().collect::<Vec<_>>(), records[records.len() - 1..]);
+    assert_eq!(follower.events(), restored.events());
+    assert_eq!(follower.list_links(), restored.list_links());
+
+    // Test event log - records survive a round trip through the file format, and edited lines are detected
+    #[cfg(feature = "event-log")]
+    {
+        let mut file = Vec::new();
+        let checksum = event_log::write_records(&mut file, "", service.events()).unwrap();
+        let (records, last_checksum) = event_log::read_records(file.as_slice()).unwrap();
+        assert_eq!(records, service.events());
+        assert_eq!(last_checksum, checksum);
+        let edited = String::from_utf8(file).unwrap().replacen("https://example.com", "https://example.org", 1);
+        let read = event_log::read_records(edited.as_bytes());
+        assert!(matches!(read, Err(event_log::EventLogError::ChecksumMismatch { .. })));
+        let verification = event_log::verify(edited.as_bytes()).unwrap();
+        assert!(matches!(verification.problems[..], [event_log::EventLogError::ChecksumMismatch { .. }]));
+        assert_eq!(verification.records, records.len() - 1);
+
+        // rewritten logs keep only the given records, and appending continues their checksum chain
+        let path = std::env::temp_dir().join(format!("urlshort-rewrite-{}.jsonl", std::process::id()));
+        let (mut log, _) = event_log::EventLogFile::open(&path).unwrap();
+        log.append(service.events()).unwrap();
+        log.rewrite(&records[..2]).unwrap();
+        assert_eq!(log.append(&records[..3]).unwrap(), 1);
+        assert_eq!(event_log::EventLogFile::open(&path).unwrap().1, records[..3]);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    // Test import of links - rows of CSV are created with their tags, taken slugs and invalid rows are reported
+    let csv = "url,slug,tags\r\n\
+        https://example.com/imported,imported,\"promo, summer\"\r\n\
+        https://example.com/generated,,\r\n\
+        \r\n\
+        https://example.com/other,imported,promo\r\n\
+        not a url,broken,\r\n";
+    let report = service.import_links_csv(csv.as_bytes()).unwrap();
+    assert_eq!(report.created.len(), 2);
+    assert_eq!(report.conflicts, vec![(5, Slug(String::from("imported")))]);
+    assert!(matches!(report.invalid[..], [(6, _)]));
+    let imported = service.get_link(&Slug(String::from("imported"))).unwrap();
+    assert_eq!(imported.tags, BTreeSet::from([Tag(String::from("promo")), Tag(String::from("summer"))]));
+
+    // Test inspection of the event log - recorded events are matched by their resolved slugs and current tags
+    service.handle_redirect(Slug(String::from("imported"))).unwrap();
+    let filter = EventFilter::all().with_kinds([EventKind::Redirected]).with_tag(Tag(String::from("summer")));
+    let matching: Vec<_> = service.events_matching(&filter).collect();
+    assert!(matches!(matching[..], [EventRecord { event: Event::Redirected { .. }, .. }]));
+}